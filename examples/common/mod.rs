@@ -0,0 +1,276 @@
+//! Setup shared by the examples: a minimal JSON-RPC client, funded wallets
+//! and a test mint. Build the program, start a local validator with it, then
+//! point the examples at it:
+//!
+//! ```text
+//! cargo build-bpf
+//! solana-test-validator --reset --bpf-program <PROGRAM_ID> target/deploy/vesting.so
+//! VESTING_CLUSTER=localnet VESTING_PROGRAM_ID=<PROGRAM_ID> \
+//!     cargo run --features client --example payroll_run
+//! ```
+//!
+//! Every step waits for confirmation and fails the example otherwise, so a
+//! clean exit means the whole flow worked.
+
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use vesting::cluster::{Cluster, ClusterConfig};
+use vesting::state::{StreamInstruction, TokenStreamData};
+use vesting::utils::associated_token_address;
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+pub struct Rpc {
+    address: String,
+}
+
+impl Rpc {
+    /// Plain `http://host:port` URLs only, which is all a local validator
+    /// needs.
+    pub fn new(url: &str) -> Self {
+        let address = url.trim_start_matches("http://").trim_end_matches('/');
+        Rpc {
+            address: address.to_string(),
+        }
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let body = body.to_string();
+        let mut stream = TcpStream::connect(&self.address)?;
+        // HTTP/1.0 keeps the response unchunked; it ends when the server
+        // closes the connection.
+        write!(
+            stream,
+            "POST / HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            self.address,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body)
+            .ok_or("Malformed RPC response")?;
+        let mut reply: Value = serde_json::from_str(body)?;
+        if !reply["error"].is_null() {
+            return Err(format!("{} failed: {}", method, reply["error"]).into());
+        }
+
+        Ok(reply["result"].take())
+    }
+
+    pub fn latest_blockhash(&self) -> Result<Hash> {
+        let result = self.call("getLatestBlockhash", json!([{"commitment": "confirmed"}]))?;
+        let blockhash = result["value"]["blockhash"]
+            .as_str()
+            .ok_or("No blockhash")?;
+        Ok(Hash::from_str(blockhash)?)
+    }
+
+    pub fn minimum_balance(&self, len: usize) -> Result<u64> {
+        let result = self.call("getMinimumBalanceForRentExemption", json!([len]))?;
+        Ok(result.as_u64().ok_or("No rent")?)
+    }
+
+    pub fn airdrop(&self, to: &Pubkey, lamports: u64) -> Result<()> {
+        let signature = self.call("requestAirdrop", json!([to.to_string(), lamports]))?;
+        self.confirm(signature.as_str().ok_or("No signature")?)
+    }
+
+    /// Sends `instructions` in one transaction paid for by the first of
+    /// `signers`, and waits until it is confirmed.
+    pub fn send(&self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<()> {
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&signers[0].pubkey()),
+            &signers.to_vec(),
+            self.latest_blockhash()?,
+        );
+        let encoded = base64::encode(bincode::serialize(&tx)?);
+        let signature = self.call(
+            "sendTransaction",
+            json!([encoded, {"encoding": "base64", "preflightCommitment": "confirmed"}]),
+        )?;
+        self.confirm(signature.as_str().ok_or("No signature")?)
+    }
+
+    fn confirm(&self, signature: &str) -> Result<()> {
+        for _ in 0..60 {
+            let result = self.call("getSignatureStatuses", json!([[signature]]))?;
+            let status = &result["value"][0];
+            if !status.is_null() {
+                if !status["err"].is_null() {
+                    return Err(format!("{} failed: {}", signature, status["err"]).into());
+                }
+                if status["confirmationStatus"] != "processed" {
+                    return Ok(());
+                }
+            }
+            sleep(Duration::from_millis(500));
+        }
+
+        Err(format!("{} not confirmed", signature).into())
+    }
+
+    pub fn account_data(&self, key: &Pubkey) -> Result<Vec<u8>> {
+        let result = self.call(
+            "getAccountInfo",
+            json!([key.to_string(), {"encoding": "base64", "commitment": "confirmed"}]),
+        )?;
+        let data = result["value"]["data"][0]
+            .as_str()
+            .ok_or_else(|| format!("No account {}", key))?;
+        Ok(base64::decode(data)?)
+    }
+
+    pub fn token_balance(&self, tokens: &Pubkey) -> Result<u64> {
+        let data = self.account_data(tokens)?;
+        Ok(spl_token::state::Account::unpack(&data)?.amount)
+    }
+
+    pub fn stream(&self, metadata: &Pubkey) -> Result<TokenStreamData> {
+        Ok(TokenStreamData::decode(&self.account_data(metadata)?)?)
+    }
+}
+
+pub struct Env {
+    pub rpc: Rpc,
+    pub program_id: Pubkey,
+}
+
+/// Connects to the cluster configured by the `VESTING_*` variables, which
+/// has to be a local one: the examples mint and move tokens freely.
+pub fn connect() -> Result<Env> {
+    let config = ClusterConfig::from_env()?;
+    if config.cluster != Cluster::Localnet {
+        return Err("Run the examples against localnet".into());
+    }
+    let url = config.rpc_url.ok_or("No RPC URL")?;
+
+    Ok(Env {
+        rpc: Rpc::new(&url),
+        program_id: config.program_id,
+    })
+}
+
+/// A new wallet holding `sol` SOL from the faucet.
+pub fn funded_wallet(rpc: &Rpc, sol: u64) -> Result<Keypair> {
+    let wallet = Keypair::new();
+    rpc.airdrop(&wallet.pubkey(), sol * LAMPORTS_PER_SOL)?;
+    Ok(wallet)
+}
+
+/// A new mint with `decimals`, `authority` minting.
+pub fn create_mint(rpc: &Rpc, authority: &Keypair, decimals: u8) -> Result<Pubkey> {
+    let mint = Keypair::new();
+    let len = spl_token::state::Mint::LEN;
+    rpc.send(
+        &[
+            system_instruction::create_account(
+                &authority.pubkey(),
+                &mint.pubkey(),
+                rpc.minimum_balance(len)?,
+                len as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &authority.pubkey(),
+                None,
+                decimals,
+            )?,
+        ],
+        &[authority, &mint],
+    )?;
+
+    Ok(mint.pubkey())
+}
+
+/// Creates the associated token account of `owner` and mints `amount` into
+/// it.
+pub fn mint_to(
+    rpc: &Rpc,
+    authority: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Result<Pubkey> {
+    let tokens = associated_token_address(owner, mint, &spl_token::id());
+    rpc.send(
+        &[
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                owner,
+                mint,
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &tokens,
+                &authority.pubkey(),
+                &[],
+                amount,
+            )?,
+        ],
+        &[authority],
+    )?;
+
+    Ok(tokens)
+}
+
+/// Metadata address of the stream of `ix` from `sender` to `recipient`.
+pub fn stream_address(
+    env: &Env,
+    sender: &Pubkey,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    ix: &StreamInstruction,
+) -> Pubkey {
+    let (metadata, _) = TokenStreamData::metadata_address(
+        &env.program_id,
+        sender,
+        recipient,
+        mint,
+        &ix.metadata_seed,
+    );
+    metadata
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Sleeps until a little after `ts`, leaving the validator's clock time to
+/// catch up.
+pub fn wait_until(ts: u64) {
+    let target = ts + 2;
+    let now = now();
+    if target > now {
+        sleep(Duration::from_secs(target - now));
+    }
+}
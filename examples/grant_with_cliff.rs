@@ -0,0 +1,119 @@
+//! A contributor grant, with months compressed to seconds: four years behind
+//! a one-year cliff, funded a year at a time with `topup_stream`. Nothing
+//! can be withdrawn before the cliff; after it the contributor takes what
+//! vested, then leaves, and the DAO's `cancel` pays out the rest vested so
+//! far and returns the unvested deposit.
+//!
+//! See `common` for running it against a local validator.
+
+mod common;
+
+use solana_sdk::signature::Signer;
+use vesting::builder::StreamInstructionBuilder;
+use vesting::instruction::{cancel_stream, create_stream, topup_stream, withdraw_stream};
+
+/// A "month".
+const MONTH: u64 = 1;
+const GRANT: u64 = 48_000_000_000;
+
+fn main() -> common::Result<()> {
+    let env = common::connect()?;
+    let rpc = &env.rpc;
+
+    let dao = common::funded_wallet(rpc, 10)?;
+    let contributor = common::funded_wallet(rpc, 1)?;
+    let mint = common::create_mint(rpc, &dao, 9)?;
+    let treasury = common::mint_to(rpc, &dao, &mint, &dao.pubkey(), GRANT)?;
+
+    let start = common::now() + 5;
+    let cliff = start + 12 * MONTH;
+    let ix = StreamInstructionBuilder::new()
+        .linear(start, start + 48 * MONTH, GRANT)
+        .period(MONTH)
+        .cliff(cliff, GRANT / 4)
+        .deposit(GRANT / 4)
+        .cancelable(true, false)
+        .name("Contributor grant")
+        .build()?;
+
+    rpc.send(
+        &[create_stream(
+            &env.program_id,
+            &dao.pubkey(),
+            &treasury,
+            &contributor.pubkey(),
+            &mint,
+            &spl_token::id(),
+            &ix,
+        )?],
+        &[&dao],
+    )?;
+    let metadata = common::stream_address(&env, &dao.pubkey(), &contributor.pubkey(), &mint, &ix);
+    let stream = rpc.stream(&metadata)?;
+    println!("Granted {} to {}", GRANT, contributor.pubkey());
+
+    let withdraw = || {
+        withdraw_stream(
+            &env.program_id,
+            &contributor.pubkey(),
+            &stream.recipient_tokens,
+            &dao.pubkey(),
+            &metadata,
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+            0,
+        )
+    };
+
+    // Before the cliff a withdrawal goes through, but pays nothing.
+    common::wait_until(start);
+    rpc.send(&[withdraw()], &[&contributor])?;
+    assert_eq!(rpc.token_balance(&stream.recipient_tokens)?, 0);
+    println!("Nothing vested before the cliff");
+
+    rpc.send(
+        &[topup_stream(
+            &env.program_id,
+            &dao.pubkey(),
+            &treasury,
+            &metadata,
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+            GRANT / 4,
+        )],
+        &[&dao],
+    )?;
+    println!("Funded the second year");
+
+    common::wait_until(cliff + 3 * MONTH);
+    rpc.send(&[withdraw()], &[&contributor])?;
+    let vested = rpc.token_balance(&stream.recipient_tokens)?;
+    assert!(vested >= GRANT / 4);
+    println!("Withdrew {} after the cliff", vested);
+
+    let treasury_before = rpc.token_balance(&treasury)?;
+    rpc.send(
+        &[cancel_stream(
+            &env.program_id,
+            &dao.pubkey(),
+            &dao.pubkey(),
+            &treasury,
+            &contributor.pubkey(),
+            &stream.recipient_tokens,
+            &metadata,
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+        )],
+        &[&dao],
+    )?;
+
+    let paid = rpc.token_balance(&stream.recipient_tokens)?;
+    let returned = rpc.token_balance(&treasury)? - treasury_before;
+    assert_eq!(paid + returned, GRANT / 2);
+    println!("Contributor left: {} paid, {} returned", paid, returned);
+
+    Ok(())
+}
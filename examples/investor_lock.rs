@@ -0,0 +1,98 @@
+//! An investor allocation, with months compressed to seconds: locked for a
+//! year behind a six-month cliff, funded half at signing and half when the
+//! round closes with a `topup_stream`. The investor withdraws at the cliff
+//! and again once the lock ends, after which the stream holds nothing.
+//!
+//! See `common` for running it against a local validator.
+
+mod common;
+
+use solana_sdk::signature::Signer;
+use vesting::builder::StreamInstructionBuilder;
+use vesting::instruction::{create_stream, topup_stream, withdraw_stream};
+
+/// A "month".
+const MONTH: u64 = 2;
+const ALLOCATION: u64 = 1_200_000_000_000;
+
+fn main() -> common::Result<()> {
+    let env = common::connect()?;
+    let rpc = &env.rpc;
+
+    let project = common::funded_wallet(rpc, 10)?;
+    let investor = common::funded_wallet(rpc, 1)?;
+    let mint = common::create_mint(rpc, &project, 6)?;
+    let treasury = common::mint_to(rpc, &project, &mint, &project.pubkey(), ALLOCATION)?;
+
+    let start = common::now() + 5;
+    let end = start + 12 * MONTH;
+    let cliff = start + 6 * MONTH;
+    let ix = StreamInstructionBuilder::new()
+        .linear(start, end, ALLOCATION)
+        .period(1)
+        .cliff(cliff, ALLOCATION / 4)
+        .deposit(ALLOCATION / 2)
+        .cancelable(false, false)
+        .transferable(false, true)
+        .name("Seed round")
+        .build()?;
+
+    rpc.send(
+        &[create_stream(
+            &env.program_id,
+            &project.pubkey(),
+            &treasury,
+            &investor.pubkey(),
+            &mint,
+            &spl_token::id(),
+            &ix,
+        )?],
+        &[&project],
+    )?;
+    let metadata = common::stream_address(&env, &project.pubkey(), &investor.pubkey(), &mint, &ix);
+    let stream = rpc.stream(&metadata)?;
+    println!("Locked {} for {}", ALLOCATION, investor.pubkey());
+
+    rpc.send(
+        &[topup_stream(
+            &env.program_id,
+            &project.pubkey(),
+            &treasury,
+            &metadata,
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+            ALLOCATION / 2,
+        )],
+        &[&project],
+    )?;
+    assert_eq!(rpc.stream(&metadata)?.ix.deposited_amount, ALLOCATION);
+    println!("Round closed, allocation fully funded");
+
+    let withdraw = || {
+        withdraw_stream(
+            &env.program_id,
+            &investor.pubkey(),
+            &stream.recipient_tokens,
+            &project.pubkey(),
+            &metadata,
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+            0,
+        )
+    };
+
+    common::wait_until(cliff);
+    rpc.send(&[withdraw()], &[&investor])?;
+    let at_cliff = rpc.token_balance(&stream.recipient_tokens)?;
+    assert!((ALLOCATION / 4..ALLOCATION).contains(&at_cliff));
+    println!("Withdrew {} at the cliff", at_cliff);
+
+    common::wait_until(end);
+    rpc.send(&[withdraw()], &[&investor])?;
+    assert_eq!(rpc.token_balance(&stream.recipient_tokens)?, ALLOCATION);
+    println!("Lock ended, {} withdrawn in total", ALLOCATION);
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! A payroll run, with months compressed to seconds: one `create_many` starts
+//! a salary stream to each of three employees, the company tops the streams
+//! up for the coming months, the employees withdraw what they earned, and
+//! the last one is let go with a `cancel` paying out their final salary.
+//!
+//! See `common` for running it against a local validator.
+
+mod common;
+
+use solana_sdk::signature::{Keypair, Signer};
+use vesting::builder::StreamInstructionBuilder;
+use vesting::instruction::{cancel_stream, create_many, topup_stream, withdraw_stream};
+
+/// A "month".
+const PERIOD: u64 = 2;
+const SALARY: u64 = 5_000_000_000;
+
+fn main() -> common::Result<()> {
+    let env = common::connect()?;
+    let rpc = &env.rpc;
+
+    let company = common::funded_wallet(rpc, 10)?;
+    let mint = common::create_mint(rpc, &company, 6)?;
+    let treasury = common::mint_to(rpc, &company, &mint, &company.pubkey(), 100 * SALARY)?;
+    let employees = (0..3)
+        .map(|_| common::funded_wallet(rpc, 1))
+        .collect::<common::Result<Vec<Keypair>>>()?;
+    let recipients: Vec<_> = employees.iter().map(|e| e.pubkey()).collect();
+
+    let start = common::now() + 5;
+    let ix = StreamInstructionBuilder::new()
+        .payroll(SALARY, PERIOD)
+        .window(start, start + 12 * PERIOD)
+        .deposit(2 * SALARY)
+        .cancelable(true, false)
+        .name("Payroll")
+        .build()?;
+
+    rpc.send(
+        &[create_many(
+            &env.program_id,
+            &company.pubkey(),
+            &treasury,
+            &recipients,
+            &mint,
+            &spl_token::id(),
+            &ix,
+        )?],
+        &[&company],
+    )?;
+    println!("Created {} salary streams", recipients.len());
+
+    let streams: Vec<_> = recipients
+        .iter()
+        .map(|r| common::stream_address(&env, &company.pubkey(), r, &mint, &ix))
+        .collect();
+    for metadata in &streams {
+        let stream = rpc.stream(metadata)?;
+        rpc.send(
+            &[topup_stream(
+                &env.program_id,
+                &company.pubkey(),
+                &treasury,
+                metadata,
+                &stream.escrow_tokens,
+                &mint,
+                &spl_token::id(),
+                4 * SALARY,
+            )],
+            &[&company],
+        )?;
+    }
+    println!("Funded six months of salary");
+
+    common::wait_until(start + 3 * PERIOD);
+    for (employee, metadata) in employees.iter().zip(&streams) {
+        let stream = rpc.stream(metadata)?;
+        rpc.send(
+            &[withdraw_stream(
+                &env.program_id,
+                &employee.pubkey(),
+                &stream.recipient_tokens,
+                &company.pubkey(),
+                metadata,
+                &stream.escrow_tokens,
+                &mint,
+                &spl_token::id(),
+                0,
+            )],
+            &[employee],
+        )?;
+
+        let paid = rpc.token_balance(&stream.recipient_tokens)?;
+        assert!(paid >= 3 * SALARY);
+        println!("{} withdrew {}", employee.pubkey(), paid);
+    }
+
+    let leaver = &employees[2];
+    let stream = rpc.stream(&streams[2])?;
+    rpc.send(
+        &[cancel_stream(
+            &env.program_id,
+            &company.pubkey(),
+            &company.pubkey(),
+            &treasury,
+            &leaver.pubkey(),
+            &stream.recipient_tokens,
+            &streams[2],
+            &stream.escrow_tokens,
+            &mint,
+            &spl_token::id(),
+        )],
+        &[&company],
+    )?;
+
+    let stream = rpc.stream(&streams[2])?;
+    assert!(stream.canceled_at > 0);
+    println!(
+        "Let {} go, final pay {}",
+        leaver.pubkey(),
+        stream.withdrawn_amount
+    );
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, StreamNotSettled};
+use crate::state::{
+    ArchiveStreamAccounts, EndCause, SettlementRecord, TokenStreamData, SETTLEMENT_RECORD_SIZE,
+    SETTLEMENT_SEED,
+};
+
+/// Writes the `SettlementRecord` of an ended stream. Anyone may pay for it,
+/// and it is written once: the record never changes after.
+pub fn archive_stream(program_id: &Pubkey, acc: ArchiveStreamAccounts) -> ProgramResult {
+    msg!("Archiving SPL token stream");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.settlement.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.payer.is_writable || !acc.settlement.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let metadata = TokenStreamData::load(&acc.metadata.try_borrow_data()?)?;
+
+    let (settlement_pubkey, nonce) = SettlementRecord::address(program_id, acc.metadata.key);
+    if acc.settlement.key != &settlement_pubkey
+        || acc.system_program.key != &system_program::id()
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if metadata.end_cause == EndCause::Open {
+        msg!("Error: Stream hasn't ended");
+        return Err(StreamNotSettled.into());
+    }
+
+    let seeds = [SETTLEMENT_SEED, acc.metadata.key.as_ref(), &[nonce]];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.payer.key,
+            acc.settlement.key,
+            Rent::get()?.minimum_balance(SETTLEMENT_RECORD_SIZE),
+            SETTLEMENT_RECORD_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.payer.clone(),
+            acc.settlement.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    // Whatever the recipient didn't get went back to the sender, except for
+    // rollovers, which moved it into the new stream.
+    let refunded = match metadata.end_cause {
+        EndCause::RolledOver => 0,
+        _ => metadata
+            .ix
+            .deposited_amount
+            .saturating_sub(metadata.withdrawn_amount),
+    };
+    let record = SettlementRecord {
+        stream: *acc.metadata.key,
+        sender: metadata.sender,
+        recipient: metadata.recipient,
+        mint: metadata.mint,
+        end_cause: metadata.end_cause,
+        created_at: metadata.created_at,
+        ended_at: metadata.canceled_at.max(metadata.completed_at),
+        deposited: metadata.ix.deposited_amount,
+        paid: metadata.withdrawn_amount,
+        refunded,
+        fees_paid: metadata.fees_paid,
+    };
+    let bytes = record.try_to_vec()?;
+    let mut data = acc.settlement.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Settled {:?}: paid {}, refunded {}, fees {}",
+        record.end_cause,
+        record.paid,
+        record.refunded,
+        record.fees_paid
+    );
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Builds `StreamInstruction`s for the common schedules, catching invalid
+//! combinations before a transaction is sent:
+//!
+//! ```ignore
+//! let ix = StreamInstructionBuilder::new()
+//!     .linear(start, end, 1_000_000)
+//!     .cliff(start + 90 * DAY, 250_000)
+//!     .build()?;
+//! ```
+//!
+//! Anything without a helper can be set on the result before sending.
+
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::state::{encode_name, RecipientShare, StreamInstruction};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BuilderError {
+    #[error("No schedule set, call linear or payroll")]
+    MissingSchedule,
+
+    #[error("A stream is either linear or payroll, not both")]
+    ConflictingSchedules,
+
+    #[error("Start must be before end, and the cliff between them")]
+    InvalidTimes,
+
+    #[error("Cliff amount exceeds the total")]
+    CliffTooLarge,
+
+    #[error("Nothing deposited")]
+    EmptyDeposit,
+
+    #[error("Schedule rejected: {0}")]
+    InvalidSchedule(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StreamInstructionBuilder {
+    ix: StreamInstruction,
+    linear: bool,
+    payroll: bool,
+    deposit: Option<u64>,
+}
+
+impl StreamInstructionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Releases `amount` evenly from `start` to `end`, fully deposited up
+    /// front unless `deposit` says otherwise.
+    pub fn linear(mut self, start: u64, end: u64, amount: u64) -> Self {
+        self.ix.start_time = start;
+        self.ix.end_time = end;
+        self.ix.total_amount = amount;
+        self.linear = true;
+        self
+    }
+
+    /// Releases `amount` at `ts`, then the rest of the schedule.
+    pub fn cliff(mut self, ts: u64, amount: u64) -> Self {
+        self.ix.cliff = ts;
+        self.ix.cliff_amount = amount;
+        self
+    }
+
+    /// Releases `rate` every `period` seconds for as long as it's funded,
+    /// starting at `window`'s start.
+    pub fn payroll(mut self, rate: u64, period: u64) -> Self {
+        self.ix.release_rate = rate;
+        self.ix.period = period;
+        self.payroll = true;
+        self
+    }
+
+    /// A payroll stream charging `price` at the start of every `period`
+    /// seconds, renewing while funded, see `StreamInstruction::subscription`.
+    pub fn subscription(mut self, price: u64, period: u64) -> Self {
+        self.ix.subscription = true;
+        self.payroll(price, period)
+    }
+
+    /// Start and end of a payroll stream.
+    pub fn window(mut self, start: u64, end: u64) -> Self {
+        self.ix.start_time = start;
+        self.ix.end_time = end;
+        self
+    }
+
+    pub fn period(mut self, period: u64) -> Self {
+        self.ix.period = period;
+        self
+    }
+
+    /// Initial deposit; for linear streams, less than the total makes the
+    /// stream underfunded until topped up.
+    pub fn deposit(mut self, amount: u64) -> Self {
+        self.deposit = Some(amount);
+        self
+    }
+
+    /// Pays `recipient` `bps` of every payout, see `StreamInstruction::shares`.
+    pub fn share(mut self, recipient: Pubkey, bps: u16) -> Self {
+        self.ix.shares.push(RecipientShare { recipient, bps });
+        self
+    }
+
+    /// Cut to `STREAM_NAME_SIZE` bytes, see `encode_name`.
+    pub fn name(mut self, name: &str) -> Self {
+        self.ix.stream_name = encode_name(name);
+        self
+    }
+
+    pub fn cancelable(mut self, by_sender: bool, by_recipient: bool) -> Self {
+        self.ix.cancelable_by_sender = by_sender;
+        self.ix.cancelable_by_recipient = by_recipient;
+        self
+    }
+
+    pub fn transferable(mut self, by_sender: bool, by_recipient: bool) -> Self {
+        self.ix.transferable_by_sender = by_sender;
+        self.ix.transferable_by_recipient = by_recipient;
+        self
+    }
+
+    pub fn build(self) -> Result<StreamInstruction, BuilderError> {
+        let mut ix = self.ix;
+        match (self.linear, self.payroll) {
+            (false, false) => return Err(BuilderError::MissingSchedule),
+            (true, true) => return Err(BuilderError::ConflictingSchedules),
+            _ => {}
+        }
+
+        let cliff_within = ix.cliff == 0 || (ix.start_time <= ix.cliff && ix.cliff <= ix.end_time);
+        if ix.start_time >= ix.end_time || !cliff_within {
+            return Err(BuilderError::InvalidTimes);
+        }
+
+        ix.deposited_amount = self.deposit.unwrap_or(ix.total_amount);
+        if self.payroll {
+            // Release rate streams have no total; it's whatever gets deposited.
+            ix.total_amount = ix.deposited_amount;
+        }
+        if ix.cliff_amount > ix.total_amount {
+            return Err(BuilderError::CliffTooLarge);
+        }
+        if ix.deposited_amount == 0 {
+            return Err(BuilderError::EmptyDeposit);
+        }
+
+        ix.check_schedule()
+            .map_err(|e| BuilderError::InvalidSchedule(e.to_string()))?;
+
+        Ok(ix)
+    }
+}
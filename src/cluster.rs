@@ -0,0 +1,194 @@
+//! Cluster selection for off-chain tooling, so one build works against
+//! mainnet, devnet or a local validator with whatever program id is deployed
+//! there.
+//!
+//! Settings come from a config file of `key = value` lines, optionally grouped
+//! in `[cluster]` sections, and `VESTING_*` environment variables override
+//! them:
+//!
+//! ```text
+//! cluster = devnet
+//!
+//! [devnet]
+//! program_id = <base58>
+//! genesis_hash = <base58>
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use solana_program::{hash::Hash, pubkey::Pubkey};
+use thiserror::Error;
+
+pub const ENV_CLUSTER: &str = "VESTING_CLUSTER";
+pub const ENV_RPC_URL: &str = "VESTING_RPC_URL";
+pub const ENV_PROGRAM_ID: &str = "VESTING_PROGRAM_ID";
+pub const ENV_GENESIS_HASH: &str = "VESTING_GENESIS_HASH";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(String),
+
+    #[error("Malformed config line {0}")]
+    Syntax(usize),
+
+    #[error("No program id configured for {0}")]
+    MissingProgramId(Cluster),
+
+    #[error("No RPC URL configured for {0}")]
+    MissingRpcUrl(Cluster),
+
+    #[error("Invalid value for {0}")]
+    InvalidValue(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cluster {
+    Mainnet,
+    Devnet,
+    Testnet,
+    Localnet,
+    /// Any other cluster, named after its config section.
+    Custom(String),
+}
+
+impl Cluster {
+    fn name(&self) -> &str {
+        match self {
+            Cluster::Mainnet => "mainnet",
+            Cluster::Devnet => "devnet",
+            Cluster::Testnet => "testnet",
+            Cluster::Localnet => "localnet",
+            Cluster::Custom(name) => name,
+        }
+    }
+
+    pub fn default_rpc_url(&self) -> Option<&'static str> {
+        match self {
+            Cluster::Mainnet => Some("https://api.mainnet-beta.solana.com"),
+            Cluster::Devnet => Some("https://api.devnet.solana.com"),
+            Cluster::Testnet => Some("https://api.testnet.solana.com"),
+            Cluster::Localnet => Some("http://127.0.0.1:8899"),
+            Cluster::Custom(_) => None,
+        }
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "mainnet" | "mainnet-beta" => Cluster::Mainnet,
+            "devnet" => Cluster::Devnet,
+            "testnet" => Cluster::Testnet,
+            "localnet" | "localhost" => Cluster::Localnet,
+            "" => return Err(ConfigError::InvalidValue("cluster")),
+            other => Cluster::Custom(other.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterConfig {
+    pub cluster: Cluster,
+    pub rpc_url: Option<String>,
+    pub program_id: Pubkey,
+    /// Expected genesis hash, for tooling to check it talks to the right
+    /// cluster before sending anything.
+    pub genesis_hash: Option<Hash>,
+}
+
+impl ClusterConfig {
+    /// Reads `path`, then applies environment overrides.
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(e.to_string()))?;
+        Self::resolve(&contents, |key| std::env::var(key).ok())
+    }
+
+    /// Configuration from environment variables alone.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::resolve("", |key| std::env::var(key).ok())
+    }
+
+    /// Resolves the config file `contents` against the overrides returned by
+    /// `env`.
+    pub fn resolve(
+        contents: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let sections = parse(contents)?;
+        let global = sections.get("").cloned().unwrap_or_default();
+
+        let cluster: Cluster = env(ENV_CLUSTER)
+            .or_else(|| global.get("cluster").cloned())
+            .unwrap_or_else(|| "mainnet".to_string())
+            .parse()?;
+
+        let lookup = |env_key: &str, key: &str| {
+            env(env_key).or_else(|| {
+                sections
+                    .get(cluster.name())
+                    .and_then(|s| s.get(key))
+                    .or_else(|| global.get(key))
+                    .cloned()
+            })
+        };
+
+        let program_id = lookup(ENV_PROGRAM_ID, "program_id")
+            .ok_or_else(|| ConfigError::MissingProgramId(cluster.clone()))?
+            .parse()
+            .map_err(|_| ConfigError::InvalidValue("program_id"))?;
+        let genesis_hash = match lookup(ENV_GENESIS_HASH, "genesis_hash") {
+            Some(v) => Some(
+                v.parse()
+                    .map_err(|_| ConfigError::InvalidValue("genesis_hash"))?,
+            ),
+            None => None,
+        };
+        let rpc_url = lookup(ENV_RPC_URL, "rpc_url")
+            .or_else(|| cluster.default_rpc_url().map(str::to_string));
+
+        Ok(ClusterConfig {
+            cluster,
+            rpc_url,
+            program_id,
+            genesis_hash,
+        })
+    }
+}
+
+/// Splits `key = value` lines into sections; keys before any section header
+/// land in the "" section. `#` starts a comment.
+fn parse(contents: &str) -> Result<HashMap<String, HashMap<String, String>>, ConfigError> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut section = String::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or(ConfigError::Syntax(i + 1))?;
+        sections
+            .entry(section.clone())
+            .or_default()
+            .insert(key.trim().to_string(), value.trim().to_string());
+    }
+
+    Ok(sections)
+}
@@ -2,7 +2,7 @@ use solana_program::msg;
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
-#[derive(Error, Debug, Copy, Clone)]
+#[derive(Error, Debug, Copy, Clone, PartialEq)]
 pub enum StreamFlowError {
     #[error("Accounts not writable!")]
     AccountsNotWritable,
@@ -18,6 +18,162 @@ pub enum StreamFlowError {
 
     #[error("Stream closed")]
     StreamClosed,
+
+    #[error("Recipient not revealed")]
+    RecipientNotRevealed,
+
+    #[error("Recipient proof does not match")]
+    InvalidRecipientProof,
+
+    #[error("Stream is paused")]
+    StreamPaused,
+
+    #[error("Stream is not paused")]
+    StreamNotPaused,
+
+    #[error("Pause budget exhausted")]
+    PauseBudgetExhausted,
+
+    #[error("Fee exemption list is full")]
+    TooManyFeeExemptions,
+
+    #[error("Stream terms changed since approval was requested")]
+    StaleNonce,
+
+    #[error("Freeze authority does not delegate to the program")]
+    FreezeAuthorityMismatch,
+
+    #[error("Ledger has no room for more grants")]
+    LedgerFull,
+
+    #[error("Claim is below the recipient's minimum")]
+    ClaimBelowMinimum,
+
+    #[error("Streams cannot be netted against each other")]
+    NettingNotAllowed,
+
+    #[error("Stream cannot migrate to the new mint")]
+    MigrationNotAllowed,
+
+    #[error("Stream was already withdrawn from in this slot")]
+    DuplicateWithdrawal,
+
+    #[error("Nothing available to withdraw")]
+    NothingToWithdraw,
+
+    #[error("Withdrawals are frozen by the compliance authority")]
+    ComplianceFrozen,
+
+    #[error("Too many stream accounts for one instruction")]
+    TooManyStreams,
+
+    #[error("Too many multisig signers")]
+    TooManySigners,
+
+    #[error("Ledger holds more grants than its capacity")]
+    LedgerOverCapacity,
+
+    #[error("Stream cannot be rolled over")]
+    RolloverNotAllowed,
+
+    #[error("LP pool is not of a supported AMM")]
+    UnsupportedLpPool,
+
+    #[error("Stream is not due for a crank")]
+    CrankNotDue,
+
+    #[error("Stream cannot be split")]
+    SplitNotAllowed,
+
+    #[error("Stream recipient was transferred too recently")]
+    TransferCooldown,
+
+    #[error("No recipient transfer is pending")]
+    NoPendingTransfer,
+
+    #[error("Transfer destination is not on the stream's allowlist")]
+    DestinationNotAllowed,
+
+    #[error("Contributor may not top up this stream")]
+    TopUpNotAllowed,
+
+    #[error("Top-up allowlist is full")]
+    TopUpAllowlistFull,
+
+    #[error("Timestamps look like milliseconds, expected seconds")]
+    TimestampPrecisionError,
+
+    #[error("Stream starts too far in the future")]
+    StartTooFar,
+
+    #[error("Cancel is waiting out the stream's cancel delay")]
+    CancelDelayPending,
+
+    #[error("Stream not settled")]
+    StreamNotSettled,
+
+    #[error("Stream index page is full")]
+    StreamIndexFull,
+
+    #[error("Recipient holds less than the stream requires")]
+    HoldingBelowMinimum,
+
+    #[error("Stream metadata is in an older layout, migrate it first")]
+    MetadataNeedsMigration,
+
+    #[error("Stream is shared between recipients, pay it out with withdraw_shares")]
+    SharedStream,
+
+    #[error("Amounts overflow")]
+    ArithmeticOverflow,
+}
+
+impl StreamFlowError {
+    /// Every variant in discriminant order, so `ALL[code]` is the variant of
+    /// custom program error `code`. Append new variants here too.
+    pub const ALL: [StreamFlowError; 41] = [
+        Self::AccountsNotWritable,
+        Self::InvalidMetadata,
+        Self::MintMismatch,
+        Self::TransferNotAllowed,
+        Self::StreamClosed,
+        Self::RecipientNotRevealed,
+        Self::InvalidRecipientProof,
+        Self::StreamPaused,
+        Self::StreamNotPaused,
+        Self::PauseBudgetExhausted,
+        Self::TooManyFeeExemptions,
+        Self::StaleNonce,
+        Self::FreezeAuthorityMismatch,
+        Self::LedgerFull,
+        Self::ClaimBelowMinimum,
+        Self::NettingNotAllowed,
+        Self::MigrationNotAllowed,
+        Self::DuplicateWithdrawal,
+        Self::NothingToWithdraw,
+        Self::ComplianceFrozen,
+        Self::TooManyStreams,
+        Self::TooManySigners,
+        Self::LedgerOverCapacity,
+        Self::RolloverNotAllowed,
+        Self::UnsupportedLpPool,
+        Self::CrankNotDue,
+        Self::SplitNotAllowed,
+        Self::TransferCooldown,
+        Self::NoPendingTransfer,
+        Self::DestinationNotAllowed,
+        Self::TopUpNotAllowed,
+        Self::TopUpAllowlistFull,
+        Self::TimestampPrecisionError,
+        Self::StartTooFar,
+        Self::CancelDelayPending,
+        Self::StreamNotSettled,
+        Self::StreamIndexFull,
+        Self::HoldingBelowMinimum,
+        Self::MetadataNeedsMigration,
+        Self::SharedStream,
+        Self::ArithmeticOverflow,
+    ];
 }
 
 impl From<StreamFlowError> for ProgramError {
@@ -25,4 +181,4 @@ impl From<StreamFlowError> for ProgramError {
         msg!(&e.to_string());
         ProgramError::Custom(e as u32)
     }
-}
\ No newline at end of file
+}
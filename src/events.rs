@@ -0,0 +1,124 @@
+//! Borsh-encoded events logged with `sol_log_data`, one `StreamEvent` per
+//! call, so indexers decode a stable layout instead of parsing `msg!` text.
+//! Each event carries the stream's `notify_key` and `origin` (default if none
+//! is set).
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, pubkey::Pubkey};
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StreamCreated {
+    pub stream: Pubkey,
+    pub sender: Pubkey,
+    /// Default for private streams until the recipient is revealed.
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub deposited: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Withdrawn {
+    pub stream: Pubkey,
+    pub recipient: Pubkey,
+    /// Taken out of the escrow, fees included.
+    pub amount: u64,
+    pub fees: u64,
+    pub withdrawn_total: u64,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Cancelled {
+    pub stream: Pubkey,
+    pub authority: Pubkey,
+    /// Paid to the recipient, fees included.
+    pub paid: u64,
+    /// Returned to the sender, bond included unless it was forfeited.
+    pub returned: u64,
+    pub fees: u64,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Transferred {
+    pub stream: Pubkey,
+    pub old_recipient: Pubkey,
+    pub new_recipient: Pubkey,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ToppedUp {
+    pub stream: Pubkey,
+    pub payer: Pubkey,
+    /// What the escrow received, net of transfer fees.
+    pub amount: u64,
+    pub deposited_total: u64,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+/// Outcome of `verify_stream`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Verified {
+    pub stream: Pubkey,
+    pub passed: bool,
+    /// `verify` failure codes OR-ed together, 0 if it passed.
+    pub failures: u32,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+/// One position's part of a `withdraw_shares` payout, after fees: the
+/// recipient's remainder, then each co-recipient's share, following the
+/// stream's `Withdrawn`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SharePaid {
+    pub stream: Pubkey,
+    pub holder: Pubkey,
+    pub tokens: Pubkey,
+    pub amount: u64,
+    pub bps: u16,
+    pub notify_key: Pubkey,
+    pub origin: [u8; 16],
+}
+
+/// New variants go at the end; the variant index is the event's first byte.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum StreamEvent {
+    StreamCreated(StreamCreated),
+    Withdrawn(Withdrawn),
+    Cancelled(Cancelled),
+    Transferred(Transferred),
+    ToppedUp(ToppedUp),
+    Verified(Verified),
+    SharePaid(SharePaid),
+}
+
+impl StreamEvent {
+    /// Decodes one `sol_log_data` entry, as found base64 encoded after
+    /// "Program data: " in the transaction logs.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Self::try_from_slice(data).ok()
+    }
+}
+
+/// Encoded length of the largest event, `StreamCreated`.
+const MAX_EVENT_LEN: usize = 1 + 5 * 32 + 3 * 8 + 16;
+
+pub fn emit(event: StreamEvent) {
+    // Encoded on the stack, as every withdrawal emits one.
+    let mut buf = [0u8; MAX_EVENT_LEN];
+    let mut rest = &mut buf[..];
+    if event.serialize(&mut rest).is_ok() {
+        let len = MAX_EVENT_LEN - rest.len();
+        sol_log_data(&[&buf[..len]]);
+    }
+}
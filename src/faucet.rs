@@ -0,0 +1,132 @@
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+
+use crate::state::{
+    encode_name, FaucetAccounts, InitializeAccounts, StreamInstruction, FAUCET_SEED,
+};
+use crate::token::{create, create_recipient_ata};
+
+pub const DEMO_DECIMALS: u8 = 6;
+pub const DEMO_AMOUNT: u64 = 1_000_000_000; // 1000 tokens
+pub const DEMO_DURATION: u64 = 60 * 60;
+
+/// Devnet only: mints a throwaway token and streams it from `sender` to
+/// `recipient` over the next hour, so integrators can try withdraw and cancel
+/// without setting up a mint and a funded wallet first.
+pub fn faucet_stream(program_id: &Pubkey, acc: FaucetAccounts) -> ProgramResult {
+    msg!("Creating demo stream");
+
+    if !acc.mint.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.sender.is_signer || !acc.mint.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (faucet_pubkey, nonce) = Pubkey::find_program_address(&[FAUCET_SEED], program_id);
+    if acc.faucet_authority.key != &faucet_pubkey || acc.token_program.key != &spl_token::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mint_size = spl_token::state::Mint::LEN;
+    invoke(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.mint.key,
+            Rent::get()?.minimum_balance(mint_size),
+            mint_size as u64,
+            &spl_token::id(),
+        ),
+        &[
+            acc.sender.clone(),
+            acc.mint.clone(),
+            acc.system_program.clone(),
+        ],
+    )?;
+
+    invoke(
+        &spl_token::instruction::initialize_mint(
+            acc.token_program.key,
+            acc.mint.key,
+            acc.faucet_authority.key,
+            None,
+            DEMO_DECIMALS,
+        )?,
+        &[acc.mint.clone(), acc.rent.clone()],
+    )?;
+
+    create_recipient_ata(
+        &acc.sender,
+        &acc.sender,
+        &acc.sender_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    invoke_signed(
+        &spl_token::instruction::mint_to(
+            acc.token_program.key,
+            acc.mint.key,
+            acc.sender_tokens.key,
+            acc.faucet_authority.key,
+            &[],
+            DEMO_AMOUNT,
+        )?,
+        &[
+            acc.mint.clone(),
+            acc.sender_tokens.clone(),
+            acc.faucet_authority.clone(),
+            acc.token_program.clone(),
+        ],
+        &[&[FAUCET_SEED, &[nonce]]],
+    )?;
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let ix = StreamInstruction {
+        start_time: now + 1,
+        end_time: now + 1 + DEMO_DURATION,
+        deposited_amount: DEMO_AMOUNT,
+        total_amount: DEMO_AMOUNT,
+        cancelable_by_recipient: true,
+        transferable_by_sender: true,
+        stream_name: encode_name("Demo stream"),
+        ..Default::default()
+    };
+
+    let ia = InitializeAccounts {
+        sender: acc.sender,
+        sender_tokens: acc.sender_tokens,
+        recipient: acc.recipient,
+        recipient_tokens: acc.recipient_tokens,
+        metadata: acc.metadata,
+        escrow_tokens: acc.escrow_tokens,
+        mint: acc.mint,
+        rent: acc.rent,
+        token_program: acc.token_program,
+        associated_token_program: acc.associated_token_program,
+        system_program: acc.system_program,
+        stream_counter: Some(acc.stream_counter),
+        freeze_authority: None,
+        freeze_issuer: None,
+        obligation_mint: None,
+        obligation_tokens: None,
+        token_2022_program: None,
+        indexes: vec![],
+        multisig: None,
+        multisig_signers: vec![],
+        payer: None,
+    };
+
+    create(program_id, ia, ix)
+}
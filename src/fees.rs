@@ -0,0 +1,259 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, InvalidMetadata, TooManyFeeExemptions};
+use crate::state::{
+    FeeConfig, FeeConfigUpdate, FeeExemptionUpdate, FeeExemptions, InitFeeConfigAccounts,
+    InitFeeExemptionsAccounts, UpdateFeeConfigAccounts, UpdateFeeExemptionsAccounts,
+    FEE_CONFIG_SEED, FEE_CONFIG_SIZE, FEE_EXEMPTIONS_SEED, FEE_EXEMPTIONS_SIZE, MAX_FEE_BPS,
+    MAX_FEE_EXEMPTIONS,
+};
+use crate::utils::program_upgrade_authority;
+
+pub fn initialize_fee_exemptions(
+    program_id: &Pubkey,
+    acc: InitFeeExemptionsAccounts,
+) -> ProgramResult {
+    msg!("Initializing fee exemption list");
+
+    if !acc.exemptions.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.admin.is_writable || !acc.exemptions.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (exemptions_pubkey, nonce) =
+        Pubkey::find_program_address(&[FEE_EXEMPTIONS_SEED], program_id);
+
+    if acc.exemptions.key != &exemptions_pubkey || acc.system_program.key != &system_program::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if program_upgrade_authority(program_id, &acc.program_data)? != Some(*acc.admin.key) {
+        msg!("Error: Only the program upgrade authority can initialize fee exemptions");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let seeds = [FEE_EXEMPTIONS_SEED, &[nonce]];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.admin.key,
+            acc.exemptions.key,
+            Rent::get()?.minimum_balance(FEE_EXEMPTIONS_SIZE),
+            FEE_EXEMPTIONS_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.admin.clone(),
+            acc.exemptions.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let exemptions = FeeExemptions {
+        admin: *acc.admin.key,
+        members: vec![],
+    };
+    let bytes = exemptions.try_to_vec()?;
+    let mut data = acc.exemptions.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Fee exemptions managed by {}", acc.admin.key);
+
+    Ok(())
+}
+
+pub fn initialize_fee_config(
+    program_id: &Pubkey,
+    acc: InitFeeConfigAccounts,
+    update: FeeConfigUpdate,
+) -> ProgramResult {
+    msg!("Initializing protocol fee config");
+
+    if !acc.config.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.admin.is_writable || !acc.config.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (config_pubkey, nonce) = Pubkey::find_program_address(&[FEE_CONFIG_SEED], program_id);
+
+    if acc.config.key != &config_pubkey || acc.system_program.key != &system_program::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if program_upgrade_authority(program_id, &acc.program_data)? != Some(*acc.admin.key) {
+        msg!("Error: Only the program upgrade authority can initialize the fee config");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if update.fee_bps > MAX_FEE_BPS {
+        msg!("Error: Fee can't exceed {} bps", MAX_FEE_BPS);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let seeds = [FEE_CONFIG_SEED, &[nonce]];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.admin.key,
+            acc.config.key,
+            Rent::get()?.minimum_balance(FEE_CONFIG_SIZE),
+            FEE_CONFIG_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.admin.clone(),
+            acc.config.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let config = FeeConfig {
+        admin: *acc.admin.key,
+        treasury: update.treasury,
+        fee_bps: update.fee_bps,
+    };
+    let bytes = config.try_to_vec()?;
+    let mut data = acc.config.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Protocol fee {} bps to {}, managed by {}",
+        config.fee_bps,
+        config.treasury,
+        acc.admin.key
+    );
+
+    Ok(())
+}
+
+pub fn update_fee_config(
+    program_id: &Pubkey,
+    acc: UpdateFeeConfigAccounts,
+    update: FeeConfigUpdate,
+) -> ProgramResult {
+    msg!("Updating protocol fee config");
+
+    if acc.config.data_is_empty() || acc.config.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.config.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (config_pubkey, _) = Pubkey::find_program_address(&[FEE_CONFIG_SEED], program_id);
+    if acc.config.key != &config_pubkey {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = acc.config.try_borrow_mut_data()?;
+    let mut config: FeeConfig = match solana_borsh::try_from_slice_unchecked(&data) {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidMetadata.into()),
+    };
+
+    if acc.admin.key != &config.admin {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if update.fee_bps > MAX_FEE_BPS {
+        msg!("Error: Fee can't exceed {} bps", MAX_FEE_BPS);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.treasury = update.treasury;
+    config.fee_bps = update.fee_bps;
+    let bytes = config.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Protocol fee {} bps to {}", config.fee_bps, config.treasury);
+
+    Ok(())
+}
+
+pub fn update_fee_exemptions(
+    program_id: &Pubkey,
+    acc: UpdateFeeExemptionsAccounts,
+    update: FeeExemptionUpdate,
+) -> ProgramResult {
+    msg!("Updating fee exemption list");
+
+    if acc.exemptions.data_is_empty() || acc.exemptions.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.exemptions.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (exemptions_pubkey, _) = Pubkey::find_program_address(&[FEE_EXEMPTIONS_SEED], program_id);
+    if acc.exemptions.key != &exemptions_pubkey {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = acc.exemptions.try_borrow_mut_data()?;
+    let mut exemptions: FeeExemptions = match solana_borsh::try_from_slice_unchecked(&data) {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidMetadata.into()),
+    };
+
+    if exemptions.members.len() > MAX_FEE_EXEMPTIONS {
+        return Err(TooManyFeeExemptions.into());
+    }
+
+    if acc.admin.key != &exemptions.admin {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if update.exempt {
+        if !exemptions.is_exempt(&update.member) {
+            if exemptions.members.len() >= MAX_FEE_EXEMPTIONS {
+                return Err(TooManyFeeExemptions.into());
+            }
+            exemptions.members.push(update.member);
+        }
+        msg!("Exempted {} from fees", update.member);
+    } else {
+        exemptions.members.retain(|m| m != &update.member);
+        msg!("Removed fee exemption for {}", update.member);
+    }
+
+    // Clear the old encoding first, the list may have shrunk.
+    let bytes = exemptions.try_to_vec()?;
+    data.fill(0);
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    Ok(())
+}
@@ -0,0 +1,624 @@
+//! In-process runtime for instruction tests: a bank of accounts and syscall
+//! stubs serving the clock, rent, logs, events and CPIs into SPL Token, the system
+//! program and the associated token program. Only what the handlers use is
+//! emulated; `solana-program-test` needs a newer `solana-program` than this
+//! crate builds against.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Once;
+
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program, sysvar,
+};
+
+use solana_sdk::signer::{keypair::keypair_from_seed, Signer};
+
+use crate::events::StreamEvent;
+use crate::instruction;
+use crate::processor::process_instruction;
+use crate::state::{StreamInstruction, TokenStreamData, FREEZE_AUTHORITY_SEED};
+use crate::utils::associated_token_address;
+
+/// Program id the tests deploy the program at.
+pub const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+/// Clock of a new bank; `stream_ix` schedules start right after it.
+pub const NOW: u64 = 1_700_000_000;
+
+/// What the runtime fails a transaction with that changed an account it
+/// passed as read-only; it has no `ProgramError` equivalent.
+pub const READONLY_DATA_MODIFIED: ProgramError = ProgramError::Custom(u32::MAX);
+/// Same for a token program call that changed an account it doesn't own.
+pub const EXTERNAL_ACCOUNT_DATA_MODIFIED: ProgramError = ProgramError::Custom(u32::MAX - 1);
+
+const ATA_CREATE: u8 = 0;
+const ATA_CREATE_IDEMPOTENT: u8 = 1;
+
+thread_local! {
+    static CLOCK: RefCell<u64> = const { RefCell::new(0) };
+    static CALLER: RefCell<Vec<Pubkey>> = const { RefCell::new(Vec::new()) };
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static EVENTS: RefCell<Vec<StreamEvent>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let events = fields.iter().filter_map(|f| StreamEvent::decode(f));
+        EVENTS.with(|e| e.borrow_mut().extend(events));
+    }
+
+    /// One slot per second, so instructions at the same time share a slot.
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let now = CLOCK.with(|now| *now.borrow());
+        let clock = Clock {
+            slot: now,
+            unix_timestamp: now as i64,
+            ..Default::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = CALLER.with(|caller| *caller.borrow().last().unwrap());
+        let signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &caller).unwrap())
+            .collect();
+
+        let mut accounts = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let mut info = account_infos
+                .iter()
+                .find(|a| a.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            if meta.is_signer && !info.is_signer && !signers.contains(info.key) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            info.is_signer = meta.is_signer;
+            info.is_writable = meta.is_writable;
+            accounts.push(info);
+        }
+
+        // The system and associated token program stand-ins are trusted, the
+        // token program gets the runtime's ownership check.
+        let before: Vec<(Vec<u8>, Pubkey)> = accounts
+            .iter()
+            .map(|a| (a.data.borrow().to_vec(), *a.owner))
+            .collect();
+
+        CALLER.with(|caller| caller.borrow_mut().push(instruction.program_id));
+        let result = dispatch(&instruction.program_id, &accounts, &instruction.data);
+        CALLER.with(|caller| caller.borrow_mut().pop());
+        result?;
+
+        if instruction.program_id == spl_token::id() {
+            for (account, (data, owner)) in accounts.iter().zip(before) {
+                if owner != spl_token::id() && account.data.borrow()[..] != data[..] {
+                    return Err(EXTERNAL_ACCOUNT_DATA_MODIFIED);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dispatch(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if program_id == &spl_token::id() {
+        spl_token::processor::Processor::process(program_id, accounts, data)
+    } else if program_id == &system_program::id() {
+        system(accounts, data)
+    } else if program_id == &spl_associated_token_account::id() {
+        associated_token(accounts, data)
+    } else if program_id == &PROGRAM_ID {
+        process_instruction(program_id, accounts, data)
+    } else {
+        Err(ProgramError::IncorrectProgramId)
+    }
+}
+
+/// Gives `account` a zeroed data buffer of `space` bytes, owned by `owner`.
+fn allocate(account: &AccountInfo, space: u64, owner: &Pubkey) {
+    let data: &'static mut [u8] = Box::leak(vec![0; space as usize].into_boxed_slice());
+    *account.data.borrow_mut() = data;
+    account.assign(owner);
+}
+
+fn system(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let instruction: SystemInstruction =
+        bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+    match instruction {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if to.lamports() > 0 || !to.data_is_empty() || to.owner != &system_program::id() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            if from.lamports() < lamports {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            **from.lamports.borrow_mut() -= lamports;
+            **to.lamports.borrow_mut() += lamports;
+            allocate(to, space, &owner);
+            Ok(())
+        }
+        SystemInstruction::Transfer { lamports } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if from.lamports() < lamports {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            **from.lamports.borrow_mut() -= lamports;
+            **to.lamports.borrow_mut() += lamports;
+            Ok(())
+        }
+        SystemInstruction::Allocate { space } => {
+            allocate(&accounts[0], space, &system_program::id());
+            Ok(())
+        }
+        SystemInstruction::Assign { owner } => {
+            accounts[0].assign(&owner);
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+/// `Create` and `CreateIdempotent` of the associated token program: payer,
+/// account, wallet, mint, system program, token program.
+fn associated_token(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (payer, tokens, wallet, mint, token_program) = (
+        &accounts[0],
+        &accounts[1],
+        &accounts[2],
+        &accounts[3],
+        &accounts[5],
+    );
+    if tokens.key != &associated_token_address(wallet.key, mint.key, token_program.key) {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let idempotent = match data {
+        [] | [ATA_CREATE] => false,
+        [ATA_CREATE_IDEMPOTENT] => true,
+        _ => return Err(ProgramError::InvalidInstructionData),
+    };
+    if idempotent && tokens.owner == token_program.key {
+        let existing = spl_token::state::Account::unpack(&tokens.data.borrow())?;
+        if &existing.owner != wallet.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+        if &existing.mint != mint.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        return Ok(());
+    }
+
+    let space = spl_token::state::Account::LEN;
+    let lamports = Rent::default().minimum_balance(space);
+    system(
+        &[payer.clone(), tokens.clone()],
+        &bincode::serialize(&SystemInstruction::CreateAccount {
+            lamports,
+            space: space as u64,
+            owner: *token_program.key,
+        })
+        .unwrap(),
+    )?;
+    let init = spl_token::instruction::initialize_account3(
+        token_program.key,
+        tokens.key,
+        mint.key,
+        wallet.key,
+    )?;
+    spl_token::processor::Processor::process(
+        token_program.key,
+        &[tokens.clone(), mint.clone()],
+        &init.data,
+    )
+}
+
+/// The program's freeze PDA of `mint`, a member of the freeze authority
+/// multisig of `managed_freeze` mints.
+pub fn freeze_signer(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[FREEZE_AUTHORITY_SEED, mint.as_ref()], &PROGRAM_ID).0
+}
+
+/// 1000 tokens over 1000 seconds from `NOW + 100`, 1 per second.
+pub fn stream_ix() -> StreamInstruction {
+    StreamInstruction {
+        start_time: NOW + 100,
+        end_time: NOW + 1_100,
+        deposited_amount: 1_000,
+        total_amount: 1_000,
+        ..Default::default()
+    }
+}
+
+/// Accounts of a stream created by `Bank::create_stream`.
+pub struct TestStream {
+    pub sender: Pubkey,
+    pub sender_tokens: Pubkey,
+    pub recipient: Pubkey,
+    pub recipient_tokens: Pubkey,
+    pub mint: Pubkey,
+    pub metadata: Pubkey,
+    pub escrow_tokens: Pubkey,
+}
+
+impl TestStream {
+    pub fn withdraw(&self, amount: u64) -> Instruction {
+        instruction::withdraw_stream(
+            &PROGRAM_ID,
+            &self.recipient,
+            &self.recipient_tokens,
+            &self.sender,
+            &self.metadata,
+            &self.escrow_tokens,
+            &self.mint,
+            &spl_token::id(),
+            amount,
+        )
+    }
+
+    pub fn cancel(&self, authority: &Pubkey) -> Instruction {
+        instruction::cancel_stream(
+            &PROGRAM_ID,
+            authority,
+            &self.sender,
+            &self.sender_tokens,
+            &self.recipient,
+            &self.recipient_tokens,
+            &self.metadata,
+            &self.escrow_tokens,
+            &self.mint,
+            &spl_token::id(),
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+}
+
+/// Accounts by address. Instructions run against them and only write back
+/// when they succeed, like a transaction.
+pub struct Bank {
+    pub accounts: HashMap<Pubkey, Account>,
+    next_key: u8,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(Stubs));
+        });
+        CLOCK.with(|v| *v.borrow_mut() = NOW);
+
+        let mut bank = Bank {
+            accounts: HashMap::new(),
+            next_key: 0,
+        };
+        bank.set(
+            sysvar::rent::id(),
+            Account {
+                lamports: 1,
+                data: bincode::serialize(&Rent::default()).unwrap(),
+                owner: sysvar::id(),
+            },
+        );
+        bank
+    }
+
+    pub fn warp(&self, now: u64) {
+        CLOCK.with(|v| *v.borrow_mut() = now);
+    }
+
+    /// Creates a stream of `ix` from a new sender to a new recipient of a
+    /// new mint, funded with exactly what it needs.
+    pub fn create_stream(&mut self, ix: &StreamInstruction) -> TestStream {
+        let mint_authority = self.key();
+        let mint = self.mint(&mint_authority, None);
+        self.create_stream_of(&mint, ix, &[])
+    }
+
+    /// Creates a `managed_freeze` stream like `create_stream`, of a mint whose
+    /// freeze authority is a 1-of-2 multisig of an issuer and the program's
+    /// freeze PDA, and returns it with the multisig. The recipient's token
+    /// account starts out frozen.
+    pub fn create_managed_stream(&mut self, ix: &StreamInstruction) -> (TestStream, Pubkey) {
+        let issuer = self.wallet();
+        let mint_authority = self.key();
+        let freeze_authority = self.key();
+        let mint = self.mint(&mint_authority, Some(&freeze_authority));
+        let mut signers = [Pubkey::default(); spl_token::instruction::MAX_SIGNERS];
+        signers[0] = issuer;
+        signers[1] = freeze_signer(&mint);
+        let multisig = spl_token::state::Multisig {
+            m: 1,
+            n: 2,
+            is_initialized: true,
+            signers,
+        };
+        let mut data = vec![0; spl_token::state::Multisig::LEN];
+        multisig.pack_into_slice(&mut data);
+        self.set_token_account(freeze_authority, data);
+
+        let ix = StreamInstruction {
+            managed_freeze: true,
+            ..ix.clone()
+        };
+        let stream = self.create_stream_of(
+            &mint,
+            &ix,
+            &[
+                AccountMeta::new_readonly(freeze_authority, false),
+                AccountMeta::new_readonly(issuer, true),
+            ],
+        );
+        self.set_frozen(&stream.recipient_tokens, true);
+        (stream, freeze_authority)
+    }
+
+    fn create_stream_of(
+        &mut self,
+        mint: &Pubkey,
+        ix: &StreamInstruction,
+        extra_accounts: &[AccountMeta],
+    ) -> TestStream {
+        let mint = *mint;
+        let sender = self.wallet();
+        let recipient = self.wallet();
+        let sender_tokens = self.key();
+        self.tokens_at(
+            sender_tokens,
+            &mint,
+            &sender,
+            ix.deposited_amount + ix.bond_amount,
+        );
+
+        let mut create = instruction::create_stream(
+            &PROGRAM_ID,
+            &sender,
+            &sender_tokens,
+            &recipient,
+            &mint,
+            &spl_token::id(),
+            ix,
+        )
+        .unwrap();
+        create.accounts.extend_from_slice(extra_accounts);
+        self.process(&create).unwrap();
+
+        TestStream {
+            sender,
+            sender_tokens,
+            recipient,
+            recipient_tokens: associated_token_address(&recipient, &mint, &spl_token::id()),
+            mint,
+            metadata: create.accounts[4].pubkey,
+            escrow_tokens: create.accounts[5].pubkey,
+        }
+    }
+
+    /// A fresh wallet address, distinct within this bank. On the curve, so
+    /// it's never taken for a PDA.
+    pub fn key(&mut self) -> Pubkey {
+        self.next_key += 1;
+        keypair_from_seed(&[self.next_key; 32]).unwrap().pubkey()
+    }
+
+    pub fn set(&mut self, key: Pubkey, account: Account) {
+        self.accounts.insert(key, account);
+    }
+
+    pub fn get(&self, key: &Pubkey) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    /// A system account holding 10 SOL.
+    pub fn wallet(&mut self) -> Pubkey {
+        let key = self.key();
+        self.set(
+            key,
+            Account {
+                lamports: 10_000_000_000,
+                data: vec![],
+                owner: system_program::id(),
+            },
+        );
+        key
+    }
+
+    pub fn mint(&mut self, authority: &Pubkey, freeze_authority: Option<&Pubkey>) -> Pubkey {
+        let key = self.key();
+        let mint = spl_token::state::Mint {
+            mint_authority: COption::Some(*authority),
+            supply: 0,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: freeze_authority.copied().into(),
+        };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        mint.pack_into_slice(&mut data);
+        self.set_token_account(key, data);
+        key
+    }
+
+    /// Writes a token account of `owner` holding `amount` at `key`.
+    pub fn tokens_at(&mut self, key: Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) {
+        let tokens = spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        tokens.pack_into_slice(&mut data);
+        self.set_token_account(key, data);
+    }
+
+    fn set_token_account(&mut self, key: Pubkey, data: Vec<u8>) {
+        self.set(
+            key,
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: spl_token::id(),
+            },
+        );
+    }
+
+    /// Freezes or thaws the token account at `key` in place.
+    pub fn set_frozen(&mut self, key: &Pubkey, frozen: bool) {
+        let mut tokens = self.token_account(key);
+        tokens.state = if frozen {
+            spl_token::state::AccountState::Frozen
+        } else {
+            spl_token::state::AccountState::Initialized
+        };
+        tokens.pack_into_slice(&mut self.accounts.get_mut(key).unwrap().data);
+    }
+
+    pub fn token_account(&self, key: &Pubkey) -> spl_token::state::Account {
+        spl_token::state::Account::unpack(&self.get(key).unwrap().data).unwrap()
+    }
+
+    pub fn balance(&self, key: &Pubkey) -> u64 {
+        self.token_account(key).amount
+    }
+
+    pub fn stream(&self, metadata: &Pubkey) -> TokenStreamData {
+        TokenStreamData::load(&self.get(metadata).unwrap().data).unwrap()
+    }
+
+    /// Events emitted by the last `process`.
+    pub fn events(&self) -> Vec<StreamEvent> {
+        EVENTS.with(|events| events.borrow().clone())
+    }
+
+    /// Runs `instruction` as a transaction signed by the accounts it marks
+    /// as signers. Accounts it names but the bank lacks start out empty.
+    pub fn process(&mut self, instruction: &Instruction) -> ProgramResult {
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        EVENTS.with(|events| events.borrow_mut().clear());
+
+        let mut keys: Vec<Pubkey> = Vec::new();
+        let mut metas: HashMap<Pubkey, AccountMeta> = HashMap::new();
+        for meta in &instruction.accounts {
+            let merged = metas.entry(meta.pubkey).or_insert_with(|| {
+                keys.push(meta.pubkey);
+                AccountMeta::new_readonly(meta.pubkey, false)
+            });
+            merged.is_signer |= meta.is_signer;
+            merged.is_writable |= meta.is_writable;
+        }
+
+        let mut infos: HashMap<Pubkey, AccountInfo<'static>> = HashMap::new();
+        for key in &keys {
+            let account = self.accounts.get(key).cloned().unwrap_or(Account {
+                lamports: 0,
+                data: vec![],
+                owner: system_program::id(),
+            });
+            let meta = &metas[key];
+            infos.insert(
+                *key,
+                AccountInfo::new(
+                    Box::leak(Box::new(*key)),
+                    meta.is_signer,
+                    meta.is_writable,
+                    Box::leak(Box::new(account.lamports)),
+                    Box::leak(account.data.into_boxed_slice()),
+                    Box::leak(Box::new(account.owner)),
+                    false,
+                    0,
+                ),
+            );
+        }
+        let accounts: Vec<AccountInfo> = instruction
+            .accounts
+            .iter()
+            .map(|meta| infos[&meta.pubkey].clone())
+            .collect();
+
+        CALLER.with(|caller| caller.borrow_mut().push(instruction.program_id));
+        let result = dispatch(&instruction.program_id, &accounts, &instruction.data);
+        CALLER.with(|caller| caller.borrow_mut().pop());
+        if result.is_err() {
+            // Shown with the output of the failing test.
+            LOGS.with(|logs| logs.borrow().iter().for_each(|l| println!("{}", l)));
+        }
+        result?;
+
+        for (key, info) in &infos {
+            let before = self.accounts.get(key);
+            let changed = match before {
+                Some(a) => {
+                    a.lamports != info.lamports()
+                        || a.data[..] != info.data.borrow()[..]
+                        || &a.owner != info.owner
+                }
+                None => info.lamports() > 0,
+            };
+            if changed && !info.is_writable {
+                return Err(READONLY_DATA_MODIFIED);
+            }
+        }
+
+        for (key, info) in infos {
+            if info.lamports() == 0 {
+                self.accounts.remove(&key);
+                continue;
+            }
+            let data = info.data.borrow().to_vec();
+            self.set(
+                key,
+                Account {
+                    lamports: info.lamports(),
+                    data,
+                    owner: *info.owner,
+                },
+            );
+        }
+        Ok(())
+    }
+}
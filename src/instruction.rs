@@ -0,0 +1,1011 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+use crate::state::{
+    ConversionRatio, CreateFromProgramInstruction, CreateLedgerInstruction, FeeConfig, IndexRole,
+    SettlementRecord, SplitInstruction, StreamIndex, StreamIndexKey, StreamInstruction,
+    StreamMirror, StreamUpdate, TokenStreamData, TopUpAllowlistUpdate, STREAM_COUNTER_SEED,
+};
+#[cfg(all(feature = "fees", not(feature = "no-admin")))]
+use crate::state::{FeeConfigUpdate, FeeExemptionUpdate};
+use crate::utils::associated_token_address;
+
+/// Set on the tag byte when an instruction-version byte follows it.
+/// Instructions without it are legacy encodings, treated as version 0.
+pub const VERSIONED_TAG_FLAG: u8 = 0x80;
+
+/// Version of the instruction encodings this program build produces.
+pub const IX_VERSION: u8 = 1;
+
+/// Oldest encoding still accepted. Encodings between this and `IX_VERSION`
+/// are deprecated: still processed, but a warning is logged.
+pub const MIN_IX_VERSION: u8 = 0;
+
+/// Splits instruction data into its tag and payload, validating the
+/// instruction-version byte against the supported range.
+pub fn unpack_version(ix: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
+    let (&tag, rest) = ix
+        .split_first()
+        .ok_or(ProgramError::InvalidInstructionData)?;
+
+    let (tag, version, payload) = if tag & VERSIONED_TAG_FLAG != 0 {
+        let (&version, payload) = rest
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        (tag & !VERSIONED_TAG_FLAG, version, payload)
+    } else {
+        (tag, 0, rest)
+    };
+
+    if !(MIN_IX_VERSION..=IX_VERSION).contains(&version) {
+        msg!(
+            "Error: Instruction version {} not supported, expected {}..={}",
+            version,
+            MIN_IX_VERSION,
+            IX_VERSION
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    if version < IX_VERSION {
+        msg!(
+            "Warning: Instruction version {} is deprecated, use version {}",
+            version,
+            IX_VERSION
+        );
+    }
+
+    Ok((tag, payload))
+}
+
+/// Decoded instruction data, one variant per tag in tag order. Payloads keep
+/// their wire formats: Borsh structs, little-endian integers, and optional
+/// trailing fields.
+#[derive(Clone, Debug)]
+pub enum VestingInstruction {
+    Create(StreamInstruction),
+    /// Private streams append the 32-byte salt on the first withdrawal.
+    Withdraw {
+        amount: u64,
+        salt: Option<[u8; 32]>,
+    },
+    Cancel,
+    TransferRecipient,
+    TopUp {
+        amount: u64,
+    },
+    PauseByRecipient,
+    ResumeByRecipient,
+    CancelBatch,
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    InitFeeExemptions,
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    UpdateFeeExemptions(FeeExemptionUpdate),
+    CreateLedger(CreateLedgerInstruction),
+    AddLedgerGrant(StreamInstruction),
+    WithdrawLedger {
+        amount: u64,
+    },
+    CancelLedgerGrant {
+        index: u64,
+    },
+    SetMinClaim {
+        amount: u64,
+    },
+    /// Simulations may pass a timestamp to preview the stream at.
+    Preview {
+        at: Option<u64>,
+    },
+    NetStreams,
+    InitConversionPool(ConversionRatio),
+    MigrateMint,
+    #[cfg(feature = "devnet-faucet")]
+    Faucet,
+    EscrowProof,
+    ComplianceFreeze {
+        frozen_until: u64,
+    },
+    MintSummary,
+    UndoTopUp,
+    Rollover(StreamInstruction),
+    SetForward,
+    ClearForward,
+    Prewarm,
+    PauseStream,
+    ResumeStream,
+    CrankWithdraw,
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    InitFeeConfig(FeeConfigUpdate),
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    UpdateFeeConfig(FeeConfigUpdate),
+    RefreshClosable,
+    SplitStream(SplitInstruction),
+    ProposeTransfer,
+    AcceptTransfer,
+    CancelTransfer,
+    UpdateStream(StreamUpdate),
+    VestingSnapshot {
+        at: u64,
+    },
+    InitTopUpAllowlist,
+    UpdateTopUpAllowlist(TopUpAllowlistUpdate),
+    WithdrawSurplus,
+    CloseStream,
+    InitStreamIndex(StreamIndexKey),
+    ArchiveStream,
+    UpgradeV1ToV2,
+    MigrateStream,
+    CreateMany(StreamInstruction),
+    InitStreamMirror,
+    SyncMirror,
+    CancelSubscription,
+    CreateFromProgram(CreateFromProgramInstruction),
+    VerifyStream,
+    WithdrawTo {
+        amount: u64,
+    },
+    WithdrawShares {
+        amount: u64,
+        shares: u8,
+    },
+}
+
+impl VestingInstruction {
+    /// Decodes versioned or legacy instruction data. Unknown tags, short or
+    /// overlong payloads and malformed Borsh all fail with
+    /// `InvalidInstructionData`.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        use VestingInstruction::*;
+
+        let (tag, payload) = unpack_version(data)?;
+        Ok(match tag {
+            0 => Create(unpack_borsh(payload)?),
+            1 => Withdraw {
+                amount: unpack_u64(payload.get(..8))?,
+                salt: payload.get(8..40).and_then(|salt| salt.try_into().ok()),
+            },
+            2 => Cancel,
+            3 => TransferRecipient,
+            4 => TopUp {
+                amount: unpack_u64(Some(payload))?,
+            },
+            5 => PauseByRecipient,
+            6 => ResumeByRecipient,
+            7 => CancelBatch,
+            #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+            8 => InitFeeExemptions,
+            #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+            9 => UpdateFeeExemptions(unpack_borsh(payload)?),
+            10 => CreateLedger(unpack_borsh(payload)?),
+            11 => AddLedgerGrant(unpack_borsh(payload)?),
+            12 => WithdrawLedger {
+                amount: unpack_u64(Some(payload))?,
+            },
+            13 => CancelLedgerGrant {
+                index: unpack_u64(Some(payload))?,
+            },
+            14 => SetMinClaim {
+                amount: unpack_u64(Some(payload))?,
+            },
+            15 => Preview {
+                at: match payload.get(..8) {
+                    Some(at) => Some(unpack_u64(Some(at))?),
+                    None => None,
+                },
+            },
+            16 => NetStreams,
+            17 => InitConversionPool(unpack_borsh(payload)?),
+            18 => MigrateMint,
+            #[cfg(feature = "devnet-faucet")]
+            19 => Faucet,
+            20 => EscrowProof,
+            21 => ComplianceFreeze {
+                frozen_until: unpack_u64(Some(payload))?,
+            },
+            22 => MintSummary,
+            23 => UndoTopUp,
+            24 => Rollover(unpack_borsh(payload)?),
+            25 => SetForward,
+            26 => ClearForward,
+            27 => Prewarm,
+            28 => PauseStream,
+            29 => ResumeStream,
+            30 => CrankWithdraw,
+            #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+            31 => InitFeeConfig(unpack_borsh(payload)?),
+            #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+            32 => UpdateFeeConfig(unpack_borsh(payload)?),
+            33 => RefreshClosable,
+            34 => SplitStream(unpack_borsh(payload)?),
+            35 => ProposeTransfer,
+            36 => AcceptTransfer,
+            37 => CancelTransfer,
+            38 => UpdateStream(unpack_borsh(payload)?),
+            39 => VestingSnapshot {
+                at: unpack_u64(Some(payload))?,
+            },
+            40 => InitTopUpAllowlist,
+            41 => UpdateTopUpAllowlist(unpack_borsh(payload)?),
+            42 => WithdrawSurplus,
+            43 => CloseStream,
+            44 => InitStreamIndex(unpack_borsh(payload)?),
+            45 => ArchiveStream,
+            46 => UpgradeV1ToV2,
+            47 => MigrateStream,
+            48 => CreateMany(unpack_borsh(payload)?),
+            49 => InitStreamMirror,
+            50 => SyncMirror,
+            51 => CancelSubscription,
+            52 => CreateFromProgram(unpack_borsh(payload)?),
+            53 => VerifyStream,
+            54 => WithdrawTo {
+                amount: unpack_u64(Some(payload))?,
+            },
+            55 => WithdrawShares {
+                amount: unpack_u64(payload.get(..8))?,
+                shares: match payload.get(8..) {
+                    Some([shares]) => *shares,
+                    _ => return Err(ProgramError::InvalidInstructionData),
+                },
+            },
+            _ => return Err(ProgramError::InvalidInstructionData),
+        })
+    }
+}
+
+fn unpack_u64(bytes: Option<&[u8]>) -> Result<u64, ProgramError> {
+    match bytes.map(<[u8; 8]>::try_from) {
+        Some(Ok(v)) => Ok(u64::from_le_bytes(v)),
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn unpack_borsh<T: BorshDeserialize>(payload: &[u8]) -> Result<T, ProgramError> {
+    T::try_from_slice(payload).map_err(|_| ProgramError::InvalidInstructionData)
+}
+
+/// Prefixes `payload` with the versioned encoding of `tag`.
+pub fn pack_versioned(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 2);
+    data.push(tag | VERSIONED_TAG_FLAG);
+    data.push(IX_VERSION);
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Builds `create` for a public or private stream of `ix`, deriving the
+/// metadata, escrow, recipient token and stream counter accounts. Streams
+/// with `managed_freeze` or `obligation_nft` need their extra accounts pushed
+/// onto the result, ahead of any multisig ones.
+pub fn create_stream(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    sender_tokens: &Pubkey,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    ix: &StreamInstruction,
+) -> Result<Instruction, ProgramError> {
+    let derived_recipient = if ix.recipient_hash != [0; 32] {
+        Pubkey::new_from_array(ix.recipient_hash)
+    } else {
+        *recipient
+    };
+    let (metadata, _) = TokenStreamData::metadata_address(
+        program_id,
+        sender,
+        &derived_recipient,
+        mint,
+        &ix.metadata_seed,
+    );
+    let (escrow_tokens, _) = Pubkey::find_program_address(&[metadata.as_ref()], program_id);
+    let (stream_counter, _) = Pubkey::find_program_address(&[STREAM_COUNTER_SEED], program_id);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*sender_tokens, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(
+                associated_token_address(recipient, mint, token_program),
+                false,
+            ),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(stream_counter, false),
+        ],
+        data: pack_versioned(0, &ix.try_to_vec()?),
+    })
+}
+
+/// Builds `create_from_program` of a stream of `ix` from `sender`, a PDA of
+/// `owner_program` derived from `seeds`, for that program to invoke with
+/// `invoke_signed`. `payer` pays the rent.
+#[allow(clippy::too_many_arguments)]
+pub fn create_from_program(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    sender: &Pubkey,
+    sender_tokens: &Pubkey,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    owner_program: &Pubkey,
+    seeds: &[&[u8]],
+    ix: &StreamInstruction,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = create_stream(
+        program_id,
+        sender,
+        sender_tokens,
+        recipient,
+        mint,
+        token_program,
+        ix,
+    )?;
+    instruction
+        .accounts
+        .insert(0, AccountMeta::new(*payer, true));
+    let ci = CreateFromProgramInstruction {
+        owner_program: *owner_program,
+        seeds: seeds.iter().map(|s| s.to_vec()).collect(),
+        stream: ix.clone(),
+    };
+    instruction.data = pack_versioned(52, &ci.try_to_vec()?);
+    Ok(instruction)
+}
+
+/// Builds `create_many` of one stream of `ix` to each of `recipients`, all
+/// funded from `sender_tokens`.
+pub fn create_many(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    sender_tokens: &Pubkey,
+    recipients: &[Pubkey],
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    ix: &StreamInstruction,
+) -> Result<Instruction, ProgramError> {
+    let (stream_counter, _) = Pubkey::find_program_address(&[STREAM_COUNTER_SEED], program_id);
+    let mut accounts = vec![
+        AccountMeta::new(*sender, true),
+        AccountMeta::new(*sender_tokens, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(stream_counter, false),
+    ];
+    for recipient in recipients {
+        let (metadata, _) = TokenStreamData::metadata_address(
+            program_id,
+            sender,
+            recipient,
+            mint,
+            &ix.metadata_seed,
+        );
+        let (escrow_tokens, _) = Pubkey::find_program_address(&[metadata.as_ref()], program_id);
+        accounts.extend([
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(
+                associated_token_address(recipient, mint, token_program),
+                false,
+            ),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(escrow_tokens, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: pack_versioned(48, &ix.try_to_vec()?),
+    })
+}
+
+/// Optional accounts of `withdraw`, `withdraw_to`, `withdraw_shares` and
+/// `crank_withdraw`, which take them in fixed slots, in field order, after
+/// their other accounts. Slots ahead of the last one used are filled with the
+/// program id, which the program reads as absent.
+#[derive(Clone, Debug, Default)]
+pub struct OptionalWithdrawAccounts {
+    /// Treasury token account and exemption list of the protocol fee.
+    pub treasury_tokens: Option<Pubkey>,
+    pub fee_exemptions: Option<Pubkey>,
+    /// Required for streams with a partner fee.
+    pub partner_tokens: Option<Pubkey>,
+    /// Required for `managed_freeze` streams.
+    pub freeze_authority: Option<Pubkey>,
+    pub freeze_signer: Option<Pubkey>,
+    /// Required for the last withdrawal of `obligation_nft` streams.
+    pub obligation_mint: Option<Pubkey>,
+    pub obligation_tokens: Option<Pubkey>,
+    pub token_2022_program: Option<Pubkey>,
+    /// Required once the recipient set `forward_to`.
+    pub forward_tokens: Option<Pubkey>,
+    /// Required for streams with a `hold_amount`.
+    pub hold_tokens: Option<Pubkey>,
+    /// Required once the stream has a `StreamMirror`.
+    pub mirror: Option<Pubkey>,
+    /// Opt-in LP token breakdown.
+    pub lp_pool: Option<Pubkey>,
+    pub lp_reserve_a: Option<Pubkey>,
+    pub lp_reserve_b: Option<Pubkey>,
+}
+
+impl OptionalWithdrawAccounts {
+    /// Appends the slots up to the last account given to `instruction`.
+    pub fn append_to(&self, instruction: &mut Instruction) {
+        append_slots(
+            instruction,
+            &[
+                (self.treasury_tokens, true),
+                (self.fee_exemptions, false),
+                (self.partner_tokens, true),
+                (self.freeze_authority, false),
+                (self.freeze_signer, false),
+                (self.obligation_mint, true),
+                (self.obligation_tokens, true),
+                (self.token_2022_program, false),
+                (self.forward_tokens, true),
+                (self.hold_tokens, false),
+                (self.mirror, true),
+                (self.lp_pool, false),
+                (self.lp_reserve_a, false),
+                (self.lp_reserve_b, false),
+            ],
+        );
+    }
+}
+
+/// Optional accounts of `cancel`, in fixed slots like
+/// `OptionalWithdrawAccounts`.
+#[derive(Clone, Debug, Default)]
+pub struct OptionalCancelAccounts {
+    /// Treasury token account and exemption list of the protocol fee.
+    pub treasury_tokens: Option<Pubkey>,
+    pub fee_exemptions: Option<Pubkey>,
+    /// Required for streams with a partner fee.
+    pub partner_tokens: Option<Pubkey>,
+    /// Required to pay out `managed_freeze` streams.
+    pub freeze_authority: Option<Pubkey>,
+    pub freeze_signer: Option<Pubkey>,
+    /// Required for `obligation_nft` streams.
+    pub obligation_mint: Option<Pubkey>,
+    pub obligation_tokens: Option<Pubkey>,
+    pub token_2022_program: Option<Pubkey>,
+}
+
+impl OptionalCancelAccounts {
+    /// Appends the slots up to the last account given to `instruction`.
+    pub fn append_to(&self, instruction: &mut Instruction) {
+        append_slots(
+            instruction,
+            &[
+                (self.treasury_tokens, true),
+                (self.fee_exemptions, false),
+                (self.partner_tokens, true),
+                (self.freeze_authority, false),
+                (self.freeze_signer, false),
+                (self.obligation_mint, true),
+                (self.obligation_tokens, true),
+                (self.token_2022_program, false),
+            ],
+        );
+    }
+}
+
+/// Appends `slots`, with whether each is writable, up to the last one given,
+/// filling the ones before it with the program id.
+fn append_slots(instruction: &mut Instruction, slots: &[(Option<Pubkey>, bool)]) {
+    let used = slots
+        .iter()
+        .rposition(|(key, _)| key.is_some())
+        .map_or(0, |i| i + 1);
+    instruction
+        .accounts
+        .extend(slots[..used].iter().map(|&(key, writable)| match key {
+            Some(key) if writable => AccountMeta::new(key, false),
+            Some(key) => AccountMeta::new_readonly(key, false),
+            None => AccountMeta::new_readonly(instruction.program_id, false),
+        }));
+}
+
+/// Builds `withdraw` of `amount` by the recipient, 0 for all that's
+/// available. Accounts are as stored in the stream's metadata; optional ones
+/// are appended with `OptionalWithdrawAccounts`.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_stream(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    sender: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*recipient, true),
+            AccountMeta::new(*sender, false),
+            AccountMeta::new(*recipient, true),
+            AccountMeta::new(*recipient_tokens, false),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(FeeConfig::address(program_id).0, false),
+        ],
+        data: pack_versioned(1, &amount.to_le_bytes()),
+    }
+}
+
+/// Builds `withdraw_to` of `amount`, 0 for all that's available, into
+/// `destination_tokens`, a token account of the recipient's.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_to(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    sender: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    destination_tokens: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut instruction = withdraw_stream(
+        program_id,
+        recipient,
+        recipient_tokens,
+        sender,
+        metadata,
+        escrow_tokens,
+        mint,
+        token_program,
+        amount,
+    );
+    // The destination goes ahead of the fee config.
+    instruction
+        .accounts
+        .insert(8, AccountMeta::new(*destination_tokens, false));
+    instruction.data = pack_versioned(54, &amount.to_le_bytes());
+    instruction
+}
+
+/// Builds `withdraw_shares` of `amount`, 0 for all that's available, signed
+/// by the recipient or the sender. `share_tokens` are the co-recipients'
+/// associated token accounts, in the stream's `shares` order.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_shares(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    sender: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    share_tokens: &[Pubkey],
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*sender, false),
+        AccountMeta::new(*recipient, authority == recipient),
+        AccountMeta::new(*recipient_tokens, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new(*escrow_tokens, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ];
+    accounts.extend(share_tokens.iter().map(|t| AccountMeta::new(*t, false)));
+    accounts.push(AccountMeta::new_readonly(
+        FeeConfig::address(program_id).0,
+        false,
+    ));
+
+    let mut data = amount.to_le_bytes().to_vec();
+    data.push(share_tokens.len() as u8);
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: pack_versioned(55, &data),
+    }
+}
+
+/// `withdraw_stream` as a message paid for by `fee_payer`, e.g. a relayer
+/// serving recipients without SOL. The recipient still signs, but pays
+/// nothing: no handler draws on the transaction fee payer.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_message(
+    program_id: &Pubkey,
+    fee_payer: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    sender: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Message {
+    let ix = withdraw_stream(
+        program_id,
+        recipient,
+        recipient_tokens,
+        sender,
+        metadata,
+        escrow_tokens,
+        mint,
+        token_program,
+        amount,
+    );
+    Message::new(&[ix], Some(fee_payer))
+}
+
+/// Builds `crank_withdraw` by `keeper`, paid the stream's cranker fee into
+/// `keeper_tokens`. Accounts are as stored in the stream's metadata.
+#[allow(clippy::too_many_arguments)]
+pub fn crank_withdraw(
+    program_id: &Pubkey,
+    keeper: &Pubkey,
+    keeper_tokens: &Pubkey,
+    sender: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*keeper, true),
+            AccountMeta::new(*keeper_tokens, false),
+            AccountMeta::new(*sender, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(*recipient_tokens, false),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(FeeConfig::address(program_id).0, false),
+        ],
+        data: pack_versioned(30, &[]),
+    }
+}
+
+/// Builds `cancel` signed by `cancel_authority`: the sender, the recipient
+/// if the stream allows it, or anyone once it is closable. Optional accounts
+/// are appended with `OptionalCancelAccounts`.
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_stream(
+    program_id: &Pubkey,
+    cancel_authority: &Pubkey,
+    sender: &Pubkey,
+    sender_tokens: &Pubkey,
+    recipient: &Pubkey,
+    recipient_tokens: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*cancel_authority, true),
+            AccountMeta::new(*sender, false),
+            AccountMeta::new(*sender_tokens, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(*recipient_tokens, false),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(FeeConfig::address(program_id).0, false),
+        ],
+        data: pack_versioned(2, &[]),
+    }
+}
+
+/// Builds `transfer_recipient` to `new_recipient`, whose associated token
+/// account `authorized_wallet` pays for if it doesn't exist yet.
+pub fn transfer_recipient(
+    program_id: &Pubkey,
+    authorized_wallet: &Pubkey,
+    new_recipient: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*authorized_wallet, true),
+            AccountMeta::new_readonly(*new_recipient, false),
+            AccountMeta::new(
+                associated_token_address(new_recipient, mint, token_program),
+                false,
+            ),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(3, &[]),
+    }
+}
+
+/// Builds `propose_transfer` to `new_recipient`, who takes over once they
+/// sign `accept_transfer`.
+pub fn propose_transfer(
+    program_id: &Pubkey,
+    authorized_wallet: &Pubkey,
+    new_recipient: &Pubkey,
+    metadata: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*authorized_wallet, true),
+            AccountMeta::new_readonly(*new_recipient, false),
+            AccountMeta::new(*metadata, false),
+        ],
+        data: pack_versioned(35, &[]),
+    }
+}
+
+/// Builds `accept_transfer`, signed by the pending `new_recipient`, who pays
+/// for their associated token account if it doesn't exist yet.
+pub fn accept_transfer(
+    program_id: &Pubkey,
+    new_recipient: &Pubkey,
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*new_recipient, true),
+            AccountMeta::new(
+                associated_token_address(new_recipient, mint, token_program),
+                false,
+            ),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(36, &[]),
+    }
+}
+
+/// Builds `cancel_transfer`, signed by whoever proposed the pending one.
+pub fn cancel_transfer(
+    program_id: &Pubkey,
+    authorized_wallet: &Pubkey,
+    metadata: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*authorized_wallet, true),
+            AccountMeta::new(*metadata, false),
+        ],
+        data: pack_versioned(37, &[]),
+    }
+}
+
+/// Builds `close_stream`, refunding the metadata rent of a settled stream to
+/// `sender`.
+pub fn close_stream(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*escrow_tokens, false),
+        ],
+        data: pack_versioned(43, &[]),
+    }
+}
+
+/// Builds `init_stream_index` for `page` of `owner`'s streams in `role`,
+/// paid for by `payer`.
+pub fn init_stream_index(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    role: IndexRole,
+    page: u32,
+) -> Result<Instruction, ProgramError> {
+    let (index, _) = StreamIndex::address(program_id, owner, role, page);
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new(index, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(44, &StreamIndexKey { role, page }.try_to_vec()?),
+    })
+}
+
+/// Builds `init_stream_mirror` of the stream at `metadata`, signed by its
+/// sender, who pays the rent.
+pub fn init_stream_mirror(program_id: &Pubkey, sender: &Pubkey, metadata: &Pubkey) -> Instruction {
+    let (mirror, _) = StreamMirror::address(program_id, metadata);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(mirror, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(49, &[]),
+    }
+}
+
+/// Builds `sync_mirror` of the stream at `metadata`.
+pub fn sync_mirror(program_id: &Pubkey, metadata: &Pubkey) -> Instruction {
+    let (mirror, _) = StreamMirror::address(program_id, metadata);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*metadata, false),
+            AccountMeta::new(mirror, false),
+        ],
+        data: pack_versioned(50, &[]),
+    }
+}
+
+/// Builds `verify_stream` of the stream at `metadata`, whose escrow may be
+/// closed.
+pub fn verify_stream(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*metadata, false),
+            AccountMeta::new_readonly(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+        ],
+        data: pack_versioned(53, &[]),
+    }
+}
+
+/// Builds `archive_stream`, writing the settlement record of the ended
+/// stream at `metadata`, paid for by `payer`.
+pub fn archive_stream(program_id: &Pubkey, payer: &Pubkey, metadata: &Pubkey) -> Instruction {
+    let (settlement, _) = SettlementRecord::address(program_id, metadata);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*metadata, false),
+            AccountMeta::new(settlement, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(45, &[]),
+    }
+}
+
+/// Builds `upgrade_v1_to_v2` of the v1 stream at `metadata`, signed by its
+/// sender, who pays the added rent.
+pub fn upgrade_v1_to_v2(program_id: &Pubkey, sender: &Pubkey, metadata: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(46, &[]),
+    }
+}
+
+/// Builds `migrate_stream` of the stream at `metadata` to the current layout,
+/// signed by its sender, who pays any added rent.
+pub fn migrate_stream(program_id: &Pubkey, sender: &Pubkey, metadata: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: pack_versioned(47, &[]),
+    }
+}
+
+/// Builds `topup_stream` of `amount` from `sender_tokens`, owned by `sender`.
+#[allow(clippy::too_many_arguments)]
+pub fn topup_stream(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    sender_tokens: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(*sender_tokens, false),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: pack_versioned(4, &amount.to_le_bytes()),
+    }
+}
+
+/// Builds `cancel_subscription` of the subscription at `metadata`, signed by
+/// its sender or recipient as `authority`.
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_subscription(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    sender_tokens: &Pubkey,
+    metadata: &Pubkey,
+    escrow_tokens: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(*sender_tokens, false),
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new(*escrow_tokens, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: pack_versioned(51, &[]),
+    }
+}
@@ -0,0 +1,525 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction, system_program, sysvar,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::error::StreamFlowError::{
+    AccountsNotWritable, InvalidMetadata, LedgerFull, LedgerOverCapacity, MintMismatch,
+    TransferNotAllowed,
+};
+use crate::state::{
+    encode_name, AddLedgerGrantAccounts, CancelLedgerGrantAccounts, CreateLedgerInstruction,
+    GrantLedger, InitializeAccounts, LedgerGrant, StreamInstruction, WithdrawLedgerAccounts,
+    LEDGER_MAGIC, MAX_LEDGER_GRANTS,
+};
+use crate::token::{check_multisig_owner, create_recipient_ata, transfer_from_sender};
+use crate::utils::{
+    duration_sanity, encode_base10, sanitize_name, unpack_mint_account, unpack_token_account,
+};
+
+pub fn create_ledger(
+    program_id: &Pubkey,
+    acc: InitializeAccounts,
+    mut ix: CreateLedgerInstruction,
+) -> ProgramResult {
+    msg!("Initializing grant ledger");
+
+    if !acc.escrow_tokens.data_is_empty() || !acc.metadata.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.sender.is_writable
+        || !acc.sender_tokens.is_writable
+        || !acc.recipient.is_writable
+        || !acc.recipient_tokens.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
+    let recipient_tokens_key = get_associated_token_address(acc.recipient.key, acc.mint.key);
+
+    if acc.system_program.key != &system_program::id()
+        || acc.token_program.key != &spl_token::id()
+        || acc.associated_token_program.key != &spl_associated_token_account::id()
+        || acc.rent.key != &sysvar::rent::id()
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.recipient_tokens.key != &recipient_tokens_key
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer || !acc.metadata.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if ix.capacity == 0 || ix.capacity > MAX_LEDGER_GRANTS {
+        msg!(
+            "Error: Ledger capacity must be within 1..={}",
+            MAX_LEDGER_GRANTS
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let sender_token_info = unpack_token_account(&acc.sender_tokens)?;
+    let mint_info = unpack_mint_account(&acc.mint)?;
+
+    if &sender_token_info.mint != acc.mint.key {
+        return Err(MintMismatch.into());
+    }
+
+    check_multisig_owner(&sender_token_info, &acc.multisig, &acc.multisig_signers)?;
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    check_grant(now, &mut ix.grant)?;
+
+    if sender_token_info.amount < ix.grant.deposited_amount {
+        msg!("Error: Insufficient tokens in sender's wallet");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let ledger = GrantLedger {
+        magic: LEDGER_MAGIC,
+        capacity: ix.capacity,
+        sender: *acc.sender.key,
+        sender_tokens: *acc.sender_tokens.key,
+        recipient: *acc.recipient.key,
+        recipient_tokens: *acc.recipient_tokens.key,
+        mint: *acc.mint.key,
+        escrow_tokens: *acc.escrow_tokens.key,
+        grants: vec![LedgerGrant {
+            created_at: now,
+            withdrawn_amount: 0,
+            canceled_at: 0,
+            ix: ix.grant,
+        }],
+    };
+
+    let ledger_size = GrantLedger::size(ix.capacity);
+    let tokens_struct_size = spl_token::state::Account::LEN;
+    let cluster_rent = Rent::get()?;
+    let ledger_rent = cluster_rent.minimum_balance(ledger_size);
+    let mut tokens_rent = cluster_rent.minimum_balance(tokens_struct_size);
+    if acc.recipient_tokens.data_is_empty() {
+        tokens_rent += cluster_rent.minimum_balance(tokens_struct_size);
+    }
+
+    if acc.sender.lamports() < ledger_rent + tokens_rent {
+        msg!("Error: Insufficient funds in {}", acc.sender.key);
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    msg!("Initializing recipient's associated token account");
+    create_recipient_ata(
+        &acc.sender,
+        &acc.recipient,
+        &acc.recipient_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    msg!("Creating account for holding the ledger");
+    invoke(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.metadata.key,
+            ledger_rent,
+            ledger_size as u64,
+            program_id,
+        ),
+        &[
+            acc.sender.clone(),
+            acc.metadata.clone(),
+            acc.system_program.clone(),
+        ],
+    )?;
+
+    let bytes = ledger.try_to_vec()?;
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    let seeds = [acc.metadata.key.as_ref(), &[nonce]];
+    msg!("Creating account for holding tokens");
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.escrow_tokens.key,
+            cluster_rent.minimum_balance(tokens_struct_size),
+            tokens_struct_size as u64,
+            &spl_token::id(),
+        ),
+        &[
+            acc.sender.clone(),
+            acc.escrow_tokens.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    msg!("Initializing escrow account for {} token", acc.mint.key);
+    invoke(
+        &spl_token::instruction::initialize_account(
+            acc.token_program.key,
+            acc.escrow_tokens.key,
+            acc.mint.key,
+            acc.escrow_tokens.key,
+        )?,
+        &[
+            acc.token_program.clone(),
+            acc.escrow_tokens.clone(),
+            acc.mint.clone(),
+            acc.escrow_tokens.clone(),
+            acc.rent.clone(),
+        ],
+    )?;
+
+    msg!("Moving funds into escrow account");
+    transfer_from_sender(
+        &acc.token_program,
+        &acc.sender_tokens,
+        &acc.mint,
+        &acc.escrow_tokens,
+        acc.multisig.as_ref().unwrap_or(&acc.sender),
+        &acc.multisig_signers,
+        ledger.grants[0].ix.deposited_amount,
+        mint_info.decimals,
+    )?;
+
+    msg!(
+        "Successfully initialized ledger with {} {} tokens for {}",
+        encode_base10(
+            ledger.grants[0].ix.deposited_amount,
+            mint_info.decimals.into()
+        ),
+        ledger.mint,
+        ledger.recipient
+    );
+
+    Ok(())
+}
+
+pub fn add_ledger_grant(
+    program_id: &Pubkey,
+    acc: AddLedgerGrantAccounts,
+    mut ix: StreamInstruction,
+) -> ProgramResult {
+    msg!("Adding grant to ledger");
+
+    if !acc.sender.is_writable
+        || !acc.sender_tokens.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut ledger = load_ledger(program_id, &acc.metadata)?;
+
+    if acc.sender.key != &ledger.sender
+        || acc.mint.key != &ledger.mint
+        || acc.escrow_tokens.key != &ledger.escrow_tokens
+        || acc.token_program.key != &spl_token::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let sender_token_info = unpack_token_account(&acc.sender_tokens)?;
+    if &sender_token_info.mint != acc.mint.key {
+        return Err(MintMismatch.into());
+    }
+
+    check_multisig_owner(&sender_token_info, &acc.multisig, &acc.multisig_signers)?;
+
+    if ledger.grants.len() >= ledger.capacity as usize {
+        return Err(LedgerFull.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    check_grant(now, &mut ix)?;
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    transfer_from_sender(
+        &acc.token_program,
+        &acc.sender_tokens,
+        &acc.mint,
+        &acc.escrow_tokens,
+        acc.multisig.as_ref().unwrap_or(&acc.sender),
+        &acc.multisig_signers,
+        ix.deposited_amount,
+        mint_info.decimals,
+    )?;
+
+    let deposited = ix.deposited_amount;
+    ledger.grants.push(LedgerGrant {
+        created_at: now,
+        withdrawn_amount: 0,
+        canceled_at: 0,
+        ix,
+    });
+
+    let bytes = ledger.try_to_vec()?;
+    acc.metadata.try_borrow_mut_data()?[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Added grant #{} of {} tokens",
+        ledger.grants.len() - 1,
+        encode_base10(deposited, mint_info.decimals.into())
+    );
+
+    Ok(())
+}
+
+pub fn withdraw_ledger(
+    program_id: &Pubkey,
+    acc: WithdrawLedgerAccounts,
+    amount: u64,
+) -> ProgramResult {
+    msg!("Withdrawing from grant ledger");
+
+    if !acc.recipient_tokens.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut ledger = load_ledger(program_id, &acc.metadata)?;
+
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
+
+    if acc.recipient.key != &ledger.recipient
+        || acc.recipient_tokens.key != &ledger.recipient_tokens
+        || acc.mint.key != &ledger.mint
+        || acc.escrow_tokens.key != &ledger.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.token_program.key != &spl_token::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let available = ledger.available(now)?;
+
+    if amount > available {
+        msg!("Amount requested for withdraw is more than what is available");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let requested = if amount == 0 { available } else { amount };
+
+    // Settle the oldest grants first.
+    let mut remaining = requested;
+    for grant in ledger.grants.iter_mut() {
+        let take = grant.available(now).min(remaining);
+        grant.withdrawn_amount += take;
+        remaining -= take;
+    }
+
+    let seeds = [acc.metadata.key.as_ref(), &[nonce]];
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            acc.token_program.key,
+            acc.escrow_tokens.key,
+            acc.recipient_tokens.key,
+            acc.escrow_tokens.key,
+            &[],
+            requested,
+        )?,
+        &[
+            acc.escrow_tokens.clone(),
+            acc.recipient_tokens.clone(),
+            acc.escrow_tokens.clone(),
+            acc.token_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let bytes = ledger.try_to_vec()?;
+    acc.metadata.try_borrow_mut_data()?[0..bytes.len()].clone_from_slice(&bytes);
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    msg!(
+        "Withdrawn: {} {} tokens",
+        encode_base10(requested, mint_info.decimals.into()),
+        ledger.mint
+    );
+    msg!(
+        "Remaining: {} {} tokens",
+        encode_base10(ledger.escrowed()?, mint_info.decimals.into()),
+        ledger.mint
+    );
+
+    Ok(())
+}
+
+pub fn cancel_ledger_grant(
+    program_id: &Pubkey,
+    acc: CancelLedgerGrantAccounts,
+    index: u64,
+) -> ProgramResult {
+    msg!("Cancelling ledger grant #{}", index);
+
+    if !acc.sender_tokens.is_writable
+        || !acc.recipient_tokens.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut ledger = load_ledger(program_id, &acc.metadata)?;
+
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
+
+    if acc.sender.key != &ledger.sender
+        || acc.sender_tokens.key != &ledger.sender_tokens
+        || acc.recipient_tokens.key != &ledger.recipient_tokens
+        || acc.mint.key != &ledger.mint
+        || acc.escrow_tokens.key != &ledger.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.token_program.key != &spl_token::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let grant = match ledger.grants.get_mut(index as usize) {
+        Some(v) if v.canceled_at == 0 => v,
+        _ => return Err(ProgramError::InvalidArgument),
+    };
+
+    if !grant.ix.cancelable_by_sender {
+        msg!("Error: Grant is not cancelable by the sender");
+        return Err(TransferNotAllowed.into());
+    }
+
+    let available = grant.available(now);
+    grant.withdrawn_amount += available;
+    let remains = grant.ix.deposited_amount - grant.withdrawn_amount;
+    grant.canceled_at = now;
+
+    let seeds = [acc.metadata.key.as_ref(), &[nonce]];
+    for (destination, amount) in [
+        (&acc.recipient_tokens, available),
+        (&acc.sender_tokens, remains),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                destination.key,
+                acc.escrow_tokens.key,
+                &[],
+                amount,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                destination.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    let bytes = ledger.try_to_vec()?;
+    acc.metadata.try_borrow_mut_data()?[0..bytes.len()].clone_from_slice(&bytes);
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    msg!(
+        "Transferred: {} {} tokens",
+        encode_base10(available, mint_info.decimals.into()),
+        ledger.mint
+    );
+    msg!(
+        "Returned: {} {} tokens",
+        encode_base10(remains, mint_info.decimals.into()),
+        ledger.mint
+    );
+
+    Ok(())
+}
+
+fn load_ledger(program_id: &Pubkey, metadata: &AccountInfo) -> Result<GrantLedger, ProgramError> {
+    if metadata.data_is_empty() || metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let ledger =
+        match solana_borsh::try_from_slice_unchecked::<GrantLedger>(&metadata.data.borrow()) {
+            Ok(v) if v.magic == LEDGER_MAGIC => v,
+            _ => return Err(InvalidMetadata.into()),
+        };
+
+    // Every handler walks `grants`, so never trust its length blindly.
+    if ledger.capacity > MAX_LEDGER_GRANTS || ledger.grants.len() > ledger.capacity as usize {
+        return Err(LedgerOverCapacity.into());
+    }
+
+    Ok(ledger)
+}
+
+/// Grants only support plain schedules; per-stream extras such as private
+/// recipients, pauses or managed freezing need a standalone stream.
+fn check_grant(now: u64, ix: &mut StreamInstruction) -> ProgramResult {
+    ix.check_start(now)?;
+    if !duration_sanity(now, ix.start_time, ix.end_time, ix.cliff) {
+        msg!("Error: Given timestamps are invalid");
+        return Err(ProgramError::InvalidArgument);
+    }
+    ix.check_schedule()?;
+
+    ix.stream_name = encode_name(&sanitize_name(&ix.name()));
+
+    if ix.recipient_hash != [0; 32]
+        || ix.max_recipient_pause > 0
+        || ix.managed_freeze
+        || ix.bond_amount > 0
+        || ix.partner != Pubkey::default()
+        || !ix.tranches.is_empty()
+        || !ix.transfer_allowlist.is_empty()
+    {
+        msg!("Error: Option not supported for ledger grants");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if ix.deposited_amount == 0 {
+        msg!("Error: Amount can't be zero.");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    Ok(())
+}
@@ -1,10 +1,51 @@
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
+#[cfg(any(not(feature = "no-entrypoint"), feature = "program-test", test))]
+mod processor;
+/// For embedding the program in `solana-program-test` without a compiled
+/// .so: `processor!(vesting::process_instruction)`.
+#[cfg(feature = "program-test")]
+pub use processor::process_instruction;
 
+pub mod archive;
+#[cfg(feature = "client")]
+pub mod builder;
+#[cfg(feature = "client")]
+pub mod cluster;
 pub mod error;
+pub mod events;
+#[cfg(test)]
+mod harness;
+#[cfg(feature = "devnet-faucet")]
+pub mod faucet;
+#[cfg(all(feature = "fees", not(feature = "no-admin")))]
+pub mod fees;
+pub mod instruction;
+pub mod ledger;
+pub mod lp;
+pub mod migrations;
+pub mod mint_migration;
+pub mod mirror;
+pub mod netting;
+#[cfg(feature = "token2022")]
+pub mod obligation;
+pub mod permissions;
+#[cfg(feature = "client")]
+pub mod preflight;
+pub mod registry;
+pub mod rollover;
+pub mod split;
+#[cfg(feature = "client")]
+pub mod stream_client;
 
 pub mod token;
+pub mod topup_allowlist;
+pub mod update;
 pub mod utils;
-mod state;
+pub mod state;
+pub mod verify;
+
+#[cfg(feature = "client")]
+pub mod valuation;
 
 
@@ -0,0 +1,99 @@
+//! Underlying asset breakdown of streamed LP tokens, for cost-basis reporting.
+//! Pool state is read straight from the accounts of whitelisted AMMs rather
+//! than through CPI, which none of them expose a view for.
+
+use solana_program::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::error::StreamFlowError::UnsupportedLpPool;
+use crate::utils::unpack_token_account;
+
+pub mod spl_token_swap {
+    solana_program::declare_id!("SwapsVeCiPHMUAtzQWZw7RjsKjgCjhwU55QGu4U1Szw");
+}
+
+/// Pool layouts the breakdown can be read from, by owning program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LpAdapter {
+    /// SPL Token Swap and its forks sharing the `SwapV1` layout.
+    TokenSwap,
+}
+
+impl LpAdapter {
+    pub fn for_program(program_id: &Pubkey) -> Option<Self> {
+        if program_id == &spl_token_swap::id() {
+            Some(LpAdapter::TokenSwap)
+        } else {
+            None
+        }
+    }
+
+    /// Reserve token accounts and LP mint of an initialized pool.
+    fn parse(self, data: &[u8]) -> Option<(Pubkey, Pubkey, Pubkey)> {
+        match self {
+            // Version byte, then `is_initialized`, the bump seed, the token
+            // program, both reserves and the pool mint.
+            LpAdapter::TokenSwap => {
+                if data.len() < 131 || data[0] != 1 || data[1] != 1 {
+                    return None;
+                }
+                let key = |at: usize| Pubkey::new(&data[at..at + 32]);
+                Some((key(35), key(67), key(99)))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LpBreakdown {
+    pub mint_a: Pubkey,
+    pub amount_a: u64,
+    pub mint_b: Pubkey,
+    pub amount_b: u64,
+}
+
+/// What `amount` of `lp_mint`, whose supply is `lp_supply`, redeems for from
+/// `pool` at current reserves, in base units of either reserve's mint.
+pub fn underlying(
+    pool: &AccountInfo,
+    reserve_a: &Option<AccountInfo>,
+    reserve_b: &Option<AccountInfo>,
+    lp_mint: &Pubkey,
+    lp_supply: u64,
+    amount: u64,
+) -> Result<LpBreakdown, ProgramError> {
+    let adapter = match LpAdapter::for_program(pool.owner) {
+        Some(v) => v,
+        None => return Err(UnsupportedLpPool.into()),
+    };
+
+    let (reserve_a, reserve_b) = match (reserve_a, reserve_b) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Err(ProgramError::NotEnoughAccountKeys),
+    };
+
+    let (reserve_a_key, reserve_b_key, pool_mint) = match adapter.parse(&pool.data.borrow()) {
+        Some(v) => v,
+        None => return Err(UnsupportedLpPool.into()),
+    };
+
+    if &pool_mint != lp_mint || reserve_a.key != &reserve_a_key || reserve_b.key != &reserve_b_key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let reserve_a = unpack_token_account(reserve_a)?;
+    let reserve_b = unpack_token_account(reserve_b)?;
+    let share = |reserve: u64| {
+        if lp_supply == 0 {
+            0
+        } else {
+            (reserve as u128 * amount as u128 / lp_supply as u128) as u64
+        }
+    };
+
+    Ok(LpBreakdown {
+        mint_a: reserve_a.mint,
+        amount_a: share(reserve_a.amount),
+        mint_b: reserve_b.mint,
+        amount_b: share(reserve_b.amount),
+    })
+}
@@ -0,0 +1,332 @@
+//! Layout migrations of metadata accounts, as opposed to `mint_migration`, which
+//! moves streams to a new mint.
+//!
+//! Streams store the version of their layout in `magic`, and every change
+//! to the layout of `TokenStreamData` bumps `PROGRAM_VERSION` and adds a
+//! decoder of the layout it replaced to `decode_outdated`.
+//!
+//! v1 is the layout of the first release: a keypair metadata account holding
+//! only the core schedule. v2 is the one that grew from it until
+//! `stream_name` became fixed size. Both predate this rule and store
+//! `V2_VERSION`; v1 accounts are the ones exactly as large as their data, see
+//! `TokenStreamDataV1::decode`. `upgrade_v1_to_v2` rewrites v1 accounts in
+//! place so the current handlers accept them; `migrate_stream` does the same
+//! for any outdated stream, which `TokenStreamData::load` refuses until then.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, InvalidMetadata};
+use crate::state::{
+    encode_name, MigrateStreamAccounts, StreamInstruction, TokenStreamData, METADATA_BASE_SIZE,
+    METADATA_STREAM_NAME_OFFSET, PROGRAM_VERSION,
+};
+#[cfg(not(feature = "no-admin"))]
+use crate::utils::program_upgrade_authority;
+
+/// `magic` of v1 and v2 streams.
+pub const V2_VERSION: u64 = 2;
+
+/// The layout version `data` was stored with, its `magic`.
+pub fn stored_version(data: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(..8)?.try_into().ok()?))
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct StreamInstructionV1 {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub deposited_amount: u64,
+    pub total_amount: u64,
+    pub period: u64,
+    pub cliff: u64,
+    pub cliff_amount: u64,
+    pub cancelable_by_sender: bool,
+    pub cancelable_by_recipient: bool,
+    pub withdrawal_public: bool,
+    pub transferable_by_sender: bool,
+    pub transferable_by_recipient: bool,
+    pub release_rate: u64,
+    pub stream_name: String,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct TokenStreamDataV1 {
+    pub magic: u64,
+    pub created_at: u64,
+    pub withdrawn_amount: u64,
+    pub canceled_at: u64,
+    pub closable_at: u64,
+    pub last_withdrawn_at: u64,
+    pub sender: Pubkey,
+    pub sender_tokens: Pubkey,
+    pub recipient: Pubkey,
+    pub recipient_tokens: Pubkey,
+    pub mint: Pubkey,
+    pub escrow_tokens: Pubkey,
+    pub ix: StreamInstructionV1,
+}
+
+impl TokenStreamDataV1 {
+    /// Decodes `data` if it holds a v1 stream. The first release sized
+    /// accounts to fit their data, rounded up to a multiple of 8, while v2
+    /// ones have hundreds of bytes past the end of the v1 fields.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let stream = match solana_borsh::try_from_slice_unchecked::<Self>(data) {
+            Ok(v) if v.magic == V2_VERSION => v,
+            _ => return None,
+        };
+
+        let len = stream.try_to_vec().ok()?.len();
+        if data.len() != len + (8 - len % 8) % 8 {
+            return None;
+        }
+
+        Some(stream)
+    }
+
+    /// The stream in the current layout, at the keypair address `key`. New
+    /// fields take their defaults.
+    pub fn upgrade(self, key: &Pubkey) -> TokenStreamData {
+        let mut stream = TokenStreamData {
+            magic: PROGRAM_VERSION,
+            created_at: self.created_at,
+            withdrawn_amount: self.withdrawn_amount,
+            canceled_at: self.canceled_at,
+            closable_at: self.closable_at,
+            last_withdrawn_at: self.last_withdrawn_at,
+            sender: self.sender,
+            sender_tokens: self.sender_tokens,
+            recipient: self.recipient,
+            recipient_tokens: self.recipient_tokens,
+            mint: self.mint,
+            escrow_tokens: self.escrow_tokens,
+            ix: StreamInstruction {
+                start_time: self.ix.start_time,
+                end_time: self.ix.end_time,
+                deposited_amount: self.ix.deposited_amount,
+                total_amount: self.ix.total_amount,
+                period: self.ix.period,
+                cliff: self.ix.cliff,
+                cliff_amount: self.ix.cliff_amount,
+                cancelable_by_sender: self.ix.cancelable_by_sender,
+                cancelable_by_recipient: self.ix.cancelable_by_recipient,
+                withdrawal_public: self.ix.withdrawal_public,
+                transferable_by_sender: self.ix.transferable_by_sender,
+                transferable_by_recipient: self.ix.transferable_by_recipient,
+                release_rate: self.ix.release_rate,
+                stream_name: encode_name(&self.ix.stream_name),
+                ..Default::default()
+            },
+            derived_recipient: self.recipient,
+            derived_mint: self.mint,
+            legacy_address: *key,
+            ..Default::default()
+        };
+        stream.cache_schedule();
+        stream
+    }
+}
+
+#[cfg(not(feature = "no-admin"))]
+fn is_upgrade_authority(
+    program_id: &Pubkey,
+    acc: &MigrateStreamAccounts,
+) -> Result<bool, ProgramError> {
+    match &acc.program_data {
+        Some(program_data) => {
+            Ok(program_upgrade_authority(program_id, program_data)? == Some(*acc.authority.key))
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(feature = "no-admin")]
+fn is_upgrade_authority(_: &Pubkey, _: &MigrateStreamAccounts) -> Result<bool, ProgramError> {
+    Ok(false)
+}
+
+/// Checks the accounts of a migration of the stream of `sender`, which must
+/// be signed by `sender` or, unless built with `no-admin`, the program's
+/// upgrade authority.
+fn check_migrate_accounts(
+    program_id: &Pubkey,
+    acc: &MigrateStreamAccounts,
+    sender: &Pubkey,
+) -> ProgramResult {
+    if acc.system_program.key != &system_program::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if acc.authority.key != sender && !is_upgrade_authority(program_id, acc)? {
+        msg!("Error: Only the sender or the upgrade authority can migrate a stream");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    Ok(())
+}
+
+/// Writes `stream` over `metadata`, first growing the account to `size` and
+/// topping up its rent from `authority`, and zeroes the rest. Returns the
+/// lamports added.
+fn rewrite(
+    acc: &MigrateStreamAccounts,
+    stream: &TokenStreamData,
+    size: usize,
+) -> Result<u64, ProgramError> {
+    let rent = Rent::get()?.minimum_balance(size);
+    let shortfall = rent.saturating_sub(acc.metadata.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(acc.authority.key, acc.metadata.key, shortfall),
+            &[
+                acc.authority.clone(),
+                acc.metadata.clone(),
+                acc.system_program.clone(),
+            ],
+        )?;
+    }
+
+    acc.metadata.realloc(size, true)?;
+    let bytes = stream.try_to_vec()?;
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+    data[bytes.len()..].fill(0);
+
+    Ok(shortfall)
+}
+
+/// Rewrites the v1 stream at `metadata` in the current layout, growing the
+/// account and topping up its rent from `authority`: the stream's sender or,
+/// unless built with `no-admin`, the program's upgrade authority.
+pub fn upgrade_v1_to_v2(program_id: &Pubkey, acc: MigrateStreamAccounts) -> ProgramResult {
+    msg!("Upgrading v1 stream metadata");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.authority.is_writable || !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let legacy = match TokenStreamDataV1::decode(&acc.metadata.try_borrow_data()?) {
+        Some(v) => v,
+        None => return Err(InvalidMetadata.into()),
+    };
+
+    check_migrate_accounts(program_id, &acc, &legacy.sender)?;
+
+    let stream = legacy.upgrade(acc.metadata.key);
+    let size = TokenStreamData::account_size(0, 0, 0);
+    let added = rewrite(&acc, &stream, size)?;
+
+    msg!(
+        "Upgraded {}, {} lamports of rent added",
+        acc.metadata.key,
+        added
+    );
+
+    Ok(())
+}
+
+/// Decodes a stream stored in an earlier layout, whichever its version, in
+/// the current one. `key` is the address of the account holding `data`.
+pub fn decode_outdated(data: &[u8], key: &Pubkey) -> Option<TokenStreamData> {
+    match stored_version(data)? {
+        V2_VERSION => match TokenStreamDataV1::decode(data) {
+            Some(legacy) => Some(legacy.upgrade(key)),
+            None => decode_v2(data),
+        },
+        _ => None,
+    }
+}
+
+/// Decodes a v2 stream in the current layout. Its length prefixed
+/// `stream_name` is swapped for the fixed size one, cut to
+/// `STREAM_NAME_SIZE` bytes. Accounts created before fields were appended to
+/// `TokenStreamData` are missing its tail, which reads as zeros, every new
+/// field's default.
+pub fn decode_v2(data: &[u8]) -> Option<TokenStreamData> {
+    let name_at = METADATA_STREAM_NAME_OFFSET + 4;
+    let name_len = u32::from_le_bytes(
+        data.get(METADATA_STREAM_NAME_OFFSET..name_at)?
+            .try_into()
+            .ok()?,
+    );
+    let name = data.get(name_at..name_at.checked_add(name_len as usize)?)?;
+
+    let mut current = data[..METADATA_STREAM_NAME_OFFSET].to_vec();
+    current.extend_from_slice(&encode_name(&String::from_utf8_lossy(name)));
+    current.extend_from_slice(&data[name_at + name.len()..]);
+    current.resize(current.len() + METADATA_BASE_SIZE, 0);
+
+    match solana_borsh::try_from_slice_unchecked::<TokenStreamData>(&current) {
+        Ok(mut v) if v.magic == V2_VERSION => {
+            v.magic = PROGRAM_VERSION;
+            Some(v)
+        }
+        _ => None,
+    }
+}
+
+/// Brings the stream at `metadata` to the current layout, whichever it is
+/// in: v1 streams are upgraded as by `upgrade_v1_to_v2`, v2 ones rewritten,
+/// growing accounts too short for the current `TokenStreamData`. Streams
+/// already current are left alone.
+pub fn migrate_stream(program_id: &Pubkey, acc: MigrateStreamAccounts) -> ProgramResult {
+    msg!("Migrating stream metadata");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.authority.is_writable || !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let stream = {
+        let data = acc.metadata.try_borrow_data()?;
+        if stored_version(&data) == Some(PROGRAM_VERSION) {
+            msg!("Stream metadata is already current");
+            return Ok(());
+        }
+
+        match decode_outdated(&data, acc.metadata.key) {
+            Some(v) => v,
+            None => return Err(InvalidMetadata.into()),
+        }
+    };
+
+    check_migrate_accounts(program_id, &acc, &stream.sender)?;
+
+    let size = TokenStreamData::account_size(
+        stream.ix.tranches.len(),
+        stream.ix.transfer_allowlist.len(),
+        stream.ix.shares.len(),
+    )
+    .max(acc.metadata.data_len());
+    let added = rewrite(&acc, &stream, size)?;
+
+    msg!(
+        "Migrated {} to {} bytes, {} lamports of rent added",
+        acc.metadata.key,
+        size,
+        added
+    );
+
+    Ok(())
+}
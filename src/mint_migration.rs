@@ -0,0 +1,372 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction, system_program, sysvar,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::error::StreamFlowError::{AccountsNotWritable, InvalidMetadata, MigrationNotAllowed};
+use crate::state::{
+    ConversionPool, ConversionRatio, InitConversionPoolAccounts, MigrateMintAccounts,
+    TokenStreamData, CONVERSION_POOL_SEED, CONVERSION_POOL_SIZE,
+};
+use crate::token::notify;
+use crate::utils::{encode_base10, unpack_mint_account, unpack_token_account};
+
+pub fn init_conversion_pool(
+    program_id: &Pubkey,
+    acc: InitConversionPoolAccounts,
+    ratio: ConversionRatio,
+) -> ProgramResult {
+    msg!("Initializing conversion pool");
+
+    if !acc.pool.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.authority.is_writable || !acc.pool.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (pool_pubkey, nonce) = Pubkey::find_program_address(
+        &[
+            CONVERSION_POOL_SEED,
+            acc.old_mint.key.as_ref(),
+            acc.new_mint.key.as_ref(),
+        ],
+        program_id,
+    );
+
+    if acc.pool.key != &pool_pubkey
+        || acc.old_mint.key == acc.new_mint.key
+        || acc.system_program.key != &system_program::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Only the team behind the new mint gets to define what it's worth.
+    unpack_mint_account(&acc.old_mint)?;
+    let new_mint_info = unpack_mint_account(&acc.new_mint)?;
+    if new_mint_info.mint_authority != COption::Some(*acc.authority.key) {
+        msg!("Error: Only the new mint's authority can set up a conversion pool");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if ratio.numerator == 0 || ratio.denominator == 0 {
+        msg!("Error: Conversion ratio can't be zero");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let seeds = [
+        CONVERSION_POOL_SEED,
+        acc.old_mint.key.as_ref(),
+        acc.new_mint.key.as_ref(),
+        &[nonce],
+    ];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.authority.key,
+            acc.pool.key,
+            Rent::get()?.minimum_balance(CONVERSION_POOL_SIZE),
+            CONVERSION_POOL_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.authority.clone(),
+            acc.pool.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let pool = ConversionPool {
+        authority: *acc.authority.key,
+        old_mint: *acc.old_mint.key,
+        new_mint: *acc.new_mint.key,
+        ratio,
+    };
+    let bytes = pool.try_to_vec()?;
+    let mut data = acc.pool.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Converting {} into {} at {}/{}",
+        pool.old_mint,
+        pool.new_mint,
+        ratio.numerator,
+        ratio.denominator
+    );
+    msg!(
+        "Fund {} with {} tokens",
+        get_associated_token_address(acc.pool.key, acc.new_mint.key),
+        pool.new_mint
+    );
+
+    Ok(())
+}
+
+/// Moves a stream onto a new mint: the escrowed old tokens go to the
+/// conversion pool, a fresh escrow is funded from the pool at its ratio and
+/// every amount in the stream's terms is converted alike. Needs both the
+/// sender and the recipient to sign.
+pub fn migrate_mint(program_id: &Pubkey, acc: MigrateMintAccounts) -> ProgramResult {
+    msg!("Migrating stream to a new mint");
+
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || acc.escrow_tokens.data_is_empty()
+        || acc.escrow_tokens.owner != &spl_token::id()
+        || acc.pool.data_is_empty()
+        || acc.pool.owner != program_id
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.new_escrow_tokens.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.sender.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+        || !acc.new_escrow_tokens.is_writable
+        || !acc.pool_old_tokens.is_writable
+        || !acc.pool_new_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if acc.token_program.key != &spl_token::id()
+        || acc.system_program.key != &system_program::id()
+        || acc.rent.key != &sysvar::rent::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer || !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+    let pool: ConversionPool = match solana_borsh::try_from_slice_unchecked(&acc.pool.data.borrow())
+    {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidMetadata.into()),
+    };
+
+    let old_mint = metadata.mint;
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    let (new_escrow_tokens_pubkey, new_nonce) = Pubkey::find_program_address(
+        &[acc.metadata.key.as_ref(), acc.new_mint.key.as_ref()],
+        program_id,
+    );
+    let (pool_pubkey, pool_nonce) = Pubkey::find_program_address(
+        &[
+            CONVERSION_POOL_SEED,
+            old_mint.as_ref(),
+            acc.new_mint.key.as_ref(),
+        ],
+        program_id,
+    );
+
+    if acc.sender.key != &metadata.sender
+        || acc.recipient.key != &metadata.recipient
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.new_escrow_tokens.key != &new_escrow_tokens_pubkey
+        || acc.pool.key != &pool_pubkey
+        || acc.new_mint.key != &pool.new_mint
+        || !metadata.has_address(program_id, acc.metadata.key)
+        || pool.old_mint != old_mint
+        || acc.pool_old_tokens.key != &get_associated_token_address(acc.pool.key, &old_mint)
+        || acc.pool_new_tokens.key != &get_associated_token_address(acc.pool.key, acc.new_mint.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if metadata.canceled_at > 0 || metadata.ix.managed_freeze || metadata.ix.bond_amount > 0 {
+        return Err(MigrationNotAllowed.into());
+    }
+
+    let escrowed = unpack_token_account(&acc.escrow_tokens)?.amount;
+    let convert = |amount| {
+        pool.ratio
+            .convert(amount)
+            .ok_or(ProgramError::InvalidArgument)
+    };
+    let converted = convert(escrowed)?;
+    let withdrawn_amount = convert(metadata.withdrawn_amount)?;
+    // Keep fully funded streams fully funded despite rounding.
+    let total_amount = if metadata.ix.deposited_amount == metadata.ix.total_amount {
+        withdrawn_amount + converted
+    } else {
+        convert(metadata.ix.total_amount)?
+    };
+    let cliff_amount = convert(metadata.ix.cliff_amount)?;
+    let release_rate = convert(metadata.ix.release_rate)?;
+    let min_claim_amount = convert(metadata.min_claim_amount)?;
+
+    if unpack_token_account(&acc.pool_new_tokens)?.amount < converted {
+        msg!(
+            "Error: Conversion pool is short of {} tokens",
+            pool.new_mint
+        );
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    if escrowed > 0 {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                acc.pool_old_tokens.key,
+                acc.escrow_tokens.key,
+                &[],
+                escrowed,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                acc.pool_old_tokens.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    invoke_signed(
+        &spl_token::instruction::close_account(
+            acc.token_program.key,
+            acc.escrow_tokens.key,
+            acc.sender.key,
+            acc.escrow_tokens.key,
+            &[],
+        )?,
+        &[
+            acc.escrow_tokens.clone(),
+            acc.sender.clone(),
+            acc.escrow_tokens.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let tokens_struct_size = spl_token::state::Account::LEN;
+    let new_seeds = [
+        acc.metadata.key.as_ref(),
+        acc.new_mint.key.as_ref(),
+        &[new_nonce],
+    ];
+    msg!("Creating account for holding {} tokens", pool.new_mint);
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.new_escrow_tokens.key,
+            Rent::get()?.minimum_balance(tokens_struct_size),
+            tokens_struct_size as u64,
+            &spl_token::id(),
+        ),
+        &[
+            acc.sender.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&new_seeds],
+    )?;
+
+    invoke(
+        &spl_token::instruction::initialize_account(
+            acc.token_program.key,
+            acc.new_escrow_tokens.key,
+            acc.new_mint.key,
+            acc.new_escrow_tokens.key,
+        )?,
+        &[
+            acc.token_program.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.new_mint.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.rent.clone(),
+        ],
+    )?;
+
+    if converted > 0 {
+        let pool_seeds = [
+            CONVERSION_POOL_SEED,
+            old_mint.as_ref(),
+            acc.new_mint.key.as_ref(),
+            &[pool_nonce],
+        ];
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                acc.token_program.key,
+                acc.pool_new_tokens.key,
+                acc.new_escrow_tokens.key,
+                acc.pool.key,
+                &[],
+                converted,
+            )?,
+            &[
+                acc.pool_new_tokens.clone(),
+                acc.new_escrow_tokens.clone(),
+                acc.pool.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&pool_seeds],
+        )?;
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    metadata.settle_halt(now);
+
+    metadata.withdrawn_amount = withdrawn_amount;
+    metadata.ix.deposited_amount = withdrawn_amount + converted;
+    metadata.ix.total_amount = total_amount;
+    metadata.ix.cliff_amount = cliff_amount;
+    metadata.ix.release_rate = release_rate;
+    metadata.min_claim_amount = min_claim_amount;
+
+    metadata.mint = pool.new_mint;
+    metadata.mint_migrated = true;
+    metadata.escrow_tokens = *acc.new_escrow_tokens.key;
+    metadata.sender_tokens = get_associated_token_address(&metadata.sender, &pool.new_mint);
+    metadata.recipient_tokens = get_associated_token_address(&metadata.recipient, &pool.new_mint);
+
+    metadata.cache_schedule();
+    if metadata.ix.deposited_amount < metadata.ix.total_amount || metadata.ix.release_rate > 0 {
+        metadata.closable_at = metadata.closable();
+    }
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    let new_mint_info = unpack_mint_account(&acc.new_mint)?;
+    msg!(
+        "Converted {} {} tokens into {} {} tokens",
+        escrowed,
+        old_mint,
+        encode_base10(converted, new_mint_info.decimals.into()),
+        metadata.mint
+    );
+    msg!("New escrow: {}", metadata.escrow_tokens);
+
+    notify(&metadata);
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! `StreamMirror` accounts: a small copy of what `available()` needs, for
+//! cranks polling many hot streams. Withdrawals of a mirrored stream take its
+//! mirror along and rewrite it in the same instruction.
+
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::AccountsNotWritable;
+use crate::state::{
+    InitStreamMirrorAccounts, StreamMirror, SyncMirrorAccounts, TokenStreamData,
+    STREAM_MIRROR_SEED, STREAM_MIRROR_SIZE,
+};
+
+pub fn initialize_stream_mirror(
+    program_id: &Pubkey,
+    acc: InitStreamMirrorAccounts,
+) -> ProgramResult {
+    msg!("Initializing stream mirror");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.mirror.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.sender.is_writable || !acc.metadata.is_writable || !acc.mirror.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let (mirror_pubkey, nonce) = StreamMirror::address(program_id, acc.metadata.key);
+    if acc.mirror.key != &mirror_pubkey
+        || acc.system_program.key != &system_program::id()
+        || acc.sender.key != &metadata.sender
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if !metadata.ix.tranches.is_empty() {
+        msg!("Error: Streams with tranches can't be mirrored");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let seeds = [STREAM_MIRROR_SEED, acc.metadata.key.as_ref(), &[nonce]];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.mirror.key,
+            Rent::get()?.minimum_balance(STREAM_MIRROR_SIZE),
+            STREAM_MIRROR_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.sender.clone(),
+            acc.mirror.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    metadata.has_mirror = true;
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    write_mirror(
+        &acc.mirror,
+        &StreamMirror::new(acc.metadata.key, &metadata, now),
+    )?;
+
+    msg!("Mirror of {}: {}", acc.metadata.key, acc.mirror.key);
+
+    Ok(())
+}
+
+/// Rewrites the mirror of a stream from its metadata. Anyone may call it, it
+/// only copies.
+pub fn sync_mirror(program_id: &Pubkey, acc: SyncMirrorAccounts) -> ProgramResult {
+    msg!("Syncing stream mirror");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let metadata = TokenStreamData::load(&acc.metadata.try_borrow_data()?)?;
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    update_mirror(
+        program_id,
+        Some(&acc.mirror),
+        acc.metadata.key,
+        &metadata,
+        now,
+    )
+}
+
+/// Brings the mirror of the stream at `key` in line with `stream`, as just
+/// written by the caller. A no-op for streams without a mirror, which pass
+/// none.
+pub(crate) fn update_mirror(
+    program_id: &Pubkey,
+    mirror: Option<&AccountInfo>,
+    key: &Pubkey,
+    stream: &TokenStreamData,
+    now: u64,
+) -> ProgramResult {
+    if !stream.has_mirror {
+        return Ok(());
+    }
+
+    let mirror = match mirror {
+        Some(v) => v,
+        None => {
+            msg!("Error: Stream is mirrored, its mirror account is required");
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+    };
+
+    if mirror.data_is_empty() || mirror.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !mirror.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let (mirror_pubkey, _) = StreamMirror::address(program_id, key);
+    if mirror.key != &mirror_pubkey {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    write_mirror(mirror, &StreamMirror::new(key, stream, now))
+}
+
+fn write_mirror(account: &AccountInfo, mirror: &StreamMirror) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    let mut rest = &mut data[..];
+    mirror.serialize(&mut rest)?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, ComplianceFrozen, NettingNotAllowed};
+use crate::state::{NetStreamsAccounts, TokenStreamData};
+use crate::token::notify;
+use crate::utils::{encode_base10, unpack_mint_account};
+
+/// Settles two streams running in opposite directions between the same
+/// parties. The amount both sides owe each other is offset without moving any
+/// tokens: it counts as withdrawn on each stream and stays in its escrow as a
+/// top-up. Only the difference is paid out, to the recipient of the stream
+/// that owes more.
+pub fn net_streams(program_id: &Pubkey, acc: NetStreamsAccounts) -> ProgramResult {
+    msg!("Netting counter-streams");
+
+    if acc.metadata_a.data_is_empty()
+        || acc.metadata_a.owner != program_id
+        || acc.metadata_b.data_is_empty()
+        || acc.metadata_b.owner != program_id
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata_a.is_writable
+        || !acc.escrow_tokens_a.is_writable
+        || !acc.metadata_b.is_writable
+        || !acc.escrow_tokens_b.is_writable
+        || !acc.creditor_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if acc.token_program.key != &spl_token::id() || acc.metadata_a.key == acc.metadata_b.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.party_a.is_signer || !acc.party_b.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data_a = acc.metadata_a.try_borrow_mut_data()?;
+    let mut a = TokenStreamData::load(&data_a)?;
+    let mut data_b = acc.metadata_b.try_borrow_mut_data()?;
+    let mut b = TokenStreamData::load(&data_b)?;
+
+    let escrow_seed_a = a.escrow_seed();
+    let (escrow_a_pubkey, nonce_a) =
+        Pubkey::find_program_address(&[acc.metadata_a.key.as_ref(), &escrow_seed_a], program_id);
+    let escrow_seed_b = b.escrow_seed();
+    let (escrow_b_pubkey, nonce_b) =
+        Pubkey::find_program_address(&[acc.metadata_b.key.as_ref(), &escrow_seed_b], program_id);
+
+    if acc.escrow_tokens_a.key != &escrow_a_pubkey
+        || acc.escrow_tokens_b.key != &escrow_b_pubkey
+        || acc.escrow_tokens_a.key != &a.escrow_tokens
+        || acc.escrow_tokens_b.key != &b.escrow_tokens
+        || acc.mint.key != &a.mint
+        || !a.has_address(program_id, acc.metadata_a.key)
+        || !b.has_address(program_id, acc.metadata_b.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if a.mint != b.mint
+        || a.sender != *acc.party_a.key
+        || a.recipient != *acc.party_b.key
+        || b.sender != *acc.party_b.key
+        || b.recipient != *acc.party_a.key
+        || a.canceled_at > 0
+        || b.canceled_at > 0
+        || a.ix.managed_freeze
+        || b.ix.managed_freeze
+        || !a.ix.shares.is_empty()
+        || !b.ix.shares.is_empty()
+        || a.ix.bond_amount > 0
+        || b.ix.bond_amount > 0
+    {
+        return Err(NettingNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if a.compliance_frozen(now) || b.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    let available_a = a.available(now);
+    let available_b = b.available(now);
+    let offset = available_a.min(available_b);
+
+    let (creditor_tokens, escrow_tokens, metadata_key, escrow_seed, nonce, difference) =
+        if available_a >= available_b {
+            (
+                a.recipient_tokens,
+                &acc.escrow_tokens_a,
+                acc.metadata_a.key,
+                &escrow_seed_a,
+                nonce_a,
+                available_a - offset,
+            )
+        } else {
+            (
+                b.recipient_tokens,
+                &acc.escrow_tokens_b,
+                acc.metadata_b.key,
+                &escrow_seed_b,
+                nonce_b,
+                available_b - offset,
+            )
+        };
+
+    if acc.creditor_tokens.key != &creditor_tokens {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    for (stream, available) in [(&mut a, available_a), (&mut b, available_b)] {
+        stream.settle_halt(now);
+        stream.withdrawn_amount += available;
+        stream.ix.deposited_amount += offset;
+        stream.last_withdrawn_at = now;
+        stream.cache_schedule();
+        stream.closable_at = stream.closable();
+        stream.bump_nonce();
+    }
+
+    if difference > 0 {
+        let seeds = [metadata_key.as_ref(), escrow_seed, &[nonce]];
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                acc.token_program.key,
+                escrow_tokens.key,
+                acc.creditor_tokens.key,
+                escrow_tokens.key,
+                &[],
+                difference,
+            )?,
+            &[
+                escrow_tokens.clone(),
+                acc.creditor_tokens.clone(),
+                escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    let bytes = a.try_to_vec()?;
+    data_a[0..bytes.len()].clone_from_slice(&bytes);
+    let bytes = b.try_to_vec()?;
+    data_b[0..bytes.len()].clone_from_slice(&bytes);
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    msg!(
+        "Netted: {} {} tokens",
+        encode_base10(offset, mint_info.decimals.into()),
+        a.mint
+    );
+    msg!(
+        "Settled difference: {} {} tokens to {}",
+        encode_base10(difference, mint_info.decimals.into()),
+        a.mint,
+        acc.creditor_tokens.key
+    );
+
+    notify(&a);
+    notify(&b);
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+//! Soulbound Token-2022 tokens mirroring a stream's liability in the sender's
+//! wallet, for accounting systems that track liabilities by token balances.
+//! Instructions are encoded by hand to avoid pulling in the Token-2022 crate
+//! for the handful used here.
+
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::state::OBLIGATION_SEED;
+pub use crate::utils::token_2022;
+
+/// Base mint padded to an account's length, the account type byte, and the
+/// `NonTransferable` (empty) and `PermanentDelegate` (32 bytes) extensions.
+pub const OBLIGATION_MINT_SIZE: usize = 165 + 1 + 4 + 4 + 32;
+/// Base account, the account type byte and the empty `NonTransferableAccount`
+/// extension.
+pub const OBLIGATION_TOKENS_SIZE: usize = 165 + 1 + 4;
+
+const INITIALIZE_ACCOUNT_3: u8 = 18;
+const INITIALIZE_MINT_2: u8 = 20;
+const INITIALIZE_NON_TRANSFERABLE_MINT: u8 = 32;
+const INITIALIZE_PERMANENT_DELEGATE: u8 = 35;
+const MINT_TO: u8 = 7;
+const BURN: u8 = 8;
+
+/// The obligation mint is a PDA of the stream and its own mint authority and
+/// permanent delegate, so the program can burn without the sender signing.
+pub fn find_obligation_mint(program_id: &Pubkey, metadata: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[OBLIGATION_SEED, metadata.as_ref()], program_id)
+}
+
+pub fn find_obligation_tokens(program_id: &Pubkey, obligation_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[OBLIGATION_SEED, obligation_mint.as_ref()], program_id)
+}
+
+fn check_accounts(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    obligation_mint: &AccountInfo,
+    obligation_tokens: &AccountInfo,
+    token_2022_program: &AccountInfo,
+) -> Result<(u8, u8), ProgramError> {
+    let (mint_pubkey, mint_nonce) = find_obligation_mint(program_id, metadata);
+    let (tokens_pubkey, tokens_nonce) = find_obligation_tokens(program_id, &mint_pubkey);
+
+    if obligation_mint.key != &mint_pubkey
+        || obligation_tokens.key != &tokens_pubkey
+        || token_2022_program.key != &token_2022::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !obligation_mint.is_writable || !obligation_tokens.is_writable {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok((mint_nonce, tokens_nonce))
+}
+
+/// Creates the stream's obligation mint and mints a single token of it to
+/// `sender`, with `payer` paying the rent.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn mint_obligation<'a>(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    sender: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    obligation_mint: &Option<AccountInfo<'a>>,
+    obligation_tokens: &Option<AccountInfo<'a>>,
+    token_2022_program: &Option<AccountInfo<'a>>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (obligation_mint, obligation_tokens, token_2022_program) =
+        match (obligation_mint, obligation_tokens, token_2022_program) {
+            (Some(m), Some(t), Some(p)) => (m, t, p),
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+    let (mint_nonce, tokens_nonce) = check_accounts(
+        program_id,
+        metadata,
+        obligation_mint,
+        obligation_tokens,
+        token_2022_program,
+    )?;
+
+    let rent = Rent::get()?;
+    let mint_seeds = [OBLIGATION_SEED, metadata.as_ref(), &[mint_nonce]];
+    let tokens_seeds = [
+        OBLIGATION_SEED,
+        obligation_mint.key.as_ref(),
+        &[tokens_nonce],
+    ];
+
+    msg!("Creating obligation mint {}", obligation_mint.key);
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            obligation_mint.key,
+            rent.minimum_balance(OBLIGATION_MINT_SIZE),
+            OBLIGATION_MINT_SIZE as u64,
+            &token_2022::id(),
+        ),
+        &[
+            payer.clone(),
+            obligation_mint.clone(),
+            system_program.clone(),
+        ],
+        &[&mint_seeds],
+    )?;
+
+    let mint_only = |data: Vec<u8>| Instruction {
+        program_id: token_2022::id(),
+        accounts: vec![AccountMeta::new(*obligation_mint.key, false)],
+        data,
+    };
+    let mint_accounts = [obligation_mint.clone(), token_2022_program.clone()];
+
+    invoke(
+        &mint_only(vec![INITIALIZE_NON_TRANSFERABLE_MINT]),
+        &mint_accounts,
+    )?;
+
+    let mut data = vec![INITIALIZE_PERMANENT_DELEGATE];
+    data.extend_from_slice(obligation_mint.key.as_ref());
+    invoke(&mint_only(data), &mint_accounts)?;
+
+    // Zero decimals, the mint itself as mint authority, no freeze authority.
+    let mut data = vec![INITIALIZE_MINT_2, 0];
+    data.extend_from_slice(obligation_mint.key.as_ref());
+    data.push(0);
+    invoke(&mint_only(data), &mint_accounts)?;
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            obligation_tokens.key,
+            rent.minimum_balance(OBLIGATION_TOKENS_SIZE),
+            OBLIGATION_TOKENS_SIZE as u64,
+            &token_2022::id(),
+        ),
+        &[
+            payer.clone(),
+            obligation_tokens.clone(),
+            system_program.clone(),
+        ],
+        &[&tokens_seeds],
+    )?;
+
+    let mut data = vec![INITIALIZE_ACCOUNT_3];
+    data.extend_from_slice(sender.key.as_ref());
+    invoke(
+        &Instruction {
+            program_id: token_2022::id(),
+            accounts: vec![
+                AccountMeta::new(*obligation_tokens.key, false),
+                AccountMeta::new_readonly(*obligation_mint.key, false),
+            ],
+            data,
+        },
+        &[
+            obligation_tokens.clone(),
+            obligation_mint.clone(),
+            token_2022_program.clone(),
+        ],
+    )?;
+
+    let mut data = vec![MINT_TO];
+    data.extend_from_slice(&1u64.to_le_bytes());
+    invoke_signed(
+        &Instruction {
+            program_id: token_2022::id(),
+            accounts: vec![
+                AccountMeta::new(*obligation_mint.key, false),
+                AccountMeta::new(*obligation_tokens.key, false),
+                AccountMeta::new_readonly(*obligation_mint.key, true),
+            ],
+            data,
+        },
+        &[
+            obligation_mint.clone(),
+            obligation_tokens.clone(),
+            token_2022_program.clone(),
+        ],
+        &[&mint_seeds],
+    )?;
+
+    msg!("Obligation token held in {}", obligation_tokens.key);
+
+    Ok(())
+}
+
+/// Burns the sender's obligation token once the stream is settled.
+pub(crate) fn burn_obligation<'a>(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    obligation_mint: &Option<AccountInfo<'a>>,
+    obligation_tokens: &Option<AccountInfo<'a>>,
+    token_2022_program: &Option<AccountInfo<'a>>,
+) -> ProgramResult {
+    let (obligation_mint, obligation_tokens, token_2022_program) =
+        match (obligation_mint, obligation_tokens, token_2022_program) {
+            (Some(m), Some(t), Some(p)) => (m, t, p),
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+    let (mint_nonce, _) = check_accounts(
+        program_id,
+        metadata,
+        obligation_mint,
+        obligation_tokens,
+        token_2022_program,
+    )?;
+
+    let mut data = vec![BURN];
+    data.extend_from_slice(&1u64.to_le_bytes());
+    invoke_signed(
+        &Instruction {
+            program_id: token_2022::id(),
+            accounts: vec![
+                AccountMeta::new(*obligation_tokens.key, false),
+                AccountMeta::new(*obligation_mint.key, false),
+                AccountMeta::new_readonly(*obligation_mint.key, true),
+            ],
+            data,
+        },
+        &[
+            obligation_tokens.clone(),
+            obligation_mint.clone(),
+            token_2022_program.clone(),
+        ],
+        &[&[OBLIGATION_SEED, metadata.as_ref(), &[mint_nonce]]],
+    )?;
+
+    msg!("Burned obligation token in {}", obligation_tokens.key);
+
+    Ok(())
+}
+
+/// Fails early, before any tokens move, if a settling instruction lacks the
+/// accounts `burn_obligation` will need.
+pub(crate) fn require_obligation_accounts(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    obligation_mint: &Option<AccountInfo>,
+    obligation_tokens: &Option<AccountInfo>,
+    token_2022_program: &Option<AccountInfo>,
+) -> ProgramResult {
+    match (obligation_mint, obligation_tokens, token_2022_program) {
+        (Some(m), Some(t), Some(p)) => check_accounts(program_id, metadata, m, t, p).map(|_| ()),
+        _ => Err(ProgramError::NotEnoughAccountKeys),
+    }
+}
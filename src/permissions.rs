@@ -0,0 +1,210 @@
+//! Who may call each instruction, as data, so wallets can render accurate
+//! permission prompts. Mirrors the signer checks in the handlers; keep it in
+//! sync when adding instructions or changing who signs.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Sender,
+    Recipient,
+    /// The stream's `compliance_authority`.
+    ComplianceAuthority,
+    /// Whoever paid the stream's last top-up.
+    TopUpPayer,
+    /// A member of the stream's `TopUpAllowlist`.
+    TopUpAllowlisted,
+    /// The recipient proposed by `propose_transfer`.
+    PendingRecipient,
+    /// Mint authority of the mint a conversion pool converts into.
+    MintAuthority,
+    /// The program's upgrade authority, or the admin it appointed.
+    Admin,
+    Anyone,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    Always,
+    /// The named `StreamInstruction` flag is set.
+    Flag(&'static str),
+    /// The named `StreamInstruction` field is non-zero.
+    NonZero(&'static str),
+    /// The stream is past `closable_at`.
+    Closable,
+    /// The named `StreamInstruction` policy is set to the named variant.
+    Policy(&'static str, &'static str),
+}
+
+/// One way of calling an instruction: every role in `signers` signs, under
+/// `condition`. Instructions callable in several ways have several entries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Permission {
+    pub tag: u8,
+    pub instruction: &'static str,
+    pub signers: &'static [Role],
+    pub condition: Condition,
+}
+
+const fn allow(
+    tag: u8,
+    instruction: &'static str,
+    signers: &'static [Role],
+    condition: Condition,
+) -> Permission {
+    Permission {
+        tag,
+        instruction,
+        signers,
+        condition,
+    }
+}
+
+use Condition::{Always, Closable, Flag, NonZero, Policy};
+use Role::*;
+
+pub const PERMISSIONS: &[Permission] = &[
+    allow(0, "create", &[Sender], Always),
+    allow(1, "withdraw", &[Recipient], Always),
+    allow(1, "withdraw", &[Anyone], Flag("withdrawal_public")),
+    allow(2, "cancel", &[Sender], Flag("cancelable_by_sender")),
+    allow(2, "cancel", &[Recipient], Flag("cancelable_by_recipient")),
+    allow(2, "cancel", &[Anyone], Closable),
+    allow(
+        3,
+        "transfer_recipient",
+        &[Recipient],
+        Flag("transferable_by_recipient"),
+    ),
+    allow(
+        3,
+        "transfer_recipient",
+        &[Sender],
+        Flag("transferable_by_sender"),
+    ),
+    allow(4, "topup_stream", &[Sender], Always),
+    allow(
+        4,
+        "topup_stream",
+        &[TopUpAllowlisted],
+        Policy("can_topup", "Allowlist"),
+    ),
+    allow(4, "topup_stream", &[Anyone], Policy("can_topup", "Anyone")),
+    allow(5, "pause_by_recipient", &[Recipient], Always),
+    allow(6, "resume_by_recipient", &[Recipient], Always),
+    allow(7, "cancel_batch", &[Sender], Always),
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    allow(8, "initialize_fee_exemptions", &[Admin], Always),
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    allow(9, "update_fee_exemptions", &[Admin], Always),
+    allow(10, "create_ledger", &[Sender], Always),
+    allow(11, "add_ledger_grant", &[Sender], Always),
+    allow(12, "withdraw_ledger", &[Recipient], Always),
+    allow(
+        13,
+        "cancel_ledger_grant",
+        &[Sender],
+        Flag("cancelable_by_sender"),
+    ),
+    allow(14, "set_min_claim", &[Recipient], Always),
+    allow(15, "preview", &[Anyone], Always),
+    allow(16, "net_streams", &[Sender, Recipient], Always),
+    allow(17, "init_conversion_pool", &[MintAuthority], Always),
+    allow(18, "migrate_mint", &[Sender, Recipient], Always),
+    #[cfg(feature = "devnet-faucet")]
+    allow(19, "faucet_stream", &[Anyone], Always),
+    allow(20, "escrow_proof", &[Anyone], Always),
+    allow(21, "compliance_freeze", &[ComplianceAuthority], Always),
+    allow(22, "mint_summary", &[Anyone], Always),
+    allow(23, "undo_topup", &[TopUpPayer], Always),
+    allow(24, "rollover", &[Sender], Always),
+    allow(25, "set_forward", &[Recipient], Always),
+    allow(26, "clear_forward", &[Recipient], Always),
+    allow(27, "prewarm", &[Anyone], Always),
+    allow(28, "pause_stream", &[Sender], Always),
+    allow(29, "resume_stream", &[Sender], Always),
+    allow(
+        30,
+        "crank_withdraw",
+        &[Anyone],
+        NonZero("withdraw_frequency"),
+    ),
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    allow(31, "initialize_fee_config", &[Admin], Always),
+    #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+    allow(32, "update_fee_config", &[Admin], Always),
+    allow(33, "refresh_closable", &[Anyone], Always),
+    allow(
+        34,
+        "split_stream",
+        &[Recipient],
+        Flag("transferable_by_recipient"),
+    ),
+    allow(
+        34,
+        "split_stream",
+        &[Sender],
+        Flag("transferable_by_sender"),
+    ),
+    allow(
+        35,
+        "propose_transfer",
+        &[Recipient],
+        Flag("transferable_by_recipient"),
+    ),
+    allow(
+        35,
+        "propose_transfer",
+        &[Sender],
+        Flag("transferable_by_sender"),
+    ),
+    allow(36, "accept_transfer", &[PendingRecipient], Always),
+    allow(
+        37,
+        "cancel_transfer",
+        &[Recipient],
+        Flag("transferable_by_recipient"),
+    ),
+    allow(
+        37,
+        "cancel_transfer",
+        &[Sender],
+        Flag("transferable_by_sender"),
+    ),
+    // Changes unfavorable to the recipient need both.
+    allow(38, "update_stream", &[Sender], Always),
+    allow(38, "update_stream", &[Sender, Recipient], Always),
+    allow(39, "vesting_snapshot", &[Anyone], Always),
+    allow(40, "initialize_topup_allowlist", &[Sender], Always),
+    allow(41, "update_topup_allowlist", &[Sender], Always),
+    allow(42, "withdraw_surplus", &[Sender], Always),
+    allow(43, "close_stream", &[Sender], Always),
+    allow(44, "init_stream_index", &[Anyone], Always),
+    allow(45, "archive_stream", &[Anyone], Always),
+    allow(46, "upgrade_v1_to_v2", &[Sender], Always),
+    #[cfg(not(feature = "no-admin"))]
+    allow(46, "upgrade_v1_to_v2", &[Admin], Always),
+    allow(47, "migrate_stream", &[Sender], Always),
+    #[cfg(not(feature = "no-admin"))]
+    allow(47, "migrate_stream", &[Admin], Always),
+    allow(48, "create_many", &[Sender], Always),
+    allow(49, "init_stream_mirror", &[Sender], Always),
+    allow(50, "sync_mirror", &[Anyone], Always),
+    allow(51, "cancel_subscription", &[Sender], Flag("subscription")),
+    allow(
+        51,
+        "cancel_subscription",
+        &[Recipient],
+        Flag("subscription"),
+    ),
+    allow(52, "create_from_program", &[Sender], Always),
+    allow(53, "verify_stream", &[Anyone], Always),
+    allow(54, "withdraw_to", &[Recipient], Always),
+    allow(55, "withdraw_shares", &[Recipient], Always),
+    allow(55, "withdraw_shares", &[Sender], Always),
+    allow(55, "withdraw_shares", &[Anyone], Flag("withdrawal_public")),
+];
+
+/// Every way of calling the instruction with `tag`; empty if this build
+/// doesn't have it.
+pub fn permissions(tag: u8) -> impl Iterator<Item = &'static Permission> {
+    PERMISSIONS.iter().filter(move |p| p.tag == tag)
+}
@@ -0,0 +1,94 @@
+//! Turns a simulated create or withdraw into issues front-ends and bots can
+//! act on before submitting. Simulating is left to the caller's RPC client;
+//! only its outcome and logs are needed here.
+
+use crate::error::StreamFlowError;
+use crate::state::TokenStreamData;
+
+/// Outcome of a transaction simulation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Simulation {
+    pub failed: bool,
+    pub logs: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreflightIssue {
+    /// The payer can't cover rent of the accounts being created.
+    MissingRent,
+    /// The sender holds fewer tokens than the deposit.
+    InsufficientTokens,
+    /// The token account being paid into doesn't exist, see `prewarm`.
+    TokenAccountMissing,
+    /// Nothing vests until `cliff`.
+    CliffNotReached { cliff: u64 },
+    /// Nothing vested is left to withdraw right now.
+    NothingAvailable,
+    /// Any other error of this program.
+    Program(StreamFlowError),
+    /// A failure the logs don't explain, with the last line logged.
+    Unknown(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreflightReport {
+    pub issues: Vec<PreflightIssue>,
+}
+
+impl PreflightReport {
+    /// Reads issues off `simulation`'s logs. For withdrawals, pass the stream
+    /// and the time simulated at to tell a pending cliff from a drained one.
+    pub fn new(simulation: &Simulation, stream: Option<&TokenStreamData>, now: u64) -> Self {
+        let mut issues = vec![];
+        if !simulation.failed {
+            return PreflightReport { issues };
+        }
+
+        for log in &simulation.logs {
+            let message = log.strip_prefix("Program log: ").unwrap_or(log);
+            let issue = if message.starts_with("Error: Insufficient funds in") {
+                PreflightIssue::MissingRent
+            } else if message.starts_with("Error: Insufficient tokens") {
+                PreflightIssue::InsufficientTokens
+            } else if message.starts_with("Error: Token account") {
+                PreflightIssue::TokenAccountMissing
+            } else if message.contains("more than what is available") {
+                PreflightIssue::NothingAvailable
+            } else {
+                match StreamFlowError::ALL
+                    .iter()
+                    .find(|e| e.to_string() == message)
+                {
+                    Some(StreamFlowError::NothingToWithdraw) => PreflightIssue::NothingAvailable,
+                    Some(e) => PreflightIssue::Program(*e),
+                    None => continue,
+                }
+            };
+
+            let issue = match (issue, stream) {
+                (PreflightIssue::NothingAvailable, Some(s))
+                    if s.effective_now(now) < s.effective_cliff =>
+                {
+                    PreflightIssue::CliffNotReached {
+                        cliff: s.effective_cliff,
+                    }
+                }
+                (issue, _) => issue,
+            };
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+
+        if issues.is_empty() {
+            let last = simulation.logs.last().cloned().unwrap_or_default();
+            issues.push(PreflightIssue::Unknown(last));
+        }
+
+        PreflightReport { issues }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
@@ -0,0 +1,2625 @@
+use std::slice::Iter;
+
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::archive::archive_stream;
+#[cfg(feature = "devnet-faucet")]
+use crate::faucet::faucet_stream;
+#[cfg(all(feature = "fees", not(feature = "no-admin")))]
+use crate::fees::{
+    initialize_fee_config, initialize_fee_exemptions, update_fee_config, update_fee_exemptions,
+};
+use crate::instruction::VestingInstruction;
+use crate::ledger::{add_ledger_grant, cancel_ledger_grant, create_ledger, withdraw_ledger};
+use crate::migrations::{migrate_stream, upgrade_v1_to_v2};
+use crate::mint_migration::{init_conversion_pool, migrate_mint};
+use crate::mirror::{initialize_stream_mirror, sync_mirror};
+use crate::netting::net_streams;
+use crate::registry::initialize_stream_index;
+use crate::rollover::rollover;
+use crate::split::split_stream;
+#[cfg(feature = "devnet-faucet")]
+use crate::state::FaucetAccounts;
+use crate::state::{
+    AcceptTransferAccounts, AddLedgerGrantAccounts, ArchiveStreamAccounts, CancelAccounts,
+    CancelBatchAccounts, CancelLedgerGrantAccounts, CancelSubscriptionAccounts,
+    CancelTransferAccounts, CloseStreamAccounts, ComplianceFreezeAccounts, CrankWithdrawAccounts,
+    CreateManyAccounts, EscrowProofAccounts, FeeAccounts, InitConversionPoolAccounts,
+    InitStreamIndexAccounts, InitStreamMirrorAccounts, InitTopUpAllowlistAccounts,
+    InitializeAccounts, MigrateMintAccounts, MigrateStreamAccounts, MintSummaryAccounts,
+    NetStreamsAccounts, PreviewAccounts, PrewarmAccounts, ProposeTransferAccounts,
+    RecipientAccounts, RefreshClosableAccounts, RolloverAccounts, SenderAccounts,
+    SetForwardAccounts, SnapshotAccounts, SplitAccounts, StreamInstruction, SyncMirrorAccounts,
+    TopUpAccounts, TransferAccounts, UndoTopUpAccounts, UpdateStreamAccounts,
+    UpdateTopUpAllowlistAccounts, VerifyStreamAccounts, WithdrawAccounts, WithdrawLedgerAccounts,
+    WithdrawSurplusAccounts,
+};
+#[cfg(all(feature = "fees", not(feature = "no-admin")))]
+use crate::state::{
+    InitFeeConfigAccounts, InitFeeExemptionsAccounts, UpdateFeeConfigAccounts,
+    UpdateFeeExemptionsAccounts,
+};
+use crate::token::{
+    accept_transfer, cancel, cancel_batch, cancel_subscription, cancel_transfer, clear_forward,
+    close_stream, compliance_freeze, crank_withdraw, create, create_from_program, create_many,
+    escrow_proof, mint_summary, pause_by_recipient, pause_stream, preview, prewarm,
+    propose_transfer, refresh_closable, resume_by_recipient, resume_stream, set_forward,
+    set_min_claim, topup_stream, transfer_recipient, undo_topup, vesting_snapshot, withdraw,
+    withdraw_shares, withdraw_surplus, withdraw_to,
+};
+use crate::topup_allowlist::{initialize_topup_allowlist, update_topup_allowlist};
+use crate::update::update_stream;
+use crate::utils::{optional_slot, MockClock, SysvarClock};
+use crate::verify::verify_stream;
+
+pub fn process_instruction(pid: &Pubkey, acc: &[AccountInfo], ix: &[u8]) -> ProgramResult {
+    let ai = &mut acc.iter();
+
+    match VestingInstruction::unpack(ix)? {
+        VestingInstruction::Create(si) => {
+            let ia = initialize_accounts(pid, ai, &si, None)?;
+            create(pid, ia, si)
+        }
+        VestingInstruction::Withdraw { amount, salt } => {
+            let wa = withdraw_accounts(pid, ai, false, 0)?;
+            withdraw(pid, wa, amount, salt)
+        }
+
+        VestingInstruction::Cancel => {
+            let ca = cancel_accounts(pid, ai)?;
+            cancel(pid, ca)
+        }
+        VestingInstruction::TransferRecipient => {
+            let ta = TransferAccounts {
+                authorized_wallet: next_account_info(ai)?.clone(),
+                new_recipient: next_account_info(ai)?.clone(),
+                new_recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                indexes: program_owned(pid, ai),
+            };
+
+            transfer_recipient(pid, ta)
+        }
+        VestingInstruction::TopUp { amount } => {
+            let ta = TopUpAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                allowlist: match ai.as_slice().first() {
+                    Some(a) if a.owner == pid => ai.next().cloned(),
+                    _ => None,
+                },
+                multisig: next_account_info(ai).ok().cloned(),
+                multisig_signers: ai.cloned().collect(),
+            };
+
+            topup_stream(pid, ta, amount)
+        }
+        VestingInstruction::PauseByRecipient => {
+            let pa = RecipientAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            pause_by_recipient(pid, pa)
+        }
+        VestingInstruction::ResumeByRecipient => {
+            let pa = RecipientAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            resume_by_recipient(pid, pa)
+        }
+        VestingInstruction::CancelBatch => {
+            let ca = CancelBatchAccounts {
+                cancel_authority: next_account_info(ai)?.clone(),
+                sender: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                fee_config: next_account_info(ai)?.clone(),
+                fee_exemptions: optional_slot(pid, next_account_info(ai)?),
+                streams: ai.cloned().collect(),
+            };
+
+            cancel_batch(pid, ca)
+        }
+        #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+        VestingInstruction::InitFeeExemptions => {
+            let fa = InitFeeExemptionsAccounts {
+                admin: next_account_info(ai)?.clone(),
+                exemptions: next_account_info(ai)?.clone(),
+                program_data: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            initialize_fee_exemptions(pid, fa)
+        }
+        #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+        VestingInstruction::UpdateFeeExemptions(update) => {
+            let fa = UpdateFeeExemptionsAccounts {
+                admin: next_account_info(ai)?.clone(),
+                exemptions: next_account_info(ai)?.clone(),
+            };
+
+            update_fee_exemptions(pid, fa, update)
+        }
+        VestingInstruction::CreateLedger(li) => {
+            let ia = InitializeAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                stream_counter: None,
+                freeze_authority: None,
+                freeze_issuer: None,
+                obligation_mint: None,
+                obligation_tokens: None,
+                token_2022_program: None,
+                indexes: vec![],
+                multisig: next_account_info(ai).ok().cloned(),
+                multisig_signers: ai.cloned().collect(),
+                payer: None,
+            };
+
+            create_ledger(pid, ia, li)
+        }
+        VestingInstruction::AddLedgerGrant(si) => {
+            let la = AddLedgerGrantAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                multisig: next_account_info(ai).ok().cloned(),
+                multisig_signers: ai.cloned().collect(),
+            };
+
+            add_ledger_grant(pid, la, si)
+        }
+        VestingInstruction::WithdrawLedger { amount } => {
+            let la = WithdrawLedgerAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+
+            withdraw_ledger(pid, la, amount)
+        }
+        VestingInstruction::CancelLedgerGrant { index } => {
+            let la = CancelLedgerGrantAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+            cancel_ledger_grant(pid, la, index)
+        }
+        VestingInstruction::SetMinClaim { amount } => {
+            let ra = RecipientAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            set_min_claim(pid, ra, amount)
+        }
+        VestingInstruction::Preview { at } => {
+            let pa = PreviewAccounts {
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            match at {
+                Some(at) => preview(pid, pa, &MockClock(at)),
+                None => preview(pid, pa, &SysvarClock),
+            }
+        }
+        VestingInstruction::NetStreams => {
+            let na = NetStreamsAccounts {
+                party_a: next_account_info(ai)?.clone(),
+                party_b: next_account_info(ai)?.clone(),
+                metadata_a: next_account_info(ai)?.clone(),
+                escrow_tokens_a: next_account_info(ai)?.clone(),
+                metadata_b: next_account_info(ai)?.clone(),
+                escrow_tokens_b: next_account_info(ai)?.clone(),
+                creditor_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+
+            net_streams(pid, na)
+        }
+        VestingInstruction::InitConversionPool(ratio) => {
+            let pa = InitConversionPoolAccounts {
+                authority: next_account_info(ai)?.clone(),
+                pool: next_account_info(ai)?.clone(),
+                old_mint: next_account_info(ai)?.clone(),
+                new_mint: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            init_conversion_pool(pid, pa, ratio)
+        }
+        VestingInstruction::MigrateMint => {
+            let ma = MigrateMintAccounts {
+                sender: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                new_escrow_tokens: next_account_info(ai)?.clone(),
+                pool: next_account_info(ai)?.clone(),
+                pool_old_tokens: next_account_info(ai)?.clone(),
+                pool_new_tokens: next_account_info(ai)?.clone(),
+                new_mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            migrate_mint(pid, ma)
+        }
+        #[cfg(feature = "devnet-faucet")]
+        VestingInstruction::Faucet => {
+            let fa = FaucetAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                faucet_authority: next_account_info(ai)?.clone(),
+                stream_counter: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            faucet_stream(pid, fa)
+        }
+        VestingInstruction::EscrowProof => {
+            let pa = EscrowProofAccounts {
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+            };
+
+            escrow_proof(pid, pa)
+        }
+        VestingInstruction::ComplianceFreeze { frozen_until } => {
+            let ca = ComplianceFreezeAccounts {
+                compliance_authority: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+            compliance_freeze(pid, ca, frozen_until)
+        }
+        VestingInstruction::MintSummary => {
+            let ma = MintSummaryAccounts {
+                mint: next_account_info(ai)?.clone(),
+                streams: ai.cloned().collect(),
+            };
+
+            mint_summary(pid, ma)
+        }
+        VestingInstruction::UndoTopUp => {
+            let ua = UndoTopUpAccounts {
+                payer: next_account_info(ai)?.clone(),
+                payer_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+
+            undo_topup(pid, ua)
+        }
+        VestingInstruction::Rollover(si) => {
+            let ra = RolloverAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                old_metadata: next_account_info(ai)?.clone(),
+                old_escrow_tokens: next_account_info(ai)?.clone(),
+                old_recipient_tokens: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                stream_counter: next_account_info(ai)?.clone(),
+            };
+
+            rollover(pid, ra, si)
+        }
+        VestingInstruction::SetForward => {
+            let fa = SetForwardAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                forward_tokens: next_account_info(ai)?.clone(),
+            };
+
+            set_forward(pid, fa)
+        }
+        VestingInstruction::ClearForward => {
+            let ra = RecipientAccounts {
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            clear_forward(pid, ra)
+        }
+        VestingInstruction::Prewarm => {
+            let pa = PrewarmAccounts {
+                payer: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            prewarm(pid, pa)
+        }
+        VestingInstruction::PauseStream => {
+            let sa = SenderAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            pause_stream(pid, sa)
+        }
+        VestingInstruction::ResumeStream => {
+            let sa = SenderAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            resume_stream(pid, sa)
+        }
+        VestingInstruction::CrankWithdraw => {
+            let keeper = next_account_info(ai)?.clone();
+            let ca = CrankWithdrawAccounts {
+                keeper_tokens: next_account_info(ai)?.clone(),
+                withdraw: authorized_withdraw_accounts(pid, keeper, ai, false, 0)?,
+            };
+
+            crank_withdraw(pid, ca)
+        }
+        #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+        VestingInstruction::InitFeeConfig(update) => {
+            let fa = InitFeeConfigAccounts {
+                admin: next_account_info(ai)?.clone(),
+                config: next_account_info(ai)?.clone(),
+                program_data: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            initialize_fee_config(pid, fa, update)
+        }
+        #[cfg(all(feature = "fees", not(feature = "no-admin")))]
+        VestingInstruction::UpdateFeeConfig(update) => {
+            let fa = UpdateFeeConfigAccounts {
+                admin: next_account_info(ai)?.clone(),
+                config: next_account_info(ai)?.clone(),
+            };
+
+            update_fee_config(pid, fa, update)
+        }
+        VestingInstruction::RefreshClosable => {
+            let ra = RefreshClosableAccounts {
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            refresh_closable(pid, ra)
+        }
+        VestingInstruction::SplitStream(split) => {
+            let sa = SplitAccounts {
+                authority: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                new_recipient: next_account_info(ai)?.clone(),
+                new_recipient_tokens: next_account_info(ai)?.clone(),
+                new_metadata: next_account_info(ai)?.clone(),
+                new_escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                stream_counter: next_account_info(ai)?.clone(),
+            };
+
+            split_stream(pid, sa, split)
+        }
+        VestingInstruction::ProposeTransfer => {
+            let pa = ProposeTransferAccounts {
+                authorized_wallet: next_account_info(ai)?.clone(),
+                new_recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            propose_transfer(pid, pa)
+        }
+        VestingInstruction::AcceptTransfer => {
+            let aa = AcceptTransferAccounts {
+                new_recipient: next_account_info(ai)?.clone(),
+                new_recipient_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                indexes: program_owned(pid, ai),
+            };
+
+            accept_transfer(pid, aa)
+        }
+        VestingInstruction::CancelTransfer => {
+            let ca = CancelTransferAccounts {
+                authorized_wallet: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            cancel_transfer(pid, ca)
+        }
+        VestingInstruction::UpdateStream(update) => {
+            let ua = UpdateStreamAccounts {
+                sender: next_account_info(ai)?.clone(),
+                recipient: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            update_stream(pid, ua, update)
+        }
+        VestingInstruction::VestingSnapshot { at } => {
+            let sa = SnapshotAccounts {
+                metadata: next_account_info(ai)?.clone(),
+            };
+
+            vesting_snapshot(pid, sa, at)
+        }
+        VestingInstruction::InitTopUpAllowlist => {
+            let ia = InitTopUpAllowlistAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                allowlist: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            initialize_topup_allowlist(pid, ia)
+        }
+        VestingInstruction::UpdateTopUpAllowlist(update) => {
+            let ua = UpdateTopUpAllowlistAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                allowlist: next_account_info(ai)?.clone(),
+            };
+
+            update_topup_allowlist(pid, ua, update)
+        }
+        VestingInstruction::WithdrawSurplus => {
+            let wa = WithdrawSurplusAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+
+            withdraw_surplus(pid, wa)
+        }
+        VestingInstruction::CloseStream => {
+            let ca = CloseStreamAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                indexes: program_owned(pid, ai),
+            };
+
+            close_stream(pid, ca)
+        }
+        VestingInstruction::InitStreamIndex(key) => {
+            let ia = InitStreamIndexAccounts {
+                payer: next_account_info(ai)?.clone(),
+                owner: next_account_info(ai)?.clone(),
+                index: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            initialize_stream_index(pid, ia, key)
+        }
+        VestingInstruction::ArchiveStream => {
+            let aa = ArchiveStreamAccounts {
+                payer: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                settlement: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            archive_stream(pid, aa)
+        }
+        VestingInstruction::UpgradeV1ToV2 => {
+            let ua = MigrateStreamAccounts {
+                authority: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                program_data: next_account_info(ai).ok().cloned(),
+            };
+
+            upgrade_v1_to_v2(pid, ua)
+        }
+        VestingInstruction::MigrateStream => {
+            let ma = MigrateStreamAccounts {
+                authority: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                program_data: next_account_info(ai).ok().cloned(),
+            };
+
+            migrate_stream(pid, ma)
+        }
+        VestingInstruction::CreateMany(si) => {
+            let ca = CreateManyAccounts {
+                sender: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                rent: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+                associated_token_program: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+                stream_counter: next_account_info(ai)?.clone(),
+                streams: ai.cloned().collect(),
+            };
+
+            create_many(pid, ca, si)
+        }
+        VestingInstruction::InitStreamMirror => {
+            let ma = InitStreamMirrorAccounts {
+                sender: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                mirror: next_account_info(ai)?.clone(),
+                system_program: next_account_info(ai)?.clone(),
+            };
+
+            initialize_stream_mirror(pid, ma)
+        }
+        VestingInstruction::SyncMirror => {
+            let sa = SyncMirrorAccounts {
+                metadata: next_account_info(ai)?.clone(),
+                mirror: next_account_info(ai)?.clone(),
+            };
+
+            sync_mirror(pid, sa)
+        }
+        VestingInstruction::CancelSubscription => {
+            let ca = CancelSubscriptionAccounts {
+                authority: next_account_info(ai)?.clone(),
+                sender_tokens: next_account_info(ai)?.clone(),
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+                token_program: next_account_info(ai)?.clone(),
+            };
+
+            cancel_subscription(pid, ca)
+        }
+        VestingInstruction::CreateFromProgram(ci) => {
+            let payer = next_account_info(ai)?.clone();
+            let ia = initialize_accounts(pid, ai, &ci.stream, Some(payer))?;
+            create_from_program(pid, ia, ci)
+        }
+        VestingInstruction::VerifyStream => {
+            let va = VerifyStreamAccounts {
+                metadata: next_account_info(ai)?.clone(),
+                escrow_tokens: next_account_info(ai)?.clone(),
+                mint: next_account_info(ai)?.clone(),
+            };
+
+            verify_stream(pid, va)
+        }
+        VestingInstruction::WithdrawTo { amount } => {
+            let wa = withdraw_accounts(pid, ai, true, 0)?;
+            withdraw_to(pid, wa, amount)
+        }
+        VestingInstruction::WithdrawShares { amount, shares } => {
+            let wa = withdraw_accounts(pid, ai, false, shares.into())?;
+            withdraw_shares(pid, wa, amount)
+        }
+    }
+}
+
+/// The accounts of `withdraw`; `withdraw_to` has its destination after
+/// `token_program`, `withdraw_shares` the `shares` co-recipients' token
+/// accounts. The fee config comes next, then the optional accounts in fixed
+/// slots, see `OptionalWithdrawAccounts`.
+fn withdraw_accounts<'a>(
+    pid: &Pubkey,
+    ai: &mut Iter<AccountInfo<'a>>,
+    with_destination: bool,
+    shares: usize,
+) -> Result<WithdrawAccounts<'a>, ProgramError> {
+    let withdraw_authority = next_account_info(ai)?.clone();
+    authorized_withdraw_accounts(pid, withdraw_authority, ai, with_destination, shares)
+}
+
+/// `withdraw_accounts` after the withdraw authority, which `crank_withdraw`
+/// passes ahead of its own accounts.
+fn authorized_withdraw_accounts<'a>(
+    pid: &Pubkey,
+    withdraw_authority: AccountInfo<'a>,
+    ai: &mut Iter<AccountInfo<'a>>,
+    with_destination: bool,
+    shares: usize,
+) -> Result<WithdrawAccounts<'a>, ProgramError> {
+    Ok(WithdrawAccounts {
+        withdraw_authority,
+        sender: next_account_info(ai)?.clone(),
+        recipient: next_account_info(ai)?.clone(),
+        recipient_tokens: next_account_info(ai)?.clone(),
+        metadata: next_account_info(ai)?.clone(),
+        escrow_tokens: next_account_info(ai)?.clone(),
+        mint: next_account_info(ai)?.clone(),
+        token_program: next_account_info(ai)?.clone(),
+        destination_tokens: if with_destination {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        share_tokens: (0..shares)
+            .map(|_| next_account_info(ai).cloned())
+            .collect::<Result<_, _>>()?,
+        // Struct fields are evaluated in order, which is the slot order.
+        fees: FeeAccounts {
+            config: next_account_info(ai)?.clone(),
+            treasury_tokens: next_optional(pid, ai),
+            exemptions: next_optional(pid, ai),
+            partner_tokens: next_optional(pid, ai),
+        },
+        freeze_authority: next_optional(pid, ai),
+        freeze_signer: next_optional(pid, ai),
+        obligation_mint: next_optional(pid, ai),
+        obligation_tokens: next_optional(pid, ai),
+        token_2022_program: next_optional(pid, ai),
+        forward_tokens: next_optional(pid, ai),
+        hold_tokens: next_optional(pid, ai),
+        mirror: next_optional(pid, ai),
+        lp_pool: next_optional(pid, ai),
+        lp_reserve_a: next_optional(pid, ai),
+        lp_reserve_b: next_optional(pid, ai),
+    })
+}
+
+/// The accounts of `cancel`: the fee config follows `token_program`, then
+/// the optional accounts in fixed slots, see `OptionalCancelAccounts`.
+fn cancel_accounts<'a>(
+    pid: &Pubkey,
+    ai: &mut Iter<AccountInfo<'a>>,
+) -> Result<CancelAccounts<'a>, ProgramError> {
+    Ok(CancelAccounts {
+        cancel_authority: next_account_info(ai)?.clone(),
+        sender: next_account_info(ai)?.clone(),
+        sender_tokens: next_account_info(ai)?.clone(),
+        recipient: next_account_info(ai)?.clone(),
+        recipient_tokens: next_account_info(ai)?.clone(),
+        metadata: next_account_info(ai)?.clone(),
+        escrow_tokens: next_account_info(ai)?.clone(),
+        mint: next_account_info(ai)?.clone(),
+        token_program: next_account_info(ai)?.clone(),
+        fees: FeeAccounts {
+            config: next_account_info(ai)?.clone(),
+            treasury_tokens: next_optional(pid, ai),
+            exemptions: next_optional(pid, ai),
+            partner_tokens: next_optional(pid, ai),
+        },
+        freeze_authority: next_optional(pid, ai),
+        freeze_signer: next_optional(pid, ai),
+        obligation_mint: next_optional(pid, ai),
+        obligation_tokens: next_optional(pid, ai),
+        token_2022_program: next_optional(pid, ai),
+    })
+}
+
+/// The account in the next optional slot, if any: clients leave out trailing
+/// slots and fill skipped ones with placeholders.
+fn next_optional<'a>(pid: &Pubkey, ai: &mut Iter<AccountInfo<'a>>) -> Option<AccountInfo<'a>> {
+    optional_slot(pid, next_account_info(ai).ok()?)
+}
+
+/// The accounts of `create`, shared with `create_from_program`, which puts
+/// `payer` in front of them.
+fn initialize_accounts<'a>(
+    pid: &Pubkey,
+    ai: &mut Iter<AccountInfo<'a>>,
+    si: &StreamInstruction,
+    payer: Option<AccountInfo<'a>>,
+) -> Result<InitializeAccounts<'a>, ProgramError> {
+    Ok(InitializeAccounts {
+        sender: next_account_info(ai)?.clone(),
+        sender_tokens: next_account_info(ai)?.clone(),
+        recipient: next_account_info(ai)?.clone(),
+        recipient_tokens: next_account_info(ai)?.clone(),
+        metadata: next_account_info(ai)?.clone(),
+        escrow_tokens: next_account_info(ai)?.clone(),
+        mint: next_account_info(ai)?.clone(),
+        rent: next_account_info(ai)?.clone(),
+        token_program: next_account_info(ai)?.clone(),
+        associated_token_program: next_account_info(ai)?.clone(),
+        system_program: next_account_info(ai)?.clone(),
+        stream_counter: Some(next_account_info(ai)?.clone()),
+        freeze_authority: if si.managed_freeze {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        freeze_issuer: if si.managed_freeze {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        obligation_mint: if si.obligation_nft {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        obligation_tokens: if si.obligation_nft {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        token_2022_program: if si.obligation_nft {
+            Some(next_account_info(ai)?.clone())
+        } else {
+            None
+        },
+        indexes: program_owned(pid, ai),
+        multisig: next_account_info(ai).ok().cloned(),
+        multisig_signers: ai.cloned().collect(),
+        payer,
+    })
+}
+
+/// Takes the accounts owned by the program at the front of `ai`, e.g. the
+/// `StreamIndex` pages ahead of an optional multisig.
+fn program_owned<'a>(pid: &Pubkey, ai: &mut Iter<AccountInfo<'a>>) -> Vec<AccountInfo<'a>> {
+    let mut owned = vec![];
+    while let Some(a) = ai.as_slice().first().filter(|a| a.owner == pid) {
+        owned.push(a.clone());
+        ai.next();
+    }
+    owned
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program_pack::Pack,
+        rent::Rent,
+        system_program, sysvar,
+    };
+
+    use super::*;
+    use crate::error::StreamFlowError::*;
+    use crate::events::{SharePaid, StreamEvent, Withdrawn};
+    use crate::harness::{
+        freeze_signer, stream_ix, Account, Bank, TestStream, EXTERNAL_ACCOUNT_DATA_MODIFIED, NOW,
+        PROGRAM_ID,
+    };
+    use crate::instruction::{self as builder, OptionalCancelAccounts, OptionalWithdrawAccounts};
+    use crate::permissions::{permissions, Condition, Role};
+    use crate::state::{
+        EndCause, FeeConfig, RecipientShare, StreamMirror, StreamUpdate, TokenStreamData,
+        TopUpPolicy, UnderfundedPolicy,
+    };
+    use crate::utils::associated_token_address;
+
+    #[test]
+    fn create_and_withdraw() {
+        let mut bank = Bank::new();
+        let stream = bank.create_stream(&stream_ix());
+        assert_eq!(bank.balance(&stream.escrow_tokens), 1_000);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 0);
+
+        bank.warp(NOW + 350);
+        bank.process(&stream.withdraw(0)).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+        assert_eq!(bank.stream(&stream.metadata).withdrawn_amount, 250);
+    }
+
+    #[test]
+    fn withdraw_before_the_cliff_is_a_no_op_with_an_event() {
+        let mut bank = Bank::new();
+        let stream = bank.create_stream(&stream_ix());
+        bank.process(&stream.withdraw(0)).unwrap();
+        assert_eq!(bank.balance(&stream.escrow_tokens), 1_000);
+        assert_eq!(
+            bank.events(),
+            vec![StreamEvent::Withdrawn(Withdrawn {
+                stream: stream.metadata,
+                recipient: stream.recipient,
+                amount: 0,
+                fees: 0,
+                withdrawn_total: 0,
+                notify_key: Pubkey::default(),
+                origin: [0; 16],
+            })]
+        );
+
+        let strict = bank.create_stream(&StreamInstruction {
+            strict_withdraw: true,
+            ..stream_ix()
+        });
+        assert_eq!(
+            bank.process(&strict.withdraw(0)),
+            Err(NothingToWithdraw.into())
+        );
+    }
+
+    /// `create` of `ix` from a new sender holding `amount` to a new
+    /// recipient of a new mint, not yet processed.
+    fn create_ix(bank: &mut Bank, ix: &StreamInstruction, amount: u64) -> Instruction {
+        let sender = bank.wallet();
+        let recipient = bank.wallet();
+        let mint_authority = bank.key();
+        let mint = bank.mint(&mint_authority, None);
+        let sender_tokens = bank.key();
+        bank.tokens_at(sender_tokens, &mint, &sender, amount);
+        builder::create_stream(
+            &PROGRAM_ID,
+            &sender,
+            &sender_tokens,
+            &recipient,
+            &mint,
+            &spl_token::id(),
+            ix,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn create_rejects_an_overflowing_bond() {
+        let mut bank = Bank::new();
+        let ix = StreamInstruction {
+            deposited_amount: u64::MAX,
+            total_amount: u64::MAX,
+            bond_amount: 1,
+            bond_until: NOW + 2_000,
+            ..stream_ix()
+        };
+        let create = create_ix(&mut bank, &ix, u64::MAX);
+
+        assert_eq!(bank.process(&create), Err(ArithmeticOverflow.into()));
+    }
+
+    #[test]
+    fn create_keeps_an_existing_recipient_account() {
+        let mut bank = Bank::new();
+        let create = create_ix(&mut bank, &stream_ix(), 1_000);
+        let (recipient, recipient_tokens, mint) = (
+            create.accounts[2].pubkey,
+            create.accounts[3].pubkey,
+            create.accounts[6].pubkey,
+        );
+        bank.tokens_at(recipient_tokens, &mint, &recipient, 7);
+
+        bank.process(&create).unwrap();
+        assert_eq!(bank.balance(&recipient_tokens), 7);
+    }
+
+    #[test]
+    fn create_accepts_a_frozen_recipient_account() {
+        let mut bank = Bank::new();
+        let create = create_ix(&mut bank, &stream_ix(), 1_000);
+        let (recipient, recipient_tokens, mint) = (
+            create.accounts[2].pubkey,
+            create.accounts[3].pubkey,
+            create.accounts[6].pubkey,
+        );
+        bank.tokens_at(recipient_tokens, &mint, &recipient, 0);
+        bank.set_frozen(&recipient_tokens, true);
+
+        bank.process(&create).unwrap();
+        assert!(bank.token_account(&recipient_tokens).is_frozen());
+        assert_eq!(
+            bank.stream(&create.accounts[4].pubkey).recipient_tokens,
+            recipient_tokens
+        );
+    }
+
+    #[test]
+    fn create_rejects_a_recipient_account_of_another_mint() {
+        let mut bank = Bank::new();
+        let create = create_ix(&mut bank, &stream_ix(), 1_000);
+        let (recipient, recipient_tokens) = (create.accounts[2].pubkey, create.accounts[3].pubkey);
+        let other_mint = bank.key();
+        bank.tokens_at(recipient_tokens, &other_mint, &recipient, 0);
+
+        assert_eq!(bank.process(&create), Err(ProgramError::InvalidAccountData));
+    }
+
+    fn account_infos(keys: &[Pubkey]) -> Vec<AccountInfo<'static>> {
+        keys.iter()
+            .map(|key| {
+                AccountInfo::new(
+                    Box::leak(Box::new(*key)),
+                    false,
+                    false,
+                    Box::leak(Box::new(0)),
+                    Box::leak(Box::new([])),
+                    Box::leak(Box::new(Pubkey::default())),
+                    false,
+                    0,
+                )
+            })
+            .collect()
+    }
+
+    fn keys(count: u8) -> Vec<Pubkey> {
+        (1..=count)
+            .map(|i| Pubkey::new_from_array([i; 32]))
+            .collect()
+    }
+
+    #[cfg(feature = "token2022")]
+    #[test]
+    fn multisigs_of_either_token_program_unpack() {
+        let multisig = spl_token::state::Multisig {
+            m: 1,
+            n: 2,
+            is_initialized: true,
+            signers: [Pubkey::new_unique(); spl_token::instruction::MAX_SIGNERS],
+        };
+        let mut data = vec![0; spl_token::state::Multisig::LEN];
+        spl_token::state::Multisig::pack(multisig, &mut data).unwrap();
+        let mut lamports = 0;
+        let key = Pubkey::new_unique();
+
+        for owner in [spl_token::id(), crate::utils::token_2022::id(), PROGRAM_ID] {
+            let info = AccountInfo::new(
+                &key,
+                false,
+                false,
+                &mut lamports,
+                &mut data,
+                &owner,
+                false,
+                0,
+            );
+            let unpacked = crate::utils::unpack_multisig_account(&info);
+            if owner == PROGRAM_ID {
+                assert_eq!(unpacked, Err(ProgramError::InvalidAccountData));
+            } else {
+                assert_eq!(unpacked.unwrap().m, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn withdraw_optional_slots_follow_required_accounts() {
+        let keys = keys(8 + 1 + 2 + 1 + 14);
+        let infos = account_infos(&keys);
+        let wa = withdraw_accounts(&PROGRAM_ID, &mut infos.iter(), true, 2).unwrap();
+
+        assert_eq!(wa.token_program.key, &keys[7]);
+        assert_eq!(wa.destination_tokens.unwrap().key, &keys[8]);
+        let shares: Vec<_> = wa.share_tokens.iter().map(|a| *a.key).collect();
+        assert_eq!(shares, keys[9..11]);
+        assert_eq!(wa.fees.config.key, &keys[11]);
+        let slots = [
+            wa.fees.treasury_tokens,
+            wa.fees.exemptions,
+            wa.fees.partner_tokens,
+            wa.freeze_authority,
+            wa.freeze_signer,
+            wa.obligation_mint,
+            wa.obligation_tokens,
+            wa.token_2022_program,
+            wa.forward_tokens,
+            wa.hold_tokens,
+            wa.mirror,
+            wa.lp_pool,
+            wa.lp_reserve_a,
+            wa.lp_reserve_b,
+        ];
+        let slots: Vec<_> = slots.iter().map(|a| *a.as_ref().unwrap().key).collect();
+        assert_eq!(slots, keys[12..]);
+    }
+
+    #[test]
+    fn skipped_and_trailing_slots_are_absent() {
+        let mut keys = keys(8 + 1 + 11);
+        keys[9] = PROGRAM_ID;
+        keys[10..19].fill(Pubkey::default());
+        let infos = account_infos(&keys);
+        let wa = withdraw_accounts(&PROGRAM_ID, &mut infos.iter(), false, 0).unwrap();
+
+        assert!(wa.destination_tokens.is_none() && wa.share_tokens.is_empty());
+        assert_eq!(wa.fees.config.key, &keys[8]);
+        assert!(wa.fees.treasury_tokens.is_none() && wa.fees.exemptions.is_none());
+        assert!(wa.forward_tokens.is_none() && wa.hold_tokens.is_none());
+        assert_eq!(wa.mirror.unwrap().key, &keys[19]);
+        assert!(wa.lp_pool.is_none() && wa.lp_reserve_b.is_none());
+    }
+
+    #[test]
+    fn crank_withdraw_takes_the_withdraw_slots_after_the_keeper() {
+        let keys = keys(2 + 7 + 1 + 11);
+        let infos = account_infos(&keys);
+        let ai = &mut infos.iter();
+        let keeper = next_account_info(ai).unwrap().clone();
+        next_account_info(ai).unwrap();
+        let wa = authorized_withdraw_accounts(&PROGRAM_ID, keeper, ai, false, 0).unwrap();
+
+        assert_eq!(wa.withdraw_authority.key, &keys[0]);
+        assert_eq!(wa.sender.key, &keys[2]);
+        assert_eq!(wa.fees.config.key, &keys[9]);
+        assert_eq!(wa.mirror.unwrap().key, &keys[20]);
+        assert!(wa.lp_pool.is_none());
+    }
+
+    #[test]
+    fn cancel_optional_slots_follow_fee_config() {
+        let keys = keys(9 + 1 + 8);
+        let infos = account_infos(&keys);
+        let ca = cancel_accounts(&PROGRAM_ID, &mut infos.iter()).unwrap();
+
+        assert_eq!(ca.cancel_authority.key, &keys[0]);
+        assert_eq!(ca.token_program.key, &keys[8]);
+        assert_eq!(ca.fees.config.key, &keys[9]);
+        let slots = [
+            ca.fees.treasury_tokens,
+            ca.fees.exemptions,
+            ca.fees.partner_tokens,
+            ca.freeze_authority,
+            ca.freeze_signer,
+            ca.obligation_mint,
+            ca.obligation_tokens,
+            ca.token_2022_program,
+        ];
+        let slots: Vec<_> = slots.iter().map(|a| *a.as_ref().unwrap().key).collect();
+        assert_eq!(slots, keys[10..]);
+    }
+
+    #[test]
+    fn cancel_skipped_and_trailing_slots_are_absent() {
+        let mut keys = keys(9 + 1 + 5);
+        keys[10] = PROGRAM_ID;
+        keys[11] = Pubkey::default();
+        let infos = account_infos(&keys);
+        let ca = cancel_accounts(&PROGRAM_ID, &mut infos.iter()).unwrap();
+
+        assert!(ca.fees.treasury_tokens.is_none() && ca.fees.exemptions.is_none());
+        assert_eq!(ca.fees.partner_tokens.unwrap().key, &keys[12]);
+        assert_eq!(ca.freeze_signer.unwrap().key, &keys[14]);
+        assert!(ca.obligation_mint.is_none() && ca.token_2022_program.is_none());
+
+        let infos = account_infos(&keys[..9]);
+        assert_eq!(
+            cancel_accounts(&PROGRAM_ID, &mut infos.iter()).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn cancel_with_every_slot_skipped() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let mut cancel = stream.cancel(&stream.sender);
+        OptionalCancelAccounts {
+            token_2022_program: Some(PROGRAM_ID),
+            ..Default::default()
+        }
+        .append_to(&mut cancel);
+        assert_eq!(cancel.accounts.len(), 9 + 1 + 8);
+
+        bank.process(&cancel).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+        assert_ne!(bank.stream(&stream.metadata).canceled_at, 0);
+    }
+
+    #[test]
+    fn withdraw_with_every_slot_skipped() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            lp_reserve_b: Some(PROGRAM_ID),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+        assert_eq!(withdraw.accounts.len(), 8 + 1 + 14);
+
+        bank.process(&withdraw).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+    }
+
+    #[test]
+    fn lp_breakdown_needs_a_supported_pool() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            lp_pool: Some(stream.escrow_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+
+        assert_eq!(bank.process(&withdraw), Err(UnsupportedLpPool.into()));
+    }
+
+    #[test]
+    fn withdraw_hold_stream() {
+        let mut bank = Bank::new();
+        let hold_mint = bank.key();
+        let stream = bank.create_stream(&StreamInstruction {
+            hold_mint,
+            hold_amount: 10,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 350);
+        let hold_tokens = bank.key();
+        bank.tokens_at(hold_tokens, &hold_mint, &stream.recipient, 9);
+
+        assert_eq!(
+            bank.process(&stream.withdraw(0)),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            hold_tokens: Some(hold_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+        assert_eq!(bank.process(&withdraw), Err(HoldingBelowMinimum.into()));
+
+        bank.tokens_at(hold_tokens, &hold_mint, &stream.recipient, 10);
+        bank.process(&withdraw).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+    }
+
+    #[test]
+    fn withdraw_mirrored_stream() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        bank.process(&builder::init_stream_mirror(
+            &PROGRAM_ID,
+            &stream.sender,
+            &stream.metadata,
+        ))
+        .unwrap();
+        let (mirror, _) = StreamMirror::address(&PROGRAM_ID, &stream.metadata);
+
+        assert_eq!(
+            bank.process(&stream.withdraw(0)),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            mirror: Some(mirror),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+        bank.process(&withdraw).unwrap();
+
+        let mirror = StreamMirror::try_from_slice(&bank.get(&mirror).unwrap().data).unwrap();
+        assert_eq!(mirror.withdrawn_amount, 250);
+        assert_eq!(mirror.synced_at, NOW + 350);
+    }
+
+    #[test]
+    fn withdraw_pays_partner_fee() {
+        let mut bank = Bank::new();
+        let partner = bank.wallet();
+        let stream = bank.create_stream(&StreamInstruction {
+            partner,
+            partner_fee_bps: 100,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 350);
+        let partner_tokens =
+            spl_associated_token_account::get_associated_token_address(&partner, &stream.mint);
+        bank.tokens_at(partner_tokens, &stream.mint, &partner, 0);
+
+        assert_eq!(
+            bank.process(&stream.withdraw(0)),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            partner_tokens: Some(partner_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+        bank.process(&withdraw).unwrap();
+        assert_eq!(bank.balance(&partner_tokens), 2);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 248);
+        assert_eq!(bank.stream(&stream.metadata).fees_paid, 2);
+    }
+
+    /// Initializes the fee config with a fee of `fee_bps` and returns its
+    /// treasury's token account of `mint`.
+    fn charge_fees(bank: &mut Bank, mint: &Pubkey, fee_bps: u16) -> Pubkey {
+        let treasury = bank.wallet();
+        let config = FeeConfig {
+            admin: treasury,
+            treasury,
+            fee_bps,
+        };
+        let data = config.try_to_vec().unwrap();
+        bank.set(
+            FeeConfig::address(&PROGRAM_ID).0,
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: PROGRAM_ID,
+            },
+        );
+        let treasury_tokens =
+            spl_associated_token_account::get_associated_token_address(&treasury, mint);
+        bank.tokens_at(treasury_tokens, mint, &treasury, 0);
+        treasury_tokens
+    }
+
+    #[test]
+    fn withdraw_pays_protocol_fee_once_configured() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        bank.process(&stream.withdraw(100)).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 100);
+
+        let treasury_tokens = charge_fees(&mut bank, &stream.mint, 100);
+        bank.warp(NOW + 351);
+        assert_eq!(
+            bank.process(&stream.withdraw(0)),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        let mut withdraw = stream.withdraw(0);
+        OptionalWithdrawAccounts {
+            treasury_tokens: Some(treasury_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut withdraw);
+        bank.process(&withdraw).unwrap();
+        assert_eq!(bank.balance(&treasury_tokens), 1);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 100 + 150);
+    }
+
+    /// `cancel_batch` of `streams`, all of the first one's sender, paying
+    /// protocol fees to `treasury_tokens`.
+    fn cancel_batch(streams: &[&TestStream], treasury_tokens: Option<Pubkey>) -> Instruction {
+        let sender = streams[0].sender;
+        let mut accounts = vec![
+            AccountMeta::new_readonly(sender, true),
+            AccountMeta::new(sender, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(FeeConfig::address(&PROGRAM_ID).0, false),
+            AccountMeta::new_readonly(PROGRAM_ID, false),
+        ];
+        for stream in streams {
+            accounts.extend([
+                AccountMeta::new(stream.metadata, false),
+                AccountMeta::new(stream.escrow_tokens, false),
+                AccountMeta::new(stream.sender_tokens, false),
+                AccountMeta::new(stream.recipient, false),
+                AccountMeta::new(stream.recipient_tokens, false),
+                AccountMeta::new_readonly(stream.mint, false),
+                AccountMeta::new(treasury_tokens.unwrap_or(PROGRAM_ID), false),
+                AccountMeta::new_readonly(PROGRAM_ID, false),
+                AccountMeta::new_readonly(PROGRAM_ID, false),
+                AccountMeta::new_readonly(PROGRAM_ID, false),
+            ]);
+        }
+        Instruction {
+            program_id: PROGRAM_ID,
+            accounts,
+            data: builder::pack_versioned(7, &[]),
+        }
+    }
+
+    #[test]
+    fn cancel_batch_pays_protocol_fee() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let treasury_tokens = charge_fees(&mut bank, &stream.mint, 100);
+
+        bank.process(&cancel_batch(&[&stream], Some(treasury_tokens)))
+            .unwrap();
+        assert_eq!(bank.balance(&treasury_tokens), 2);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 248);
+        assert_eq!(bank.balance(&stream.sender_tokens), 750);
+    }
+
+    #[test]
+    fn cancel_batch_fails_as_a_whole() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let missing = bank.key();
+
+        let mut batch = cancel_batch(&[&stream, &stream], None);
+        batch.accounts[5 + 10].pubkey = missing;
+        assert_eq!(
+            bank.process(&batch),
+            Err(ProgramError::UninitializedAccount)
+        );
+        assert_eq!(bank.stream(&stream.metadata).canceled_at, 0);
+        assert_eq!(bank.balance(&stream.escrow_tokens), 1_000);
+
+        bank.process(&cancel_batch(&[&stream], None)).unwrap();
+        assert_eq!(bank.stream(&stream.metadata).canceled_at, NOW + 350);
+    }
+
+    /// Moves `stream` to a new recipient, directly or through a proposal they
+    /// accept, and returns a withdrawal of all that's available to them.
+    fn hand_over(bank: &mut Bank, stream: &TestStream, propose: bool) -> Instruction {
+        let new_recipient = bank.wallet();
+        if propose {
+            bank.process(&builder::propose_transfer(
+                &PROGRAM_ID,
+                &stream.recipient,
+                &new_recipient,
+                &stream.metadata,
+            ))
+            .unwrap();
+            bank.process(&builder::accept_transfer(
+                &PROGRAM_ID,
+                &new_recipient,
+                &stream.metadata,
+                &stream.mint,
+                &spl_token::id(),
+            ))
+            .unwrap();
+        } else {
+            bank.process(&builder::transfer_recipient(
+                &PROGRAM_ID,
+                &stream.recipient,
+                &new_recipient,
+                &stream.metadata,
+                &stream.escrow_tokens,
+                &stream.mint,
+                &spl_token::id(),
+            ))
+            .unwrap();
+        }
+        TestStream {
+            recipient: new_recipient,
+            recipient_tokens: spl_associated_token_account::get_associated_token_address(
+                &new_recipient,
+                &stream.mint,
+            ),
+            ..*stream
+        }
+        .withdraw(0)
+    }
+
+    /// Forwards `stream`'s withdrawals to a new token account of its
+    /// recipient, returned.
+    fn set_forward(bank: &mut Bank, stream: &TestStream) -> Pubkey {
+        let forward_tokens = bank.key();
+        bank.tokens_at(forward_tokens, &stream.mint, &stream.recipient, 0);
+        let set_forward = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(stream.recipient, true),
+                AccountMeta::new(stream.metadata, false),
+                AccountMeta::new_readonly(forward_tokens, false),
+            ],
+            data: builder::pack_versioned(25, &[]),
+        };
+        bank.process(&set_forward).unwrap();
+        forward_tokens
+    }
+
+    #[test]
+    fn cranked_and_pushed_withdrawals_are_forwarded() {
+        let mut bank = Bank::new();
+        let stream = bank.create_stream(&StreamInstruction {
+            withdraw_frequency: 100,
+            cranker_fee: 10,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 350);
+        let forward_tokens = set_forward(&mut bank, &stream);
+        let keeper = bank.wallet();
+        let keeper_tokens = bank.key();
+        bank.tokens_at(keeper_tokens, &stream.mint, &keeper, 0);
+        let mut crank = builder::crank_withdraw(
+            &PROGRAM_ID,
+            &keeper,
+            &keeper_tokens,
+            &stream.sender,
+            &stream.recipient,
+            &stream.recipient_tokens,
+            &stream.metadata,
+            &stream.escrow_tokens,
+            &stream.mint,
+            &spl_token::id(),
+        );
+        assert_eq!(bank.process(&crank), Err(ProgramError::InvalidAccountData));
+
+        OptionalWithdrawAccounts {
+            forward_tokens: Some(forward_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut crank);
+        bank.process(&crank).unwrap();
+        assert_eq!(bank.balance(&forward_tokens), 240);
+        assert_eq!(bank.balance(&keeper_tokens), 10);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 0);
+
+        let mut bank = Bank::new();
+        let stream = bank.create_stream(&StreamInstruction {
+            withdrawal_public: true,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 350);
+        let forward_tokens = set_forward(&mut bank, &stream);
+        let mut push = stream.withdraw(0);
+        push.accounts[0] = AccountMeta::new_readonly(bank.wallet(), true);
+        assert_eq!(bank.process(&push), Err(ProgramError::InvalidAccountData));
+
+        OptionalWithdrawAccounts {
+            forward_tokens: Some(forward_tokens),
+            ..Default::default()
+        }
+        .append_to(&mut push);
+        bank.process(&push).unwrap();
+        assert_eq!(bank.balance(&forward_tokens), 250);
+        assert_eq!(bank.balance(&stream.recipient_tokens), 0);
+    }
+
+    #[test]
+    fn shared_withdrawal_pays_every_position_with_a_receipt() {
+        let mut bank = Bank::new();
+        let holders = [bank.wallet(), bank.wallet()];
+        let stream = bank.create_stream(&StreamInstruction {
+            shares: vec![
+                RecipientShare {
+                    recipient: holders[0],
+                    bps: 2_500,
+                },
+                RecipientShare {
+                    recipient: holders[1],
+                    bps: 1_000,
+                },
+            ],
+            ..stream_ix()
+        });
+        let share_tokens =
+            holders.map(|h| associated_token_address(&h, &stream.mint, &spl_token::id()));
+        for (holder, tokens) in holders.iter().zip(share_tokens) {
+            bank.tokens_at(tokens, &stream.mint, holder, 0);
+        }
+
+        bank.warp(NOW + 350);
+        assert_eq!(bank.process(&stream.withdraw(0)), Err(SharedStream.into()));
+        let withdraw = builder::withdraw_shares(
+            &PROGRAM_ID,
+            &stream.sender,
+            &stream.recipient,
+            &stream.recipient_tokens,
+            &stream.sender,
+            &stream.metadata,
+            &stream.escrow_tokens,
+            &stream.mint,
+            &spl_token::id(),
+            &share_tokens,
+            0,
+        );
+        bank.process(&withdraw).unwrap();
+
+        assert_eq!(bank.balance(&stream.recipient_tokens), 163);
+        assert_eq!(bank.balance(&share_tokens[0]), 62);
+        assert_eq!(bank.balance(&share_tokens[1]), 25);
+        assert_eq!(bank.stream(&stream.metadata).withdrawn_amount, 250);
+
+        let receipt = |holder: Pubkey, tokens: Pubkey, amount: u64, bps: u16| {
+            StreamEvent::SharePaid(SharePaid {
+                stream: stream.metadata,
+                holder,
+                tokens,
+                amount,
+                bps,
+                notify_key: Pubkey::default(),
+                origin: [0; 16],
+            })
+        };
+        assert_eq!(
+            bank.events()[1..],
+            [
+                receipt(stream.recipient, stream.recipient_tokens, 163, 6_500),
+                receipt(holders[0], share_tokens[0], 62, 2_500),
+                receipt(holders[1], share_tokens[1], 25, 1_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn transfers_drop_forwarding() {
+        for propose in [false, true] {
+            let mut bank = Bank::new();
+            let stream = live_stream(&mut bank);
+            let forward_tokens = set_forward(&mut bank, &stream);
+
+            let withdraw = hand_over(&mut bank, &stream, propose);
+            bank.process(&withdraw).unwrap();
+            assert_eq!(bank.stream(&stream.metadata).forward_to, Pubkey::default());
+            assert_eq!(bank.balance(&withdraw.accounts[3].pubkey), 250);
+            assert_eq!(bank.balance(&forward_tokens), 0);
+        }
+    }
+
+    #[test]
+    fn sender_transfers_only_when_transferable_by_sender() {
+        for transferable_by_sender in [false, true] {
+            let mut bank = Bank::new();
+            let stream = bank.create_stream(&StreamInstruction {
+                transferable_by_sender,
+                ..stream_ix()
+            });
+            let new_recipient = bank.wallet();
+            let transfer = builder::transfer_recipient(
+                &PROGRAM_ID,
+                &stream.sender,
+                &new_recipient,
+                &stream.metadata,
+                &stream.escrow_tokens,
+                &stream.mint,
+                &spl_token::id(),
+            );
+
+            if transferable_by_sender {
+                bank.process(&transfer).unwrap();
+                assert_eq!(bank.stream(&stream.metadata).recipient, new_recipient);
+            } else {
+                assert_eq!(bank.process(&transfer), Err(TransferNotAllowed.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn transfers_drop_min_claim() {
+        for propose in [false, true] {
+            let mut bank = Bank::new();
+            let stream = live_stream(&mut bank);
+            let set_min_claim = Instruction {
+                program_id: PROGRAM_ID,
+                accounts: vec![
+                    AccountMeta::new_readonly(stream.recipient, true),
+                    AccountMeta::new(stream.metadata, false),
+                ],
+                data: builder::pack_versioned(14, &500u64.to_le_bytes()),
+            };
+            bank.process(&set_min_claim).unwrap();
+
+            let withdraw = hand_over(&mut bank, &stream, propose);
+            bank.process(&withdraw).unwrap();
+            assert_eq!(bank.stream(&stream.metadata).min_claim_amount, 0);
+            assert_eq!(bank.balance(&withdraw.accounts[3].pubkey), 250);
+        }
+    }
+
+    #[test]
+    fn cancel_follows_updated_cancelable_flags() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        let update = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(stream.sender, true),
+                AccountMeta::new_readonly(stream.recipient, false),
+                AccountMeta::new(stream.metadata, false),
+            ],
+            data: builder::pack_versioned(
+                38,
+                &StreamUpdate {
+                    cancelable_by_sender: Some(false),
+                    cancelable_by_recipient: Some(true),
+                    ..Default::default()
+                }
+                .try_to_vec()
+                .unwrap(),
+            ),
+        };
+        bank.process(&update).unwrap();
+
+        assert_eq!(
+            bank.process(&stream.cancel(&stream.sender)),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            bank.process(&cancel_batch(&[&stream], None)),
+            Err(ProgramError::InvalidAccountData)
+        );
+        bank.process(&stream.cancel(&stream.recipient)).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+    }
+
+    #[test]
+    fn cancel_thaws_and_refreezes_managed_recipient() {
+        let mut bank = Bank::new();
+        let (stream, freeze_authority) = bank.create_managed_stream(&stream_ix());
+        bank.warp(NOW + 350);
+        assert_eq!(
+            bank.process(&stream.cancel(&stream.sender)),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut cancel = stream.cancel(&stream.sender);
+        OptionalCancelAccounts {
+            freeze_authority: Some(freeze_authority),
+            freeze_signer: Some(freeze_signer(&stream.mint)),
+            ..Default::default()
+        }
+        .append_to(&mut cancel);
+        bank.process(&cancel).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+        assert!(bank.token_account(&stream.recipient_tokens).is_frozen());
+    }
+
+    #[test]
+    fn cancel_batch_thaws_and_refreezes_managed_recipient() {
+        let mut bank = Bank::new();
+        let (stream, freeze_authority) = bank.create_managed_stream(&stream_ix());
+        bank.warp(NOW + 350);
+        let mut batch = cancel_batch(&[&stream], None);
+        assert_eq!(
+            bank.process(&batch),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        batch.accounts[5 + 8].pubkey = freeze_authority;
+        batch.accounts[5 + 9].pubkey = freeze_signer(&stream.mint);
+        bank.process(&batch).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 250);
+        assert!(bank.token_account(&stream.recipient_tokens).is_frozen());
+    }
+
+    #[test]
+    fn halted_stream_stays_open_for_a_topup() {
+        let mut bank = Bank::new();
+        let stream = bank.create_stream(&StreamInstruction {
+            deposited_amount: 400,
+            underfunded_policy: UnderfundedPolicy::HaltAccrual,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 600);
+        bank.process(&stream.withdraw(0)).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 400);
+        let halted = bank.stream(&stream.metadata);
+        assert_eq!(halted.end_cause, EndCause::Open);
+        assert_eq!(halted.completed_at, 0);
+        assert_eq!(bank.balance(&stream.escrow_tokens), 0);
+
+        let stranger = bank.wallet();
+        bank.warp(NOW + 1_200);
+        assert_eq!(
+            bank.process(&stream.cancel(&stranger)),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        bank.tokens_at(stream.sender_tokens, &stream.mint, &stream.sender, 600);
+        bank.process(&builder::topup_stream(
+            &PROGRAM_ID,
+            &stream.sender,
+            &stream.sender_tokens,
+            &stream.metadata,
+            &stream.escrow_tokens,
+            &stream.mint,
+            &spl_token::id(),
+            600,
+        ))
+        .unwrap();
+        // Accrual resumes where it halted, at 400 of 1_000.
+        bank.warp(NOW + 1_300);
+        bank.process(&stream.withdraw(0)).unwrap();
+        assert_eq!(bank.balance(&stream.recipient_tokens), 500);
+    }
+
+    #[test]
+    fn undo_topup_returns_it_to_the_payer() {
+        let mut bank = Bank::new();
+        let stream = live_stream(&mut bank);
+        bank.tokens_at(stream.sender_tokens, &stream.mint, &stream.sender, 500);
+        bank.process(&builder::topup_stream(
+            &PROGRAM_ID,
+            &stream.sender,
+            &stream.sender_tokens,
+            &stream.metadata,
+            &stream.escrow_tokens,
+            &stream.mint,
+            &spl_token::id(),
+            500,
+        ))
+        .unwrap();
+        assert_eq!(bank.balance(&stream.escrow_tokens), 1_500);
+
+        let undo_topup = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(stream.sender, true),
+                AccountMeta::new(stream.sender_tokens, false),
+                AccountMeta::new(stream.metadata, false),
+                AccountMeta::new(stream.escrow_tokens, false),
+                AccountMeta::new_readonly(stream.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            data: builder::pack_versioned(23, &[]),
+        };
+        bank.process(&undo_topup).unwrap();
+        assert_eq!(bank.balance(&stream.sender_tokens), 500);
+        assert_eq!(bank.balance(&stream.escrow_tokens), 1_000);
+        assert_eq!(bank.stream(&stream.metadata).ix.deposited_amount, 1_000);
+    }
+
+    #[test]
+    fn compliance_freeze_cannot_be_extended_while_active() {
+        let mut bank = Bank::new();
+        let compliance_authority = bank.wallet();
+        let stream = bank.create_stream(&StreamInstruction {
+            compliance_authority,
+            ..stream_ix()
+        });
+        bank.warp(NOW + 350);
+        let freeze = |frozen_until: u64| Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(compliance_authority, true),
+                AccountMeta::new(stream.metadata, false),
+            ],
+            data: builder::pack_versioned(21, &frozen_until.to_le_bytes()),
+        };
+
+        bank.process(&freeze(NOW + 1_000)).unwrap();
+        assert_eq!(
+            bank.process(&freeze(NOW + 1_001)),
+            Err(ComplianceFrozen.into())
+        );
+        bank.process(&freeze(NOW + 500)).unwrap();
+        assert_eq!(
+            bank.stream(&stream.metadata).compliance_frozen_until,
+            NOW + 500
+        );
+
+        bank.warp(NOW + 500);
+        bank.process(&freeze(NOW + 1_000)).unwrap();
+        bank.process(&freeze(0)).unwrap();
+        bank.process(&stream.withdraw(0)).unwrap();
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Mutation {
+        NotSigner,
+        NotWritable,
+        WrongOwner,
+        WrongPda,
+        WrongMint,
+    }
+    use Mutation::*;
+
+    /// An instruction ready to succeed, and the role of each of its accounts.
+    struct Case {
+        name: &'static str,
+        setup: fn(&mut Bank) -> Instruction,
+        roles: &'static [&'static str],
+    }
+
+    fn live_stream(bank: &mut Bank) -> TestStream {
+        let stream = bank.create_stream(&stream_ix());
+        bank.warp(NOW + 350);
+        stream
+    }
+
+    /// Withdrawing all of it closes the escrow, refunding the sender.
+    fn ended_stream(bank: &mut Bank) -> TestStream {
+        let stream = bank.create_stream(&stream_ix());
+        bank.warp(NOW + 1_200);
+        stream
+    }
+
+    fn canceled_stream(bank: &mut Bank) -> TestStream {
+        let stream = live_stream(bank);
+        bank.process(&stream.cancel(&stream.sender)).unwrap();
+        bank.warp(NOW + 351);
+        stream
+    }
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "create",
+            setup: |bank| {
+                let sender = bank.wallet();
+                let recipient = bank.wallet();
+                let authority = bank.key();
+                let mint = bank.mint(&authority, None);
+                let sender_tokens = bank.key();
+                bank.tokens_at(sender_tokens, &mint, &sender, 1_000);
+                builder::create_stream(
+                    &PROGRAM_ID,
+                    &sender,
+                    &sender_tokens,
+                    &recipient,
+                    &mint,
+                    &spl_token::id(),
+                    &stream_ix(),
+                )
+                .unwrap()
+            },
+            roles: &[
+                "sender",
+                "sender_tokens",
+                "recipient",
+                "recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "rent",
+                "token_program",
+                "associated_token_program",
+                "system_program",
+                "stream_counter",
+            ],
+        },
+        Case {
+            name: "withdraw",
+            setup: |bank| ended_stream(bank).withdraw(0),
+            roles: &[
+                "authority",
+                "sender",
+                "recipient",
+                "recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+                "fee_config",
+            ],
+        },
+        Case {
+            name: "withdraw_to",
+            setup: |bank| {
+                let stream = ended_stream(bank);
+                let destination = bank.key();
+                bank.tokens_at(destination, &stream.mint, &stream.recipient, 0);
+                builder::withdraw_to(
+                    &PROGRAM_ID,
+                    &stream.recipient,
+                    &stream.recipient_tokens,
+                    &stream.sender,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                    &stream.mint,
+                    &spl_token::id(),
+                    &destination,
+                    0,
+                )
+            },
+            roles: &[
+                "authority",
+                "sender",
+                "recipient",
+                "recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+                "destination_tokens",
+                "fee_config",
+            ],
+        },
+        Case {
+            name: "crank_withdraw",
+            setup: |bank| {
+                let stream = bank.create_stream(&StreamInstruction {
+                    withdraw_frequency: 60,
+                    cranker_fee: 5,
+                    ..stream_ix()
+                });
+                bank.warp(NOW + 1_200);
+                let keeper = bank.wallet();
+                let keeper_tokens = bank.key();
+                bank.tokens_at(keeper_tokens, &stream.mint, &keeper, 0);
+                builder::crank_withdraw(
+                    &PROGRAM_ID,
+                    &keeper,
+                    &keeper_tokens,
+                    &stream.sender,
+                    &stream.recipient,
+                    &stream.recipient_tokens,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                    &stream.mint,
+                    &spl_token::id(),
+                )
+            },
+            roles: &[
+                "keeper",
+                "keeper_tokens",
+                "sender",
+                "recipient",
+                "recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+                "fee_config",
+            ],
+        },
+        Case {
+            name: "cancel",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                stream.cancel(&stream.sender)
+            },
+            roles: &[
+                "authority",
+                "sender",
+                "sender_tokens",
+                "recipient",
+                "recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+                "fee_config",
+            ],
+        },
+        Case {
+            name: "topup",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                bank.tokens_at(stream.sender_tokens, &stream.mint, &stream.sender, 500);
+                builder::topup_stream(
+                    &PROGRAM_ID,
+                    &stream.sender,
+                    &stream.sender_tokens,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                    &stream.mint,
+                    &spl_token::id(),
+                    500,
+                )
+            },
+            roles: &[
+                "sender",
+                "sender_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+            ],
+        },
+        Case {
+            name: "transfer_recipient",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                let new_recipient = bank.wallet();
+                builder::transfer_recipient(
+                    &PROGRAM_ID,
+                    &stream.recipient,
+                    &new_recipient,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                    &stream.mint,
+                    &spl_token::id(),
+                )
+            },
+            roles: &[
+                "authority",
+                "new_recipient",
+                "new_recipient_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "rent",
+                "token_program",
+                "associated_token_program",
+                "system_program",
+            ],
+        },
+        Case {
+            name: "propose_transfer",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                let new_recipient = bank.wallet();
+                builder::propose_transfer(
+                    &PROGRAM_ID,
+                    &stream.recipient,
+                    &new_recipient,
+                    &stream.metadata,
+                )
+            },
+            roles: &["authority", "new_recipient", "metadata"],
+        },
+        Case {
+            name: "accept_transfer",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                let new_recipient = bank.wallet();
+                let propose = builder::propose_transfer(
+                    &PROGRAM_ID,
+                    &stream.recipient,
+                    &new_recipient,
+                    &stream.metadata,
+                );
+                bank.process(&propose).unwrap();
+                builder::accept_transfer(
+                    &PROGRAM_ID,
+                    &new_recipient,
+                    &stream.metadata,
+                    &stream.mint,
+                    &spl_token::id(),
+                )
+            },
+            roles: &[
+                "new_recipient",
+                "new_recipient_tokens",
+                "metadata",
+                "mint",
+                "rent",
+                "token_program",
+                "associated_token_program",
+                "system_program",
+            ],
+        },
+        Case {
+            name: "cancel_transfer",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                let new_recipient = bank.wallet();
+                let propose = builder::propose_transfer(
+                    &PROGRAM_ID,
+                    &stream.recipient,
+                    &new_recipient,
+                    &stream.metadata,
+                );
+                bank.process(&propose).unwrap();
+                builder::cancel_transfer(&PROGRAM_ID, &stream.recipient, &stream.metadata)
+            },
+            roles: &["authority", "metadata"],
+        },
+        Case {
+            name: "close_stream",
+            setup: |bank| {
+                let stream = canceled_stream(bank);
+                builder::close_stream(
+                    &PROGRAM_ID,
+                    &stream.sender,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                )
+            },
+            roles: &["sender", "metadata", "escrow_tokens"],
+        },
+        Case {
+            name: "init_stream_mirror",
+            setup: |bank| {
+                let stream = live_stream(bank);
+                builder::init_stream_mirror(&PROGRAM_ID, &stream.sender, &stream.metadata)
+            },
+            roles: &["sender", "metadata", "mirror", "system_program"],
+        },
+        Case {
+            name: "archive_stream",
+            setup: |bank| {
+                let stream = canceled_stream(bank);
+                let payer = bank.wallet();
+                builder::archive_stream(&PROGRAM_ID, &payer, &stream.metadata)
+            },
+            roles: &["payer", "metadata", "settlement", "system_program"],
+        },
+        Case {
+            name: "cancel_subscription",
+            setup: |bank| {
+                let stream = bank.create_stream(&StreamInstruction {
+                    release_rate: 100,
+                    period: 100,
+                    subscription: true,
+                    ..stream_ix()
+                });
+                bank.warp(NOW + 350);
+                builder::cancel_subscription(
+                    &PROGRAM_ID,
+                    &stream.sender,
+                    &stream.sender_tokens,
+                    &stream.metadata,
+                    &stream.escrow_tokens,
+                    &stream.mint,
+                    &spl_token::id(),
+                )
+            },
+            roles: &[
+                "authority",
+                "sender_tokens",
+                "metadata",
+                "escrow_tokens",
+                "mint",
+                "token_program",
+            ],
+        },
+    ];
+
+    /// Every mutation of `instruction`'s accounts the validation layer must
+    /// catch, by account index. Programs and sysvars are left alone.
+    fn mutations(bank: &Bank, instruction: &Instruction) -> Vec<(usize, Mutation)> {
+        let fixed = [
+            system_program::id(),
+            sysvar::rent::id(),
+            spl_token::id(),
+            spl_associated_token_account::id(),
+        ];
+        let mut seen = Vec::new();
+        let mut mutations = Vec::new();
+        for (i, meta) in instruction.accounts.iter().enumerate() {
+            if fixed.contains(&meta.pubkey) || seen.contains(&meta.pubkey) {
+                continue;
+            }
+            seen.push(meta.pubkey);
+
+            if meta.is_signer {
+                mutations.push((i, NotSigner));
+            }
+            if meta.is_writable {
+                mutations.push((i, NotWritable));
+            }
+            if !meta.pubkey.is_on_curve() {
+                mutations.push((i, WrongPda));
+            }
+            let account = match bank.get(&meta.pubkey) {
+                Some(v) if !v.data.is_empty() => v,
+                _ => continue,
+            };
+            if account.owner == PROGRAM_ID || account.owner == spl_token::id() {
+                mutations.push((i, WrongOwner));
+            }
+            if account.owner == spl_token::id() && account.data.len() == spl_token::state::Mint::LEN
+            {
+                mutations.push((i, WrongMint));
+            }
+        }
+        mutations
+    }
+
+    fn mutate(bank: &mut Bank, instruction: &mut Instruction, index: usize, mutation: Mutation) {
+        let key = instruction.accounts[index].pubkey;
+        let substitute = |instruction: &mut Instruction, new_key: Pubkey| {
+            for meta in instruction.accounts.iter_mut().filter(|m| m.pubkey == key) {
+                meta.pubkey = new_key;
+            }
+        };
+        match mutation {
+            NotSigner | NotWritable => {
+                for meta in instruction.accounts.iter_mut().filter(|m| m.pubkey == key) {
+                    if mutation == NotSigner {
+                        meta.is_signer = false;
+                    } else {
+                        meta.is_writable = false;
+                    }
+                }
+            }
+            WrongOwner => {
+                let owner = bank.key();
+                bank.accounts.get_mut(&key).unwrap().owner = owner;
+            }
+            WrongPda => {
+                let new_key = Pubkey::find_program_address(&[b"elsewhere"], &bank.key()).0;
+                if let Some(copy) = bank.get(&key).cloned() {
+                    bank.set(new_key, copy);
+                }
+                substitute(instruction, new_key);
+            }
+            WrongMint => {
+                let authority = bank.key();
+                let new_key = bank.mint(&authority, None);
+                substitute(instruction, new_key);
+            }
+        }
+    }
+
+    type Outcome = Result<(), ProgramError>;
+
+    /// What each mutation of each case fails with. `EXTERNAL_ACCOUNT_DATA_MODIFIED`
+    /// marks accounts only the token program's ownership check guards.
+    #[rustfmt::skip]
+    fn expected() -> Vec<(&'static str, &'static str, Mutation, Outcome)> {
+        use ProgramError::{
+            InvalidAccountData, MissingRequiredSignature, UninitializedAccount,
+        };
+
+        vec![
+            ("create", "sender", NotSigner, Err(MissingRequiredSignature)),
+            ("create", "sender", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "sender_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "sender_tokens", WrongOwner, Err(InvalidAccountData)),
+            ("create", "recipient", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "recipient_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("create", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("create", "escrow_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("create", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("create", "mint", WrongMint, Err(InvalidAccountData)),
+            ("create", "stream_counter", NotWritable, Err(AccountsNotWritable.into())),
+            ("create", "stream_counter", WrongPda, Err(InvalidAccountData)),
+            ("withdraw", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("withdraw", "authority", NotWritable, Err(InvalidAccountData)),
+            ("withdraw", "sender", NotWritable, Err(InvalidAccountData)),
+            ("withdraw", "recipient_tokens", NotWritable, Err(InvalidAccountData)),
+            ("withdraw", "recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("withdraw", "recipient_tokens", WrongOwner, Err(EXTERNAL_ACCOUNT_DATA_MODIFIED)),
+            ("withdraw", "metadata", NotWritable, Err(InvalidAccountData)),
+            ("withdraw", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("withdraw", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("withdraw", "escrow_tokens", NotWritable, Err(InvalidAccountData)),
+            ("withdraw", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("withdraw", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("withdraw", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("withdraw", "mint", WrongMint, Err(InvalidAccountData)),
+            ("withdraw", "fee_config", WrongPda, Err(InvalidAccountData)),
+            ("withdraw_to", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("withdraw_to", "authority", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "sender", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "recipient_tokens", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            // Only names the recipient, withdraw_to pays into the destination.
+            ("withdraw_to", "recipient_tokens", WrongOwner, Ok(())),
+            ("withdraw_to", "metadata", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("withdraw_to", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("withdraw_to", "escrow_tokens", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("withdraw_to", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("withdraw_to", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("withdraw_to", "mint", WrongMint, Err(InvalidAccountData)),
+            ("withdraw_to", "destination_tokens", NotWritable, Err(InvalidAccountData)),
+            ("withdraw_to", "destination_tokens", WrongOwner, Err(InvalidAccountData)),
+            ("withdraw_to", "fee_config", WrongPda, Err(InvalidAccountData)),
+            ("crank_withdraw", "keeper", NotSigner, Err(MissingRequiredSignature)),
+            ("crank_withdraw", "keeper_tokens", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "keeper_tokens", WrongOwner, Err(InvalidAccountData)),
+            ("crank_withdraw", "sender", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "recipient", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "recipient_tokens", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("crank_withdraw", "recipient_tokens", WrongOwner, Err(EXTERNAL_ACCOUNT_DATA_MODIFIED)),
+            ("crank_withdraw", "metadata", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("crank_withdraw", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("crank_withdraw", "escrow_tokens", NotWritable, Err(InvalidAccountData)),
+            ("crank_withdraw", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("crank_withdraw", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("crank_withdraw", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("crank_withdraw", "mint", WrongMint, Err(InvalidAccountData)),
+            ("crank_withdraw", "fee_config", WrongPda, Err(InvalidAccountData)),
+            ("cancel", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("cancel", "sender_tokens", NotWritable, Err(InvalidAccountData)),
+            ("cancel", "sender_tokens", WrongOwner, Err(EXTERNAL_ACCOUNT_DATA_MODIFIED)),
+            ("cancel", "recipient", NotWritable, Err(InvalidAccountData)),
+            ("cancel", "recipient_tokens", NotWritable, Err(InvalidAccountData)),
+            ("cancel", "recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("cancel", "recipient_tokens", WrongOwner, Err(EXTERNAL_ACCOUNT_DATA_MODIFIED)),
+            ("cancel", "metadata", NotWritable, Err(InvalidAccountData)),
+            ("cancel", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("cancel", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("cancel", "escrow_tokens", NotWritable, Err(InvalidAccountData)),
+            ("cancel", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("cancel", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("cancel", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("cancel", "mint", WrongMint, Err(InvalidAccountData)),
+            ("cancel", "fee_config", WrongPda, Err(InvalidAccountData)),
+            ("topup", "sender", NotSigner, Err(MissingRequiredSignature)),
+            ("topup", "sender", NotWritable, Err(AccountsNotWritable.into())),
+            ("topup", "sender_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("topup", "sender_tokens", WrongOwner, Err(InvalidAccountData)),
+            ("topup", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("topup", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("topup", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("topup", "escrow_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("topup", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("topup", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("topup", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("topup", "mint", WrongMint, Err(MintMismatch.into())),
+            ("transfer_recipient", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("transfer_recipient", "authority", NotWritable, Err(InvalidAccountData)),
+            ("transfer_recipient", "new_recipient_tokens", NotWritable, Err(InvalidAccountData)),
+            ("transfer_recipient", "new_recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("transfer_recipient", "metadata", NotWritable, Err(InvalidAccountData)),
+            ("transfer_recipient", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("transfer_recipient", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("transfer_recipient", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("transfer_recipient", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("transfer_recipient", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("transfer_recipient", "mint", WrongMint, Err(InvalidAccountData)),
+            ("propose_transfer", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("propose_transfer", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("propose_transfer", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("propose_transfer", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("accept_transfer", "new_recipient", NotSigner, Err(MissingRequiredSignature)),
+            ("accept_transfer", "new_recipient", NotWritable, Err(AccountsNotWritable.into())),
+            ("accept_transfer", "new_recipient_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("accept_transfer", "new_recipient_tokens", WrongPda, Err(InvalidAccountData)),
+            ("accept_transfer", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("accept_transfer", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("accept_transfer", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("accept_transfer", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("accept_transfer", "mint", WrongMint, Err(InvalidAccountData)),
+            ("cancel_transfer", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("cancel_transfer", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("cancel_transfer", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("cancel_transfer", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("close_stream", "sender", NotSigner, Err(MissingRequiredSignature)),
+            ("close_stream", "sender", NotWritable, Err(AccountsNotWritable.into())),
+            ("close_stream", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("close_stream", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("close_stream", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("close_stream", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("init_stream_mirror", "sender", NotSigner, Err(MissingRequiredSignature)),
+            ("init_stream_mirror", "sender", NotWritable, Err(AccountsNotWritable.into())),
+            ("init_stream_mirror", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("init_stream_mirror", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("init_stream_mirror", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("init_stream_mirror", "mirror", NotWritable, Err(AccountsNotWritable.into())),
+            ("init_stream_mirror", "mirror", WrongPda, Err(InvalidAccountData)),
+            ("archive_stream", "payer", NotSigner, Err(MissingRequiredSignature)),
+            ("archive_stream", "payer", NotWritable, Err(AccountsNotWritable.into())),
+            ("archive_stream", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("archive_stream", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("archive_stream", "settlement", NotWritable, Err(AccountsNotWritable.into())),
+            ("archive_stream", "settlement", WrongPda, Err(InvalidAccountData)),
+            ("cancel_subscription", "authority", NotSigner, Err(MissingRequiredSignature)),
+            ("cancel_subscription", "sender_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("cancel_subscription", "sender_tokens", WrongOwner, Err(EXTERNAL_ACCOUNT_DATA_MODIFIED)),
+            ("cancel_subscription", "metadata", NotWritable, Err(AccountsNotWritable.into())),
+            ("cancel_subscription", "metadata", WrongPda, Err(InvalidAccountData)),
+            ("cancel_subscription", "metadata", WrongOwner, Err(UninitializedAccount)),
+            ("cancel_subscription", "escrow_tokens", NotWritable, Err(AccountsNotWritable.into())),
+            ("cancel_subscription", "escrow_tokens", WrongPda, Err(InvalidAccountData)),
+            ("cancel_subscription", "escrow_tokens", WrongOwner, Err(UninitializedAccount)),
+            ("cancel_subscription", "mint", WrongOwner, Err(InvalidAccountData)),
+            ("cancel_subscription", "mint", WrongMint, Err(InvalidAccountData)),
+        ]
+    }
+
+    #[test]
+    fn negative_path_matrix() {
+        let expected = expected();
+        let mut checked = 0;
+        for case in CASES {
+            let mut bank = Bank::new();
+            let instruction = (case.setup)(&mut bank);
+            assert_eq!(
+                instruction.accounts.len(),
+                case.roles.len(),
+                "{}",
+                case.name
+            );
+            let mutations = mutations(&bank, &instruction);
+            bank.process(&instruction).unwrap();
+
+            for (index, mutation) in mutations {
+                let role = case.roles[index];
+                let outcome = expected
+                    .iter()
+                    .find(|(name, r, m, _)| *name == case.name && *r == role && *m == mutation)
+                    .map(|(.., outcome)| outcome)
+                    .unwrap_or_else(|| panic!("{} {} {:?} untested", case.name, role, mutation));
+
+                let mut bank = Bank::new();
+                let mut instruction = (case.setup)(&mut bank);
+                mutate(&mut bank, &mut instruction, index, mutation);
+                assert_eq!(
+                    &bank.process(&instruction),
+                    outcome,
+                    "{} {} {:?}",
+                    case.name,
+                    role,
+                    mutation
+                );
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, expected.len());
+    }
+
+    /// Whether `condition` of the permission table holds for `stream`.
+    fn holds(condition: Condition, stream: &TokenStreamData, now: u64) -> bool {
+        let ix = &stream.ix;
+        match condition {
+            Condition::Always => true,
+            Condition::Closable => stream.effective_now(now) >= stream.closable_at,
+            Condition::Flag("withdrawal_public") => ix.withdrawal_public,
+            Condition::Flag("cancelable_by_sender") => ix.cancelable_by_sender,
+            Condition::Flag("cancelable_by_recipient") => ix.cancelable_by_recipient,
+            Condition::Flag("transferable_by_sender") => ix.transferable_by_sender,
+            Condition::Flag("transferable_by_recipient") => ix.transferable_by_recipient,
+            Condition::NonZero("withdraw_frequency") => ix.withdraw_frequency > 0,
+            Condition::Policy("can_topup", variant) => format!("{:?}", ix.can_topup) == variant,
+            other => panic!("{:?} not modeled", other),
+        }
+    }
+
+    type Call = fn(&mut Bank, &TestStream, Pubkey) -> Instruction;
+
+    /// Signer-only instructions of a stream, each built with the given wallet
+    /// as its authority.
+    fn calls() -> Vec<(u8, Call)> {
+        vec![
+            (1, |_, s, signer| {
+                let mut withdraw = s.withdraw(0);
+                withdraw.accounts[0] = AccountMeta::new(signer, true);
+                withdraw
+            }),
+            (2, |_, s, signer| s.cancel(&signer)),
+            (3, |bank, s, signer| {
+                builder::transfer_recipient(
+                    &PROGRAM_ID,
+                    &signer,
+                    &bank.wallet(),
+                    &s.metadata,
+                    &s.escrow_tokens,
+                    &s.mint,
+                    &spl_token::id(),
+                )
+            }),
+            (4, |bank, s, signer| {
+                let tokens = bank.key();
+                bank.tokens_at(tokens, &s.mint, &signer, 100);
+                builder::topup_stream(
+                    &PROGRAM_ID,
+                    &signer,
+                    &tokens,
+                    &s.metadata,
+                    &s.escrow_tokens,
+                    &s.mint,
+                    &spl_token::id(),
+                    100,
+                )
+            }),
+            (5, |_, s, signer| Instruction {
+                program_id: PROGRAM_ID,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer, true),
+                    AccountMeta::new(s.metadata, false),
+                ],
+                data: builder::pack_versioned(5, &[]),
+            }),
+            (14, |_, s, signer| Instruction {
+                program_id: PROGRAM_ID,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer, true),
+                    AccountMeta::new(s.metadata, false),
+                ],
+                data: builder::pack_versioned(14, &10u64.to_le_bytes()),
+            }),
+            (28, |_, s, signer| Instruction {
+                program_id: PROGRAM_ID,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer, true),
+                    AccountMeta::new(s.metadata, false),
+                ],
+                data: builder::pack_versioned(28, &[]),
+            }),
+            (30, |bank, s, signer| {
+                let keeper_tokens = bank.key();
+                bank.tokens_at(keeper_tokens, &s.mint, &signer, 0);
+                builder::crank_withdraw(
+                    &PROGRAM_ID,
+                    &signer,
+                    &keeper_tokens,
+                    &s.sender,
+                    &s.recipient,
+                    &s.recipient_tokens,
+                    &s.metadata,
+                    &s.escrow_tokens,
+                    &s.mint,
+                    &spl_token::id(),
+                )
+            }),
+            (35, |bank, s, signer| {
+                builder::propose_transfer(&PROGRAM_ID, &signer, &bank.wallet(), &s.metadata)
+            }),
+        ]
+    }
+
+    #[test]
+    fn handlers_match_the_permission_table() {
+        let streams = [
+            StreamInstruction {
+                max_recipient_pause: 100,
+                ..stream_ix()
+            },
+            StreamInstruction {
+                cancelable_by_sender: false,
+                cancelable_by_recipient: true,
+                transferable_by_sender: true,
+                transferable_by_recipient: false,
+                withdrawal_public: true,
+                withdraw_frequency: 100,
+                can_topup: TopUpPolicy::Anyone,
+                max_recipient_pause: 100,
+                ..stream_ix()
+            },
+        ];
+        let calls = calls();
+        let roles = [Role::Sender, Role::Recipient, Role::Anyone];
+
+        for (variant, ix) in streams.iter().enumerate() {
+            // Before the end only the table's signers get in; after it,
+            // anyone may settle a cancel.
+            for (tag, at) in calls
+                .iter()
+                .map(|(tag, _)| (*tag, NOW + 350))
+                .chain([(2, NOW + 1_200)])
+            {
+                let call = calls.iter().find(|(t, _)| *t == tag).unwrap().1;
+                for role in roles {
+                    let mut bank = Bank::new();
+                    let stream = bank.create_stream(ix);
+                    bank.warp(at);
+                    let signer = match role {
+                        Role::Sender => stream.sender,
+                        Role::Recipient => stream.recipient,
+                        _ => bank.wallet(),
+                    };
+                    let metadata = bank.stream(&stream.metadata);
+                    let allowed = permissions(tag).any(|p| {
+                        (p.signers == [role] || p.signers == [Role::Anyone])
+                            && holds(p.condition, &metadata, at)
+                    });
+
+                    let instruction = call(&mut bank, &stream, signer);
+                    let result = bank.process(&instruction);
+                    assert_eq!(
+                        result.is_ok(),
+                        allowed,
+                        "tag {} as {:?} at {} of stream {}: {:?}",
+                        tag,
+                        role,
+                        at,
+                        variant,
+                        result
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Per-wallet index of streams, so wallets can list the streams a user sends
+//! or receives with a few account fetches instead of a `getProgramAccounts`
+//! scan. Indexes are paged `StreamIndex` PDAs created on demand with
+//! `init_stream_index`; `create`, `transfer_recipient` and `close_stream`
+//! keep the pages passed to them up to date.
+
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, InvalidMetadata, StreamIndexFull};
+use crate::state::{
+    IndexRole, InitStreamIndexAccounts, StreamIndex, StreamIndexKey, TokenStreamData,
+    STREAM_INDEX_PAGE_LEN, STREAM_INDEX_SEED, STREAM_INDEX_SIZE,
+};
+
+pub fn initialize_stream_index(
+    program_id: &Pubkey,
+    acc: InitStreamIndexAccounts,
+    key: StreamIndexKey,
+) -> ProgramResult {
+    msg!("Initializing stream index");
+
+    if !acc.index.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.payer.is_writable || !acc.index.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (index_pubkey, nonce) = StreamIndex::address(program_id, acc.owner.key, key.role, key.page);
+    if acc.index.key != &index_pubkey || acc.system_program.key != &system_program::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let role = [key.role as u8];
+    let page = key.page.to_le_bytes();
+    let seeds = [
+        STREAM_INDEX_SEED,
+        acc.owner.key.as_ref(),
+        &role,
+        &page,
+        &[nonce],
+    ];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.payer.key,
+            acc.index.key,
+            Rent::get()?.minimum_balance(STREAM_INDEX_SIZE),
+            STREAM_INDEX_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.payer.clone(),
+            acc.index.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let index = StreamIndex {
+        owner: *acc.owner.key,
+        role: key.role,
+        page: key.page,
+        streams: vec![],
+    };
+    let bytes = index.try_to_vec()?;
+    let mut data = acc.index.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Stream index page {} of {}", key.page, acc.owner.key);
+
+    Ok(())
+}
+
+/// Loads `account` as a `StreamIndex` page.
+fn load_index(program_id: &Pubkey, account: &AccountInfo) -> Result<StreamIndex, ProgramError> {
+    if account.data_is_empty() || account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !account.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let index: StreamIndex =
+        match solana_borsh::try_from_slice_unchecked(&account.try_borrow_data()?) {
+            Ok(v) => v,
+            Err(_) => return Err(InvalidMetadata.into()),
+        };
+
+    let (address, _) = StreamIndex::address(program_id, &index.owner, index.role, index.page);
+    if account.key != &address {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(index)
+}
+
+/// The wallet whose `role` index lists `stream`; hidden recipients aren't
+/// listed, that would reveal them.
+fn party(stream: &TokenStreamData, role: IndexRole) -> Option<Pubkey> {
+    match role {
+        IndexRole::Sender => Some(stream.sender),
+        IndexRole::Recipient if stream.recipient_revealed() => Some(stream.recipient),
+        IndexRole::Recipient => None,
+    }
+}
+
+/// Brings the index pages in `indexes` up to date with the stream at `key`
+/// going from `before` to `after`, `None` for not existing: pages of a party
+/// it no longer has drop it, pages of its current parties list it. Every
+/// page must belong to one of them.
+pub(crate) fn sync_indexes(
+    program_id: &Pubkey,
+    indexes: &[AccountInfo],
+    key: &Pubkey,
+    before: Option<&TokenStreamData>,
+    after: Option<&TokenStreamData>,
+) -> ProgramResult {
+    for account in indexes {
+        let mut index = load_index(program_id, account)?;
+        let was = before.and_then(|s| party(s, index.role)) == Some(index.owner);
+        let is = after.and_then(|s| party(s, index.role)) == Some(index.owner);
+
+        if is {
+            if index.streams.contains(key) {
+                continue;
+            }
+            if index.streams.len() >= STREAM_INDEX_PAGE_LEN {
+                msg!("Error: Stream index page {} is full", index.page);
+                return Err(StreamIndexFull.into());
+            }
+            index.streams.push(*key);
+        } else if was {
+            index.streams.retain(|s| s != key);
+        } else {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // Clear the old encoding first, the list may have shrunk.
+        let bytes = index.try_to_vec()?;
+        let mut data = account.try_borrow_mut_data()?;
+        data.fill(0);
+        data[0..bytes.len()].clone_from_slice(&bytes);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+use crate::error::StreamFlowError::{
+    AccountsNotWritable, ComplianceFrozen, RecipientNotRevealed, RolloverNotAllowed, StreamClosed,
+};
+use crate::state::{
+    EndCause, InitializeAccounts, RolloverAccounts, StreamInstruction, TokenStreamData,
+};
+use crate::token::{create_stream, notify, EscrowSource};
+use crate::utils::{encode_base10, unpack_mint_account, volatile};
+
+/// Ends a stream early and funds a new one, with its own schedule and
+/// recipient, straight from the old escrow. What vested so far goes to the old
+/// recipient; the new stream's `deposited_amount` is the unvested remainder,
+/// whatever `ix` says, and `total_amount` is raised to match if needed.
+pub fn rollover(
+    program_id: &Pubkey,
+    acc: RolloverAccounts,
+    mut ix: StreamInstruction,
+) -> ProgramResult {
+    msg!("Rolling over SPL token stream");
+
+    if acc.old_metadata.data_is_empty()
+        || acc.old_metadata.owner != program_id
+        || acc.old_escrow_tokens.data_is_empty()
+        || acc.old_escrow_tokens.owner != &spl_token::id()
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.old_metadata.is_writable
+        || !acc.old_escrow_tokens.is_writable
+        || !acc.old_recipient_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if acc.token_program.key != &spl_token::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = acc.old_metadata.try_borrow_mut_data()?;
+    let mut old = TokenStreamData::load(&data)?;
+
+    let escrow_seed = old.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.old_metadata.key.as_ref(), &escrow_seed], program_id);
+    if acc.old_escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.old_escrow_tokens.key != &old.escrow_tokens
+        || acc.sender.key != &old.sender
+        || acc.sender_tokens.key != &old.sender_tokens
+        || acc.mint.key != &old.mint
+        || !old.has_address(program_id, acc.old_metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Streams whose settlement needs extra accounts are canceled instead.
+    if old.canceled_at > 0
+        || !old.ix.shares.is_empty()
+        || old.ix.managed_freeze
+        || old.ix.obligation_nft
+        || old.ix.bond_amount > 0
+        || ix.bond_amount > 0
+    {
+        return Err(RolloverNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if old.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    if old.effective_now(now) >= old.closable_at {
+        msg!("Error: Nothing left to roll over");
+        return Err(StreamClosed.into());
+    }
+
+    let available = old.available(now);
+    if available > 0 && !old.recipient_revealed() {
+        return Err(RecipientNotRevealed.into());
+    }
+    if available > 0 && acc.old_recipient_tokens.key != &old.recipient_tokens {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let remains = old.ix.deposited_amount - old.withdrawn_amount - available;
+    if remains == 0 {
+        msg!("Error: Nothing left to roll over");
+        return Err(StreamClosed.into());
+    }
+
+    let seeds = [acc.old_metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    if available > 0 {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                acc.token_program.key,
+                acc.old_escrow_tokens.key,
+                acc.old_recipient_tokens.key,
+                acc.old_escrow_tokens.key,
+                &[],
+                available,
+            )?,
+            &[
+                acc.old_escrow_tokens.clone(),
+                acc.old_recipient_tokens.clone(),
+                acc.old_escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    ix.deposited_amount = remains;
+    ix.total_amount = ix.total_amount.max(remains);
+
+    let ia = InitializeAccounts {
+        sender: acc.sender.clone(),
+        sender_tokens: acc.sender_tokens.clone(),
+        recipient: acc.recipient.clone(),
+        recipient_tokens: acc.recipient_tokens.clone(),
+        metadata: acc.metadata.clone(),
+        escrow_tokens: acc.escrow_tokens.clone(),
+        mint: acc.mint.clone(),
+        rent: acc.rent.clone(),
+        token_program: acc.token_program.clone(),
+        associated_token_program: acc.associated_token_program.clone(),
+        system_program: acc.system_program.clone(),
+        stream_counter: Some(acc.stream_counter.clone()),
+        freeze_authority: None,
+        freeze_issuer: None,
+        obligation_mint: None,
+        obligation_tokens: None,
+        token_2022_program: None,
+        indexes: vec![],
+        multisig: None,
+        multisig_signers: vec![],
+        payer: None,
+    };
+    let source = EscrowSource {
+        metadata: *acc.old_metadata.key,
+        escrow_tokens: acc.old_escrow_tokens.clone(),
+        seeds: &seeds,
+    };
+    create_stream(program_id, ia, ix, Some(source))?;
+
+    let escrow_tokens_rent = acc.old_escrow_tokens.lamports();
+    invoke_signed(
+        &spl_token::instruction::close_account(
+            acc.token_program.key,
+            acc.old_escrow_tokens.key,
+            acc.sender.key,
+            acc.old_escrow_tokens.key,
+            &[],
+        )?,
+        &[
+            acc.old_escrow_tokens.clone(),
+            acc.sender.clone(),
+            acc.old_escrow_tokens.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    old.withdrawn_amount += available;
+    old.last_withdrawn_at = now;
+    old.canceled_at = now;
+    old.end_cause = EndCause::RolledOver;
+    let bytes = old.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    msg!(
+        "Transferred: {} {} tokens",
+        encode_base10(available, mint_info.decimals.into()),
+        old.mint
+    );
+    msg!(
+        "Rolled over {} {} tokens into {}",
+        encode_base10(remains, mint_info.decimals.into()),
+        old.mint,
+        acc.metadata.key
+    );
+    msg!("Returned rent: {} lamports", volatile(escrow_tokens_rent));
+
+    notify(&old);
+
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program, sysvar,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{
+    AccountsNotWritable, ComplianceFrozen, DestinationNotAllowed, SplitNotAllowed, StreamClosed,
+    TransferCooldown, TransferNotAllowed,
+};
+use crate::state::{
+    ClaimHistory, EndCause, SplitAccounts, SplitInstruction, TokenStreamData, METADATA_SEED,
+};
+use crate::token::{
+    create_recipient_ata, escrow_account_size, next_stream_seq, notify, retarget, transfer_checked,
+};
+use crate::utils::{
+    associated_token_address, encode_base10, is_token_program, unpack_mint_account,
+    unpack_token_account,
+};
+
+/// `share_bps` basis points of `amount`, rounded down.
+fn share(amount: u64, share_bps: u16) -> u64 {
+    (amount as u128 * share_bps as u128 / 10_000) as u64
+}
+
+/// Splits `share_bps` of a stream off into a new one for `new_recipient`,
+/// on the same schedule and terms. Deposited, withdrawn and scheduled
+/// amounts are divided proportionally, rounding in favor of the original
+/// stream, and the new stream's unwithdrawn part moves into its own escrow.
+/// The recipient may split if the stream is transferable by the recipient,
+/// the sender if it is transferable by the sender; whoever splits pays rent.
+pub fn split_stream(
+    program_id: &Pubkey,
+    acc: SplitAccounts,
+    split: SplitInstruction,
+) -> ProgramResult {
+    msg!("Splitting SPL token stream");
+
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || acc.escrow_tokens.data_is_empty()
+        || !is_token_program(acc.escrow_tokens.owner)
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.new_metadata.data_is_empty() || !acc.new_escrow_tokens.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.authority.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+        || !acc.new_recipient_tokens.is_writable
+        || !acc.new_metadata.is_writable
+        || !acc.new_escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if split.share_bps == 0 || split.share_bps >= 10_000 {
+        msg!("Error: Share must be between 0 and 10000 bps, exclusive");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    let (new_metadata_pubkey, new_metadata_bump) = TokenStreamData::metadata_address(
+        program_id,
+        &metadata.sender,
+        acc.new_recipient.key,
+        acc.mint.key,
+        &split.metadata_seed,
+    );
+    let (new_escrow_tokens_pubkey, new_nonce) =
+        Pubkey::find_program_address(&[acc.new_metadata.key.as_ref()], program_id);
+    let new_recipient_tokens_key =
+        associated_token_address(acc.new_recipient.key, acc.mint.key, acc.token_program.key);
+
+    if acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.mint.key != &metadata.mint
+        || acc.new_metadata.key != &new_metadata_pubkey
+        || acc.new_escrow_tokens.key != &new_escrow_tokens_pubkey
+        || acc.new_recipient_tokens.key != &new_recipient_tokens_key
+        || acc.token_program.key != acc.escrow_tokens.owner
+        || acc.system_program.key != &system_program::id()
+        || acc.rent.key != &sysvar::rent::id()
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let by_recipient = metadata.ix.transferable_by_recipient
+        && metadata.recipient_revealed()
+        && acc.authority.key == &metadata.recipient;
+    let by_sender = metadata.ix.transferable_by_sender && acc.authority.key == &metadata.sender;
+    if !by_recipient && !by_sender {
+        return Err(TransferNotAllowed.into());
+    }
+
+    // Streams whose settlement needs extra accounts are canceled instead.
+    if !metadata.recipient_revealed()
+        || !metadata.ix.shares.is_empty()
+        || metadata.ix.managed_freeze
+        || metadata.ix.obligation_nft
+        || metadata.ix.bond_amount > 0
+    {
+        return Err(SplitNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.canceled_at > 0
+        || metadata.end_cause != EndCause::Open
+        || metadata.effective_now(now) >= metadata.closable_at
+    {
+        return Err(StreamClosed.into());
+    }
+
+    if metadata.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    if metadata.transfer_cooling_down(now) {
+        return Err(TransferCooldown.into());
+    }
+
+    if !metadata.may_transfer_to(acc.new_recipient.key) {
+        return Err(DestinationNotAllowed.into());
+    }
+
+    metadata.settle_halt(now);
+    let mut stream: TokenStreamData = metadata.clone();
+    stream.ix.deposited_amount = share(metadata.ix.deposited_amount, split.share_bps);
+    stream.withdrawn_amount = share(metadata.withdrawn_amount, split.share_bps);
+    stream.ix.total_amount = share(metadata.ix.total_amount, split.share_bps);
+    stream.ix.cliff_amount = share(metadata.ix.cliff_amount, split.share_bps);
+    stream.ix.release_rate = share(metadata.ix.release_rate, split.share_bps);
+    for tranche in stream.ix.tranches.iter_mut() {
+        tranche.amount = share(tranche.amount, split.share_bps);
+    }
+    if !stream.ix.tranches.is_empty() {
+        stream.ix.total_amount = stream.ix.tranches.iter().map(|t| t.amount).sum();
+    }
+
+    metadata.ix.deposited_amount -= stream.ix.deposited_amount;
+    metadata.withdrawn_amount -= stream.withdrawn_amount;
+    metadata.ix.total_amount -= stream.ix.total_amount;
+    metadata.ix.cliff_amount -= stream.ix.cliff_amount;
+    metadata.ix.release_rate -= stream.ix.release_rate;
+    for (tranche, split_off) in metadata.ix.tranches.iter_mut().zip(&stream.ix.tranches) {
+        tranche.amount -= split_off.amount;
+    }
+
+    let moved = stream.ix.deposited_amount - stream.withdrawn_amount;
+    if moved == 0 || (metadata.ix.release_rate > 0 && stream.ix.release_rate == 0) {
+        msg!("Error: Share too small to split off");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    stream.created_at = now;
+    stream.recipient = *acc.new_recipient.key;
+    stream.recipient_tokens = *acc.new_recipient_tokens.key;
+    stream.escrow_tokens = *acc.new_escrow_tokens.key;
+    stream.nonce = 0;
+    stream.min_claim_amount = 0;
+    stream.mint_migrated = false;
+    stream.last_withdraw_slot = 0;
+    stream.last_transferred_at = now;
+    stream.pending_recipient = Pubkey::default();
+    stream.pending_proposer = Pubkey::default();
+    stream.other_contributions = 0;
+    stream.contributions = vec![];
+    stream.cancel_requested_at = 0;
+    stream.fees_paid = 0;
+    stream.legacy_address = Pubkey::default();
+    stream.has_mirror = false;
+    stream.last_topup_at = 0;
+    stream.last_topup_amount = 0;
+    stream.last_topup_payer = Pubkey::default();
+    stream.last_topup_tokens = Pubkey::default();
+    stream.rolled_from = *acc.metadata.key;
+    stream.forward_to = Pubkey::default();
+    stream.claim_history = ClaimHistory::default();
+    stream.ix.recipient_hash = [0; 32];
+    stream.ix.metadata_seed = split.metadata_seed;
+    stream.derived_recipient = *acc.new_recipient.key;
+    stream.derived_mint = *acc.mint.key;
+    stream.metadata_bump = new_metadata_bump;
+    msg!("Split from {}", stream.rolled_from);
+
+    stream.stream_seq = next_stream_seq(
+        program_id,
+        &Some(acc.stream_counter.clone()),
+        &acc.authority,
+        &acc.system_program,
+    )?;
+    msg!("Stream seq: {}", stream.stream_seq);
+
+    let metadata_struct_size = TokenStreamData::account_size(
+        stream.ix.tranches.len(),
+        stream.ix.transfer_allowlist.len(),
+        stream.ix.shares.len(),
+    );
+    let tokens_struct_size = escrow_account_size(&acc.token_program, &acc.mint)?;
+    let cluster_rent = Rent::get()?;
+
+    msg!("Initializing new recipient's associated token account");
+    create_recipient_ata(
+        &acc.authority,
+        &acc.new_recipient,
+        &acc.new_recipient_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    let new_metadata_seeds = [
+        METADATA_SEED,
+        stream.sender.as_ref(),
+        stream.derived_recipient.as_ref(),
+        stream.derived_mint.as_ref(),
+        &stream.ix.metadata_seed,
+        &[new_metadata_bump],
+    ];
+    msg!("Creating account for holding metadata");
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.authority.key,
+            acc.new_metadata.key,
+            cluster_rent.minimum_balance(metadata_struct_size),
+            metadata_struct_size as u64,
+            program_id,
+        ),
+        &[
+            acc.authority.clone(),
+            acc.new_metadata.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&new_metadata_seeds],
+    )?;
+
+    let new_seeds = [acc.new_metadata.key.as_ref(), &[new_nonce]];
+    msg!("Creating account for holding tokens");
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.authority.key,
+            acc.new_escrow_tokens.key,
+            cluster_rent.minimum_balance(tokens_struct_size),
+            tokens_struct_size as u64,
+            acc.token_program.key,
+        ),
+        &[
+            acc.authority.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&new_seeds],
+    )?;
+
+    invoke(
+        &retarget(
+            spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                acc.new_escrow_tokens.key,
+                acc.mint.key,
+                acc.new_escrow_tokens.key,
+            )?,
+            acc.token_program.key,
+        ),
+        &[
+            acc.token_program.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.mint.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.rent.clone(),
+        ],
+    )?;
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    invoke_signed(
+        &transfer_checked(
+            acc.token_program.key,
+            acc.escrow_tokens.key,
+            acc.mint.key,
+            acc.new_escrow_tokens.key,
+            acc.escrow_tokens.key,
+            &[],
+            moved,
+            mint_info.decimals,
+        )?,
+        &[
+            acc.escrow_tokens.clone(),
+            acc.mint.clone(),
+            acc.new_escrow_tokens.clone(),
+            acc.escrow_tokens.clone(),
+            acc.token_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    // As on create, the new stream only promises what actually arrived.
+    let received = unpack_token_account(&acc.new_escrow_tokens)?.amount;
+    if received < moved {
+        msg!(
+            "Transfer fee: {}",
+            encode_base10(moved - received, mint_info.decimals.into())
+        );
+        stream.ix.deposited_amount = stream.withdrawn_amount + received;
+    }
+
+    for s in [&mut metadata, &mut stream] {
+        s.cache_schedule();
+        s.closable_at = s.closable();
+    }
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+    let bytes = stream.try_to_vec()?;
+    acc.new_metadata.try_borrow_mut_data()?[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Split {} {} tokens off into {} for {}",
+        encode_base10(stream.ix.deposited_amount, mint_info.decimals.into()),
+        stream.mint,
+        acc.new_metadata.key,
+        stream.recipient
+    );
+
+    notify(&metadata);
+    notify(&stream);
+
+    Ok(())
+}
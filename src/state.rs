@@ -1,9 +1,212 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
+use solana_program::{
+    account_info::AccountInfo, borsh as solana_borsh, msg, program_error::ProgramError,
+    pubkey::Pubkey,
+};
 
-pub const PROGRAM_VERSION: u64 = 2;
+use crate::error::StreamFlowError::{
+    ArithmeticOverflow, InvalidMetadata, MetadataNeedsMigration, StaleNonce, StartTooFar,
+};
+use crate::migrations::{decode_outdated, stored_version};
+use crate::utils::normalize_timestamp;
 
-#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+/// Stored in `TokenStreamData::magic`, bumped whenever the layout changes,
+/// fields added included; see `migrations` for earlier ones. 3 since `stream_name` became
+/// fixed size.
+pub const PROGRAM_VERSION: u64 = 3;
+
+/// Seed, along with the mint, of the PDA the program signs thaw/freeze with.
+pub const FREEZE_AUTHORITY_SEED: &[u8] = b"freeze";
+
+/// Marks `GrantLedger` accounts, as opposed to `TokenStreamData` ones.
+pub const LEDGER_MAGIC: u64 = 0x5245_4744_454c; // "LEDGER"
+pub const MAX_LEDGER_GRANTS: u8 = 16;
+
+/// Bytes of `StreamInstruction::stream_name`.
+pub const STREAM_NAME_SIZE: usize = 64;
+
+/// Fixed point scale of `TokenStreamData::period_amount_scaled`.
+pub const AMOUNT_SCALE: u128 = 1_000_000_000_000;
+
+/// Seed, along with the old and the new mint, of `ConversionPool` accounts.
+pub const CONVERSION_POOL_SEED: &[u8] = b"conversion";
+pub const CONVERSION_POOL_SIZE: usize = 32 * 3 + 8 * 2;
+
+/// Seed of a stream's obligation mint, and with that mint, of its token account.
+pub const OBLIGATION_SEED: &[u8] = b"obligation";
+
+/// Longest a single `compliance_freeze` may block withdrawals for.
+pub const MAX_COMPLIANCE_FREEZE: u64 = 30 * 24 * 60 * 60;
+
+/// Furthest in the future a stream may start, 10 years.
+pub const MAX_START_DELAY: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// Epochs kept in `TokenStreamData::claim_history`.
+pub const CLAIM_HISTORY_LEN: usize = 12;
+
+/// Default of `StreamInstruction::topup_refund_window`.
+pub const DEFAULT_TOPUP_REFUND_WINDOW: u64 = 10 * 60;
+
+/// Byte offsets of `TokenStreamData` fields, e.g. for `memcmp` filters. Only
+/// fields ahead of `tranches`, the first variable length one, have fixed
+/// offsets, so fields worth filtering on must stay ahead of it; add new ones
+/// at the end instead.
+///
+/// `magic` is `PROGRAM_VERSION` for streams; filter on it as well to skip
+/// ledgers and other program accounts.
+pub const METADATA_MAGIC_OFFSET: usize = 0;
+pub const METADATA_CREATED_AT_OFFSET: usize = 8;
+pub const METADATA_CANCELED_AT_OFFSET: usize = 24;
+pub const METADATA_SENDER_OFFSET: usize = 48;
+pub const METADATA_SENDER_TOKENS_OFFSET: usize = 80;
+pub const METADATA_RECIPIENT_OFFSET: usize = 112;
+pub const METADATA_RECIPIENT_TOKENS_OFFSET: usize = 144;
+pub const METADATA_MINT_OFFSET: usize = 176;
+pub const METADATA_ESCROW_TOKENS_OFFSET: usize = 208;
+pub const METADATA_START_TIME_OFFSET: usize = 240;
+pub const METADATA_END_TIME_OFFSET: usize = 248;
+pub const METADATA_STREAM_NAME_OFFSET: usize = 309;
+pub const METADATA_NOTIFY_KEY_OFFSET: usize = 414;
+pub const METADATA_COMPLIANCE_AUTHORITY_OFFSET: usize = 449;
+pub const METADATA_PARTNER_OFFSET: usize = 554;
+/// Length prefix of `tranches`, the first field that moves.
+pub const METADATA_TRANCHES_OFFSET: usize = 588;
+/// Serialized size of `TokenStreamData` with empty `tranches`,
+/// `transfer_allowlist` and `shares`.
+pub const METADATA_BASE_SIZE: usize = 1340;
+/// Most tranches a milestone schedule may have, see `StreamInstruction::tranches`.
+pub const MAX_TRANCHES: usize = 32;
+pub const TRANCHE_SIZE: usize = 16;
+/// Most co-recipients a stream may share its payouts with, see
+/// `StreamInstruction::shares`.
+pub const MAX_SHARES: usize = 7;
+pub const RECIPIENT_SHARE_SIZE: usize = 34;
+/// Most destinations a stream's `transfer_allowlist` may hold.
+pub const MAX_TRANSFER_ALLOWLIST: usize = 8;
+/// Most contributors whose top-ups a stream tracks one by one, see
+/// `TokenStreamData::contributions`. Metadata accounts have room for them
+/// from the start.
+pub const MAX_CONTRIBUTORS: usize = 8;
+pub const CONTRIBUTION_SIZE: usize = 40;
+
+/// Seed, along with the stream's metadata, of its `TopUpAllowlist`.
+pub const TOPUP_ALLOWLIST_SEED: &[u8] = b"topup_allowlist";
+pub const MAX_TOPUP_ALLOWLIST: usize = 16;
+pub const TOPUP_ALLOWLIST_SIZE: usize = 32 + 4 + 32 * MAX_TOPUP_ALLOWLIST;
+/// Seed, along with the metadata account, of `SettlementRecord` PDAs.
+pub const SETTLEMENT_SEED: &[u8] = b"settlement";
+pub const SETTLEMENT_RECORD_SIZE: usize = 4 * 32 + 1 + 6 * 8;
+/// Seed, along with the metadata account, of `StreamMirror` PDAs.
+pub const STREAM_MIRROR_SEED: &[u8] = b"mirror";
+pub const STREAM_MIRROR_SIZE: usize = 32 + 3 + 18 * 8;
+/// Seed, along with the owner, role and page, of `StreamIndex` PDAs.
+pub const STREAM_INDEX_SEED: &[u8] = b"stream_index";
+/// Streams listed per `StreamIndex` page.
+pub const STREAM_INDEX_PAGE_LEN: usize = 64;
+pub const STREAM_INDEX_SIZE: usize = 32 + 1 + 4 + 4 + 32 * STREAM_INDEX_PAGE_LEN;
+
+/// Seed, along with the sender, recipient, mint and
+/// `StreamInstruction::metadata_seed`, of stream metadata accounts.
+pub const METADATA_SEED: &[u8] = b"metadata";
+
+/// Seed of the PDA counting created streams, see `TokenStreamData::stream_seq`.
+pub const STREAM_COUNTER_SEED: &[u8] = b"stream_seq";
+pub const STREAM_COUNTER_SIZE: usize = 8;
+
+/// Seed of the mint authority of demo mints, see the `devnet-faucet` feature.
+pub const FAUCET_SEED: &[u8] = b"faucet";
+
+pub const FEE_EXEMPTIONS_SEED: &[u8] = b"fee_exemptions";
+pub const MAX_FEE_EXEMPTIONS: usize = 32;
+pub const FEE_EXEMPTIONS_SIZE: usize = 32 + 4 + 32 * MAX_FEE_EXEMPTIONS;
+
+pub const FEE_CONFIG_SEED: &[u8] = b"fee_config";
+pub const FEE_CONFIG_SIZE: usize = 32 + 32 + 2;
+/// Highest protocol or partner fee, 10%.
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+/// What happens once an underfunded stream (`deposited_amount < total_amount`,
+/// or any release rate stream) has released everything deposited so far.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnderfundedPolicy {
+    /// The stream ends when funds run out and anyone can close it.
+    AutoClose,
+    /// Accrual halts until the next top-up, the stream stays open.
+    HaltAccrual,
+}
+
+/// What the recipient of a release rate stream is paid for the period in
+/// progress when it gets canceled.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartialPeriodPolicy {
+    /// Only whole periods are paid.
+    Forfeit,
+    /// The period in progress is paid in full.
+    PayFull,
+    /// The period in progress is paid for the seconds elapsed.
+    Prorate,
+}
+
+/// Who may top up a stream besides its sender, see `topup_stream`.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TopUpPolicy {
+    SenderOnly,
+    /// Members of the stream's `TopUpAllowlist`.
+    Allowlist,
+    Anyone,
+}
+
+/// Why a stream ended, see `TokenStreamData::end_cause`.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum EndCause {
+    /// Still running.
+    #[default]
+    Open,
+    /// Everything was released and withdrawn, or closed after the end.
+    Completed,
+    CanceledBySender,
+    CanceledByRecipient,
+    /// Closed once an `AutoClose` stream released all it was funded with.
+    AutoClosedUnderfunded,
+    /// Unvested remainder moved into a new stream, see `rollover`.
+    RolledOver,
+}
+
+/// Shape of a fixed schedule's release between the cliff and `end_time`.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnlockCurve {
+    /// The same amount every period.
+    Linear,
+    /// The linear amount, released only every `step_periods` periods, e.g.
+    /// quarterly over monthly periods.
+    Stepwise,
+    /// Released in proportion to the square of the periods passed, so most
+    /// of it vests towards the end.
+    BackLoaded,
+}
+
+/// Lump release of a milestone schedule.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Tranche {
+    pub unlock_time: u64,
+    pub amount: u64,
+}
+
+/// Co-recipient of a shared stream and their cut of every payout.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RecipientShare {
+    pub recipient: Pubkey,
+    pub bps: u16,
+}
+
+impl RecipientShare {
+    /// `bps` basis points of `amount`, rounded down.
+    pub fn of(&self, amount: u64) -> u64 {
+        (amount as u128 * self.bps as u128 / 10_000) as u64
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Eq, PartialEq)]
 #[repr(C)]
 pub struct StreamInstruction {
     pub start_time: u64,
@@ -19,7 +222,237 @@ pub struct StreamInstruction {
     pub transferable_by_sender: bool,
     pub transferable_by_recipient: bool,
     pub release_rate: u64,
-    pub stream_name: String,
+    /// UTF-8, zero padded, see `name` and `encode_name`.
+    pub stream_name: [u8; STREAM_NAME_SIZE],
+    pub recipient_hash: [u8; 32],
+    pub max_recipient_pause: u64,
+    pub underfunded_policy: UnderfundedPolicy,
+    /// Optional key echoed in the logs of every instruction touching the stream.
+    pub notify_key: Pubkey,
+    /// The mint's freeze authority is a 1-of-N SPL multisig including the
+    /// program's freeze PDA; recipient tokens are thawed around withdrawals.
+    pub managed_freeze: bool,
+    /// Withdrawing everything while nothing is available fails instead of
+    /// succeeding as a no-op.
+    pub strict_withdraw: bool,
+    /// Mint the sender a soulbound Token-2022 token mirroring the liability,
+    /// burned once the stream is settled.
+    pub obligation_nft: bool,
+    /// May temporarily block withdrawals, see `compliance_freeze`. Default
+    /// pubkey for none.
+    pub compliance_authority: Pubkey,
+    /// Seconds during which the payer of a top-up may take it back with
+    /// `undo_topup`, as long as none of it vested. 0 disables undoing.
+    pub topup_refund_window: u64,
+    /// Picked by the sender to tell apart streams of the same mint to the
+    /// same recipient, see `METADATA_SEED`.
+    pub metadata_seed: [u8; 32],
+    /// Posted by the sender on top of the deposit. Paid to the recipient if
+    /// the sender cancels before `bond_until`, returned to the sender
+    /// otherwise. Public streams only.
+    pub bond_amount: u64,
+    pub bond_until: u64,
+    /// Settlement of the period in progress on cancel, release rate streams
+    /// only. Fixed schedules pay whole periods, like `Forfeit`.
+    pub partial_period: PartialPeriodPolicy,
+    /// Seconds between withdrawals keepers may push with `crank_withdraw`,
+    /// 0 disables cranking.
+    pub withdraw_frequency: u64,
+    /// Paid to the keeper out of each cranked withdrawal.
+    pub cranker_fee: u64,
+    /// Integrator of the front-end the stream was created through, paid
+    /// `partner_fee_bps` of vested tokens paid out, on top of the protocol
+    /// fee. Default pubkey for none.
+    pub partner: Pubkey,
+    pub partner_fee_bps: u16,
+    /// Milestone schedule replacing the cliff and linear release: each
+    /// tranche's amount vests at its unlock time. Empty for linear streams.
+    pub tranches: Vec<Tranche>,
+    /// Fixed schedules only, release rate streams are linear.
+    pub curve: UnlockCurve,
+    pub step_periods: u64,
+    /// Shortest time between two reassignments of the recipient, 0 for no
+    /// limit.
+    pub transfer_cooldown: u64,
+    /// The only wallets the recipient may be reassigned to, e.g. verified
+    /// custodians. Empty for any.
+    pub transfer_allowlist: Vec<Pubkey>,
+    pub can_topup: TopUpPolicy,
+    /// Sender cancels only go through this long after they were first
+    /// attempted, see `TokenStreamData::cancel_requested_at`. 0 for none.
+    pub cancel_delay_seconds: u64,
+    /// Withdrawals need the recipient to still hold at least `hold_amount`
+    /// of `hold_mint`, e.g. for retention programs. 0 for no condition.
+    pub hold_mint: Pubkey,
+    pub hold_amount: u64,
+    /// Identifier of the dApp that created the stream, for attributing
+    /// volume per integration. Informational only, zeroed if not set.
+    pub origin: [u8; 16],
+    /// Renews every period, e.g. a monthly salary: each period's
+    /// `release_rate` is released in full at its start, as long as the
+    /// deposit covers it, until `cancel_subscription`. `end_time` plays no
+    /// part past `create`.
+    pub subscription: bool,
+    /// Co-recipients paid `bps` of every payout each, e.g. a founding team on
+    /// one grant; the recipient keeps the rest, rounding included. Empty for
+    /// a single recipient. Shared streams pay out with `withdraw_shares`.
+    pub shares: Vec<RecipientShare>,
+}
+
+/// `name` as a `stream_name`, cut at the last whole character that fits.
+pub fn encode_name(name: &str) -> [u8; STREAM_NAME_SIZE] {
+    let mut len = name.len().min(STREAM_NAME_SIZE);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    let mut bytes = [0; STREAM_NAME_SIZE];
+    bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+    bytes
+}
+
+impl StreamInstruction {
+    /// `stream_name` without its padding.
+    pub fn name(&self) -> String {
+        let len = self
+            .stream_name
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |i| i + 1);
+        String::from_utf8_lossy(&self.stream_name[..len]).into_owned()
+    }
+
+    /// Catches fat-fingered timestamps that `duration_sanity` lets through:
+    /// milliseconds instead of seconds, and starts beyond `MAX_START_DELAY`.
+    pub fn check_start(&self, now: u64) -> Result<(), ProgramError> {
+        for t in [self.start_time, self.end_time, self.cliff] {
+            normalize_timestamp(t)?;
+        }
+
+        if self.start_time > now.saturating_add(MAX_START_DELAY) {
+            msg!("Error: Start is more than {} seconds away", MAX_START_DELAY);
+            return Err(StartTooFar.into());
+        }
+
+        Ok(())
+    }
+
+    /// Rejects schedules the vesting math has no meaning for.
+    pub fn check_schedule(&self) -> Result<(), ProgramError> {
+        if self.period == 0 || self.cliff_amount > self.total_amount {
+            msg!("Error: Period must be positive and the cliff within the total");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let curved = self.curve != UnlockCurve::Linear;
+        if (curved && (self.release_rate > 0 || !self.tranches.is_empty()))
+            || (self.curve == UnlockCurve::Stepwise && self.step_periods == 0)
+        {
+            msg!("Error: Curves need a fixed schedule, steps a positive length");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        if self.subscription
+            && (self.release_rate == 0
+                || self.cliff_amount > 0
+                || !self.tranches.is_empty()
+                || self.underfunded_policy != UnderfundedPolicy::AutoClose)
+        {
+            msg!("Error: Subscriptions need a release rate and AutoClose, without cliff amount");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        if !self.tranches.is_empty() {
+            return self.check_tranches();
+        }
+
+        // Fractions of a base unit carry over to the next period, see
+        // `AMOUNT_SCALE`, but a schedule releasing less than one per period
+        // has periods releasing nothing and looks stuck to its recipient.
+        let vesting_start = if self.cliff > 0 {
+            self.cliff
+        } else {
+            self.start_time
+        };
+        let streamed = (self.total_amount - self.cliff_amount) as u128;
+        let duration = self.end_time.saturating_sub(vesting_start) as u128;
+        if self.release_rate == 0 && streamed > 0 && streamed * (self.period as u128) < duration {
+            msg!("Error: Less than one base unit released per period, lengthen the period");
+            return Err(ProgramError::InvalidArgument);
+        }
+        // Keeps the per-period amount within the streamed amount, see
+        // `cache_schedule`.
+        if self.release_rate == 0 && duration > 0 && self.period as u128 > duration {
+            msg!("Error: Period is longer than the vesting duration");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    /// Co-recipients must be distinct, other than `recipient`, and leave the
+    /// recipient a share of their own.
+    pub fn check_shares(&self, recipient: &Pubkey) -> Result<(), ProgramError> {
+        let mut total = 0u32;
+        for (i, share) in self.shares.iter().enumerate() {
+            if share.bps == 0
+                || &share.recipient == recipient
+                || self.shares[..i]
+                    .iter()
+                    .any(|s| s.recipient == share.recipient)
+            {
+                msg!("Error: Share {} is empty or a duplicate", i);
+                return Err(ProgramError::InvalidArgument);
+            }
+            total += share.bps as u32;
+        }
+
+        if self.shares.len() > MAX_SHARES || total >= 10_000 {
+            msg!(
+                "Error: At most {} shares, adding up to less than 10000 bps",
+                MAX_SHARES
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    /// Milestones must unlock in order within `start_time..=end_time` and add
+    /// up to `total_amount`, with no cliff or release rate on the side.
+    fn check_tranches(&self) -> Result<(), ProgramError> {
+        if self.tranches.len() > MAX_TRANCHES
+            || self.cliff > 0
+            || self.cliff_amount > 0
+            || self.release_rate > 0
+        {
+            msg!(
+                "Error: At most {} tranches, without cliff or release rate",
+                MAX_TRANCHES
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut total = 0u64;
+        for (i, tranche) in self.tranches.iter().enumerate() {
+            let in_order = match i {
+                0 => tranche.unlock_time >= self.start_time,
+                _ => tranche.unlock_time > self.tranches[i - 1].unlock_time,
+            };
+            if tranche.amount == 0 || !in_order || tranche.unlock_time > self.end_time {
+                msg!("Error: Tranche {} is empty or out of order", i);
+                return Err(ProgramError::InvalidArgument);
+            }
+            total = total.saturating_add(tranche.amount);
+        }
+
+        if total != self.total_amount {
+            msg!("Error: Tranches add up to {}, not the total", total);
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for StreamInstruction {
@@ -38,12 +471,44 @@ impl Default for StreamInstruction {
             transferable_by_sender: false,
             transferable_by_recipient: true,
             release_rate: 0,
-            stream_name: "Stream".to_string(),
+            stream_name: encode_name("Stream"),
+            recipient_hash: [0; 32],
+            max_recipient_pause: 0,
+            underfunded_policy: UnderfundedPolicy::AutoClose,
+            notify_key: Pubkey::default(),
+            managed_freeze: false,
+            strict_withdraw: false,
+            obligation_nft: false,
+            compliance_authority: Pubkey::default(),
+            topup_refund_window: DEFAULT_TOPUP_REFUND_WINDOW,
+            metadata_seed: [0; 32],
+            bond_amount: 0,
+            bond_until: 0,
+            partial_period: PartialPeriodPolicy::Forfeit,
+            withdraw_frequency: 0,
+            cranker_fee: 0,
+            partner: Pubkey::default(),
+            partner_fee_bps: 0,
+            tranches: vec![],
+            curve: UnlockCurve::Linear,
+            step_periods: 0,
+            transfer_cooldown: 0,
+            transfer_allowlist: vec![],
+            can_topup: TopUpPolicy::Anyone,
+            cancel_delay_seconds: 0,
+            hold_mint: Pubkey::default(),
+            hold_amount: 0,
+            origin: [0; 16],
+            subscription: false,
+            shares: vec![],
         }
     }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+/// Has no reserved block for future fields: v2 reserved one, but fields
+/// kept growing the layout rather than taking its bytes. Any new field is a
+/// layout change, see `PROGRAM_VERSION`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Default, Debug)]
 #[repr(C)]
 pub struct TokenStreamData {
     pub magic: u64,
@@ -59,6 +524,122 @@ pub struct TokenStreamData {
     pub mint: Pubkey,
     pub escrow_tokens: Pubkey,
     pub ix: StreamInstruction,
+    pub recipient_paused_at: u64,
+    pub recipient_paused_seconds: u64,
+    pub halted_seconds: u64,
+    /// Bumped whenever the stream's terms change. Approval instructions of
+    /// two-step flows must quote it, so stale approvals can't be replayed.
+    pub nonce: u64,
+    /// Cached at create/top-up so `available()` stays cheap, see `cache_schedule`.
+    pub effective_cliff: u64,
+    pub period_amount_scaled: u128,
+    /// Set by the recipient; withdrawals not signed by them must move at
+    /// least this much.
+    pub min_claim_amount: u64,
+    /// Set once the escrow was rotated to a new mint, see `escrow_seed`.
+    pub mint_migrated: bool,
+    /// Creation order across all streams, starting at 1. Streams that predate
+    /// the counter have 0.
+    pub stream_seq: u64,
+    /// Slot of the last withdrawal; a second one in the same slot is refused.
+    pub last_withdraw_slot: u64,
+    /// Withdrawals are blocked until then by the compliance authority.
+    pub compliance_frozen_until: u64,
+    /// The most recent top-up, kept until it is undone or the refund window
+    /// passes. `last_topup_amount` is 0 when there's nothing to undo.
+    pub last_topup_at: u64,
+    pub last_topup_amount: u64,
+    pub last_topup_payer: Pubkey,
+    pub last_topup_tokens: Pubkey,
+    /// Set instead of `canceled_at` when the stream ends on its own.
+    pub completed_at: u64,
+    pub end_cause: EndCause,
+    /// Stream whose unvested remainder funded this one, or that it was split
+    /// off from, if any.
+    pub rolled_from: Pubkey,
+    /// Token account of the recipient's choosing that withdrawals are paid
+    /// into instead of `recipient_tokens`, e.g. cold storage. Default pubkey
+    /// for none.
+    pub forward_to: Pubkey,
+    /// Cumulative withdrawn amount at the end of each of the last epochs
+    /// with a withdrawal, for claim history sparklines.
+    pub claim_history: ClaimHistory,
+    /// Employer pause, e.g. for a leave of absence. Unlike the recipient's
+    /// it has no budget; 0 when not paused.
+    pub sender_paused_at: u64,
+    pub sender_paused_seconds: u64,
+    /// Recipient and mint the metadata address was derived from, which
+    /// `recipient` and `mint` may have moved on from, and its bump.
+    pub derived_recipient: Pubkey,
+    pub derived_mint: Pubkey,
+    pub metadata_bump: u8,
+    /// When the recipient was last reassigned, 0 if never.
+    pub last_transferred_at: u64,
+    /// Recipient proposed by `propose_transfer`, who takes over on accepting,
+    /// and the wallet that proposed them. Default pubkeys when none is pending.
+    pub pending_recipient: Pubkey,
+    pub pending_proposer: Pubkey,
+    /// Top-ups by the first `MAX_CONTRIBUTORS` wallets to top up, net of
+    /// undone ones; `other_contributions` sums those of everyone after.
+    pub other_contributions: u64,
+    pub contributions: Vec<Contribution>,
+    /// When the sender first tried to cancel a stream with a cancel delay,
+    /// 0 if they haven't.
+    pub cancel_requested_at: u64,
+    /// Protocol, partner and cranker fees taken out of payouts so far.
+    pub fees_paid: u64,
+    /// Keypair address of streams created before metadata became PDAs, see
+    /// `migrations`. Default for everything else.
+    pub legacy_address: Pubkey,
+    /// Whether the stream has a `StreamMirror`, which withdrawals then have
+    /// to keep up to date.
+    pub has_mirror: bool,
+    /// When `cancel_subscription` stopped the renewals, 0 if it hasn't.
+    pub subscription_canceled_at: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Contribution {
+    pub contributor: Pubkey,
+    pub amount: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClaimBucket {
+    pub epoch: u64,
+    pub withdrawn: u64,
+}
+
+/// Ring buffer of `ClaimBucket`s; `head` is the slot of the latest one.
+/// Unused slots are zeroed.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClaimHistory {
+    pub buckets: [ClaimBucket; CLAIM_HISTORY_LEN],
+    pub head: u8,
+}
+
+impl ClaimHistory {
+    /// Notes the stream's `withdrawn` total as of `epoch`, starting a new
+    /// bucket (and dropping the oldest) on the first withdrawal of an epoch.
+    pub fn record(&mut self, epoch: u64, withdrawn: u64) {
+        let head = self.head as usize;
+        if self.buckets[head].epoch != epoch || self.buckets[head].withdrawn == 0 {
+            if self.buckets[head].withdrawn > 0 {
+                self.head = ((head + 1) % CLAIM_HISTORY_LEN) as u8;
+            }
+            self.buckets[self.head as usize].epoch = epoch;
+        }
+        self.buckets[self.head as usize].withdrawn = withdrawn;
+    }
+
+    /// Buckets in use, oldest first.
+    pub fn ordered(&self) -> Vec<ClaimBucket> {
+        let start = self.head as usize + 1;
+        (start..start + CLAIM_HISTORY_LEN)
+            .map(|i| self.buckets[i % CLAIM_HISTORY_LEN])
+            .filter(|b| b.withdrawn > 0)
+            .collect()
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -84,7 +665,36 @@ impl TokenStreamData {
         transferable_by_sender: bool,
         transferable_by_recipient: bool,
         release_rate: u64,
-        stream_name: String,
+        stream_name: [u8; STREAM_NAME_SIZE],
+        recipient_hash: [u8; 32],
+        max_recipient_pause: u64,
+        underfunded_policy: UnderfundedPolicy,
+        notify_key: Pubkey,
+        managed_freeze: bool,
+        strict_withdraw: bool,
+        obligation_nft: bool,
+        compliance_authority: Pubkey,
+        topup_refund_window: u64,
+        metadata_seed: [u8; 32],
+        bond_amount: u64,
+        bond_until: u64,
+        partial_period: PartialPeriodPolicy,
+        withdraw_frequency: u64,
+        cranker_fee: u64,
+        partner: Pubkey,
+        partner_fee_bps: u16,
+        tranches: Vec<Tranche>,
+        curve: UnlockCurve,
+        step_periods: u64,
+        transfer_cooldown: u64,
+        transfer_allowlist: Vec<Pubkey>,
+        can_topup: TopUpPolicy,
+        cancel_delay_seconds: u64,
+        hold_mint: Pubkey,
+        hold_amount: u64,
+        origin: [u8; 16],
+        subscription: bool,
+        shares: Vec<RecipientShare>,
     ) -> Self {
         let ix = StreamInstruction {
             start_time,
@@ -101,9 +711,38 @@ impl TokenStreamData {
             transferable_by_recipient,
             release_rate,
             stream_name,
+            recipient_hash,
+            max_recipient_pause,
+            underfunded_policy,
+            notify_key,
+            managed_freeze,
+            strict_withdraw,
+            obligation_nft,
+            compliance_authority,
+            topup_refund_window,
+            metadata_seed,
+            bond_amount,
+            bond_until,
+            partial_period,
+            withdraw_frequency,
+            cranker_fee,
+            partner,
+            partner_fee_bps,
+            tranches,
+            curve,
+            step_periods,
+            transfer_cooldown,
+            transfer_allowlist,
+            can_topup,
+            cancel_delay_seconds,
+            hold_mint,
+            hold_amount,
+            origin,
+            subscription,
+            shares,
         };
 
-        Self {
+        let mut data = Self {
             magic: PROGRAM_VERSION,
             created_at,
             withdrawn_amount: 0,
@@ -117,128 +756,1165 @@ impl TokenStreamData {
             mint,
             escrow_tokens,
             ix,
-        }
+            recipient_paused_at: 0,
+            recipient_paused_seconds: 0,
+            halted_seconds: 0,
+            nonce: 0,
+            effective_cliff: 0,
+            period_amount_scaled: 0,
+            min_claim_amount: 0,
+            mint_migrated: false,
+            stream_seq: 0,
+            last_withdraw_slot: 0,
+            compliance_frozen_until: 0,
+            last_topup_at: 0,
+            last_topup_amount: 0,
+            last_topup_payer: Pubkey::default(),
+            last_topup_tokens: Pubkey::default(),
+            completed_at: 0,
+            end_cause: EndCause::Open,
+            rolled_from: Pubkey::default(),
+            forward_to: Pubkey::default(),
+            claim_history: ClaimHistory::default(),
+            sender_paused_at: 0,
+            sender_paused_seconds: 0,
+            derived_recipient: Pubkey::default(),
+            derived_mint: Pubkey::default(),
+            metadata_bump: 0,
+            last_transferred_at: 0,
+            pending_recipient: Pubkey::default(),
+            pending_proposer: Pubkey::default(),
+            other_contributions: 0,
+            contributions: vec![],
+            cancel_requested_at: 0,
+            fees_paid: 0,
+            legacy_address: Pubkey::default(),
+            has_mirror: false,
+            subscription_canceled_at: 0,
+        };
+        data.cache_schedule();
+        data
     }
 
-    pub fn available(&self, now: u64) -> u64 {
-        if self.ix.start_time > now || self.ix.cliff > now {
-            return 0;
-        }
-
-        if now >= self.ix.end_time && self.ix.release_rate == 0 {
-            return self.ix.deposited_amount - self.withdrawn_amount;
-        }
-
-        let cliff = if self.ix.cliff > 0 {
+    /// Precomputes the vesting start and the per-period amount, scaled by
+    /// `AMOUNT_SCALE`, from the schedule in `ix`.
+    pub fn cache_schedule(&mut self) {
+        self.effective_cliff = if self.ix.cliff > 0 {
             self.ix.cliff
         } else {
             self.ix.start_time
         };
 
-        let cliff_amount = if self.ix.cliff_amount > 0 {
-            self.ix.cliff_amount
+        self.period_amount_scaled = if self.ix.release_rate > 0 {
+            self.ix.release_rate as u128 * AMOUNT_SCALE
+        } else if self.ix.end_time > self.effective_cliff {
+            // streamed * period / duration, split so neither product
+            // overflows while `check_schedule` keeps period <= duration.
+            let streamed =
+                (self.ix.total_amount.saturating_sub(self.ix.cliff_amount) as u128) * AMOUNT_SCALE;
+            let period = self.ix.period as u128;
+            let duration = (self.ix.end_time - self.effective_cliff) as u128;
+            (streamed / duration)
+                .saturating_mul(period)
+                .saturating_add(streamed % duration * period / duration)
         } else {
             0
         };
+    }
+
+    /// Private streams keep only `ix.recipient_hash` until the recipient
+    /// proves ownership on their first withdrawal.
+    pub fn recipient_revealed(&self) -> bool {
+        self.recipient != Pubkey::default() || self.ix.recipient_hash == [0; 32]
+    }
+
+    /// Seconds the recipient has paused accrual for, including a pause
+    /// still in progress, capped by `ix.max_recipient_pause`.
+    pub fn recipient_paused_time(&self, now: u64) -> u64 {
+        let mut paused = self.recipient_paused_seconds;
+        if self.recipient_paused_at > 0 {
+            paused += now.saturating_sub(self.recipient_paused_at);
+        }
+        paused.min(self.ix.max_recipient_pause)
+    }
+
+    /// Seconds the sender has paused accrual for, including a pause still in
+    /// progress.
+    pub fn sender_paused_time(&self, now: u64) -> u64 {
+        let mut paused = self.sender_paused_seconds;
+        if self.sender_paused_at > 0 {
+            paused += now.saturating_sub(self.sender_paused_at);
+        }
+        paused
+    }
+
+    /// All paused time, by either party. The two pauses never overlap.
+    pub fn paused_time(&self, now: u64) -> u64 {
+        self.recipient_paused_time(now) + self.sender_paused_time(now)
+    }
 
-        let num_periods = (self.ix.end_time - cliff) as f64 / self.ix.period as f64;
-        let period_amount = if self.ix.release_rate > 0 {
-            self.ix.release_rate as f64
+    /// Position on the stream's schedule, i.e. wall clock time shifted back by
+    /// paused and halted time. `closable_at` and all schedule math use this
+    /// time base. Halted streams stay frozen at the point funds ran out.
+    pub fn effective_now(&self, now: u64) -> u64 {
+        let schedule_now = now
+            .saturating_sub(self.paused_time(now))
+            .saturating_sub(self.halted_seconds);
+        if self.ix.underfunded_policy == UnderfundedPolicy::HaltAccrual {
+            schedule_now.min(self.funded_until())
         } else {
-            (self.ix.total_amount - cliff_amount) as f64 / num_periods
-        };
-        let periods_passed = (now - cliff) / self.ix.period;
-        (periods_passed as f64 * period_amount) as u64 + cliff_amount - self.withdrawn_amount
+            schedule_now
+        }
     }
 
-    pub fn closable(&self) -> u64 {
-        let cliff_time = if self.ix.cliff > 0 {
-            self.ix.cliff
+    /// Whether running out of funds only halts the stream until its next
+    /// top-up: `HaltAccrual` streams funded short of their total, or paying a
+    /// release rate.
+    pub fn awaits_topup(&self) -> bool {
+        self.ix.underfunded_policy == UnderfundedPolicy::HaltAccrual
+            && (self.ix.deposited_amount < self.ix.total_amount || self.ix.release_rate > 0)
+    }
+
+    /// Adds the time a halted stream spent waiting for funds to
+    /// `halted_seconds`. Must run before `deposited_amount` grows.
+    pub fn settle_halt(&mut self, now: u64) {
+        if self.ix.underfunded_policy != UnderfundedPolicy::HaltAccrual {
+            return;
+        }
+
+        let schedule_now = now
+            .saturating_sub(self.paused_time(now))
+            .saturating_sub(self.halted_seconds);
+        let funded_until = self.funded_until();
+        if schedule_now > funded_until {
+            self.halted_seconds += schedule_now - funded_until;
+        }
+    }
+
+    /// Address and bump of the metadata account of a stream. Private streams
+    /// pass their `recipient_hash` as `recipient`.
+    pub fn metadata_address(
+        program_id: &Pubkey,
+        sender: &Pubkey,
+        recipient: &Pubkey,
+        mint: &Pubkey,
+        seed: &[u8; 32],
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                METADATA_SEED,
+                sender.as_ref(),
+                recipient.as_ref(),
+                mint.as_ref(),
+                seed,
+            ],
+            program_id,
+        )
+    }
+
+    /// Decodes stream metadata for an instruction, by its stored version.
+    /// Anything but the current layout is refused; older streams have to go
+    /// through `migrate_stream`.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        match stored_version(data) {
+            Some(PROGRAM_VERSION) => solana_borsh::try_from_slice_unchecked::<Self>(data)
+                .map_err(|_| InvalidMetadata.into()),
+            Some(_) if decode_outdated(data, &Pubkey::default()).is_some() => {
+                msg!("Error: Stream metadata is outdated, run migrate_stream");
+                Err(MetadataNeedsMigration.into())
+            }
+            _ => Err(InvalidMetadata.into()),
+        }
+    }
+
+    /// Writes the metadata back over the start of `data`. Serializes in
+    /// place, where `try_to_vec` would put a copy of the whole layout on the
+    /// heap first.
+    pub fn save(&self, data: &mut [u8]) -> Result<(), ProgramError> {
+        let mut rest = data;
+        self.serialize(&mut rest)?;
+        Ok(())
+    }
+
+    /// Whether `key` is where this stream's metadata was derived to live, or
+    /// for upgraded v1 streams, where it was created.
+    pub fn has_address(&self, program_id: &Pubkey, key: &Pubkey) -> bool {
+        if self.legacy_address != Pubkey::default() {
+            return &self.legacy_address == key;
+        }
+
+        let address = Pubkey::create_program_address(
+            &[
+                METADATA_SEED,
+                self.sender.as_ref(),
+                self.derived_recipient.as_ref(),
+                self.derived_mint.as_ref(),
+                &self.ix.metadata_seed,
+                &[self.metadata_bump],
+            ],
+            program_id,
+        );
+        matches!(address, Ok(address) if &address == key)
+    }
+
+    /// Extra seed of the escrow PDA: empty until the stream migrates to a new
+    /// mint, then that mint. An empty seed derives the same address as none.
+    pub fn escrow_seed(&self) -> Vec<u8> {
+        if self.mint_migrated {
+            self.mint.to_bytes().to_vec()
         } else {
-            self.ix.start_time
+            Vec::new()
+        }
+    }
+
+    /// Size of a metadata account for `tranches` milestones, `allowlist`
+    /// transfer destinations and `shares` co-recipients: the serialized size
+    /// with room for `MAX_CONTRIBUTORS` contributions, rounded up to a
+    /// multiple of 8.
+    pub fn account_size(tranches: usize, allowlist: usize, shares: usize) -> usize {
+        let size = METADATA_BASE_SIZE
+            + TRANCHE_SIZE * tranches
+            + 32 * allowlist
+            + RECIPIENT_SHARE_SIZE * shares
+            + CONTRIBUTION_SIZE * MAX_CONTRIBUTORS;
+        size + (8 - size % 8) % 8
+    }
+
+    /// `contributor`'s top-up total, tracking them if there's room.
+    fn contribution_mut(&mut self, contributor: &Pubkey) -> &mut u64 {
+        let index = self
+            .contributions
+            .iter()
+            .position(|c| &c.contributor == contributor);
+        let index = match index {
+            None if self.contributions.len() < MAX_CONTRIBUTORS => {
+                self.contributions.push(Contribution {
+                    contributor: *contributor,
+                    amount: 0,
+                });
+                Some(self.contributions.len() - 1)
+            }
+            index => index,
         };
 
-        let cliff_amount = if self.ix.cliff_amount > 0 {
-            self.ix.cliff_amount
-        } else {
-            0
+        match index {
+            Some(i) => &mut self.contributions[i].amount,
+            None => &mut self.other_contributions,
+        }
+    }
+
+    pub fn record_contribution(&mut self, contributor: &Pubkey, amount: u64) {
+        let total = self.contribution_mut(contributor);
+        *total = total.saturating_add(amount);
+    }
+
+    pub fn undo_contribution(&mut self, contributor: &Pubkey, amount: u64) {
+        let total = self.contribution_mut(contributor);
+        *total = total.saturating_sub(amount);
+    }
+
+    /// Whether the recipient may be reassigned to `wallet`.
+    pub fn may_transfer_to(&self, wallet: &Pubkey) -> bool {
+        self.ix.transfer_allowlist.is_empty() || self.ix.transfer_allowlist.contains(wallet)
+    }
+
+    /// Whether the recipient was reassigned less than `transfer_cooldown`
+    /// ago.
+    pub fn transfer_cooling_down(&self, now: u64) -> bool {
+        self.last_transferred_at > 0
+            && now
+                < self
+                    .last_transferred_at
+                    .saturating_add(self.ix.transfer_cooldown)
+    }
+
+    pub fn compliance_frozen(&self, now: u64) -> bool {
+        now < self.compliance_frozen_until
+    }
+
+    pub fn bump_nonce(&mut self) {
+        self.nonce += 1;
+    }
+
+    pub fn verify_nonce(&self, nonce: u64) -> Result<(), ProgramError> {
+        if nonce != self.nonce {
+            msg!("Error: Expected nonce {}, got {}", self.nonce, nonce);
+            return Err(StaleNonce.into());
+        }
+
+        Ok(())
+    }
+
+    pub fn available(&self, now: u64) -> u64 {
+        self.vested(now).saturating_sub(self.withdrawn_amount)
+    }
+
+    /// Deposited tokens above `total_amount` of a fixed schedule, which only
+    /// vest at `end_time` as a side effect of releasing the whole deposit
+    /// then. Release rate streams pay out whatever is deposited, so they
+    /// have none.
+    pub fn surplus(&self, now: u64) -> u64 {
+        if self.ix.release_rate > 0 || self.effective_now(now) >= self.ix.end_time {
+            return 0;
+        }
+        self.ix
+            .deposited_amount
+            .saturating_sub(self.ix.total_amount)
+    }
+
+    /// What the schedule still had locked up for the recipient at `at`, for
+    /// governance snapshots. Pauses and halts aren't dated, so they're left
+    /// out; of top-ups, only the latest is, and it's left out before it
+    /// happened.
+    pub fn unvested_at(&self, at: u64) -> u64 {
+        if at < self.created_at || (self.canceled_at > 0 && self.canceled_at <= at) {
+            return 0;
+        }
+
+        let mut schedule = TokenStreamData {
+            recipient_paused_at: 0,
+            recipient_paused_seconds: 0,
+            sender_paused_at: 0,
+            sender_paused_seconds: 0,
+            halted_seconds: 0,
+            ..self.clone()
         };
-        if self.ix.deposited_amount < cliff_amount {
-            return cliff_time;
+        if self.last_topup_at > at {
+            schedule.ix.deposited_amount = schedule
+                .ix
+                .deposited_amount
+                .saturating_sub(self.last_topup_amount);
         }
-        let seconds_nr = self.ix.end_time - cliff_time;
 
-        let amount_per_second = if self.ix.release_rate > 0 {
-            self.ix.release_rate / self.ix.period
-        } else {
-            ((self.ix.total_amount - cliff_amount) / seconds_nr) as u64
+        schedule
+            .ix
+            .deposited_amount
+            .saturating_sub(schedule.vested(at))
+    }
+
+    /// What canceling at `now` pays the recipient: `available`, plus the
+    /// share of the period in progress `ix.partial_period` grants release rate
+    /// streams. Subscriptions paid for the period in progress already.
+    pub fn available_on_cancel(&self, now: u64) -> u64 {
+        let available = self.available(now);
+        let schedule_now = self.effective_now(now);
+        if self.ix.release_rate == 0
+            || self.ix.subscription
+            || self.ix.start_time > schedule_now
+            || self.ix.cliff > schedule_now
+        {
+            return available;
+        }
+
+        let period = self.ix.period.max(1);
+        let into_period = schedule_now.saturating_sub(self.effective_cliff) % period;
+        let partial = match self.ix.partial_period {
+            _ if into_period == 0 => 0,
+            PartialPeriodPolicy::Forfeit => 0,
+            PartialPeriodPolicy::PayFull => self.ix.release_rate as u128,
+            PartialPeriodPolicy::Prorate => {
+                self.ix.release_rate as u128 * into_period as u128 / period as u128
+            }
         };
-        let seconds_left = ((self.ix.deposited_amount - cliff_amount) / amount_per_second) + 1;
 
-        msg!(
-            "Release {}, Period {}, seconds left {}",
-            self.ix.release_rate,
-            self.ix.period,
-            seconds_left
-        );
-        if cliff_time + seconds_left > self.ix.end_time && self.ix.release_rate == 0 {
-            self.ix.end_time
+        let owed = (self.vested(now) as u128 + partial).min(self.ix.deposited_amount as u128);
+        (owed as u64).saturating_sub(self.withdrawn_amount)
+    }
+
+    /// Total amount released by the schedule at `now`, withdrawn or not.
+    pub fn vested(&self, now: u64) -> u64 {
+        let now = self.effective_now(now);
+        if self.ix.start_time > now || self.ix.cliff > now {
+            return 0;
+        }
+
+        if now >= self.ix.end_time && self.ix.release_rate == 0 {
+            return self.ix.deposited_amount;
+        }
+
+        if self.ix.subscription {
+            let started = now.saturating_sub(self.effective_cliff) / self.ix.period.max(1) + 1;
+            let funded = self.ix.deposited_amount / self.ix.release_rate.max(1);
+            return started.min(funded).saturating_mul(self.ix.release_rate);
+        }
+
+        if !self.ix.tranches.is_empty() {
+            let unlocked: u128 = self
+                .ix
+                .tranches
+                .iter()
+                .filter(|t| t.unlock_time <= now)
+                .map(|t| t.amount as u128)
+                .sum();
+            return unlocked.min(self.ix.deposited_amount as u128) as u64;
+        }
+
+        // Rounds down to whole periods.
+        let periods_passed = now.saturating_sub(self.effective_cliff) / self.ix.period.max(1);
+        self.released_after(periods_passed)
+            .min(self.ix.deposited_amount as u128) as u64
+    }
+
+    /// Released by the schedule `periods` whole periods after the cliff,
+    /// cliff amount included, rounded down to whole base units.
+    fn released_after(&self, periods: u64) -> u128 {
+        let streamed = match self.ix.curve {
+            UnlockCurve::Linear => {
+                (periods as u128).saturating_mul(self.period_amount_scaled) / AMOUNT_SCALE
+            }
+            UnlockCurve::Stepwise => {
+                let step = self.ix.step_periods.max(1);
+                ((periods / step * step) as u128).saturating_mul(self.period_amount_scaled)
+                    / AMOUNT_SCALE
+            }
+            UnlockCurve::BackLoaded => {
+                let total = self.total_periods() as u128;
+                let periods = (periods as u128).min(total);
+                let amount = self.ix.total_amount.saturating_sub(self.ix.cliff_amount) as u128;
+                match (amount * periods).checked_div(total) {
+                    Some(v) => v * periods / total,
+                    None => amount,
+                }
+            }
+        };
+        streamed + self.ix.cliff_amount as u128
+    }
+
+    /// Periods of a fixed schedule from the cliff to `end_time`, the last one
+    /// possibly partial.
+    fn total_periods(&self) -> u64 {
+        let span = self.ix.end_time.saturating_sub(self.effective_cliff);
+        let period = self.ix.period.max(1);
+        let mut periods = span / period;
+        if periods * period < span {
+            periods += 1;
+        }
+        periods
+    }
+
+    /// How a stream that wasn't canceled ended: underfunded `AutoClose`
+    /// streams ran out of funds, everything else ran its course.
+    pub fn natural_end_cause(&self) -> EndCause {
+        let underfunded =
+            self.ix.deposited_amount < self.ix.total_amount || self.ix.release_rate > 0;
+        if underfunded && self.ix.underfunded_policy == UnderfundedPolicy::AutoClose {
+            EndCause::AutoClosedUnderfunded
         } else {
-            cliff_time + seconds_left
+            EndCause::Completed
         }
     }
-}
 
-#[derive(Debug)]
-pub struct InitializeAccounts<'a> {
-    pub sender: AccountInfo<'a>,
-    pub sender_tokens: AccountInfo<'a>,
-    pub recipient: AccountInfo<'a>,
-    pub recipient_tokens: AccountInfo<'a>,
-    pub metadata: AccountInfo<'a>,
-    pub escrow_tokens: AccountInfo<'a>,
-    pub mint: AccountInfo<'a>,
-    pub rent: AccountInfo<'a>,
-    pub token_program: AccountInfo<'a>,
-    pub associated_token_program: AccountInfo<'a>,
-    pub system_program: AccountInfo<'a>,
-}
+    pub fn closable(&self) -> u64 {
+        match self.ix.underfunded_policy {
+            UnderfundedPolicy::AutoClose => self.funded_until(),
+            UnderfundedPolicy::HaltAccrual if self.ix.release_rate > 0 => u64::MAX,
+            UnderfundedPolicy::HaltAccrual => self.ix.end_time,
+        }
+    }
 
-pub struct WithdrawAccounts<'a> {
-    pub withdraw_authority: AccountInfo<'a>,
-    pub sender: AccountInfo<'a>,
-    pub recipient: AccountInfo<'a>,
-    pub recipient_tokens: AccountInfo<'a>,
-    pub metadata: AccountInfo<'a>,
-    pub escrow_tokens: AccountInfo<'a>,
-    pub mint: AccountInfo<'a>,
-    pub token_program: AccountInfo<'a>,
-}
+    /// Seconds left until the escrow runs dry at the current release rate,
+    /// counted on the stream's own (pause-adjusted) clock.
+    pub fn funding_runway(&self, now: u64) -> u64 {
+        if self.canceled_at > 0 {
+            return 0;
+        }
+        self.funded_until().saturating_sub(self.effective_now(now))
+    }
 
-pub struct CancelAccounts<'a> {
-    pub cancel_authority: AccountInfo<'a>,
-    pub sender: AccountInfo<'a>,
-    pub sender_tokens: AccountInfo<'a>,
-    pub recipient: AccountInfo<'a>,
-    pub recipient_tokens: AccountInfo<'a>,
-    pub metadata: AccountInfo<'a>,
-    pub escrow_tokens: AccountInfo<'a>,
-    pub mint: AccountInfo<'a>,
-    pub token_program: AccountInfo<'a>,
-}
+    /// Schedule time at which everything deposited so far has been released:
+    /// the first period boundary where `vested` reaches `deposited_amount`.
+    /// Saturates at `u64::MAX` for streams that never get there.
+    pub fn funded_until(&self) -> u64 {
+        if self.ix.deposited_amount <= self.ix.cliff_amount {
+            return self.effective_cliff;
+        }
 
-pub struct TransferAccounts<'a> {
-    pub authorized_wallet: AccountInfo<'a>,
-    pub new_recipient: AccountInfo<'a>,
-    pub new_recipient_tokens: AccountInfo<'a>,
-    pub metadata: AccountInfo<'a>,
-    pub escrow_tokens: AccountInfo<'a>,
-    pub mint: AccountInfo<'a>,
-    pub rent: AccountInfo<'a>,
-    pub token_program: AccountInfo<'a>,
-    pub associated_token_program: AccountInfo<'a>,
-    pub system_program: AccountInfo<'a>,
-}
+        if !self.ix.tranches.is_empty() {
+            let mut unlocked = 0u64;
+            for tranche in &self.ix.tranches {
+                unlocked = unlocked.saturating_add(tranche.amount);
+                if unlocked >= self.ix.deposited_amount {
+                    return tranche.unlock_time;
+                }
+            }
+            return self.ix.end_time;
+        }
+
+        // Subscriptions end with the last period paid for in full.
+        if self.ix.subscription {
+            let funded = self.ix.deposited_amount / self.ix.release_rate.max(1);
+            return funded
+                .checked_mul(self.ix.period.max(1))
+                .and_then(|t| t.checked_add(self.effective_cliff))
+                .unwrap_or(u64::MAX);
+        }
+
+        let until = self
+            .periods_until_vested(self.ix.deposited_amount)
+            .checked_mul(self.ix.period.max(1) as u128)
+            .and_then(|t| t.checked_add(self.effective_cliff as u128))
+            .and_then(|t| u64::try_from(t).ok())
+            .unwrap_or(u64::MAX);
+
+        if self.ix.release_rate == 0 {
+            until.min(self.ix.end_time)
+        } else {
+            until
+        }
+    }
+
+    /// Periods after the cliff until `amount` has vested in total. Rounds
+    /// up, as `amount` is released at the end of the period completing it;
+    /// 0 for what the cliff releases, `u128::MAX` for amounts never reached.
+    pub fn periods_until_vested(&self, amount: u64) -> u128 {
+        let remaining = amount.saturating_sub(self.ix.cliff_amount);
+        if remaining == 0 {
+            return 0;
+        }
+
+        // Milestone schedules count tranches instead.
+        if !self.ix.tranches.is_empty() {
+            let mut unlocked = 0u64;
+            for (i, tranche) in self.ix.tranches.iter().enumerate() {
+                unlocked = unlocked.saturating_add(tranche.amount);
+                if unlocked >= remaining {
+                    return i as u128 + 1;
+                }
+            }
+            return u128::MAX;
+        }
+
+        // No closed form for curves; everything is out by the last period.
+        if self.ix.curve != UnlockCurve::Linear {
+            if amount > self.ix.total_amount {
+                return u128::MAX;
+            }
+            let (mut low, mut high) = (1, self.total_periods().max(1));
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if self.released_after(mid) >= amount as u128 {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+            return low as u128;
+        }
+
+        if self.period_amount_scaled == 0 {
+            return u128::MAX;
+        }
+
+        let scaled = remaining as u128 * AMOUNT_SCALE;
+        let mut periods = scaled / self.period_amount_scaled;
+        if periods * self.period_amount_scaled < scaled {
+            periods += 1;
+        }
+        periods
+    }
+
+    /// First and last vesting period the tokens between cumulative amounts
+    /// `from` (exclusive) and `to` accrued in, for mapping claims to tax lots.
+    /// Period 0 is the cliff.
+    pub fn period_range(&self, from: u64, to: u64) -> (u64, u64) {
+        // Fixed schedules release whatever rounding held back in their last
+        // period.
+        let last_period = if !self.ix.tranches.is_empty() {
+            self.ix.tranches.len() as u128
+        } else if self.ix.release_rate == 0 {
+            self.total_periods() as u128
+        } else {
+            u128::MAX
+        };
+        let period_of = |amount: u64| {
+            self.periods_until_vested(amount)
+                .min(last_period)
+                .min(u64::MAX as u128) as u64
+        };
+
+        (period_of(from.saturating_add(1)), period_of(to))
+    }
+}
+
+/// Off-chain decoding, so services can run `available()`, `closable()` and
+/// the rest of the schedule math on fetched accounts. None of it logs or
+/// reads sysvars; callers pass the time in.
+#[cfg(feature = "client")]
+impl TokenStreamData {
+    /// Decodes the data of a stream metadata account.
+    pub fn decode(data: &[u8]) -> std::io::Result<Self> {
+        let stream = solana_program::borsh::try_from_slice_unchecked::<Self>(data)?;
+        if stream.magic != PROGRAM_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a stream metadata account",
+            ));
+        }
+
+        Ok(stream)
+    }
+}
+
+/// One escrow backing several grants from the same sender to the same
+/// recipient, each with its own schedule.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+pub struct GrantLedger {
+    pub magic: u64,
+    pub capacity: u8,
+    pub sender: Pubkey,
+    pub sender_tokens: Pubkey,
+    pub recipient: Pubkey,
+    pub recipient_tokens: Pubkey,
+    pub mint: Pubkey,
+    pub escrow_tokens: Pubkey,
+    pub grants: Vec<LedgerGrant>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, Clone)]
+pub struct LedgerGrant {
+    pub created_at: u64,
+    pub withdrawn_amount: u64,
+    pub canceled_at: u64,
+    pub ix: StreamInstruction,
+}
+
+impl GrantLedger {
+    /// Account size fitting `capacity` grants.
+    pub fn size(capacity: u8) -> usize {
+        let ledger = GrantLedger {
+            grants: vec![LedgerGrant::default(); capacity as usize],
+            ..Default::default()
+        };
+        let size = ledger.try_to_vec().unwrap().len();
+        size + (8 - size % 8) % 8
+    }
+
+    pub fn available(&self, now: u64) -> Result<u64, ProgramError> {
+        self.grants.iter().try_fold(0u64, |sum, g| {
+            sum.checked_add(g.available(now))
+                .ok_or_else(|| ArithmeticOverflow.into())
+        })
+    }
+
+    pub fn escrowed(&self) -> Result<u64, ProgramError> {
+        self.grants
+            .iter()
+            .filter(|g| g.canceled_at == 0)
+            .try_fold(0u64, |sum, g| {
+                let left =
+                    g.ix.deposited_amount
+                        .checked_sub(g.withdrawn_amount)
+                        .ok_or(ArithmeticOverflow)?;
+                sum.checked_add(left)
+                    .ok_or_else(|| ArithmeticOverflow.into())
+            })
+    }
+}
+
+impl LedgerGrant {
+    /// The grant viewed as a standalone stream, so it shares the stream math.
+    pub fn as_stream(&self) -> TokenStreamData {
+        let mut stream = TokenStreamData {
+            created_at: self.created_at,
+            withdrawn_amount: self.withdrawn_amount,
+            canceled_at: self.canceled_at,
+            ix: self.ix.clone(),
+            ..Default::default()
+        };
+        stream.cache_schedule();
+        stream.closable_at = stream.closable();
+        stream
+    }
+
+    pub fn available(&self, now: u64) -> u64 {
+        if self.canceled_at > 0 {
+            return 0;
+        }
+
+        self.as_stream().available(now)
+    }
+}
+
+/// Wallets allowed to top up a stream with the `Allowlist` top-up policy,
+/// managed by its sender.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+pub struct TopUpAllowlist {
+    pub stream: Pubkey,
+    pub members: Vec<Pubkey>,
+}
+
+impl TopUpAllowlist {
+    pub fn is_allowed(&self, wallet: &Pubkey) -> bool {
+        self.members.contains(wallet)
+    }
+}
+
+/// Final outcome of an ended stream, written once by `archive_stream`. It
+/// outlives the metadata, so reporting doesn't rely on RPC providers keeping
+/// transaction history.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct SettlementRecord {
+    pub stream: Pubkey,
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub end_cause: EndCause,
+    pub created_at: u64,
+    pub ended_at: u64,
+    pub deposited: u64,
+    /// Withdrawn by or paid out to the recipient, fees included.
+    pub paid: u64,
+    /// Returned to the sender when the stream ended.
+    pub refunded: u64,
+    pub fees_paid: u64,
+}
+
+impl SettlementRecord {
+    pub fn address(program_id: &Pubkey, metadata: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SETTLEMENT_SEED, metadata.as_ref()], program_id)
+    }
+}
+
+/// The part of a stream `available()` depends on, for cranks polling many
+/// streams to fetch a fraction of the bytes. Written by `init_stream_mirror`,
+/// every withdrawal and `sync_mirror`; other changes, e.g. pauses and
+/// top-ups, show up after the next of those. Streams with tranches have no
+/// mirror.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StreamMirror {
+    pub stream: Pubkey,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub deposited_amount: u64,
+    pub total_amount: u64,
+    pub period: u64,
+    pub cliff: u64,
+    pub cliff_amount: u64,
+    pub release_rate: u64,
+    pub subscription: bool,
+    pub curve: UnlockCurve,
+    pub step_periods: u64,
+    pub underfunded_policy: UnderfundedPolicy,
+    pub max_recipient_pause: u64,
+    pub withdrawn_amount: u64,
+    pub canceled_at: u64,
+    pub recipient_paused_at: u64,
+    pub recipient_paused_seconds: u64,
+    pub sender_paused_at: u64,
+    pub sender_paused_seconds: u64,
+    pub halted_seconds: u64,
+    /// When the mirror was last written.
+    pub synced_at: u64,
+}
+
+impl StreamMirror {
+    pub fn address(program_id: &Pubkey, metadata: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[STREAM_MIRROR_SEED, metadata.as_ref()], program_id)
+    }
+
+    pub fn new(key: &Pubkey, stream: &TokenStreamData, now: u64) -> Self {
+        StreamMirror {
+            stream: *key,
+            start_time: stream.ix.start_time,
+            end_time: stream.ix.end_time,
+            deposited_amount: stream.ix.deposited_amount,
+            total_amount: stream.ix.total_amount,
+            period: stream.ix.period,
+            cliff: stream.ix.cliff,
+            cliff_amount: stream.ix.cliff_amount,
+            release_rate: stream.ix.release_rate,
+            subscription: stream.ix.subscription,
+            curve: stream.ix.curve,
+            step_periods: stream.ix.step_periods,
+            underfunded_policy: stream.ix.underfunded_policy,
+            max_recipient_pause: stream.ix.max_recipient_pause,
+            withdrawn_amount: stream.withdrawn_amount,
+            canceled_at: stream.canceled_at,
+            recipient_paused_at: stream.recipient_paused_at,
+            recipient_paused_seconds: stream.recipient_paused_seconds,
+            sender_paused_at: stream.sender_paused_at,
+            sender_paused_seconds: stream.sender_paused_seconds,
+            halted_seconds: stream.halted_seconds,
+            synced_at: now,
+        }
+    }
+
+    /// The mirrored stream, with everything not mirrored at its default, so
+    /// it shares the stream math.
+    pub fn as_stream(&self) -> TokenStreamData {
+        let mut stream = TokenStreamData {
+            withdrawn_amount: self.withdrawn_amount,
+            canceled_at: self.canceled_at,
+            recipient_paused_at: self.recipient_paused_at,
+            recipient_paused_seconds: self.recipient_paused_seconds,
+            sender_paused_at: self.sender_paused_at,
+            sender_paused_seconds: self.sender_paused_seconds,
+            halted_seconds: self.halted_seconds,
+            ix: StreamInstruction {
+                start_time: self.start_time,
+                end_time: self.end_time,
+                deposited_amount: self.deposited_amount,
+                total_amount: self.total_amount,
+                period: self.period,
+                cliff: self.cliff,
+                cliff_amount: self.cliff_amount,
+                release_rate: self.release_rate,
+                subscription: self.subscription,
+                curve: self.curve,
+                step_periods: self.step_periods,
+                underfunded_policy: self.underfunded_policy,
+                max_recipient_pause: self.max_recipient_pause,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        stream.cache_schedule();
+        stream
+    }
+
+    pub fn available(&self, now: u64) -> u64 {
+        self.as_stream().available(now)
+    }
+}
+
+#[repr(u8)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexRole {
+    Sender,
+    Recipient,
+}
+
+/// Which `StreamIndex` page `init_stream_index` creates.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamIndexKey {
+    pub role: IndexRole,
+    pub page: u32,
+}
+
+/// One page of the streams `owner` sends or receives, see `registry`. Pages
+/// are numbered from 0; clients create the next one when a page fills up.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct StreamIndex {
+    pub owner: Pubkey,
+    pub role: IndexRole,
+    pub page: u32,
+    pub streams: Vec<Pubkey>,
+}
+
+impl StreamIndex {
+    pub fn address(
+        program_id: &Pubkey,
+        owner: &Pubkey,
+        role: IndexRole,
+        page: u32,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                STREAM_INDEX_SEED,
+                owner.as_ref(),
+                &[role as u8],
+                &page.to_le_bytes(),
+            ],
+            program_id,
+        )
+    }
+}
+
+/// Global list of wallets whose streams are exempt from protocol fees.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+pub struct FeeExemptions {
+    pub admin: Pubkey,
+    pub members: Vec<Pubkey>,
+}
+
+impl FeeExemptions {
+    pub fn is_exempt(&self, wallet: &Pubkey) -> bool {
+        self.members.contains(wallet)
+    }
+}
+
+/// Global protocol fee taken from vested tokens paid out by `withdraw` and
+/// `cancel`, in basis points, into `treasury`'s associated token account.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+pub struct FeeConfig {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub fee_bps: u16,
+}
+
+impl FeeConfig {
+    pub fn address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[FEE_CONFIG_SEED], program_id)
+    }
+
+    pub fn fee(&self, amount: u64) -> u64 {
+        (amount as u128 * self.fee_bps as u128 / 10_000) as u64
+    }
+}
+
+/// Read-only snapshot of a stream, returned by the `preview` instruction.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, PartialEq)]
+pub struct StreamPreview {
+    pub now: u64,
+    pub vested: u64,
+    pub available: u64,
+    pub closable_at: u64,
+    pub funding_runway_seconds: u64,
+}
+
+/// Locked balance at a past time, returned by the `vesting_snapshot`
+/// instruction. `recipient` is the current one; transfers aren't dated.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, PartialEq)]
+pub struct VestingSnapshot {
+    pub at: u64,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub unvested: u64,
+}
+
+/// Escrow solvency attestation, returned by the `escrow_proof` instruction.
+/// Return data is tagged with the program id by the runtime.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, PartialEq)]
+pub struct EscrowProof {
+    pub escrow_balance: u64,
+    pub deposited: u64,
+    pub withdrawn: u64,
+    /// Cancel protection bond held on top of the deposit.
+    pub bond: u64,
+    pub timestamp: u64,
+}
+
+/// Totals over streams of one mint, returned by the `mint_summary` instruction.
+/// Canceled streams are only counted, their escrow having been emptied.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, PartialEq)]
+pub struct MintSummary {
+    pub streams: u64,
+    pub canceled: u64,
+    pub deposited: u64,
+    pub vested: u64,
+    pub withdrawn: u64,
+    /// Deposited but not vested yet.
+    pub locked: u64,
+    pub supply: u64,
+    pub timestamp: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug, Clone, Copy)]
+pub struct ConversionRatio {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl ConversionRatio {
+    /// Converts an old-mint amount, rounding down.
+    pub fn convert(&self, amount: u64) -> Option<u64> {
+        let converted = amount as u128 * self.numerator as u128 / self.denominator as u128;
+        u64::try_from(converted).ok()
+    }
+}
+
+/// Swaps `old_mint` for `new_mint` at a fixed ratio. Set up by the new mint's
+/// authority, who pre-funds the pool PDA's `new_mint` associated token account.
+#[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
+pub struct ConversionPool {
+    pub authority: Pubkey,
+    pub old_mint: Pubkey,
+    pub new_mint: Pubkey,
+    pub ratio: ConversionRatio,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct CreateLedgerInstruction {
+    pub capacity: u8,
+    pub grant: StreamInstruction,
+}
+
+/// `create` by a sender that is a PDA of `owner_program`, derived from
+/// `seeds`, bump included.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct CreateFromProgramInstruction {
+    pub owner_program: Pubkey,
+    pub seeds: Vec<Vec<u8>>,
+    pub stream: StreamInstruction,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct FeeConfigUpdate {
+    pub treasury: Pubkey,
+    pub fee_bps: u16,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct FeeExemptionUpdate {
+    pub member: Pubkey,
+    pub exempt: bool,
+}
+
+/// Amendments of `update_stream`; `None` leaves a term as it is.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default)]
+pub struct StreamUpdate {
+    pub end_time: Option<u64>,
+    pub release_rate: Option<u64>,
+    pub cancelable_by_sender: Option<bool>,
+    pub cancelable_by_recipient: Option<bool>,
+    pub transferable_by_sender: Option<bool>,
+    pub transferable_by_recipient: Option<bool>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct TopUpAllowlistUpdate {
+    pub member: Pubkey,
+    pub allowed: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct SplitInstruction {
+    /// Part of the stream moved to the new one, in (0, 10000) bps.
+    pub share_bps: u16,
+    /// Seed of the new stream's metadata address, see `metadata_address`.
+    pub metadata_seed: [u8; 32],
+}
+
+#[derive(Debug)]
+pub struct InitializeAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    /// Global `STREAM_COUNTER_SEED` PDA, required for streams.
+    pub stream_counter: Option<AccountInfo<'a>>,
+    /// Mint freeze authority multisig and an issuer key co-signing on its
+    /// behalf, required for `managed_freeze` streams.
+    pub freeze_authority: Option<AccountInfo<'a>>,
+    pub freeze_issuer: Option<AccountInfo<'a>>,
+    /// Obligation mint, its token account and the Token-2022 program,
+    /// required for `obligation_nft` streams.
+    pub obligation_mint: Option<AccountInfo<'a>>,
+    pub obligation_tokens: Option<AccountInfo<'a>>,
+    pub token_2022_program: Option<AccountInfo<'a>>,
+    /// `StreamIndex` pages of the sender and recipient to list the stream in.
+    pub indexes: Vec<AccountInfo<'a>>,
+    /// SPL token multisig owning `sender_tokens`, if any.
+    pub multisig: Option<AccountInfo<'a>>,
+    pub multisig_signers: Vec<AccountInfo<'a>>,
+    /// Pays the rent instead of the sender, see `create_from_program`.
+    pub payer: Option<AccountInfo<'a>>,
+}
+
+pub struct WithdrawAccounts<'a> {
+    pub withdraw_authority: AccountInfo<'a>,
+    pub sender: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    /// A token account of the recipient's to pay into instead, see
+    /// `withdraw_to`.
+    pub destination_tokens: Option<AccountInfo<'a>>,
+    /// Associated token accounts of the co-recipients, in `shares` order, see
+    /// `withdraw_shares`.
+    pub share_tokens: Vec<AccountInfo<'a>>,
+    pub fees: FeeAccounts<'a>,
+    /// Mint freeze authority multisig and the program's freeze PDA, required
+    /// for `managed_freeze` streams.
+    pub freeze_authority: Option<AccountInfo<'a>>,
+    pub freeze_signer: Option<AccountInfo<'a>>,
+    /// Obligation mint, its token account and the Token-2022 program,
+    /// required for `obligation_nft` streams.
+    pub obligation_mint: Option<AccountInfo<'a>>,
+    pub obligation_tokens: Option<AccountInfo<'a>>,
+    pub token_2022_program: Option<AccountInfo<'a>>,
+    /// The stream's `forward_to` account, required once one is set.
+    pub forward_tokens: Option<AccountInfo<'a>>,
+    /// Recipient's token account of `ix.hold_mint`, required for streams with
+    /// a `hold_amount`.
+    pub hold_tokens: Option<AccountInfo<'a>>,
+    /// The stream's `StreamMirror`, required once it has one.
+    pub mirror: Option<AccountInfo<'a>>,
+    /// AMM pool of an LP token mint and its two reserves, to log what the
+    /// withdrawn LP tokens redeem for. Opt-in: without a pool nothing is
+    /// logged.
+    pub lp_pool: Option<AccountInfo<'a>>,
+    pub lp_reserve_a: Option<AccountInfo<'a>>,
+    pub lp_reserve_b: Option<AccountInfo<'a>>,
+}
+
+/// Accounts of `withdraw`, signed by the keeper as `withdraw_authority`.
+pub struct CrankWithdrawAccounts<'a> {
+    pub keeper_tokens: AccountInfo<'a>,
+    pub withdraw: WithdrawAccounts<'a>,
+}
+
+pub struct CancelAccounts<'a> {
+    pub cancel_authority: AccountInfo<'a>,
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub fees: FeeAccounts<'a>,
+    /// Mint's freeze authority multisig and the program's freeze PDA,
+    /// required to pay out `managed_freeze` streams.
+    pub freeze_authority: Option<AccountInfo<'a>>,
+    pub freeze_signer: Option<AccountInfo<'a>>,
+    /// Obligation mint, its token account and the Token-2022 program,
+    /// required for `obligation_nft` streams.
+    pub obligation_mint: Option<AccountInfo<'a>>,
+    pub obligation_tokens: Option<AccountInfo<'a>>,
+    pub token_2022_program: Option<AccountInfo<'a>>,
+}
+
+pub struct TransferAccounts<'a> {
+    pub authorized_wallet: AccountInfo<'a>,
+    pub new_recipient: AccountInfo<'a>,
+    pub new_recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    /// `StreamIndex` pages of the old and new recipient to update.
+    pub indexes: Vec<AccountInfo<'a>>,
+}
+
+pub struct ProposeTransferAccounts<'a> {
+    pub authorized_wallet: AccountInfo<'a>,
+    pub new_recipient: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct AcceptTransferAccounts<'a> {
+    /// The pending recipient; pays for their associated token account.
+    pub new_recipient: AccountInfo<'a>,
+    pub new_recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    /// `StreamIndex` pages of the old and new recipient to update.
+    pub indexes: Vec<AccountInfo<'a>>,
+}
+
+pub struct CancelTransferAccounts<'a> {
+    pub authorized_wallet: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
 
 #[derive(Debug)]
 pub struct TopUpAccounts<'a> {
@@ -248,4 +1924,875 @@ pub struct TopUpAccounts<'a> {
     pub escrow_tokens: AccountInfo<'a>,
     pub mint: AccountInfo<'a>,
     pub token_program: AccountInfo<'a>,
-}
\ No newline at end of file
+    /// The stream's `TopUpAllowlist`, for contributors allowed by it. Told
+    /// apart from a multisig by being owned by this program.
+    pub allowlist: Option<AccountInfo<'a>>,
+    /// SPL token multisig owning `sender_tokens`, if any.
+    pub multisig: Option<AccountInfo<'a>>,
+    pub multisig_signers: Vec<AccountInfo<'a>>,
+}
+
+pub struct MigrateStreamAccounts<'a> {
+    /// The stream's sender, or the program's upgrade authority; pays the
+    /// added rent.
+    pub authority: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    /// Program data account, to prove `authority` is the upgrade authority.
+    pub program_data: Option<AccountInfo<'a>>,
+}
+
+pub struct ArchiveStreamAccounts<'a> {
+    /// Anyone, paying the rent.
+    pub payer: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub settlement: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct InitStreamMirrorAccounts<'a> {
+    /// The stream's sender, paying the rent.
+    pub sender: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub mirror: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct SyncMirrorAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+    pub mirror: AccountInfo<'a>,
+}
+
+pub struct InitStreamIndexAccounts<'a> {
+    /// Anyone, paying the rent.
+    pub payer: AccountInfo<'a>,
+    pub owner: AccountInfo<'a>,
+    pub index: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct InitTopUpAllowlistAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub allowlist: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct UpdateTopUpAllowlistAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub allowlist: AccountInfo<'a>,
+}
+
+pub struct UndoTopUpAccounts<'a> {
+    /// Signer of the top-up being undone.
+    pub payer: AccountInfo<'a>,
+    /// The token account the top-up was paid from.
+    pub payer_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+pub struct RolloverAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    /// The stream being rolled over, and its recipient's tokens for what
+    /// vested so far.
+    pub old_metadata: AccountInfo<'a>,
+    pub old_escrow_tokens: AccountInfo<'a>,
+    pub old_recipient_tokens: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub stream_counter: AccountInfo<'a>,
+}
+
+pub struct SplitAccounts<'a> {
+    /// Recipient or sender splitting the stream; pays rent of the new one.
+    pub authority: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub new_recipient: AccountInfo<'a>,
+    pub new_recipient_tokens: AccountInfo<'a>,
+    pub new_metadata: AccountInfo<'a>,
+    pub new_escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub stream_counter: AccountInfo<'a>,
+}
+
+pub struct MintSummaryAccounts<'a> {
+    pub mint: AccountInfo<'a>,
+    /// Metadata accounts of streams of `mint`, each at most once.
+    pub streams: Vec<AccountInfo<'a>>,
+}
+
+pub struct ComplianceFreezeAccounts<'a> {
+    pub compliance_authority: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct RecipientAccounts<'a> {
+    pub recipient: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct SenderAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct WithdrawSurplusAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+pub struct CancelSubscriptionAccounts<'a> {
+    /// The sender or the recipient.
+    pub authority: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+pub struct CloseStreamAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    /// Must be closed already.
+    pub escrow_tokens: AccountInfo<'a>,
+    /// `StreamIndex` pages listing the stream, to drop it from.
+    pub indexes: Vec<AccountInfo<'a>>,
+}
+
+pub struct PrewarmAccounts<'a> {
+    /// Anyone, paying the rent.
+    pub payer: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct SetForwardAccounts<'a> {
+    pub recipient: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub forward_tokens: AccountInfo<'a>,
+}
+
+pub struct PreviewAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct SnapshotAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct RefreshClosableAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct UpdateStreamAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    /// Only needs to sign changes unfavorable to them.
+    pub recipient: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+}
+
+pub struct EscrowProofAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+}
+
+pub struct VerifyStreamAccounts<'a> {
+    pub metadata: AccountInfo<'a>,
+    /// May be closed, for streams that ended.
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+}
+
+pub struct NetStreamsAccounts<'a> {
+    /// Sender of `metadata_a` and recipient of `metadata_b`.
+    pub party_a: AccountInfo<'a>,
+    /// Sender of `metadata_b` and recipient of `metadata_a`.
+    pub party_b: AccountInfo<'a>,
+    pub metadata_a: AccountInfo<'a>,
+    pub escrow_tokens_a: AccountInfo<'a>,
+    pub metadata_b: AccountInfo<'a>,
+    pub escrow_tokens_b: AccountInfo<'a>,
+    /// Recipient tokens of whichever stream owes more.
+    pub creditor_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+pub struct InitConversionPoolAccounts<'a> {
+    pub authority: AccountInfo<'a>,
+    pub pool: AccountInfo<'a>,
+    pub old_mint: AccountInfo<'a>,
+    pub new_mint: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct MigrateMintAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub new_escrow_tokens: AccountInfo<'a>,
+    pub pool: AccountInfo<'a>,
+    /// The pool's associated token accounts for the old and the new mint.
+    pub pool_old_tokens: AccountInfo<'a>,
+    pub pool_new_tokens: AccountInfo<'a>,
+    pub new_mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct FaucetAccounts<'a> {
+    /// Pays for everything and becomes the demo stream's sender.
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    /// Fresh keypair for the demo mint.
+    pub mint: AccountInfo<'a>,
+    pub faucet_authority: AccountInfo<'a>,
+    pub stream_counter: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct CancelBatchAccounts<'a> {
+    pub cancel_authority: AccountInfo<'a>,
+    pub sender: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub fee_config: AccountInfo<'a>,
+    pub fee_exemptions: Option<AccountInfo<'a>>,
+    /// Groups of `metadata, escrow_tokens, sender_tokens, recipient,
+    /// recipient_tokens, mint, treasury_tokens, partner_tokens,
+    /// freeze_authority, freeze_signer`, one group per stream. The last four
+    /// may be placeholders where no fee is due or the mint isn't managed.
+    pub streams: Vec<AccountInfo<'a>>,
+}
+
+pub struct CreateManyAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub stream_counter: AccountInfo<'a>,
+    /// Groups of `recipient, recipient_tokens, metadata, escrow_tokens`, one
+    /// group per stream.
+    pub streams: Vec<AccountInfo<'a>>,
+}
+
+pub struct InitFeeExemptionsAccounts<'a> {
+    pub admin: AccountInfo<'a>,
+    pub exemptions: AccountInfo<'a>,
+    pub program_data: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct UpdateFeeExemptionsAccounts<'a> {
+    pub admin: AccountInfo<'a>,
+    pub exemptions: AccountInfo<'a>,
+}
+
+pub struct InitFeeConfigAccounts<'a> {
+    pub admin: AccountInfo<'a>,
+    pub config: AccountInfo<'a>,
+    pub program_data: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct UpdateFeeConfigAccounts<'a> {
+    pub admin: AccountInfo<'a>,
+    pub config: AccountInfo<'a>,
+}
+
+/// Fee accounts of `withdraw`, `cancel` and `cancel_batch`. The fee config is
+/// always passed; until it is initialized no protocol fee is charged. The
+/// exemption list is only needed by exempt streams, the partner's token
+/// account by streams with a partner.
+pub struct FeeAccounts<'a> {
+    pub config: AccountInfo<'a>,
+    pub treasury_tokens: Option<AccountInfo<'a>>,
+    pub exemptions: Option<AccountInfo<'a>>,
+    pub partner_tokens: Option<AccountInfo<'a>>,
+}
+
+pub struct AddLedgerGrantAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    /// SPL token multisig owning `sender_tokens`, if any.
+    pub multisig: Option<AccountInfo<'a>>,
+    pub multisig_signers: Vec<AccountInfo<'a>>,
+}
+
+pub struct WithdrawLedgerAccounts<'a> {
+    pub recipient: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+pub struct CancelLedgerGrantAccounts<'a> {
+    pub sender: AccountInfo<'a>,
+    pub sender_tokens: AccountInfo<'a>,
+    pub recipient_tokens: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    /// A stream with every variable length field in use.
+    fn full_stream() -> TokenStreamData {
+        let mut stream = TokenStreamData {
+            magic: PROGRAM_VERSION,
+            created_at: 7,
+            canceled_at: 9,
+            sender: key(1),
+            sender_tokens: key(2),
+            recipient: key(3),
+            recipient_tokens: key(4),
+            mint: key(5),
+            escrow_tokens: key(6),
+            ..Default::default()
+        };
+        stream.ix.start_time = 100;
+        stream.ix.end_time = 200;
+        stream.ix.stream_name = encode_name("Team grant");
+        stream.ix.notify_key = key(11);
+        stream.ix.compliance_authority = key(12);
+        stream.ix.partner = key(13);
+        stream.ix.tranches = vec![Tranche {
+            unlock_time: 150,
+            amount: 10,
+        }];
+        stream.ix.transfer_allowlist = vec![key(7), key(8)];
+        stream.ix.shares = vec![RecipientShare {
+            recipient: key(9),
+            bps: 2_500,
+        }];
+        stream.contributions = vec![Contribution {
+            contributor: key(10),
+            amount: 5,
+        }];
+        stream.subscription_canceled_at = u64::MAX;
+        stream
+    }
+
+    #[test]
+    fn base_size_is_an_empty_stream() {
+        let stream = TokenStreamData::default();
+        assert_eq!(stream.try_to_vec().unwrap().len(), METADATA_BASE_SIZE);
+    }
+
+    #[test]
+    fn fixed_offsets() {
+        let stream = full_stream();
+        let bytes = stream.try_to_vec().unwrap();
+        let at = |offset: usize, len: usize| &bytes[offset..offset + len];
+
+        assert_eq!(at(METADATA_MAGIC_OFFSET, 8), PROGRAM_VERSION.to_le_bytes());
+        assert_eq!(at(METADATA_CREATED_AT_OFFSET, 8), 7u64.to_le_bytes());
+        assert_eq!(at(METADATA_CANCELED_AT_OFFSET, 8), 9u64.to_le_bytes());
+        assert_eq!(at(METADATA_SENDER_OFFSET, 32), key(1).as_ref());
+        assert_eq!(at(METADATA_SENDER_TOKENS_OFFSET, 32), key(2).as_ref());
+        assert_eq!(at(METADATA_RECIPIENT_OFFSET, 32), key(3).as_ref());
+        assert_eq!(at(METADATA_RECIPIENT_TOKENS_OFFSET, 32), key(4).as_ref());
+        assert_eq!(at(METADATA_MINT_OFFSET, 32), key(5).as_ref());
+        assert_eq!(at(METADATA_ESCROW_TOKENS_OFFSET, 32), key(6).as_ref());
+        assert_eq!(at(METADATA_START_TIME_OFFSET, 8), 100u64.to_le_bytes());
+        assert_eq!(at(METADATA_END_TIME_OFFSET, 8), 200u64.to_le_bytes());
+        assert_eq!(at(METADATA_STREAM_NAME_OFFSET, 10), b"Team grant");
+        assert_eq!(at(METADATA_STREAM_NAME_OFFSET + 10, 54), [0; 54]);
+        assert_eq!(at(METADATA_NOTIFY_KEY_OFFSET, 32), key(11).as_ref());
+        assert_eq!(
+            at(METADATA_COMPLIANCE_AUTHORITY_OFFSET, 32),
+            key(12).as_ref()
+        );
+        assert_eq!(at(METADATA_PARTNER_OFFSET, 32), key(13).as_ref());
+        assert_eq!(at(METADATA_TRANCHES_OFFSET, 4), 1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn names_are_cut_at_a_whole_character() {
+        let name = format!("{}é", "x".repeat(STREAM_NAME_SIZE - 1));
+        let ix = StreamInstruction {
+            stream_name: encode_name(&name),
+            ..Default::default()
+        };
+        assert_eq!(ix.name(), "x".repeat(STREAM_NAME_SIZE - 1));
+        assert_eq!(StreamInstruction::default().name(), "Stream");
+    }
+
+    #[test]
+    fn subscription_canceled_at_is_trailing() {
+        let bytes = full_stream().try_to_vec().unwrap();
+        assert!(bytes.ends_with(&u64::MAX.to_le_bytes()));
+    }
+
+    #[test]
+    fn variable_fields_add_to_base_size() {
+        let stream = full_stream();
+        let expected =
+            METADATA_BASE_SIZE + TRANCHE_SIZE + 32 * 2 + RECIPIENT_SHARE_SIZE + CONTRIBUTION_SIZE;
+        assert_eq!(stream.try_to_vec().unwrap().len(), expected);
+    }
+
+    #[test]
+    fn account_size_fits_every_contributor_rounded_to_8() {
+        let mut stream = full_stream();
+        stream.contributions = vec![Contribution::default(); MAX_CONTRIBUTORS];
+        let size = TokenStreamData::account_size(1, 2, 1);
+
+        assert_eq!(size % 8, 0);
+        let len = stream.try_to_vec().unwrap().len();
+        assert!(len <= size && size < len + 8);
+    }
+
+    /// `stream` in the v2 layout, with a length prefixed `stream_name`.
+    fn v2_bytes(stream: &TokenStreamData) -> Vec<u8> {
+        let mut stream = stream.clone();
+        stream.magic = crate::migrations::V2_VERSION;
+        let bytes = stream.try_to_vec().unwrap();
+        let name = stream.ix.name();
+
+        let mut v2 = bytes[..METADATA_STREAM_NAME_OFFSET].to_vec();
+        v2.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        v2.extend_from_slice(name.as_bytes());
+        v2.extend_from_slice(&bytes[METADATA_STREAM_NAME_OFFSET + STREAM_NAME_SIZE..]);
+        v2
+    }
+
+    #[test]
+    fn v2_streams_decode_with_a_fixed_name() {
+        let stream = full_stream();
+        let bytes = stream.try_to_vec().unwrap();
+        let v2 = v2_bytes(&stream);
+        assert_eq!(
+            TokenStreamData::load(&v2).unwrap_err(),
+            MetadataNeedsMigration.into()
+        );
+
+        let decoded = crate::migrations::decode_v2(&v2).unwrap();
+        assert_eq!(decoded.try_to_vec().unwrap(), bytes);
+    }
+
+    #[test]
+    fn v2_accounts_with_or_without_later_fields_decode() {
+        let mut stream = full_stream();
+        stream.subscription_canceled_at = 0;
+        let bytes = stream.try_to_vec().unwrap();
+
+        // With v2's reserved block, and created before the last field.
+        let mut v2 = v2_bytes(&stream);
+        v2.extend_from_slice(&[0; 64]);
+        let short = &v2[..v2.len() - 64 - 8];
+        for data in [&v2[..], short] {
+            assert!(crate::migrations::TokenStreamDataV1::decode(data).is_none());
+            let decoded = crate::migrations::decode_v2(data).unwrap();
+            assert_eq!(decoded.try_to_vec().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn outdated_streams_are_decoded_by_stored_version() {
+        use crate::migrations::{
+            decode_outdated, StreamInstructionV1, TokenStreamDataV1, V2_VERSION,
+        };
+
+        let v1 = TokenStreamDataV1 {
+            magic: V2_VERSION,
+            created_at: 7,
+            withdrawn_amount: 0,
+            canceled_at: 0,
+            closable_at: 200,
+            last_withdrawn_at: 0,
+            sender: key(1),
+            sender_tokens: key(2),
+            recipient: key(3),
+            recipient_tokens: key(4),
+            mint: key(5),
+            escrow_tokens: key(6),
+            ix: StreamInstructionV1 {
+                start_time: 100,
+                end_time: 200,
+                deposited_amount: 10,
+                total_amount: 10,
+                period: 1,
+                cliff: 0,
+                cliff_amount: 0,
+                cancelable_by_sender: true,
+                cancelable_by_recipient: false,
+                withdrawal_public: false,
+                transferable_by_sender: false,
+                transferable_by_recipient: true,
+                release_rate: 0,
+                stream_name: "Grant".to_string(),
+            },
+        };
+        let mut account = v1.try_to_vec().unwrap();
+        account.resize(account.len() + (8 - account.len() % 8) % 8, 0);
+        assert_eq!(
+            TokenStreamData::load(&account).unwrap_err(),
+            MetadataNeedsMigration.into()
+        );
+        let upgraded = decode_outdated(&account, &key(8)).unwrap();
+        assert_eq!(upgraded.magic, PROGRAM_VERSION);
+        assert_eq!(upgraded.ix.name(), "Grant");
+        assert_eq!(upgraded.legacy_address, key(8));
+
+        let v2 = decode_outdated(&v2_bytes(&full_stream()), &key(8)).unwrap();
+        assert_eq!(v2.legacy_address, Pubkey::default());
+        assert_eq!(v2.ix.name(), "Team grant");
+
+        let mut unknown = full_stream();
+        unknown.magic = PROGRAM_VERSION + 1;
+        let bytes = unknown.try_to_vec().unwrap();
+        assert!(decode_outdated(&bytes, &key(8)).is_none());
+        assert_eq!(
+            TokenStreamData::load(&bytes).unwrap_err(),
+            InvalidMetadata.into()
+        );
+    }
+
+    /// Serialized length of `value`, so the fixed-size accounts below are
+    /// checked against what Borsh actually writes at full capacity.
+    fn len<T: BorshSerialize>(value: &T) -> usize {
+        value.try_to_vec().unwrap().len()
+    }
+
+    #[test]
+    fn fixed_account_sizes() {
+        let stream = full_stream();
+        let mirror = StreamMirror::new(&key(9), &stream, 1);
+        assert_eq!((STREAM_MIRROR_SIZE, len(&mirror)), (179, 179));
+
+        let record = SettlementRecord {
+            stream: key(1),
+            sender: key(2),
+            recipient: key(3),
+            mint: key(4),
+            end_cause: EndCause::CanceledBySender,
+            created_at: 1,
+            ended_at: 2,
+            deposited: 3,
+            paid: 4,
+            refunded: 5,
+            fees_paid: 6,
+        };
+        assert_eq!((SETTLEMENT_RECORD_SIZE, len(&record)), (177, 177));
+
+        let pool = ConversionPool {
+            authority: key(1),
+            old_mint: key(2),
+            new_mint: key(3),
+            ratio: ConversionRatio {
+                numerator: 1,
+                denominator: 2,
+            },
+        };
+        assert_eq!((CONVERSION_POOL_SIZE, len(&pool)), (112, 112));
+
+        let config = FeeConfig {
+            admin: key(1),
+            treasury: key(2),
+            fee_bps: 25,
+        };
+        assert_eq!((FEE_CONFIG_SIZE, len(&config)), (66, 66));
+
+        assert_eq!((STREAM_COUNTER_SIZE, len(&u64::MAX)), (8, 8));
+    }
+
+    #[test]
+    fn list_account_sizes_at_capacity() {
+        let allowlist = TopUpAllowlist {
+            stream: key(1),
+            members: vec![key(2); MAX_TOPUP_ALLOWLIST],
+        };
+        assert_eq!((TOPUP_ALLOWLIST_SIZE, len(&allowlist)), (548, 548));
+
+        let exemptions = FeeExemptions {
+            admin: key(1),
+            members: vec![key(2); MAX_FEE_EXEMPTIONS],
+        };
+        assert_eq!((FEE_EXEMPTIONS_SIZE, len(&exemptions)), (1060, 1060));
+
+        let index = StreamIndex {
+            owner: key(1),
+            role: IndexRole::Recipient,
+            page: 3,
+            streams: vec![key(2); STREAM_INDEX_PAGE_LEN],
+        };
+        assert_eq!((STREAM_INDEX_SIZE, len(&index)), (2089, 2089));
+    }
+
+    #[test]
+    fn grant_ledger_size_fits_full_names() {
+        let grant = LedgerGrant {
+            ix: StreamInstruction {
+                stream_name: [b'x'; STREAM_NAME_SIZE],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ledger = GrantLedger {
+            capacity: 4,
+            grants: vec![grant; 4],
+            ..Default::default()
+        };
+
+        let size = GrantLedger::size(4);
+        assert_eq!(size % 8, 0);
+        assert!(len(&ledger) <= size && size < len(&ledger) + 8);
+        assert_eq!(GrantLedger::size(0), 208);
+    }
+
+    /// 1000 tokens over 1000..2000 in 10 second periods, 10 per period.
+    fn linear(deposited: u64, policy: UnderfundedPolicy) -> TokenStreamData {
+        let mut stream = TokenStreamData {
+            created_at: 1_000,
+            ..Default::default()
+        };
+        stream.ix.start_time = 1_000;
+        stream.ix.end_time = 2_000;
+        stream.ix.period = 10;
+        stream.ix.total_amount = 1_000;
+        stream.ix.deposited_amount = deposited;
+        stream.ix.underfunded_policy = policy;
+        stream.cache_schedule();
+        stream.closable_at = stream.closable();
+        stream
+    }
+
+    #[test]
+    fn available_rounds_down_to_whole_periods() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        assert_eq!(stream.available(999), 0);
+        assert_eq!(stream.available(1_009), 0);
+        assert_eq!(stream.available(1_015), 10);
+        assert_eq!(stream.available(2_500), 1_000);
+
+        stream.withdrawn_amount = 300;
+        assert_eq!(stream.available(1_500), 200);
+        assert_eq!(stream.available(1_200), 0);
+    }
+
+    #[test]
+    fn period_is_bounded_by_the_vesting_duration() {
+        let mut stream = linear(u64::MAX, UnderfundedPolicy::AutoClose);
+        stream.ix.total_amount = u64::MAX;
+        stream.ix.period = 1_000;
+        assert!(stream.ix.check_schedule().is_ok());
+        stream.cache_schedule();
+        assert_eq!(stream.period_amount_scaled, u64::MAX as u128 * AMOUNT_SCALE);
+        assert_eq!(stream.available(1_999), 0);
+        assert_eq!(stream.available(2_000), u64::MAX);
+
+        stream.ix.period = 999;
+        stream.cache_schedule();
+        assert_eq!(
+            stream.available(1_999),
+            (u64::MAX as u128 * 999 / 1_000) as u64
+        );
+
+        stream.ix.period = 1_001;
+        assert_eq!(
+            stream.ix.check_schedule(),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn ledger_sums_overflow_as_errors() {
+        let grant = LedgerGrant {
+            ix: StreamInstruction {
+                deposited_amount: u64::MAX,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut ledger = GrantLedger {
+            grants: vec![grant.clone()],
+            ..Default::default()
+        };
+        assert_eq!(ledger.escrowed(), Ok(u64::MAX));
+
+        ledger.grants.push(grant);
+        assert_eq!(ledger.escrowed(), Err(ArithmeticOverflow.into()));
+    }
+
+    #[test]
+    fn cliff_releases_its_amount_then_the_rest_linearly() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        stream.ix.cliff = 1_500;
+        stream.ix.cliff_amount = 200;
+        stream.cache_schedule();
+
+        assert_eq!(stream.available(1_499), 0);
+        assert_eq!(stream.available(1_500), 200);
+        // 800 over the remaining 50 periods.
+        assert_eq!(stream.available(1_600), 360);
+        assert_eq!(stream.available(2_000), 1_000);
+        assert_eq!(stream.closable(), 2_000);
+    }
+
+    #[test]
+    fn sender_pause_stops_the_schedule() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        stream.sender_paused_at = 1_100;
+        assert_eq!(stream.available(1_200), 100);
+        assert_eq!(stream.effective_now(1_200), 1_100);
+
+        // Resumed after 100 seconds, then 50 more pass.
+        stream.sender_paused_at = 0;
+        stream.sender_paused_seconds = 100;
+        assert_eq!(stream.available(1_250), 150);
+        assert_eq!(stream.available(2_050), 950);
+        assert_eq!(stream.available(2_100), 1_000);
+    }
+
+    #[test]
+    fn recipient_sabbatical_is_capped() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        stream.ix.max_recipient_pause = 30;
+        stream.recipient_paused_at = 1_100;
+
+        assert_eq!(stream.recipient_paused_time(1_120), 20);
+        assert_eq!(stream.available(1_120), 100);
+        // Past the cap the schedule runs again, 30 seconds behind.
+        assert_eq!(stream.available(1_200), 170);
+
+        stream.sender_paused_seconds = 10;
+        assert_eq!(stream.paused_time(1_200), 40);
+        assert_eq!(stream.available(1_200), 160);
+    }
+
+    #[test]
+    fn auto_close_stream_ends_when_funds_run_out() {
+        let stream = linear(400, UnderfundedPolicy::AutoClose);
+        assert_eq!(stream.closable(), 1_400);
+        assert_eq!(stream.available(1_300), 300);
+        assert_eq!(stream.available(1_600), 400);
+        assert_eq!(stream.natural_end_cause(), EndCause::AutoClosedUnderfunded);
+        assert_eq!(stream.funding_runway(1_300), 100);
+    }
+
+    #[test]
+    fn halted_stream_resumes_where_funds_ran_out() {
+        let mut stream = linear(400, UnderfundedPolicy::HaltAccrual);
+        assert_eq!(stream.closable(), 2_000);
+        assert_eq!(stream.effective_now(1_600), 1_400);
+        assert_eq!(stream.available(1_600), 400);
+
+        // Topped up to the full amount 200 seconds after running dry.
+        stream.settle_halt(1_600);
+        stream.ix.deposited_amount = 1_000;
+        assert_eq!(stream.halted_seconds, 200);
+        assert_eq!(stream.available(1_700), 500);
+        assert_eq!(stream.available(2_200), 1_000);
+        assert_eq!(stream.natural_end_cause(), EndCause::Completed);
+    }
+
+    #[test]
+    fn release_rate_stream_closes_by_policy() {
+        let mut stream = linear(300, UnderfundedPolicy::AutoClose);
+        stream.ix.release_rate = 10;
+        stream.cache_schedule();
+        assert_eq!(stream.closable(), 1_300);
+        assert_eq!(stream.available(1_250), 250);
+        assert_eq!(stream.available(5_000), 300);
+
+        stream.ix.underfunded_policy = UnderfundedPolicy::HaltAccrual;
+        assert_eq!(stream.closable(), u64::MAX);
+    }
+
+    #[test]
+    fn canceled_release_rate_stream_pays_partial_period_by_policy() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        stream.ix.release_rate = 10;
+        stream.cache_schedule();
+
+        stream.ix.partial_period = PartialPeriodPolicy::Forfeit;
+        assert_eq!(stream.available_on_cancel(1_014), 10);
+        stream.ix.partial_period = PartialPeriodPolicy::Prorate;
+        assert_eq!(stream.available_on_cancel(1_014), 14);
+        stream.ix.partial_period = PartialPeriodPolicy::PayFull;
+        assert_eq!(stream.available_on_cancel(1_014), 20);
+    }
+
+    #[test]
+    fn private_stream_is_revealed_by_its_recipient() {
+        let mut stream = linear(1_000, UnderfundedPolicy::AutoClose);
+        assert!(stream.recipient_revealed());
+
+        stream.ix.recipient_hash = [1; 32];
+        assert!(!stream.recipient_revealed());
+        stream.recipient = key(3);
+        assert!(stream.recipient_revealed());
+    }
+
+    #[test]
+    fn protocol_fee_rounds_down_in_basis_points() {
+        let config = FeeConfig {
+            fee_bps: 25,
+            ..Default::default()
+        };
+        assert_eq!(config.fee(1_000_000), 2_500);
+        assert_eq!(config.fee(399), 0);
+        assert_eq!(config.fee(400), 1);
+
+        let config = FeeConfig {
+            fee_bps: MAX_FEE_BPS,
+            ..Default::default()
+        };
+        assert_eq!(config.fee(u64::MAX), u64::MAX / 10);
+    }
+}
@@ -0,0 +1,299 @@
+//! An async client over `solana-client` that takes streams from instruction
+//! to confirmed transaction. Every transaction is simulated before it is
+//! sent, so failures come back as `StreamFlowError`s or a preflight report
+//! instead of bare custom error codes. A transaction whose blockhash expires
+//! before it lands is re-signed with a fresh one and sent again.
+
+use std::time::Duration;
+
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcSendTransactionConfig,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::{Instruction, InstructionError},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::cluster::{ClusterConfig, ConfigError};
+use crate::error::StreamFlowError;
+use crate::instruction::{cancel_stream, create_stream, withdraw_stream};
+use crate::preflight::{PreflightReport, Simulation};
+use crate::state::{StreamInstruction, TokenStreamData};
+
+/// Times a transaction is signed and sent before giving up on it landing.
+pub const DEFAULT_ATTEMPTS: usize = 3;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum StreamClientError {
+    #[error("RPC request failed: {0}")]
+    Rpc(#[from] ClientError),
+
+    #[error("{0}")]
+    Program(StreamFlowError),
+
+    /// A simulation failing for a reason other than an error of this program.
+    #[error("Simulation failed: {error}")]
+    Preflight {
+        error: TransactionError,
+        report: PreflightReport,
+    },
+
+    #[error("Transaction failed: {0}")]
+    Transaction(TransactionError),
+
+    #[error("Transaction didn't land in {0} attempts")]
+    Expired(usize),
+
+    #[error("{0} is not a stream")]
+    InvalidStream(Pubkey),
+
+    #[error("Failed to build instruction: {0}")]
+    Instruction(#[from] ProgramError),
+}
+
+/// The error of this program that failed a transaction, if `error` was
+/// raised by the program at `program_id` and not by one it invoked. The
+/// first program `logs` report failing is the one that raised it.
+pub fn decode_error(
+    program_id: &Pubkey,
+    error: &TransactionError,
+    logs: &[String],
+) -> Option<StreamFlowError> {
+    let code = match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => *code,
+        _ => return None,
+    };
+    let failed = logs.iter().find(|log| log.contains(" failed: "))?;
+    if !failed.starts_with(&format!("Program {} failed", program_id)) {
+        return None;
+    }
+
+    StreamFlowError::ALL.get(code as usize).copied()
+}
+
+pub struct StreamClient {
+    rpc: RpcClient,
+    program_id: Pubkey,
+    attempts: usize,
+}
+
+impl StreamClient {
+    pub fn new(rpc: RpcClient, program_id: Pubkey) -> Self {
+        StreamClient {
+            rpc,
+            program_id,
+            attempts: DEFAULT_ATTEMPTS,
+        }
+    }
+
+    /// A client of the program `config` selects, confirming transactions at
+    /// `confirmed` commitment.
+    pub fn from_config(config: &ClusterConfig) -> Result<Self, ConfigError> {
+        let url = config
+            .rpc_url
+            .clone()
+            .ok_or_else(|| ConfigError::MissingRpcUrl(config.cluster.clone()))?;
+        let rpc = RpcClient::new_with_commitment(url, CommitmentConfig::confirmed());
+        Ok(Self::new(rpc, config.program_id))
+    }
+
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub async fn stream(&self, metadata: &Pubkey) -> Result<TokenStreamData, StreamClientError> {
+        let account = self.rpc.get_account(metadata).await?;
+        if account.owner != self.program_id {
+            return Err(StreamClientError::InvalidStream(*metadata));
+        }
+        TokenStreamData::decode(&account.data)
+            .map_err(|_| StreamClientError::InvalidStream(*metadata))
+    }
+
+    /// Creates a stream of `ix` from `sender_tokens` to `recipient` and
+    /// returns its metadata address once confirmed. Streams that need extra
+    /// accounts, see `create_stream`, go through `send` instead.
+    pub async fn create_and_confirm(
+        &self,
+        sender: &Keypair,
+        sender_tokens: &Pubkey,
+        recipient: &Pubkey,
+        mint: &Pubkey,
+        ix: &StreamInstruction,
+    ) -> Result<Pubkey, StreamClientError> {
+        let token_program = self.rpc.get_account(mint).await?.owner;
+        let create = create_stream(
+            &self.program_id,
+            &sender.pubkey(),
+            sender_tokens,
+            recipient,
+            mint,
+            &token_program,
+            ix,
+        )?;
+        let metadata = create.accounts[4].pubkey;
+
+        self.send(&[create], &[sender]).await?;
+        Ok(metadata)
+    }
+
+    /// Withdraws everything vested to the stream's recipient and returns the
+    /// amount, 0 when nothing was available.
+    pub async fn withdraw_available(
+        &self,
+        recipient: &Keypair,
+        metadata: &Pubkey,
+    ) -> Result<u64, StreamClientError> {
+        let before = self.stream(metadata).await?;
+        let token_program = self.rpc.get_account(&before.mint).await?.owner;
+        let withdraw = withdraw_stream(
+            &self.program_id,
+            &recipient.pubkey(),
+            &before.recipient_tokens,
+            &before.sender,
+            metadata,
+            &before.escrow_tokens,
+            &before.mint,
+            &token_program,
+            0,
+        );
+
+        match self.send(&[withdraw], &[recipient]).await {
+            Err(StreamClientError::Program(StreamFlowError::NothingToWithdraw)) => return Ok(0),
+            result => result?,
+        };
+        let after = self.stream(metadata).await?;
+        Ok(after
+            .withdrawn_amount
+            .saturating_sub(before.withdrawn_amount))
+    }
+
+    /// Cancels the stream as `authority`, its sender or recipient. Managed
+    /// freeze streams need their freeze accounts, see
+    /// `OptionalCancelAccounts`, and go through `send` instead.
+    pub async fn cancel(
+        &self,
+        authority: &Keypair,
+        metadata: &Pubkey,
+    ) -> Result<Signature, StreamClientError> {
+        let stream = self.stream(metadata).await?;
+        let token_program = self.rpc.get_account(&stream.mint).await?.owner;
+        let cancel = cancel_stream(
+            &self.program_id,
+            &authority.pubkey(),
+            &stream.sender,
+            &stream.sender_tokens,
+            &stream.recipient,
+            &stream.recipient_tokens,
+            metadata,
+            &stream.escrow_tokens,
+            &stream.mint,
+            &token_program,
+        );
+
+        self.send(&[cancel], &[authority]).await
+    }
+
+    /// Sends `instructions` in one transaction paid for by the first of
+    /// `signers` and waits until it is confirmed. Nothing is sent if the
+    /// simulation fails.
+    pub async fn send(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Signature, StreamClientError> {
+        let payer = signers[0].pubkey();
+
+        for _ in 0..self.attempts {
+            let blockhash = self.rpc.get_latest_blockhash().await?;
+            let tx = Transaction::new_signed_with_payer(
+                instructions,
+                Some(&payer),
+                &signers.to_vec(),
+                blockhash,
+            );
+
+            let simulation = self.rpc.simulate_transaction(&tx).await?.value;
+            match simulation.err {
+                Some(TransactionError::BlockhashNotFound) => continue,
+                Some(error) => return Err(self.failure(error, simulation.logs.unwrap_or_default())),
+                None => {}
+            }
+
+            let config = RpcSendTransactionConfig {
+                skip_preflight: true,
+                ..RpcSendTransactionConfig::default()
+            };
+            let signature = self.rpc.send_transaction_with_config(&tx, config).await?;
+            if self.confirm(&signature, &blockhash).await? {
+                return Ok(signature);
+            }
+        }
+
+        Err(StreamClientError::Expired(self.attempts))
+    }
+
+    /// Waits for `signature` to be confirmed. False once `blockhash` expired
+    /// without the transaction landing, so it is safe to send again.
+    async fn confirm(
+        &self,
+        signature: &Signature,
+        blockhash: &Hash,
+    ) -> Result<bool, StreamClientError> {
+        loop {
+            match self.rpc.get_signature_status(signature).await? {
+                Some(Ok(())) => return Ok(true),
+                Some(Err(error)) => return Err(StreamClientError::Transaction(error)),
+                None => {
+                    let valid = self
+                        .rpc
+                        .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+                        .await?;
+                    // One that landed before the blockhash expired still
+                    // gets confirmed.
+                    if !valid
+                        && self
+                            .rpc
+                            .get_signature_status_with_commitment(
+                                signature,
+                                CommitmentConfig::processed(),
+                            )
+                            .await?
+                            .is_none()
+                    {
+                        return Ok(false);
+                    }
+                }
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn failure(&self, error: TransactionError, logs: Vec<String>) -> StreamClientError {
+        match decode_error(&self.program_id, &error, &logs) {
+            Some(e) => StreamClientError::Program(e),
+            None => {
+                let simulation = Simulation { failed: true, logs };
+                StreamClientError::Preflight {
+                    error,
+                    report: PreflightReport::new(&simulation, None, 0),
+                }
+            }
+        }
+    }
+}
@@ -1,34 +1,130 @@
 use borsh::BorshSerialize;
 use solana_program::{
+    account_info::AccountInfo,
     borsh as solana_borsh,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
-    program::{invoke, invoke_signed},
+    program::{get_return_data, invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
     system_instruction, system_program, sysvar,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use spl_associated_token_account::{instruction:: create_associated_token_account, get_associated_token_address};
+use std::convert::TryInto;
+use std::iter;
 
 use crate::error::StreamFlowError::{
-    AccountsNotWritable, InvalidMetadata, MintMismatch, StreamClosed, TransferNotAllowed,
+    AccountsNotWritable, ArithmeticOverflow, CancelDelayPending, ClaimBelowMinimum,
+    ComplianceFrozen, CrankNotDue, DestinationNotAllowed, DuplicateWithdrawal,
+    FreezeAuthorityMismatch, HoldingBelowMinimum, InvalidMetadata, InvalidRecipientProof,
+    MintMismatch, NoPendingTransfer, NothingToWithdraw, PauseBudgetExhausted, RecipientNotRevealed,
+    SharedStream, StreamClosed, StreamNotPaused, StreamNotSettled, StreamPaused, TooManySigners,
+    TooManyStreams, TopUpNotAllowed, TransferCooldown, TransferNotAllowed,
 };
+use crate::events::{
+    emit, Cancelled, SharePaid, StreamCreated, StreamEvent, ToppedUp, Transferred, Withdrawn,
+};
+use crate::lp;
+use crate::mirror::update_mirror;
+#[cfg(feature = "token2022")]
+use crate::obligation::{burn_obligation, mint_obligation, require_obligation_accounts};
+use crate::registry::sync_indexes;
+use crate::stack_msg;
 use crate::state::{
-    CancelAccounts, InitializeAccounts, StreamInstruction, TokenStreamData, TopUpAccounts,
-    TransferAccounts, WithdrawAccounts,
+    encode_name, AcceptTransferAccounts, CancelAccounts, CancelBatchAccounts,
+    CancelSubscriptionAccounts, CancelTransferAccounts, CloseStreamAccounts,
+    ComplianceFreezeAccounts, CrankWithdrawAccounts, CreateFromProgramInstruction,
+    CreateManyAccounts, EndCause, EscrowProof, EscrowProofAccounts, FeeAccounts, FeeConfig,
+    FeeExemptions, InitializeAccounts, MintSummary, MintSummaryAccounts, PartialPeriodPolicy,
+    PreviewAccounts, PrewarmAccounts, ProposeTransferAccounts, RecipientAccounts,
+    RefreshClosableAccounts, SenderAccounts, SetForwardAccounts, SnapshotAccounts,
+    StreamInstruction, StreamPreview, TokenStreamData, TopUpAccounts, TopUpAllowlist, TopUpPolicy,
+    TransferAccounts, UndoTopUpAccounts, VestingSnapshot, WithdrawAccounts,
+    WithdrawSurplusAccounts, FEE_EXEMPTIONS_SEED, FREEZE_AUTHORITY_SEED, MAX_COMPLIANCE_FREEZE,
+    MAX_FEE_BPS, MAX_SHARES, MAX_TRANSFER_ALLOWLIST, METADATA_BASE_SIZE, METADATA_END_TIME_OFFSET,
+    METADATA_MINT_OFFSET, METADATA_RECIPIENT_OFFSET, METADATA_SEED, METADATA_SENDER_OFFSET,
+    METADATA_STREAM_NAME_OFFSET, METADATA_TRANCHES_OFFSET, RECIPIENT_SHARE_SIZE,
+    STREAM_COUNTER_SEED, STREAM_COUNTER_SIZE, TOPUP_ALLOWLIST_SEED, TRANCHE_SIZE,
 };
 use crate::utils::{
-    duration_sanity, encode_base10, pretty_time, unpack_mint_account, unpack_token_account,
+    associated_token_address, close_program_account, duration_sanity, encode_base10,
+    is_token_program, normalize_timestamp, optional_slot, pretty_time, recipient_hash,
+    sanitize_name, unpack_mint_account, unpack_multisig_account, unpack_token_account, volatile,
+    withheld_fees, Base10, Key, TimeSource,
 };
 
-const MAX_STRING_SIZE: usize = 200;
+pub const MAX_CANCEL_BATCH: usize = 8;
+pub const CANCEL_BATCH_GROUP_LEN: usize = 10;
+pub const MAX_CREATE_BATCH: usize = 8;
+pub const CREATE_BATCH_GROUP_LEN: usize = 4;
+/// Most streams a single `mint_summary` may aggregate.
+pub const MAX_MINT_SUMMARY_STREAMS: usize = 32;
+
+const GET_ACCOUNT_DATA_SIZE: u8 = 21;
+const TRANSFER_FEE_EXTENSION: u8 = 26;
+const HARVEST_WITHHELD_TOKENS_TO_MINT: u8 = 4;
+const ATA_CREATE_IDEMPOTENT: u8 = 1;
 
 pub fn create(
     program_id: &Pubkey,
     acc: InitializeAccounts,
     ix: StreamInstruction,
+) -> ProgramResult {
+    create_stream(program_id, acc, ix, None)
+}
+
+/// `create` for a sender that is a PDA of another program, e.g. an SPL
+/// Governance or Squads treasury, signing through that program's
+/// `invoke_signed`. `payer` covers the rent, as program-owned senders can't
+/// fund new accounts. The sender then cancels and tops up through the usual
+/// instructions, signed the same way.
+pub fn create_from_program(
+    program_id: &Pubkey,
+    acc: InitializeAccounts,
+    ci: CreateFromProgramInstruction,
+) -> ProgramResult {
+    msg!("Initializing SPL token stream for a program-owned sender");
+
+    let payer = match &acc.payer {
+        Some(v) => v,
+        None => return Err(ProgramError::NotEnoughAccountKeys),
+    };
+    if !payer.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let seeds: Vec<&[u8]> = ci.seeds.iter().map(Vec::as_slice).collect();
+    match Pubkey::create_program_address(&seeds, &ci.owner_program) {
+        Ok(address) if &address == acc.sender.key && &ci.owner_program != program_id => {}
+        _ => {
+            msg!("Error: Sender is not an address of {}", ci.owner_program);
+            return Err(ProgramError::InvalidSeeds);
+        }
+    }
+
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    create_stream(program_id, acc, ci.stream, None)
+}
+
+/// Escrow of another stream funding a new one instead of the sender's tokens,
+/// see `rollover`.
+pub(crate) struct EscrowSource<'a, 'b> {
+    pub metadata: Pubkey,
+    pub escrow_tokens: AccountInfo<'a>,
+    pub seeds: &'b [&'b [u8]],
+}
+
+pub(crate) fn create_stream<'a>(
+    program_id: &Pubkey,
+    acc: InitializeAccounts<'a>,
+    mut ix: StreamInstruction,
+    source: Option<EscrowSource<'a, '_>>,
 ) -> ProgramResult {
     msg!("Initializing SPL token stream");
 
@@ -36,30 +132,49 @@ pub fn create(
         return Err(ProgramError::AccountAlreadyInitialized);
     }
 
+    // Private streams only commit to a hash of the recipient, so the
+    // recipient accounts are neither checked nor touched until reveal.
+    let private = ix.recipient_hash != [0; 32];
+
     if !acc.sender.is_writable
         || !acc.sender_tokens.is_writable
-        || !acc.recipient.is_writable
-        || !acc.recipient_tokens.is_writable
+        || (!private && (!acc.recipient.is_writable || !acc.recipient_tokens.is_writable))
         || !acc.metadata.is_writable
         || !acc.escrow_tokens.is_writable
     {
         return Err(AccountsNotWritable.into());
     }
 
+    let derived_recipient = if private {
+        Pubkey::new_from_array(ix.recipient_hash)
+    } else {
+        *acc.recipient.key
+    };
+    let (metadata_pubkey, metadata_bump) = TokenStreamData::metadata_address(
+        program_id,
+        acc.sender.key,
+        &derived_recipient,
+        acc.mint.key,
+        &ix.metadata_seed,
+    );
     let (escrow_tokens_pubkey, nonce) =
         Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
-    let recipient_tokens_key = get_associated_token_address(acc.recipient.key, acc.mint.key);
+    let recipient_tokens_key =
+        associated_token_address(acc.recipient.key, acc.mint.key, acc.token_program.key);
 
     if acc.system_program.key != &system_program::id()
-        || acc.token_program.key != &spl_token::id()
+        || !is_token_program(acc.token_program.key)
+        || acc.mint.owner != acc.token_program.key
+        || acc.associated_token_program.key != &spl_associated_token_account::id()
         || acc.rent.key != &sysvar::rent::id()
+        || acc.metadata.key != &metadata_pubkey
         || acc.escrow_tokens.key != &escrow_tokens_pubkey
-        || acc.recipient_tokens.key != &recipient_tokens_key
+        || (!private && acc.recipient_tokens.key != &recipient_tokens_key)
     {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    if !acc.sender.is_signer || !acc.metadata.is_signer {
+    if !acc.sender.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
 
@@ -70,23 +185,89 @@ pub fn create(
         return Err(MintMismatch.into());
     }
 
+    check_multisig_owner(&sender_token_info, &acc.multisig, &acc.multisig_signers)?;
+
+    if ix.managed_freeze {
+        check_freeze_delegation(
+            program_id,
+            &mint_info,
+            acc.mint.key,
+            &acc.freeze_authority,
+            &acc.freeze_issuer,
+        )?;
+    }
+
     let now = Clock::get()?.unix_timestamp as u64;
+    ix.check_start(now)?;
     if !duration_sanity(now, ix.start_time, ix.end_time, ix.cliff) {
         msg!("Error: Given timestamps are invalid");
         return Err(ProgramError::InvalidArgument);
     }
+    ix.check_schedule()?;
+
+    if ix.hold_amount > 0 && ix.hold_mint == Pubkey::default() {
+        msg!("Error: A holding condition needs a mint");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if ix.transfer_allowlist.len() > MAX_TRANSFER_ALLOWLIST {
+        msg!(
+            "Error: At most {} allowed transfer destinations",
+            MAX_TRANSFER_ALLOWLIST
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if ix.bond_amount > 0 && (private || ix.bond_until <= now) {
+        msg!("Error: A bond needs a public recipient and a future release date");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if ix.partner_fee_bps > MAX_FEE_BPS
+        || (ix.partner_fee_bps > 0 && ix.partner == Pubkey::default())
+    {
+        msg!(
+            "Error: Partner fee needs a partner and can't exceed {} bps",
+            MAX_FEE_BPS
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
 
-    if ix.stream_name.len() > MAX_STRING_SIZE {
-        msg!("Error: Stream name too long!");
+    // Only withdrawals split payouts: a slashed bond or a partial period paid
+    // on cancel would go to the recipient alone, and frozen or undisclosed
+    // co-recipient accounts couldn't be paid at all.
+    if !ix.shares.is_empty() {
+        if private
+            || ix.managed_freeze
+            || ix.bond_amount > 0
+            || ix.partial_period != PartialPeriodPolicy::Forfeit
+        {
+            msg!("Error: Shared streams are public, unbonded, unpermissioned and forfeit partial periods");
+            return Err(ProgramError::InvalidArgument);
+        }
+        ix.check_shares(acc.recipient.key)?;
+    }
+
+    #[cfg(not(feature = "token2022"))]
+    if ix.obligation_nft {
+        msg!("Error: Obligation tokens are not supported by this build");
         return Err(ProgramError::InvalidArgument);
     }
 
+    ix.stream_name = encode_name(&sanitize_name(&ix.name()));
+
+    let (recipient, recipient_tokens) = if private {
+        (Pubkey::default(), Pubkey::default())
+    } else {
+        (*acc.recipient.key, *acc.recipient_tokens.key)
+    };
+
     let mut metadata = TokenStreamData::new(
         now,
         *acc.sender.key,
         *acc.sender_tokens.key,
-        *acc.recipient.key,
-        *acc.recipient_tokens.key,
+        recipient,
+        recipient_tokens,
         *acc.mint.key,
         *acc.escrow_tokens.key,
         ix.start_time,
@@ -103,6 +284,35 @@ pub fn create(
         ix.transferable_by_recipient,
         ix.release_rate,
         ix.stream_name,
+        ix.recipient_hash,
+        ix.max_recipient_pause,
+        ix.underfunded_policy,
+        ix.notify_key,
+        ix.managed_freeze,
+        ix.strict_withdraw,
+        ix.obligation_nft,
+        ix.compliance_authority,
+        ix.topup_refund_window,
+        ix.metadata_seed,
+        ix.bond_amount,
+        ix.bond_until,
+        ix.partial_period,
+        ix.withdraw_frequency,
+        ix.cranker_fee,
+        ix.partner,
+        ix.partner_fee_bps,
+        ix.tranches,
+        ix.curve,
+        ix.step_periods,
+        ix.transfer_cooldown,
+        ix.transfer_allowlist,
+        ix.can_topup,
+        ix.cancel_delay_seconds,
+        ix.hold_mint,
+        ix.hold_amount,
+        ix.origin,
+        ix.subscription,
+        ix.shares,
     );
 
     if ix.deposited_amount < ix.total_amount || ix.release_rate > 0 {
@@ -110,78 +320,103 @@ pub fn create(
         msg!("Closable at: {}", metadata.closable_at);
     }
 
-    let metadata_bytes = metadata.try_to_vec()?;
-    let mut metadata_struct_size = metadata_bytes.len();
-    while metadata_struct_size % 8 > 0 {
-        metadata_struct_size += 1;
+    metadata.derived_recipient = derived_recipient;
+    metadata.derived_mint = *acc.mint.key;
+    metadata.metadata_bump = metadata_bump;
+
+    if let Some(source) = &source {
+        metadata.rolled_from = source.metadata;
+        msg!("Rolled over from {}", metadata.rolled_from);
     }
-    let tokens_struct_size = spl_token::state::Account::LEN;
+
+    let payer = acc.payer.as_ref().unwrap_or(&acc.sender);
+    metadata.stream_seq =
+        next_stream_seq(program_id, &acc.stream_counter, payer, &acc.system_program)?;
+    msg!("Stream seq: {}", metadata.stream_seq);
+
+    let metadata_struct_size = TokenStreamData::account_size(
+        metadata.ix.tranches.len(),
+        metadata.ix.transfer_allowlist.len(),
+        metadata.ix.shares.len(),
+    );
+    let tokens_struct_size = escrow_account_size(&acc.token_program, &acc.mint)?;
 
     let cluster_rent = Rent::get()?;
     let metadata_rent = cluster_rent.minimum_balance(metadata_struct_size);
     let mut tokens_rent = cluster_rent.minimum_balance(tokens_struct_size);
-    if acc.recipient_tokens.data_is_empty() {
+    if !private && acc.recipient_tokens.data_is_empty() {
         tokens_rent += cluster_rent.minimum_balance(tokens_struct_size);
     }
 
-
-    if acc.sender.lamports() < metadata_rent + tokens_rent {
-        msg!("Error: Insufficient funds in {}", acc.sender.key);
+    if payer.lamports() < metadata_rent + tokens_rent {
+        msg!("Error: Insufficient funds in {}", payer.key);
         return Err(ProgramError::InsufficientFunds);
     }
 
-    if sender_token_info.amount < ix.deposited_amount {
+    let escrowed = ix
+        .deposited_amount
+        .checked_add(ix.bond_amount)
+        .ok_or(ArithmeticOverflow)?;
+    if source.is_none() && sender_token_info.amount < escrowed {
         msg!("Error: Insufficient tokens in sender's wallet");
         return Err(ProgramError::InsufficientFunds);
     }
 
-    if acc.recipient_tokens.data_is_empty() {
+    if !private {
         msg!("Initializing recipient's associated token account");
-        invoke(
-            &create_associated_token_account(acc.sender.key, acc.recipient.key, acc.mint.key),
-            &[
-                acc.sender.clone(),
-                acc.recipient_tokens.clone(),
-                acc.recipient.clone(),
-                acc.mint.clone(),
-                acc.system_program.clone(),
-                acc.token_program.clone(),
-                acc.rent.clone(),
-            ],
+        create_recipient_ata(
+            payer,
+            &acc.recipient,
+            &acc.recipient_tokens,
+            &acc.mint,
+            &acc.system_program,
+            &acc.token_program,
+            &acc.associated_token_program,
         )?;
+        if !ix.managed_freeze && unpack_token_account(&acc.recipient_tokens)?.is_frozen() {
+            msg!(
+                "Warning: Recipient's token account is frozen, withdrawals fail until it's thawed"
+            );
+        }
     }
 
+    let metadata_seeds = [
+        METADATA_SEED,
+        acc.sender.key.as_ref(),
+        derived_recipient.as_ref(),
+        acc.mint.key.as_ref(),
+        &metadata.ix.metadata_seed,
+        &[metadata_bump],
+    ];
     msg!("Creating account for holding metadata");
-    invoke(
+    invoke_signed(
         &system_instruction::create_account(
-            acc.sender.key,
+            payer.key,
             acc.metadata.key,
             metadata_rent,
             metadata_struct_size as u64,
             program_id,
         ),
         &[
-            acc.sender.clone(),
+            payer.clone(),
             acc.metadata.clone(),
             acc.system_program.clone(),
         ],
+        &[&metadata_seeds],
     )?;
 
-    let mut data = acc.metadata.try_borrow_mut_data()?;
-    data[0..metadata_bytes.len()].clone_from_slice(&metadata_bytes);
-
     let seeds = [acc.metadata.key.as_ref(), &[nonce]];
     msg!("Creating account for holding tokens");
     invoke_signed(
         &system_instruction::create_account(
-            acc.sender.key,
+            payer.key,
             acc.escrow_tokens.key,
             cluster_rent.minimum_balance(tokens_struct_size),
             tokens_struct_size as u64,
-            &spl_token::id(),
+            acc.token_program.key,
         ),
         &[
-            acc.sender.clone(),
+            payer.clone(),
             acc.escrow_tokens.clone(),
             acc.system_program.clone(),
         ],
@@ -190,12 +425,15 @@ pub fn create(
 
     msg!("Initializing escrow account for {} token", acc.mint.key);
     invoke(
-        &spl_token::instruction::initialize_account(
+        &retarget(
+            spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                acc.escrow_tokens.key,
+                acc.mint.key,
+                acc.escrow_tokens.key,
+            )?,
             acc.token_program.key,
-            acc.escrow_tokens.key,
-            acc.mint.key,
-            acc.escrow_tokens.key,
-        )?,
+        ),
         &[
             acc.token_program.clone(),
             acc.escrow_tokens.clone(),
@@ -206,28 +444,110 @@ pub fn create(
     )?;
 
     msg!("Moving funds into escrow account");
-    invoke(
-        &spl_token::instruction::transfer(
-            acc.token_program.key,
-            acc.sender_tokens.key,
-            acc.escrow_tokens.key,
-            acc.sender.key,
-            &[],
-            metadata.ix.deposited_amount,
+    match &source {
+        Some(source) => invoke_signed(
+            &transfer_checked(
+                acc.token_program.key,
+                source.escrow_tokens.key,
+                acc.mint.key,
+                acc.escrow_tokens.key,
+                source.escrow_tokens.key,
+                &[],
+                metadata.ix.deposited_amount,
+                mint_info.decimals,
+            )?,
+            &[
+                source.escrow_tokens.clone(),
+                acc.mint.clone(),
+                acc.escrow_tokens.clone(),
+                source.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[source.seeds],
         )?,
-        &[
-            acc.sender_tokens.clone(),
-            acc.escrow_tokens.clone(),
-            acc.sender.clone(),
-            acc.token_program.clone(),
-        ],
+        None => transfer_from_sender(
+            &acc.token_program,
+            &acc.sender_tokens,
+            &acc.mint,
+            &acc.escrow_tokens,
+            acc.multisig.as_ref().unwrap_or(&acc.sender),
+            &acc.multisig_signers,
+            escrowed,
+            mint_info.decimals,
+        )?,
+    }
+
+    // Token-2022 transfer fees come out of what the escrow receives; the
+    // stream only ever promises what actually arrived. The bond is kept whole.
+    let received = unpack_token_account(&acc.escrow_tokens)?
+        .amount
+        .saturating_sub(metadata.ix.bond_amount);
+    if received < metadata.ix.deposited_amount {
+        msg!(
+            "Transfer fee: {}",
+            encode_base10(
+                metadata.ix.deposited_amount - received,
+                mint_info.decimals.into()
+            )
+        );
+        if metadata.ix.total_amount == metadata.ix.deposited_amount {
+            metadata.ix.total_amount = received;
+        }
+        metadata.ix.deposited_amount = received;
+        metadata.cache_schedule();
+        if metadata.ix.deposited_amount < metadata.ix.total_amount || metadata.ix.release_rate > 0 {
+            metadata.closable_at = metadata.closable();
+        }
+    }
+
+    let metadata_bytes = metadata.try_to_vec()?;
+    debug_assert_eq!(
+        metadata_bytes.len(),
+        METADATA_BASE_SIZE
+            + TRANCHE_SIZE * metadata.ix.tranches.len()
+            + 32 * metadata.ix.transfer_allowlist.len()
+            + RECIPIENT_SHARE_SIZE * metadata.ix.shares.len()
+    );
+    debug_assert!(
+        metadata_bytes[METADATA_SENDER_OFFSET..].starts_with(metadata.sender.as_ref())
+            && metadata_bytes[METADATA_RECIPIENT_OFFSET..].starts_with(metadata.recipient.as_ref())
+            && metadata_bytes[METADATA_MINT_OFFSET..].starts_with(metadata.mint.as_ref())
+            && metadata_bytes[METADATA_END_TIME_OFFSET..]
+                .starts_with(&metadata.ix.end_time.to_le_bytes())
+            && metadata_bytes[METADATA_STREAM_NAME_OFFSET..].starts_with(&metadata.ix.stream_name)
+            && metadata_bytes[METADATA_TRANCHES_OFFSET..]
+                .starts_with(&(metadata.ix.tranches.len() as u32).to_le_bytes())
+    );
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    data[0..metadata_bytes.len()].clone_from_slice(&metadata_bytes);
+
+    sync_indexes(
+        program_id,
+        &acc.indexes,
+        acc.metadata.key,
+        None,
+        Some(&metadata),
     )?;
 
+    #[cfg(feature = "token2022")]
+    if metadata.ix.obligation_nft {
+        mint_obligation(
+            program_id,
+            acc.metadata.key,
+            &acc.sender,
+            payer,
+            &acc.obligation_mint,
+            &acc.obligation_tokens,
+            &acc.token_2022_program,
+            &acc.system_program,
+        )?;
+    }
+
     msg!(
         "Successfully initialized {} {} token stream for {}",
         encode_base10(metadata.ix.deposited_amount, mint_info.decimals.into()),
         metadata.mint,
-        acc.recipient.key
+        metadata.recipient
     );
     msg!("Called by {}", acc.sender.key);
     msg!("Metadata written in {}", acc.metadata.key);
@@ -241,14 +561,69 @@ pub fn create(
         msg!("Cliff happens at {}", pretty_time(metadata.ix.cliff));
     }
 
-    return Ok(());
+    emit(StreamEvent::StreamCreated(StreamCreated {
+        stream: *acc.metadata.key,
+        sender: metadata.sender,
+        recipient: metadata.recipient,
+        mint: metadata.mint,
+        deposited: metadata.ix.deposited_amount,
+        start_time: metadata.ix.start_time,
+        end_time: metadata.ix.end_time,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    notify(&metadata);
+
+    Ok(())
+}
+
+pub fn withdraw(
+    program_id: &Pubkey,
+    acc: WithdrawAccounts,
+    amount: u64,
+    salt: Option<[u8; 32]>,
+) -> ProgramResult {
+    withdraw_vested(program_id, acc, amount, salt, None)
+}
+
+/// `withdraw` into a token account of the recipient's choosing rather than
+/// their associated one, e.g. one owned by the recipient at a non-default
+/// address. It has to be owned by the recipient and hold the stream's mint;
+/// takes precedence over `forward_to`.
+pub fn withdraw_to(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> ProgramResult {
+    withdraw_vested(program_id, acc, amount, None, None)
+}
+
+/// `withdraw` of a shared stream: each co-recipient gets their `bps` of the
+/// payout, after fees, in their associated token account, and the recipient
+/// the rest, each with a `SharePaid` receipt. The sender may call it too, to
+/// settle the shares before a `cancel`.
+pub fn withdraw_shares(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> ProgramResult {
+    withdraw_vested(program_id, acc, amount, None, None)
+}
+
+/// Withdraws everything available to the recipient on behalf of a keeper,
+/// at most once per `withdraw_frequency`, paying `keeper_tokens` the
+/// `cranker_fee` out of it.
+pub fn crank_withdraw(program_id: &Pubkey, acc: CrankWithdrawAccounts) -> ProgramResult {
+    if !acc.keeper_tokens.is_writable {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    withdraw_vested(program_id, acc.withdraw, 0, None, Some(&acc.keeper_tokens))
 }
 
-pub fn withdraw(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> ProgramResult {
+fn withdraw_vested<'a>(
+    program_id: &Pubkey,
+    acc: WithdrawAccounts<'a>,
+    amount: u64,
+    salt: Option<[u8; 32]>,
+    keeper_tokens: Option<&AccountInfo<'a>>,
+) -> ProgramResult {
     msg!("Withdrawing from SPL token stream");
 
     if acc.escrow_tokens.data_is_empty()
-        || acc.escrow_tokens.owner != &spl_token::id()
+        || !is_token_program(acc.escrow_tokens.owner)
         || acc.metadata.data_is_empty()
         || acc.metadata.owner != program_id
     {
@@ -263,14 +638,12 @@ pub fn withdraw(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> Prog
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let (escrow_tokens_pubkey, nonce) =
-        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
-    let recipient_tokens_key = get_associated_token_address(acc.recipient.key, acc.mint.key);
+    let recipient_tokens_key =
+        associated_token_address(acc.recipient.key, acc.mint.key, acc.token_program.key);
 
-    if acc.token_program.key != &spl_token::id()
-        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+    if !is_token_program(acc.token_program.key)
+        || acc.mint.owner != acc.token_program.key
         || acc.recipient_tokens.key != &recipient_tokens_key
-        || acc.withdraw_authority.key != acc.recipient.key
     {
         return Err(ProgramError::InvalidAccountData);
     }
@@ -280,13 +653,43 @@ pub fn withdraw(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> Prog
     }
 
     let mut data = acc.metadata.try_borrow_mut_data()?;
-    let mut metadata: TokenStreamData = match solana_borsh::try_from_slice_unchecked(&data) {
-        Ok(v) => v,
-        Err(_) => return Err(InvalidMetadata.into()),
-    };
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    // Public streams can be pushed to the recipient by anyone, e.g. automation
+    // services, but only ever into the recipient's own associated account or
+    // the account they forward to.
+    // Senders push shared streams to settle them before cancels.
+    let pushed = acc.withdraw_authority.key != acc.recipient.key;
+    let by_sender =
+        !metadata.ix.shares.is_empty() && acc.withdraw_authority.key == &metadata.sender;
+    if pushed && !metadata.ix.withdrawal_public && keeper_tokens.is_none() && !by_sender {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    if acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
 
     let mint_info = unpack_mint_account(&acc.mint)?;
 
+    if !metadata.recipient_revealed() {
+        let salt = match salt {
+            Some(v) => v,
+            None => return Err(RecipientNotRevealed.into()),
+        };
+        if recipient_hash(acc.recipient.key, &salt) != metadata.ix.recipient_hash {
+            return Err(InvalidRecipientProof.into());
+        }
+        stack_msg!("Recipient revealed: {}", Key(acc.recipient.key));
+        metadata.recipient = *acc.recipient.key;
+        metadata.recipient_tokens = *acc.recipient_tokens.key;
+    }
+
     if acc.recipient.key != &metadata.recipient
         || acc.recipient_tokens.key != &metadata.recipient_tokens
         || acc.mint.key != &metadata.mint
@@ -296,96 +699,468 @@ pub fn withdraw(program_id: &Pubkey, acc: WithdrawAccounts, amount: u64) -> Prog
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let now = Clock::get()?.unix_timestamp as u64;
+    if acc.share_tokens.len() != metadata.ix.shares.len() {
+        stack_msg!(
+            "Error: Stream pays {} co-recipients",
+            metadata.ix.shares.len()
+        );
+        return Err(SharedStream.into());
+    }
+
+    let clock = Clock::get()?;
+    // Shared cranks may submit the same withdrawal twice; only the first
+    // one in a slot goes through.
+    if metadata.last_withdraw_slot == clock.slot {
+        stack_msg!("Error: Already withdrawn in slot {}", clock.slot);
+        return Err(DuplicateWithdrawal.into());
+    }
+
+    let now = clock.unix_timestamp as u64;
+    if metadata.compliance_frozen(now) {
+        stack_msg!(
+            "Error: Withdrawals frozen until {}",
+            metadata.compliance_frozen_until
+        );
+        return Err(ComplianceFrozen.into());
+    }
+
+    if metadata.ix.hold_amount > 0 {
+        check_holding(&metadata, acc.hold_tokens.as_ref())?;
+    }
+
+    if keeper_tokens.is_some() {
+        let due = metadata
+            .last_withdrawn_at
+            .saturating_add(metadata.ix.withdraw_frequency);
+        if metadata.ix.withdraw_frequency == 0 {
+            msg!("Error: Stream has cranking disabled");
+            return Err(CrankNotDue.into());
+        }
+        if now < due {
+            stack_msg!("Error: Next crank due at {}", due);
+            return Err(CrankNotDue.into());
+        }
+    }
+
     let available = metadata.available(now);
-    let requested: u64;
 
     if amount > available {
         msg!("Amount requested for withdraw is more than what is available");
         return Err(ProgramError::InvalidArgument);
     }
 
-    if amount == 0 {
-        requested = available;
+    if amount == 0 && available == 0 {
+        if metadata.ix.strict_withdraw {
+            return Err(NothingToWithdraw.into());
+        }
+        // Cranks polling during the cliff shouldn't produce failed
+        // transactions; keep a reveal if this call did one.
+        metadata.save(&mut data)?;
+        msg!("Nothing to withdraw yet");
+        emit(StreamEvent::Withdrawn(Withdrawn {
+            stream: *acc.metadata.key,
+            recipient: metadata.recipient,
+            amount: 0,
+            fees: 0,
+            withdrawn_total: metadata.withdrawn_amount,
+            notify_key: metadata.ix.notify_key,
+            origin: metadata.ix.origin,
+        }));
+        notify(&metadata);
+        return Ok(());
+    }
+
+    let requested = if amount == 0 { available } else { amount };
+
+    if acc.withdraw_authority.key != &metadata.recipient
+        && !by_sender
+        && requested < metadata.min_claim_amount
+    {
+        stack_msg!(
+            "Error: Recipient only accepts claims of at least {}",
+            metadata.min_claim_amount
+        );
+        return Err(ClaimBelowMinimum.into());
+    }
+
+    let cranker_fee = match keeper_tokens {
+        Some(keeper_tokens) => {
+            if unpack_token_account(keeper_tokens)?.mint != metadata.mint {
+                return Err(MintMismatch.into());
+            }
+            if requested <= metadata.ix.cranker_fee {
+                stack_msg!(
+                    "Error: Withdrawal of {} doesn't cover the cranker fee",
+                    requested
+                );
+                return Err(CrankNotDue.into());
+            }
+            metadata.ix.cranker_fee
+        }
+        None => 0,
+    };
+    let protocol_fee = protocol_fee(
+        program_id,
+        &metadata,
+        &acc.fees,
+        acc.token_program.key,
+        requested - cranker_fee,
+    )?;
+    let partner_fee = partner_fee(
+        &metadata,
+        &acc.fees,
+        acc.token_program.key,
+        requested - cranker_fee,
+    )?;
+    let fees = protocol_fee.map_or(0, |(fee, _)| fee) + partner_fee.map_or(0, |(fee, _)| fee);
+
+    #[cfg(feature = "token2022")]
+    if metadata.ix.obligation_nft
+        && metadata.withdrawn_amount + requested == metadata.ix.deposited_amount
+        && !metadata.awaits_topup()
+    {
+        require_obligation_accounts(
+            program_id,
+            acc.metadata.key,
+            &acc.obligation_mint,
+            &acc.obligation_tokens,
+            &acc.token_2022_program,
+        )?;
+    }
+
+    // Permissioned mints keep holder accounts frozen; thaw only for the
+    // duration of the transfer.
+    let refreeze =
+        metadata.ix.managed_freeze && unpack_token_account(&acc.recipient_tokens)?.is_frozen();
+    if refreeze {
+        set_frozen(
+            program_id,
+            false,
+            &acc.token_program,
+            &acc.recipient_tokens,
+            &acc.mint,
+            &acc.freeze_authority,
+            &acc.freeze_signer,
+        )?;
+    }
+
+    let destination = if let Some(tokens) = &acc.destination_tokens {
+        // Permissioned mints only thaw the recipient's associated account.
+        if pushed || metadata.ix.managed_freeze {
+            msg!("Error: Only the recipient picks a destination, and not on permissioned mints");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let tokens_info = unpack_token_account(tokens)?;
+        if tokens_info.mint != metadata.mint {
+            return Err(MintMismatch.into());
+        }
+        if tokens_info.owner != metadata.recipient
+            || tokens.owner != acc.token_program.key
+            || !tokens.is_writable
+        {
+            stack_msg!(
+                "Error: {} isn't a token account of the recipient",
+                Key(tokens.key)
+            );
+            return Err(ProgramError::InvalidAccountData);
+        }
+        tokens
+    } else if metadata.forward_to == Pubkey::default() {
+        &acc.recipient_tokens
     } else {
-        requested = amount;
+        match &acc.forward_tokens {
+            Some(v) if v.key == &metadata.forward_to && v.is_writable => v,
+            _ => {
+                stack_msg!(
+                    "Error: Withdrawals are forwarded to {}",
+                    Key(&metadata.forward_to)
+                );
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+    };
+
+    let payout = requested - cranker_fee - fees;
+    let mut shares = [0; MAX_SHARES];
+    for (i, (share, tokens)) in metadata.ix.shares.iter().zip(&acc.share_tokens).enumerate() {
+        let share_tokens_key =
+            associated_token_address(&share.recipient, acc.mint.key, acc.token_program.key);
+        if tokens.key != &share_tokens_key || !tokens.is_writable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        shares[i] = share.of(payout);
     }
 
-    let seeds = [acc.metadata.key.as_ref(), &[nonce]];
+    for tokens in iter::once(destination).chain(&acc.share_tokens) {
+        if tokens.data_is_empty() {
+            stack_msg!("Error: Token account {} does not exist", Key(tokens.key));
+            return Err(ProgramError::UninitializedAccount);
+        }
+    }
+
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
     invoke_signed(
-        &spl_token::instruction::transfer(
+        &transfer_checked(
             acc.token_program.key,
             acc.escrow_tokens.key,
-            acc.recipient_tokens.key,
+            acc.mint.key,
+            destination.key,
             acc.escrow_tokens.key,
             &[],
-            requested,
+            payout - shares.iter().sum::<u64>(),
+            mint_info.decimals,
         )?,
         &[
             acc.escrow_tokens.clone(),
-            acc.recipient_tokens.clone(),
+            acc.mint.clone(),
+            destination.clone(),
             acc.escrow_tokens.clone(),
             acc.token_program.clone(),
         ],
         &[&seeds],
     )?;
 
-    metadata.withdrawn_amount += requested;
-    metadata.last_withdrawn_at = now;
-    let bytes = metadata.try_to_vec()?;
-    data[0..bytes.len()].clone_from_slice(&bytes);
-
-    if metadata.withdrawn_amount == metadata.ix.deposited_amount {
-        if !acc.sender.is_writable || acc.sender.key != &metadata.sender {
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        let escrow_tokens_rent = acc.escrow_tokens.lamports();
-        msg!(
-            "Returning {} lamports (rent) to {}",
-            escrow_tokens_rent,
-            acc.sender.key
-        );
-
+    for (tokens, &share) in acc.share_tokens.iter().zip(&shares).filter(|(_, &s)| s > 0) {
+        stack_msg!("Share {} paid to {}", share, Key(tokens.key));
         invoke_signed(
-            &spl_token::instruction::close_account(
+            &transfer_checked(
                 acc.token_program.key,
                 acc.escrow_tokens.key,
-                acc.sender.key,
+                acc.mint.key,
+                tokens.key,
                 acc.escrow_tokens.key,
                 &[],
+                share,
+                mint_info.decimals,
             )?,
             &[
                 acc.escrow_tokens.clone(),
-                acc.sender.clone(),
+                acc.mint.clone(),
+                tokens.clone(),
                 acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
             ],
             &[&seeds],
         )?;
     }
 
-    msg!(
-        "Withdrawn: {} {} tokens",
-        encode_base10(requested, mint_info.decimals.into()),
-        metadata.mint
-    );
-    msg!(
-        "Remaining: {} {} tokens",
-        encode_base10(
-            metadata.ix.deposited_amount - metadata.withdrawn_amount,
-            mint_info.decimals.into()
-        ),
-        metadata.mint
-    );
-
-    Ok(())
-}
+    for (label, fee) in [("Protocol fee", protocol_fee), ("Partner fee", partner_fee)] {
+        pay_fee(
+            label,
+            fee,
+            &acc.token_program,
+            &acc.escrow_tokens,
+            &acc.mint,
+            mint_info.decimals,
+            &seeds,
+        )?;
+    }
+
+    if let Some(keeper_tokens) = keeper_tokens.filter(|_| cranker_fee > 0) {
+        stack_msg!(
+            "Cranker fee {} paid to {}",
+            cranker_fee,
+            Key(keeper_tokens.key)
+        );
+        invoke_signed(
+            &transfer_checked(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                acc.mint.key,
+                keeper_tokens.key,
+                acc.escrow_tokens.key,
+                &[],
+                cranker_fee,
+                mint_info.decimals,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                acc.mint.clone(),
+                keeper_tokens.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    if refreeze {
+        set_frozen(
+            program_id,
+            true,
+            &acc.token_program,
+            &acc.recipient_tokens,
+            &acc.mint,
+            &acc.freeze_authority,
+            &acc.freeze_signer,
+        )?;
+    }
+
+    let (first_period, last_period) = metadata.period_range(
+        metadata.withdrawn_amount,
+        metadata.withdrawn_amount + requested,
+    );
+    metadata.withdrawn_amount += requested;
+    metadata.fees_paid += fees + cranker_fee;
+    metadata.last_withdrawn_at = now;
+    metadata.last_withdraw_slot = clock.slot;
+    metadata
+        .claim_history
+        .record(clock.epoch, metadata.withdrawn_amount);
+    // Halted streams keep their escrow open for the next top-up.
+    let finished =
+        metadata.withdrawn_amount == metadata.ix.deposited_amount && !metadata.awaits_topup();
+    if finished {
+        metadata.completed_at = now;
+        metadata.end_cause = metadata.natural_end_cause();
+    }
+    metadata.save(&mut data)?;
+    update_mirror(
+        program_id,
+        acc.mirror.as_ref(),
+        acc.metadata.key,
+        &metadata,
+        now,
+    )?;
+
+    // Bonded streams keep the escrow open for the sender to get the bond back
+    // with `cancel`, which closes it instead.
+    if finished && metadata.ix.bond_amount > 0 {
+        msg!("Bond held until the sender cancels");
+    } else if finished {
+        if !acc.sender.is_writable || acc.sender.key != &metadata.sender {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let escrow_tokens_rent = acc.escrow_tokens.lamports();
+        stack_msg!(
+            "Returning {} lamports (rent) to {}",
+            volatile(escrow_tokens_rent),
+            Key(acc.sender.key)
+        );
+
+        harvest_withheld_fees(&acc.token_program, &acc.mint, &acc.escrow_tokens)?;
+        invoke_signed(
+            &retarget(
+                spl_token::instruction::close_account(
+                    &spl_token::id(),
+                    acc.escrow_tokens.key,
+                    acc.sender.key,
+                    acc.escrow_tokens.key,
+                    &[],
+                )?,
+                acc.token_program.key,
+            ),
+            &[
+                acc.escrow_tokens.clone(),
+                acc.sender.clone(),
+                acc.escrow_tokens.clone(),
+            ],
+            &[&seeds],
+        )?;
+
+        #[cfg(feature = "token2022")]
+        if metadata.ix.obligation_nft {
+            burn_obligation(
+                program_id,
+                acc.metadata.key,
+                &acc.obligation_mint,
+                &acc.obligation_tokens,
+                &acc.token_2022_program,
+            )?;
+        }
+    }
+
+    stack_msg!(
+        "Withdrawn: {} {} tokens",
+        Base10(requested, mint_info.decimals.into()),
+        Key(&metadata.mint)
+    );
+    if requested > 0 {
+        stack_msg!("Periods: {}..={}", first_period, last_period);
+    }
+    if destination.key != acc.recipient_tokens.key {
+        stack_msg!("Forwarded to {}", Key(destination.key));
+    }
+    if let Some(pool) = &acc.lp_pool {
+        let lp = lp::underlying(
+            pool,
+            &acc.lp_reserve_a,
+            &acc.lp_reserve_b,
+            acc.mint.key,
+            mint_info.supply,
+            requested,
+        )?;
+        stack_msg!(
+            "Underlying: {} {} + {} {}",
+            lp.amount_a,
+            Key(&lp.mint_a),
+            lp.amount_b,
+            Key(&lp.mint_b)
+        );
+    }
+    stack_msg!("Slot: {}", metadata.last_withdraw_slot);
+    if metadata.end_cause != EndCause::Open {
+        stack_msg!("Ended: {:?}", metadata.end_cause);
+    }
+    stack_msg!(
+        "Remaining: {} {} tokens",
+        Base10(
+            metadata.ix.deposited_amount - metadata.withdrawn_amount,
+            mint_info.decimals.into()
+        ),
+        Key(&metadata.mint)
+    );
+    stack_msg!("Funding runway: {} seconds", metadata.funding_runway(now));
+
+    emit(StreamEvent::Withdrawn(Withdrawn {
+        stream: *acc.metadata.key,
+        recipient: metadata.recipient,
+        amount: requested,
+        fees: fees + cranker_fee,
+        withdrawn_total: metadata.withdrawn_amount,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    if !metadata.ix.shares.is_empty() {
+        let shared_bps: u16 = metadata.ix.shares.iter().map(|s| s.bps).sum();
+        let positions = iter::once((
+            metadata.recipient,
+            destination.key,
+            payout - shares.iter().sum::<u64>(),
+            10_000 - shared_bps,
+        ))
+        .chain(
+            metadata
+                .ix
+                .shares
+                .iter()
+                .zip(&acc.share_tokens)
+                .zip(&shares)
+                .map(|((share, tokens), &amount)| (share.recipient, tokens.key, amount, share.bps)),
+        );
+        for (holder, tokens, amount, bps) in positions {
+            emit(StreamEvent::SharePaid(SharePaid {
+                stream: *acc.metadata.key,
+                holder,
+                tokens: *tokens,
+                amount,
+                bps,
+                notify_key: metadata.ix.notify_key,
+                origin: metadata.ix.origin,
+            }));
+        }
+    }
+    notify(&metadata);
+
+    Ok(())
+}
 
 pub fn cancel(program_id: &Pubkey, acc: CancelAccounts) -> ProgramResult {
     msg!("Cancelling SPL token stream");
 
     if acc.escrow_tokens.data_is_empty()
-        || acc.escrow_tokens.owner != &spl_token::id()
+        || !is_token_program(acc.escrow_tokens.owner)
         || acc.metadata.data_is_empty()
         || acc.metadata.owner != program_id
     {
@@ -402,28 +1177,45 @@ pub fn cancel(program_id: &Pubkey, acc: CancelAccounts) -> ProgramResult {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let (escrow_tokens_pubkey, nonce) =
-        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
-    let recipient_tokens_key = get_associated_token_address(acc.recipient.key, acc.mint.key);
+    let recipient_tokens_key =
+        associated_token_address(acc.recipient.key, acc.mint.key, acc.token_program.key);
 
-    if acc.token_program.key != &spl_token::id()
-        || acc.escrow_tokens.key != &escrow_tokens_pubkey
-        || acc.recipient_tokens.key != &recipient_tokens_key
-    {
+    if !is_token_program(acc.token_program.key) || acc.mint.owner != acc.token_program.key {
         return Err(ProgramError::InvalidAccountData);
     }
 
     let mut data = acc.metadata.try_borrow_mut_data()?;
-    let mut metadata: TokenStreamData = match solana_borsh::try_from_slice_unchecked(&data) {
-        Ok(v) => v,
-        Err(_) => return Err(InvalidMetadata.into()),
-    };
+    let mut metadata = TokenStreamData::load(&data)?;
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    if acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
     let mint_info = unpack_mint_account(&acc.mint)?;
 
     let now = Clock::get()?.unix_timestamp as u64;
-    msg!("Now: {}, closable at {}", now, metadata.closable_at);
-    if now < metadata.closable_at {
-        if acc.cancel_authority.key != acc.sender.key {
+    let schedule_now = metadata.effective_now(now);
+    stack_msg!(
+        "Now: {}, closable at {}",
+        volatile(schedule_now),
+        metadata.closable_at
+    );
+    // Halted streams stay open awaiting a top-up, not closable by anyone.
+    let open = schedule_now < metadata.closable_at || metadata.awaits_topup();
+    if open {
+        let by_sender =
+            metadata.ix.cancelable_by_sender && acc.cancel_authority.key == &metadata.sender;
+        let by_recipient = metadata.ix.cancelable_by_recipient
+            && metadata.recipient != Pubkey::default()
+            && acc.cancel_authority.key == &metadata.recipient;
+        if !by_sender && !by_recipient {
+            stack_msg!(
+                "Error: {} may not cancel this stream",
+                Key(acc.cancel_authority.key)
+            );
             return Err(ProgramError::InvalidAccountData);
         }
         if !acc.cancel_authority.is_signer {
@@ -431,60 +1223,202 @@ pub fn cancel(program_id: &Pubkey, acc: CancelAccounts) -> ProgramResult {
         }
     }
 
+    let revealed = metadata.recipient_revealed();
     if acc.sender.key != &metadata.sender
         || acc.sender_tokens.key != &metadata.sender_tokens
-        || acc.recipient.key != &metadata.recipient
-        || acc.recipient_tokens.key != &metadata.recipient_tokens
+        || (revealed
+            && (acc.recipient.key != &metadata.recipient
+                || acc.recipient_tokens.key != &metadata.recipient_tokens
+                || acc.recipient_tokens.key != &recipient_tokens_key))
         || acc.mint.key != &metadata.mint
         || acc.escrow_tokens.key != &metadata.escrow_tokens
     {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let available = metadata.available(now);
-    msg!("Available {}", available);
+    // With a cancel delay, the sender's first cancel only records the intent,
+    // giving the recipient time to withdraw what vested or dispute it.
+    let by_sender = open && acc.cancel_authority.key == &metadata.sender;
+    if by_sender && metadata.ix.cancel_delay_seconds > 0 {
+        if metadata.cancel_requested_at == 0 {
+            metadata.cancel_requested_at = now;
+            metadata.save(&mut data)?;
+
+            stack_msg!(
+                "Cancel requested, goes through from {}",
+                now.saturating_add(metadata.ix.cancel_delay_seconds)
+            );
+            notify(&metadata);
+            return Ok(());
+        }
+
+        if now
+            < metadata
+                .cancel_requested_at
+                .saturating_add(metadata.ix.cancel_delay_seconds)
+        {
+            return Err(CancelDelayPending.into());
+        }
+    }
+
+    #[cfg(feature = "token2022")]
+    if metadata.ix.obligation_nft {
+        require_obligation_accounts(
+            program_id,
+            acc.metadata.key,
+            &acc.obligation_mint,
+            &acc.obligation_tokens,
+            &acc.token_2022_program,
+        )?;
+    }
+
+    let available = metadata.available_on_cancel(now);
+    stack_msg!("Available {}", available);
+    if !metadata.ix.shares.is_empty() && available > 0 {
+        msg!("Error: Pay out the shares with withdraw_shares first");
+        return Err(SharedStream.into());
+    }
+    if !revealed && available > 0 {
+        msg!("Error: Vested tokens owed to a recipient that has not been revealed");
+        return Err(RecipientNotRevealed.into());
+    }
     let escrow_token_info = unpack_token_account(&acc.escrow_tokens)?;
-    msg!("Amount {}", escrow_token_info.amount);
-    let seeds = [acc.metadata.key.as_ref(), &[nonce]];
-    invoke_signed(
-        &spl_token::instruction::transfer(
-            acc.token_program.key,
-            acc.escrow_tokens.key,
-            acc.recipient_tokens.key,
-            acc.escrow_tokens.key,
-            &[],
-            available,
-        )?,
-        &[
-            acc.escrow_tokens.clone(),
-            acc.recipient_tokens.clone(),
-            acc.escrow_tokens.clone(),
-            acc.token_program.clone(),
-        ],
-        &[&seeds],
+    stack_msg!("Amount {}", escrow_token_info.amount);
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    let protocol_fee = protocol_fee(
+        program_id,
+        &metadata,
+        &acc.fees,
+        acc.token_program.key,
+        available,
     )?;
+    let partner_fee = partner_fee(&metadata, &acc.fees, acc.token_program.key, available)?;
+    let fees = protocol_fee.map_or(0, |(fee, _)| fee) + partner_fee.map_or(0, |(fee, _)| fee);
+    for (label, fee) in [("Protocol fee", protocol_fee), ("Partner fee", partner_fee)] {
+        pay_fee(
+            label,
+            fee,
+            &acc.token_program,
+            &acc.escrow_tokens,
+            &acc.mint,
+            mint_info.decimals,
+            &seeds,
+        )?;
+    }
+
+    // The sender backing out before `bond_until` forfeits the bond.
+    let slashed = metadata.ix.bond_amount > 0
+        && now < metadata.ix.bond_until
+        && open
+        && acc.cancel_authority.key == &metadata.sender;
+
+    // Permissioned mints keep holder accounts frozen; thaw only for the
+    // duration of the payout.
+    let refreeze = metadata.ix.managed_freeze
+        && (available > 0 || slashed)
+        && unpack_token_account(&acc.recipient_tokens)?.is_frozen();
+    if refreeze {
+        set_frozen(
+            program_id,
+            false,
+            &acc.token_program,
+            &acc.recipient_tokens,
+            &acc.mint,
+            &acc.freeze_authority,
+            &acc.freeze_signer,
+        )?;
+    }
+
+    if available > 0 {
+        invoke_signed(
+            &transfer_checked(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                acc.mint.key,
+                acc.recipient_tokens.key,
+                acc.escrow_tokens.key,
+                &[],
+                available - fees,
+                mint_info.decimals,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                acc.mint.clone(),
+                acc.recipient_tokens.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
     let escrow_token_info = unpack_token_account(&acc.escrow_tokens)?;
-    msg!("Amount {}", escrow_token_info.amount);
+    stack_msg!("Amount {}", escrow_token_info.amount);
+    let (first_period, last_period) = metadata.period_range(
+        metadata.withdrawn_amount,
+        metadata.withdrawn_amount + available,
+    );
     metadata.withdrawn_amount += available;
+    metadata.fees_paid += fees;
     let remains = metadata.ix.deposited_amount - metadata.withdrawn_amount;
-    msg!(
+    stack_msg!(
         "Deposited {} , withdrawn: {}, tokens remain {}",
         metadata.ix.deposited_amount,
         metadata.withdrawn_amount,
         remains
     );
-    if remains > 0 {
+    let returned = if slashed {
+        remains
+    } else {
+        remains + metadata.ix.bond_amount
+    };
+    if slashed {
+        invoke_signed(
+            &transfer_checked(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                acc.mint.key,
+                acc.recipient_tokens.key,
+                acc.escrow_tokens.key,
+                &[],
+                metadata.ix.bond_amount,
+                mint_info.decimals,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                acc.mint.clone(),
+                acc.recipient_tokens.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+    if refreeze {
+        set_frozen(
+            program_id,
+            true,
+            &acc.token_program,
+            &acc.recipient_tokens,
+            &acc.mint,
+            &acc.freeze_authority,
+            &acc.freeze_signer,
+        )?;
+    }
+    if returned > 0 {
         invoke_signed(
-            &spl_token::instruction::transfer(
+            &transfer_checked(
                 acc.token_program.key,
                 acc.escrow_tokens.key,
+                acc.mint.key,
                 acc.sender_tokens.key,
                 acc.escrow_tokens.key,
                 &[],
-                remains,
+                returned,
+                mint_info.decimals,
             )?,
             &[
                 acc.escrow_tokens.clone(),
+                acc.mint.clone(),
                 acc.sender_tokens.clone(),
                 acc.escrow_tokens.clone(),
                 acc.token_program.clone(),
@@ -495,14 +1429,18 @@ pub fn cancel(program_id: &Pubkey, acc: CancelAccounts) -> ProgramResult {
 
     let rent_escrow_tokens = acc.escrow_tokens.lamports();
 
+    harvest_withheld_fees(&acc.token_program, &acc.mint, &acc.escrow_tokens)?;
     invoke_signed(
-        &spl_token::instruction::close_account(
+        &retarget(
+            spl_token::instruction::close_account(
+                &spl_token::id(),
+                acc.escrow_tokens.key,
+                acc.sender.key,
+                acc.escrow_tokens.key,
+                &[],
+            )?,
             acc.token_program.key,
-            acc.escrow_tokens.key,
-            acc.sender.key,
-            acc.escrow_tokens.key,
-            &[],
-        )?,
+        ),
         &[
             acc.escrow_tokens.clone(),
             acc.sender.clone(),
@@ -511,27 +1449,67 @@ pub fn cancel(program_id: &Pubkey, acc: CancelAccounts) -> ProgramResult {
         &[&seeds],
     )?;
 
-    if now < metadata.closable_at {
+    #[cfg(feature = "token2022")]
+    if metadata.ix.obligation_nft {
+        burn_obligation(
+            program_id,
+            acc.metadata.key,
+            &acc.obligation_mint,
+            &acc.obligation_tokens,
+            &acc.token_2022_program,
+        )?;
+    }
+
+    if open {
         metadata.last_withdrawn_at = now;
         metadata.canceled_at = now;
+        metadata.end_cause = if acc.cancel_authority.key == &metadata.sender {
+            EndCause::CanceledBySender
+        } else {
+            EndCause::CanceledByRecipient
+        };
+    } else {
+        metadata.completed_at = now;
+        metadata.end_cause = metadata.natural_end_cause();
     }
-    let bytes = metadata.try_to_vec().unwrap();
-    data[0..bytes.len()].clone_from_slice(&bytes);
+    metadata.save(&mut data)?;
 
-    msg!(
+    stack_msg!(
         "Transferred: {} {} tokens",
-        encode_base10(available, mint_info.decimals.into()),
-        metadata.mint
+        Base10(available, mint_info.decimals.into()),
+        Key(&metadata.mint)
     );
-    msg!(
+    if available > 0 {
+        stack_msg!("Periods: {}..={}", first_period, last_period);
+    }
+    stack_msg!(
         "Returned: {} {} tokens",
-        encode_base10(remains, mint_info.decimals.into()),
-        metadata.mint
+        Base10(returned, mint_info.decimals.into()),
+        Key(&metadata.mint)
     );
-    msg!(
+    if slashed {
+        stack_msg!(
+            "Bond paid to recipient: {} {} tokens",
+            Base10(metadata.ix.bond_amount, mint_info.decimals.into()),
+            Key(&metadata.mint)
+        );
+    }
+    stack_msg!(
         "Returned rent: {} lamports",
-        rent_escrow_tokens /* + remains_meta */
+        volatile(rent_escrow_tokens) /* + remains_meta */
     );
+    stack_msg!("Ended: {:?}", metadata.end_cause);
+
+    emit(StreamEvent::Cancelled(Cancelled {
+        stream: *acc.metadata.key,
+        authority: *acc.cancel_authority.key,
+        paid: available,
+        returned,
+        fees,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    notify(&metadata);
 
     Ok(())
 }
@@ -542,7 +1520,7 @@ pub fn transfer_recipient(program_id: &Pubkey, acc: TransferAccounts) -> Program
     if acc.metadata.data_is_empty()
         || acc.metadata.owner != program_id
         || acc.escrow_tokens.data_is_empty()
-        || acc.escrow_tokens.owner != &spl_token::id()
+        || !is_token_program(acc.escrow_tokens.owner)
     {
         return Err(ProgramError::UninitializedAccount);
     }
@@ -559,46 +1537,54 @@ pub fn transfer_recipient(program_id: &Pubkey, acc: TransferAccounts) -> Program
     }
 
     let mut data = acc.metadata.try_borrow_mut_data()?;
-    let mut metadata: TokenStreamData = match solana_borsh::try_from_slice_unchecked(&data) {
-        Ok(v) => v,
-        Err(_) => return Err(InvalidMetadata.into()),
-    };
+    let mut metadata = TokenStreamData::load(&data)?;
 
     if !metadata.ix.transferable_by_recipient && !metadata.ix.transferable_by_sender {
         return Err(TransferNotAllowed.into());
     }
 
-    let mut authorized = false;
-    if metadata.ix.transferable_by_recipient && metadata.recipient == *acc.authorized_wallet.key {
-        authorized = true;
+    // Moving the stream to another wallet would sidestep the freeze.
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    if metadata.transfer_cooling_down(now) {
+        return Err(TransferCooldown.into());
     }
-    if metadata.ix.transferable_by_sender && &metadata.sender == acc.authorized_wallet.key {
-        authorized = true;
+
+    if !metadata.may_transfer_to(acc.new_recipient.key) {
+        return Err(DestinationNotAllowed.into());
     }
-    if !authorized {
+
+    if !may_transfer(&metadata, acc.authorized_wallet.key) {
         msg!("Error: Unauthorized wallet");
         return Err(TransferNotAllowed.into());
     }
 
-    let (escrow_tokens_pubkey, _) =
-        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
+    let (escrow_tokens_pubkey, _) = Pubkey::find_program_address(
+        &[acc.metadata.key.as_ref(), &metadata.escrow_seed()],
+        program_id,
+    );
     let new_recipient_tokens_key =
-        get_associated_token_address(acc.new_recipient.key, acc.mint.key);
+        associated_token_address(acc.new_recipient.key, acc.mint.key, acc.token_program.key);
 
     if acc.new_recipient_tokens.key != &new_recipient_tokens_key
         || acc.mint.key != &metadata.mint
-        || acc.authorized_wallet.key != &metadata.recipient
         || acc.escrow_tokens.key != &metadata.escrow_tokens
         || acc.escrow_tokens.key != &escrow_tokens_pubkey
-        || acc.token_program.key != &spl_token::id()
+        || !is_token_program(acc.token_program.key)
+        || acc.mint.owner != acc.token_program.key
+        || acc.associated_token_program.key != &spl_associated_token_account::id()
         || acc.system_program.key != &system_program::id()
         || acc.rent.key != &sysvar::rent::id()
+        || !metadata.has_address(program_id, acc.metadata.key)
     {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    if !acc.new_recipient_tokens.data_is_empty() {
-        let tokens_struct_size = spl_token::state::Account::LEN;
+    if acc.new_recipient_tokens.data_is_empty() {
+        let tokens_struct_size = escrow_account_size(&acc.token_program, &acc.mint)?;
         let cluster_rent = Rent::get()?;
         let tokens_rent = cluster_rent.minimum_balance(tokens_struct_size);
 
@@ -606,121 +1592,2072 @@ pub fn transfer_recipient(program_id: &Pubkey, acc: TransferAccounts) -> Program
             msg!("Error: Insufficient funds in {}", acc.authorized_wallet.key);
             return Err(ProgramError::InsufficientFunds);
         }
-
-        msg!("Initializing new recipient's associated token account");
-        invoke(
-            &create_associated_token_account(
-                acc.authorized_wallet.key,
-                acc.new_recipient.key,
-                acc.mint.key,
-            ),
-            &[
-                acc.authorized_wallet.clone(),
-                acc.new_recipient_tokens.clone(),
-                acc.new_recipient.clone(),
-                acc.mint.clone(),
-                acc.system_program.clone(),
-                acc.token_program.clone(),
-                acc.rent.clone(),
-            ],
-        )?;
     }
 
+    msg!("Initializing new recipient's associated token account");
+    create_recipient_ata(
+        &acc.authorized_wallet,
+        &acc.new_recipient,
+        &acc.new_recipient_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    let before = metadata.clone();
     metadata.recipient = *acc.new_recipient.key;
     metadata.recipient_tokens = *acc.new_recipient_tokens.key;
+    metadata.last_transferred_at = now;
+    metadata.pending_recipient = Pubkey::default();
+    metadata.pending_proposer = Pubkey::default();
+    // Forwarding and the claim floor were the old recipient's settings.
+    metadata.forward_to = Pubkey::default();
+    metadata.min_claim_amount = 0;
+    metadata.bump_nonce();
 
     let bytes = metadata.try_to_vec()?;
     data[0..bytes.len()].clone_from_slice(&bytes);
 
+    sync_indexes(
+        program_id,
+        &acc.indexes,
+        acc.metadata.key,
+        Some(&before),
+        Some(&metadata),
+    )?;
+
+    emit(StreamEvent::Transferred(Transferred {
+        stream: *acc.metadata.key,
+        old_recipient: before.recipient,
+        new_recipient: metadata.recipient,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    notify(&metadata);
+
     Ok(())
 }
 
-pub fn topup_stream(program_id: &Pubkey, acc: TopUpAccounts, amount: u64) -> ProgramResult {
-    msg!("Topping up the escrow account");
-
-    if acc.metadata.data_is_empty() || acc.escrow_tokens.owner != &spl_token::id() {
-        return Err(ProgramError::UninitializedAccount);
+/// Fails unless `hold_tokens` is the recipient's own token account holding
+/// at least `ix.hold_amount` of `ix.hold_mint`.
+fn check_holding(metadata: &TokenStreamData, hold_tokens: Option<&AccountInfo>) -> ProgramResult {
+    let hold_tokens = hold_tokens.ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let holding = unpack_token_account(hold_tokens)?;
+    if holding.owner != metadata.recipient || holding.mint != metadata.ix.hold_mint {
+        msg!(
+            "Error: {} isn't the recipient's holding account",
+            hold_tokens.key
+        );
+        return Err(ProgramError::InvalidAccountData);
     }
 
-    if !acc.sender.is_writable
-        || !acc.sender_tokens.is_writable
-        || !acc.metadata.is_writable
-        || !acc.escrow_tokens.is_writable
-    {
-        return Err(AccountsNotWritable.into());
+    if holding.amount < metadata.ix.hold_amount {
+        msg!(
+            "Error: Recipient holds {} of {}, at least {} required",
+            holding.amount,
+            metadata.ix.hold_mint,
+            metadata.ix.hold_amount
+        );
+        return Err(HoldingBelowMinimum.into());
     }
 
-    let (escrow_tokens_pubkey, _) =
-        Pubkey::find_program_address(&[acc.metadata.key.as_ref()], program_id);
+    Ok(())
+}
 
-    if acc.token_program.key != &spl_token::id() || acc.escrow_tokens.key != &escrow_tokens_pubkey {
-        return Err(ProgramError::InvalidAccountData);
-    }
+/// Whether `wallet` may reassign the stream's recipient.
+fn may_transfer(metadata: &TokenStreamData, wallet: &Pubkey) -> bool {
+    (metadata.ix.transferable_by_recipient && &metadata.recipient == wallet)
+        || (metadata.ix.transferable_by_sender && &metadata.sender == wallet)
+}
 
-    if !acc.sender.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+/// First step of a two-step `transfer_recipient`: records `new_recipient` as
+/// pending, replacing any earlier proposal. Nothing moves until they accept.
+pub fn propose_transfer(program_id: &Pubkey, acc: ProposeTransferAccounts) -> ProgramResult {
+    msg!("Proposing stream recipient transfer");
 
-    let sender_token_info = unpack_token_account(&acc.sender_tokens)?;
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
 
-    if &sender_token_info.mint != acc.mint.key {
-        return Err(MintMismatch.into());
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
     }
 
-    if amount == 0 {
-        msg!("Error: Amount can't be zero.");
-        return Err(ProgramError::InvalidArgument);
+    if !acc.authorized_wallet.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
     let mut data = acc.metadata.try_borrow_mut_data()?;
-    let mut metadata: TokenStreamData = match solana_borsh::try_from_slice_unchecked(&data) {
-        Ok(v) => v,
-        Err(_) => return Err(InvalidMetadata.into()),
-    };
+    let mut metadata = TokenStreamData::load(&data)?;
 
-    if acc.mint.key != &metadata.mint || acc.escrow_tokens.key != &metadata.escrow_tokens {
-        msg!("Error: Metadata does not match given accounts");
+    if !metadata.has_address(program_id, acc.metadata.key) {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let now = Clock::get()?.unix_timestamp as u64;
-    if metadata.closable() < now {
-        msg!("Error: Topup after the stream is closed");
-        return Err(StreamClosed.into());
+    if !may_transfer(&metadata, acc.authorized_wallet.key) {
+        msg!("Error: Unauthorized wallet");
+        return Err(TransferNotAllowed.into());
+    }
+
+    if acc.new_recipient.key == &metadata.recipient {
+        msg!("Error: Already the recipient");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if !metadata.may_transfer_to(acc.new_recipient.key) {
+        return Err(DestinationNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    if metadata.transfer_cooling_down(now) {
+        return Err(TransferCooldown.into());
+    }
+
+    metadata.pending_recipient = *acc.new_recipient.key;
+    metadata.pending_proposer = *acc.authorized_wallet.key;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Pending recipient: {}", metadata.pending_recipient);
+
+    Ok(())
+}
+
+/// Second step of a two-step transfer, signed by the pending recipient, who
+/// takes over the stream. Their associated token account is created if
+/// missing.
+pub fn accept_transfer(program_id: &Pubkey, acc: AcceptTransferAccounts) -> ProgramResult {
+    msg!("Accepting stream recipient transfer");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable
+        || !acc.new_recipient.is_writable
+        || !acc.new_recipient_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.new_recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if metadata.pending_recipient == Pubkey::default() {
+        return Err(NoPendingTransfer.into());
+    }
+
+    let new_recipient_tokens_key =
+        associated_token_address(acc.new_recipient.key, acc.mint.key, acc.token_program.key);
+
+    if acc.new_recipient.key != &metadata.pending_recipient
+        || acc.new_recipient_tokens.key != &new_recipient_tokens_key
+        || acc.mint.key != &metadata.mint
+        || !is_token_program(acc.token_program.key)
+        || acc.mint.owner != acc.token_program.key
+        || acc.system_program.key != &system_program::id()
+        || acc.rent.key != &sysvar::rent::id()
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // The proposer may have lost the right to transfer since, e.g. through
+    // a plain transfer of their own.
+    if !may_transfer(&metadata, &metadata.pending_proposer) {
+        return Err(TransferNotAllowed.into());
+    }
+
+    if !metadata.may_transfer_to(acc.new_recipient.key) {
+        return Err(DestinationNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.compliance_frozen(now) {
+        return Err(ComplianceFrozen.into());
+    }
+
+    if metadata.transfer_cooling_down(now) {
+        return Err(TransferCooldown.into());
+    }
+
+    msg!("Initializing new recipient's associated token account");
+    create_recipient_ata(
+        &acc.new_recipient,
+        &acc.new_recipient,
+        &acc.new_recipient_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    msg!(
+        "Recipient {} replaced by {}",
+        metadata.recipient,
+        acc.new_recipient.key
+    );
+    let before = metadata.clone();
+    metadata.recipient = *acc.new_recipient.key;
+    metadata.recipient_tokens = *acc.new_recipient_tokens.key;
+    metadata.last_transferred_at = now;
+    metadata.pending_recipient = Pubkey::default();
+    metadata.pending_proposer = Pubkey::default();
+    // Forwarding and the claim floor were the old recipient's settings.
+    metadata.forward_to = Pubkey::default();
+    metadata.min_claim_amount = 0;
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    sync_indexes(
+        program_id,
+        &acc.indexes,
+        acc.metadata.key,
+        Some(&before),
+        Some(&metadata),
+    )?;
+
+    emit(StreamEvent::Transferred(Transferred {
+        stream: *acc.metadata.key,
+        old_recipient: before.recipient,
+        new_recipient: metadata.recipient,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Withdraws a pending transfer; only whoever proposed it may.
+pub fn cancel_transfer(program_id: &Pubkey, acc: CancelTransferAccounts) -> ProgramResult {
+    msg!("Canceling stream recipient transfer");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.authorized_wallet.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if metadata.pending_recipient == Pubkey::default() {
+        return Err(NoPendingTransfer.into());
+    }
+
+    if acc.authorized_wallet.key != &metadata.pending_proposer {
+        msg!("Error: Unauthorized wallet");
+        return Err(TransferNotAllowed.into());
+    }
+
+    msg!("Dropped pending recipient {}", metadata.pending_recipient);
+    metadata.pending_recipient = Pubkey::default();
+    metadata.pending_proposer = Pubkey::default();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    Ok(())
+}
+
+pub fn topup_stream(program_id: &Pubkey, acc: TopUpAccounts, amount: u64) -> ProgramResult {
+    msg!("Topping up the escrow account");
+
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || !is_token_program(acc.escrow_tokens.owner)
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.sender.is_writable
+        || !acc.sender_tokens.is_writable
+        || !acc.metadata.is_writable
+        || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !is_token_program(acc.token_program.key) || acc.mint.owner != acc.token_program.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let sender_token_info = unpack_token_account(&acc.sender_tokens)?;
+
+    if &sender_token_info.mint != acc.mint.key {
+        return Err(MintMismatch.into());
+    }
+
+    check_multisig_owner(&sender_token_info, &acc.multisig, &acc.multisig_signers)?;
+
+    if amount == 0 {
+        msg!("Error: Amount can't be zero.");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let (escrow_tokens_pubkey, _) = Pubkey::find_program_address(
+        &[acc.metadata.key.as_ref(), &metadata.escrow_seed()],
+        program_id,
+    );
+    if acc.mint.key != &metadata.mint
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        msg!("Error: Metadata does not match given accounts");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let contributor = acc.sender.key;
+    let allowed = contributor == &metadata.sender
+        || match metadata.ix.can_topup {
+            TopUpPolicy::SenderOnly => false,
+            TopUpPolicy::Allowlist => {
+                topup_allowlisted(program_id, acc.metadata.key, &acc.allowlist, contributor)?
+            }
+            TopUpPolicy::Anyone => true,
+        };
+    if !allowed {
+        return Err(TopUpNotAllowed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.closable() < metadata.effective_now(now) {
+        msg!("Error: Topup after the stream is closed");
+        return Err(StreamClosed.into());
+    }
+
+    if metadata.subscription_canceled_at > 0 {
+        msg!("Error: Subscription was canceled");
+        return Err(StreamClosed.into());
     }
 
     msg!("Transferring to the escrow account");
-    invoke(
-        &spl_token::instruction::transfer(
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    let escrowed = unpack_token_account(&acc.escrow_tokens)?.amount;
+    transfer_from_sender(
+        &acc.token_program,
+        &acc.sender_tokens,
+        &acc.mint,
+        &acc.escrow_tokens,
+        acc.multisig.as_ref().unwrap_or(&acc.sender),
+        &acc.multisig_signers,
+        amount,
+        mint_info.decimals,
+    )?;
+    // Net of any Token-2022 transfer fee.
+    let amount = unpack_token_account(&acc.escrow_tokens)?.amount - escrowed;
+
+    metadata.settle_halt(now);
+    metadata.ix.deposited_amount += amount;
+    metadata.cache_schedule();
+    metadata.closable_at = metadata.closable();
+    metadata.last_topup_at = now;
+    metadata.last_topup_amount = amount;
+    metadata.last_topup_payer = *acc.sender.key;
+    metadata.last_topup_tokens = *acc.sender_tokens.key;
+    metadata.record_contribution(contributor, amount);
+    metadata.bump_nonce();
+
+    metadata.save(&mut data)?;
+
+    msg!(
+        "Successfully topped up {} to token stream {} on behalf of {}",
+        encode_base10(amount, mint_info.decimals.into()),
+        acc.escrow_tokens.key,
+        acc.sender.key,
+    );
+    msg!("Funding runway: {} seconds", metadata.funding_runway(now));
+
+    emit(StreamEvent::ToppedUp(ToppedUp {
+        stream: *acc.metadata.key,
+        payer: *acc.sender.key,
+        amount,
+        deposited_total: metadata.ix.deposited_amount,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Whether `contributor` is on the `allowlist` of the stream at `metadata`.
+fn topup_allowlisted(
+    program_id: &Pubkey,
+    metadata: &Pubkey,
+    allowlist: &Option<AccountInfo>,
+    contributor: &Pubkey,
+) -> Result<bool, ProgramError> {
+    let allowlist = match allowlist {
+        Some(allowlist) => allowlist,
+        None => return Ok(false),
+    };
+
+    let (allowlist_pubkey, _) =
+        Pubkey::find_program_address(&[TOPUP_ALLOWLIST_SEED, metadata.as_ref()], program_id);
+    if allowlist.key != &allowlist_pubkey || allowlist.owner != program_id {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let allowlist: TopUpAllowlist =
+        match solana_borsh::try_from_slice_unchecked(&allowlist.try_borrow_data()?) {
+            Ok(v) => v,
+            Err(_) => return Err(InvalidMetadata.into()),
+        };
+
+    Ok(allowlist.is_allowed(contributor))
+}
+
+/// Returns the most recent top-up to its payer, within the stream's refund
+/// window and only while none of the topped-up amount has vested.
+pub fn undo_topup(program_id: &Pubkey, acc: UndoTopUpAccounts) -> ProgramResult {
+    msg!("Undoing the last top-up");
+
+    if acc.metadata.data_is_empty() || !is_token_program(acc.escrow_tokens.owner) {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.payer_tokens.is_writable || !acc.metadata.is_writable || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !is_token_program(acc.token_program.key) || acc.mint.owner != acc.token_program.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let (escrow_tokens_pubkey, nonce) = Pubkey::find_program_address(
+        &[acc.metadata.key.as_ref(), &metadata.escrow_seed()],
+        program_id,
+    );
+    if acc.mint.key != &metadata.mint
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.payer.key != &metadata.last_topup_payer
+        || acc.payer_tokens.key != &metadata.last_topup_tokens
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        msg!("Error: Metadata does not match given accounts");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let amount = metadata.last_topup_amount;
+    if amount == 0
+        || metadata.canceled_at > 0
+        || now
+            >= metadata
+                .last_topup_at
+                .saturating_add(metadata.ix.topup_refund_window)
+    {
+        msg!("Error: No top-up to undo");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // The top-up went on top of the schedule, so none of it vested while
+    // everything vested still fits in the deposit from before.
+    metadata.settle_halt(now);
+    if metadata.vested(now) > metadata.ix.deposited_amount - amount {
+        msg!("Error: Top-up already started vesting");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    let escrow_seed = metadata.escrow_seed();
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    let held = unpack_token_account(&acc.payer_tokens)?.amount;
+    invoke_signed(
+        &transfer_checked(
             acc.token_program.key,
-            acc.sender_tokens.key,
             acc.escrow_tokens.key,
-            acc.sender.key,
+            acc.mint.key,
+            acc.payer_tokens.key,
+            acc.escrow_tokens.key,
             &[],
             amount,
+            mint_info.decimals,
         )?,
         &[
-            acc.sender_tokens.clone(),
             acc.escrow_tokens.clone(),
-            acc.sender.clone(),
+            acc.mint.clone(),
+            acc.payer_tokens.clone(),
+            acc.escrow_tokens.clone(),
             acc.token_program.clone(),
         ],
+        &[&seeds],
     )?;
+    // The escrow gives up all of it; a Token-2022 transfer fee comes out of
+    // what the payer receives.
+    let received = unpack_token_account(&acc.payer_tokens)?.amount - held;
 
-    metadata.ix.deposited_amount += amount;
+    metadata.ix.deposited_amount -= amount;
+    metadata.cache_schedule();
     metadata.closable_at = metadata.closable();
+    metadata.last_topup_amount = 0;
+    metadata.undo_contribution(acc.payer.key, amount);
+    metadata.bump_nonce();
 
-    let bytes = metadata.try_to_vec().unwrap();
-    data[0..bytes.len()].clone_from_slice(&bytes);
-
-    let mint_info = unpack_mint_account(&acc.mint)?;
+    metadata.save(&mut data)?;
 
     msg!(
-        "Successfully topped up {} to token stream {} on behalf of {}",
+        "Returned {} to {}, {} after transfer fees",
         encode_base10(amount, mint_info.decimals.into()),
-        acc.escrow_tokens.key,
-        acc.sender.key,
+        acc.payer_tokens.key,
+        encode_base10(received, mint_info.decimals.into())
     );
 
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Closes the metadata of a settled stream, one fully withdrawn or canceled,
+/// refunding its rent to the sender. Its escrow must be closed already, so
+/// no tokens, including a held bond, are left behind.
+pub fn close_stream(program_id: &Pubkey, acc: CloseStreamAccounts) -> ProgramResult {
+    msg!("Closing SPL token stream");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.sender.is_writable || !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let metadata = TokenStreamData::load(&acc.metadata.try_borrow_data()?)?;
+
+    if acc.sender.key != &metadata.sender
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if metadata.canceled_at == 0 && metadata.withdrawn_amount < metadata.ix.deposited_amount {
+        msg!("Error: Stream still holds unwithdrawn tokens");
+        return Err(StreamNotSettled.into());
+    }
+
+    if acc.escrow_tokens.lamports() > 0 {
+        msg!("Error: Escrow account is still open");
+        return Err(StreamNotSettled.into());
+    }
+
+    sync_indexes(
+        program_id,
+        &acc.indexes,
+        acc.metadata.key,
+        Some(&metadata),
+        None,
+    )?;
+
+    let metadata_rent = acc.metadata.lamports();
+    close_program_account(program_id, &acc.metadata, &acc.sender)?;
+
+    msg!(
+        "Returning {} lamports (rent) to {}",
+        volatile(metadata_rent),
+        acc.sender.key
+    );
+
+    Ok(())
+}
+
+/// Returns deposits the schedule doesn't need to the sender, e.g. after an
+/// over-funded top-up, without canceling the stream. See
+/// `TokenStreamData::surplus`.
+pub fn withdraw_surplus(program_id: &Pubkey, acc: WithdrawSurplusAccounts) -> ProgramResult {
+    msg!("Withdrawing surplus deposit");
+
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || acc.escrow_tokens.data_is_empty()
+        || !is_token_program(acc.escrow_tokens.owner)
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.sender_tokens.is_writable || !acc.metadata.is_writable || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    if acc.sender.key != &metadata.sender
+        || acc.sender_tokens.key != &metadata.sender_tokens
+        || acc.mint.key != &metadata.mint
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.token_program.key != acc.escrow_tokens.owner
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if metadata.canceled_at > 0 || metadata.end_cause != EndCause::Open {
+        return Err(StreamClosed.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let surplus = metadata.surplus(now);
+    if surplus == 0 {
+        msg!("Error: Nothing deposited beyond the schedule");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+    invoke_signed(
+        &transfer_checked(
+            acc.token_program.key,
+            acc.escrow_tokens.key,
+            acc.mint.key,
+            acc.sender_tokens.key,
+            acc.escrow_tokens.key,
+            &[],
+            surplus,
+            mint_info.decimals,
+        )?,
+        &[
+            acc.escrow_tokens.clone(),
+            acc.mint.clone(),
+            acc.sender_tokens.clone(),
+            acc.escrow_tokens.clone(),
+            acc.token_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    metadata.settle_halt(now);
+    metadata.ix.deposited_amount -= surplus;
+    metadata.cache_schedule();
+    metadata.closable_at = metadata.closable();
+    // The surplus may include the last top-up, which can't be undone twice.
+    metadata.last_topup_amount = 0;
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Returned {} surplus to {}",
+        encode_base10(surplus, mint_info.decimals.into()),
+        acc.sender_tokens.key
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Stops a subscription from renewing: the periods paid for in advance go
+/// back to the sender, and the stream closes at the end of the period in
+/// progress, which the recipient keeps.
+pub fn cancel_subscription(program_id: &Pubkey, acc: CancelSubscriptionAccounts) -> ProgramResult {
+    msg!("Canceling subscription");
+
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || acc.escrow_tokens.data_is_empty()
+        || !is_token_program(acc.escrow_tokens.owner)
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.sender_tokens.is_writable || !acc.metadata.is_writable || !acc.escrow_tokens.is_writable
+    {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    let escrow_seed = metadata.escrow_seed();
+    let (escrow_tokens_pubkey, nonce) =
+        Pubkey::find_program_address(&[acc.metadata.key.as_ref(), &escrow_seed], program_id);
+    if (acc.authority.key != &metadata.sender && acc.authority.key != &metadata.recipient)
+        || acc.sender_tokens.key != &metadata.sender_tokens
+        || acc.mint.key != &metadata.mint
+        || acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.escrow_tokens.key != &escrow_tokens_pubkey
+        || acc.token_program.key != acc.escrow_tokens.owner
+        || acc.mint.owner != acc.token_program.key
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if !metadata.ix.subscription {
+        msg!("Error: Not a subscription");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.canceled_at > 0
+        || metadata.end_cause != EndCause::Open
+        || metadata.subscription_canceled_at > 0
+        || metadata.effective_now(now) >= metadata.closable_at
+    {
+        return Err(StreamClosed.into());
+    }
+
+    let refund = metadata.ix.deposited_amount - metadata.vested(now);
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    if refund > 0 {
+        let seeds = [acc.metadata.key.as_ref(), &escrow_seed, &[nonce]];
+        invoke_signed(
+            &transfer_checked(
+                acc.token_program.key,
+                acc.escrow_tokens.key,
+                acc.mint.key,
+                acc.sender_tokens.key,
+                acc.escrow_tokens.key,
+                &[],
+                refund,
+                mint_info.decimals,
+            )?,
+            &[
+                acc.escrow_tokens.clone(),
+                acc.mint.clone(),
+                acc.sender_tokens.clone(),
+                acc.escrow_tokens.clone(),
+                acc.token_program.clone(),
+            ],
+            &[&seeds],
+        )?;
+    }
+
+    metadata.settle_halt(now);
+    metadata.ix.deposited_amount -= refund;
+    metadata.cache_schedule();
+    metadata.closable_at = metadata.closable();
+    metadata.subscription_canceled_at = now;
+    // The refund may include the last top-up, which can't be undone twice.
+    metadata.last_topup_amount = 0;
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Returned {} prepaid to {}, closable at {}",
+        encode_base10(refund, mint_info.decimals.into()),
+        acc.sender_tokens.key,
+        metadata.closable_at
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+pub fn pause_by_recipient(program_id: &Pubkey, acc: RecipientAccounts) -> ProgramResult {
+    msg!("Pausing stream on behalf of the recipient");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    let schedule_now = metadata.effective_now(now);
+    if metadata.canceled_at > 0 || schedule_now >= metadata.closable_at {
+        return Err(StreamClosed.into());
+    }
+
+    if metadata.recipient_paused_at > 0 || metadata.sender_paused_at > 0 {
+        return Err(StreamPaused.into());
+    }
+
+    if schedule_now < metadata.ix.start_time {
+        msg!("Error: Stream has not started yet");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if metadata.recipient_paused_seconds >= metadata.ix.max_recipient_pause {
+        return Err(PauseBudgetExhausted.into());
+    }
+
+    metadata.recipient_paused_at = now;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Paused, remaining pause budget: {}",
+        pretty_time(metadata.ix.max_recipient_pause - metadata.recipient_paused_seconds)
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+pub fn resume_by_recipient(program_id: &Pubkey, acc: RecipientAccounts) -> ProgramResult {
+    msg!("Resuming stream on behalf of the recipient");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if metadata.recipient_paused_at == 0 {
+        return Err(StreamNotPaused.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    metadata.recipient_paused_seconds = metadata.recipient_paused_time(now);
+    metadata.recipient_paused_at = 0;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Resumed, total paused: {}",
+        volatile(pretty_time(metadata.recipient_paused_seconds))
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Employer pause: stops accrual until `resume_stream`, without a budget.
+/// Ends a recipient pause in progress, so the two never overlap.
+pub fn pause_stream(program_id: &Pubkey, acc: SenderAccounts) -> ProgramResult {
+    msg!("Pausing stream on behalf of the sender");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.sender.key != &metadata.sender || !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.canceled_at > 0 || metadata.effective_now(now) >= metadata.closable_at {
+        return Err(StreamClosed.into());
+    }
+
+    if metadata.sender_paused_at > 0 {
+        return Err(StreamPaused.into());
+    }
+
+    if metadata.recipient_paused_at > 0 {
+        metadata.recipient_paused_seconds = metadata.recipient_paused_time(now);
+        metadata.recipient_paused_at = 0;
+    }
+    metadata.sender_paused_at = now;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Paused, total paused by sender: {}",
+        volatile(pretty_time(metadata.sender_paused_seconds))
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+pub fn resume_stream(program_id: &Pubkey, acc: SenderAccounts) -> ProgramResult {
+    msg!("Resuming stream on behalf of the sender");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.sender.key != &metadata.sender || !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if metadata.sender_paused_at == 0 {
+        return Err(StreamNotPaused.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    metadata.sender_paused_seconds = metadata.sender_paused_time(now);
+    metadata.sender_paused_at = 0;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "Resumed, total paused by sender: {}",
+        volatile(pretty_time(metadata.sender_paused_seconds))
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Cancels up to `MAX_CANCEL_BATCH` streams of one sender. Any failing
+/// stream fails the whole instruction, so either every stream is canceled or
+/// none is.
+pub fn cancel_batch(program_id: &Pubkey, acc: CancelBatchAccounts) -> ProgramResult {
+    msg!("Cancelling SPL token streams in batch");
+
+    let total = acc.streams.len() / CANCEL_BATCH_GROUP_LEN;
+    if total == 0 || total > MAX_CANCEL_BATCH || total * CANCEL_BATCH_GROUP_LEN != acc.streams.len()
+    {
+        msg!(
+            "Error: Expected up to {} groups of {} accounts",
+            MAX_CANCEL_BATCH,
+            CANCEL_BATCH_GROUP_LEN
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if !acc.cancel_authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    for group in acc.streams.chunks(CANCEL_BATCH_GROUP_LEN) {
+        let ca = CancelAccounts {
+            cancel_authority: acc.cancel_authority.clone(),
+            sender: acc.sender.clone(),
+            sender_tokens: group[2].clone(),
+            recipient: group[3].clone(),
+            recipient_tokens: group[4].clone(),
+            metadata: group[0].clone(),
+            escrow_tokens: group[1].clone(),
+            mint: group[5].clone(),
+            token_program: acc.token_program.clone(),
+            fees: FeeAccounts {
+                config: acc.fee_config.clone(),
+                treasury_tokens: optional_slot(program_id, &group[6]),
+                exemptions: acc.fee_exemptions.clone(),
+                partner_tokens: optional_slot(program_id, &group[7]),
+            },
+            freeze_authority: optional_slot(program_id, &group[8]),
+            freeze_signer: optional_slot(program_id, &group[9]),
+            obligation_mint: None,
+            obligation_tokens: None,
+            token_2022_program: None,
+        };
+
+        if let Err(e) = cancel(program_id, ca) {
+            msg!("Error: Stream {} failed with {:?}", group[0].key, e);
+            return Err(e);
+        }
+    }
+
+    msg!("Canceled {} streams", total);
+
+    Ok(())
+}
+
+/// Creates one stream of `ix` per recipient group, funded from the same
+/// `sender_tokens`. Like `cancel_batch`, any failing stream fails the whole
+/// instruction, so either every stream is created or none is.
+pub fn create_many(
+    program_id: &Pubkey,
+    acc: CreateManyAccounts,
+    ix: StreamInstruction,
+) -> ProgramResult {
+    msg!("Creating SPL token streams in batch");
+
+    let total = acc.streams.len() / CREATE_BATCH_GROUP_LEN;
+    if total == 0 || total > MAX_CREATE_BATCH || total * CREATE_BATCH_GROUP_LEN != acc.streams.len()
+    {
+        msg!(
+            "Error: Expected up to {} groups of {} accounts",
+            MAX_CREATE_BATCH,
+            CREATE_BATCH_GROUP_LEN
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Private streams would all derive the same metadata address, and the
+    // extra accounts of the others aren't part of the groups.
+    if ix.recipient_hash != [0; 32] || ix.managed_freeze || ix.obligation_nft {
+        msg!("Error: Private, managed freeze and obligation streams can't be batched");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    for group in acc.streams.chunks(CREATE_BATCH_GROUP_LEN) {
+        let ia = InitializeAccounts {
+            sender: acc.sender.clone(),
+            sender_tokens: acc.sender_tokens.clone(),
+            recipient: group[0].clone(),
+            recipient_tokens: group[1].clone(),
+            metadata: group[2].clone(),
+            escrow_tokens: group[3].clone(),
+            mint: acc.mint.clone(),
+            rent: acc.rent.clone(),
+            token_program: acc.token_program.clone(),
+            associated_token_program: acc.associated_token_program.clone(),
+            system_program: acc.system_program.clone(),
+            stream_counter: Some(acc.stream_counter.clone()),
+            freeze_authority: None,
+            freeze_issuer: None,
+            obligation_mint: None,
+            obligation_tokens: None,
+            token_2022_program: None,
+            indexes: vec![],
+            multisig: None,
+            multisig_signers: vec![],
+            payer: None,
+        };
+
+        if let Err(e) = create(program_id, ia, ix.clone()) {
+            msg!("Error: Stream to {} failed with {:?}", group[0].key, e);
+            return Err(e);
+        }
+    }
+
+    msg!("Created {} streams", total);
+
+    Ok(())
+}
+
+/// When `sender_tokens` is owned by an SPL token multisig, the multisig must be
+/// passed along with (some of) its signers, which sign the transfer instead.
+pub(crate) fn check_multisig_owner(
+    sender_token_info: &spl_token::state::Account,
+    multisig: &Option<AccountInfo>,
+    multisig_signers: &[AccountInfo],
+) -> ProgramResult {
+    let multisig = match multisig {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    if !is_token_program(multisig.owner) || &sender_token_info.owner != multisig.key {
+        msg!("Error: Sender tokens are not owned by the given multisig");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if multisig_signers.len() > spl_token::instruction::MAX_SIGNERS {
+        return Err(TooManySigners.into());
+    }
+
+    if multisig_signers.is_empty() || multisig_signers.iter().any(|s| !s.is_signer) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    Ok(())
+}
+
+/// Moves tokens from the sender's token account into escrow, signed either by
+/// the sender directly or by the signers of the multisig owning the account.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn transfer_from_sender<'a>(
+    token_program: &AccountInfo<'a>,
+    sender_tokens: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    escrow_tokens: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    multisig_signers: &[AccountInfo<'a>],
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    let signer_keys: Vec<&Pubkey> = multisig_signers.iter().map(|s| s.key).collect();
+    let mut accounts = vec![
+        sender_tokens.clone(),
+        mint.clone(),
+        escrow_tokens.clone(),
+        owner.clone(),
+        token_program.clone(),
+    ];
+    accounts.extend(multisig_signers.iter().cloned());
+
+    invoke(
+        &transfer_checked(
+            token_program.key,
+            sender_tokens.key,
+            mint.key,
+            escrow_tokens.key,
+            owner.key,
+            &signer_keys,
+            amount,
+            decimals,
+        )?,
+        &accounts,
+    )
+}
+
+/// Points an instruction built by `spl_token`, which only accepts its own
+/// program id, at `token_program`. Token-2022 shares the instruction layout.
+pub(crate) fn retarget(mut ix: Instruction, token_program: &Pubkey) -> Instruction {
+    ix.program_id = *token_program;
+    ix
+}
+
+/// `TransferChecked` for either token program. Token-2022 refuses plain
+/// transfers of mints with a transfer fee.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn transfer_checked(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    signers: &[&Pubkey],
+    amount: u64,
+    decimals: u8,
+) -> Result<Instruction, ProgramError> {
+    let ix = spl_token::instruction::transfer_checked(
+        &spl_token::id(),
+        source,
+        mint,
+        destination,
+        authority,
+        signers,
+        amount,
+        decimals,
+    )?;
+    Ok(retarget(ix, token_program))
+}
+
+/// Size of an escrow token account for `mint`. Token-2022 mints may require
+/// account extensions, so the token program is asked.
+pub(crate) fn escrow_account_size<'a>(
+    token_program: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+) -> Result<usize, ProgramError> {
+    if token_program.key == &spl_token::id() {
+        return Ok(spl_token::state::Account::LEN);
+    }
+
+    invoke(
+        &Instruction {
+            program_id: *token_program.key,
+            accounts: vec![AccountMeta::new_readonly(*mint.key, false)],
+            data: vec![GET_ACCOUNT_DATA_SIZE],
+        },
+        &[mint.clone(), token_program.clone()],
+    )?;
+
+    match get_return_data() {
+        Some((program, data)) if &program == token_program.key && data.len() == 8 => {
+            Ok(u64::from_le_bytes(data[..].try_into().unwrap()) as usize)
+        }
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Token-2022 keeps transfer fees withheld in the receiving account, which
+/// can't be closed until they are swept to the mint. Anyone may sweep.
+pub(crate) fn harvest_withheld_fees<'a>(
+    token_program: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    tokens: &AccountInfo<'a>,
+) -> ProgramResult {
+    if withheld_fees(tokens) == 0 {
+        return Ok(());
+    }
+
+    invoke(
+        &Instruction {
+            program_id: *token_program.key,
+            accounts: vec![
+                AccountMeta::new(*mint.key, false),
+                AccountMeta::new(*tokens.key, false),
+            ],
+            data: vec![TRANSFER_FEE_EXTENSION, HARVEST_WITHHELD_TOKENS_TO_MINT],
+        },
+        &[mint.clone(), tokens.clone(), token_program.clone()],
+    )
+}
+
+/// Protocol fee on `amount` of vested tokens paid out of `metadata`'s stream
+/// and the treasury account it goes to. None while the fee config is
+/// uninitialized, for a zero fee, or if the sender or recipient is exempt.
+pub(crate) fn protocol_fee<'b, 'a>(
+    program_id: &Pubkey,
+    metadata: &TokenStreamData,
+    fees: &'b FeeAccounts<'a>,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<Option<(u64, &'b AccountInfo<'a>)>, ProgramError> {
+    let (config_pubkey, _) = FeeConfig::address(program_id);
+    if fees.config.key != &config_pubkey {
+        msg!("Error: The fee config is {}", config_pubkey);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if fees.config.data_is_empty() {
+        return Ok(None);
+    }
+    if fees.config.owner != program_id {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let config: FeeConfig = match solana_borsh::try_from_slice_unchecked(&fees.config.data.borrow())
+    {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidMetadata.into()),
+    };
+
+    if let Some(exemptions) = fees.exemptions.as_ref().filter(|v| !v.data_is_empty()) {
+        let (exemptions_pubkey, _) =
+            Pubkey::find_program_address(&[FEE_EXEMPTIONS_SEED], program_id);
+        if exemptions.key != &exemptions_pubkey || exemptions.owner != program_id {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let exemptions: FeeExemptions =
+            match solana_borsh::try_from_slice_unchecked(&exemptions.data.borrow()) {
+                Ok(v) => v,
+                Err(_) => return Err(InvalidMetadata.into()),
+            };
+        if exemptions.is_exempt(&metadata.sender) || exemptions.is_exempt(&metadata.recipient) {
+            msg!("Exempt from protocol fees");
+            return Ok(None);
+        }
+    }
+
+    let fee = config.fee(amount);
+    if fee == 0 {
+        return Ok(None);
+    }
+
+    let treasury_tokens_key =
+        associated_token_address(&config.treasury, &metadata.mint, token_program);
+    match &fees.treasury_tokens {
+        Some(v) if v.key == &treasury_tokens_key && v.is_writable => Ok(Some((fee, v))),
+        _ => {
+            msg!("Error: Protocol fees are paid to {}", treasury_tokens_key);
+            Err(ProgramError::InvalidAccountData)
+        }
+    }
+}
+
+/// Partner's share of `amount` of vested tokens paid out of `metadata`'s
+/// stream and the partner account it goes to. None for streams without one.
+pub(crate) fn partner_fee<'b, 'a>(
+    metadata: &TokenStreamData,
+    fees: &'b FeeAccounts<'a>,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<Option<(u64, &'b AccountInfo<'a>)>, ProgramError> {
+    let fee = (amount as u128 * metadata.ix.partner_fee_bps as u128 / 10_000) as u64;
+    if fee == 0 {
+        return Ok(None);
+    }
+
+    let partner_tokens_key =
+        associated_token_address(&metadata.ix.partner, &metadata.mint, token_program);
+    match &fees.partner_tokens {
+        Some(v) if v.key == &partner_tokens_key && v.is_writable => Ok(Some((fee, v))),
+        _ => {
+            msg!("Error: Partner fees are paid to {}", partner_tokens_key);
+            Err(ProgramError::InvalidAccountData)
+        }
+    }
+}
+
+/// Pays a fee from `protocol_fee` or `partner_fee` out of the escrow.
+fn pay_fee<'a>(
+    label: &str,
+    fee: Option<(u64, &AccountInfo<'a>)>,
+    token_program: &AccountInfo<'a>,
+    escrow_tokens: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    decimals: u8,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let (fee, destination) = match fee {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    stack_msg!("{} {}", label, fee);
+    invoke_signed(
+        &transfer_checked(
+            token_program.key,
+            escrow_tokens.key,
+            mint.key,
+            destination.key,
+            escrow_tokens.key,
+            &[],
+            fee,
+            decimals,
+        )?,
+        &[
+            escrow_tokens.clone(),
+            mint.clone(),
+            destination.clone(),
+            escrow_tokens.clone(),
+            token_program.clone(),
+        ],
+        &[seeds],
+    )
+}
+
+/// Creates `wallet`'s associated token account for `mint`, paid by `payer`,
+/// unless it exists already. `wallet` may be off-curve, e.g. a PDA of a
+/// treasury program. The associated token program account is validated and
+/// passed to the CPI so a spoofed program can't stand in for it.
+pub(crate) fn create_recipient_ata<'a>(
+    payer: &AccountInfo<'a>,
+    wallet: &AccountInfo<'a>,
+    wallet_tokens: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    associated_token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if associated_token_program.key != &spl_associated_token_account::id()
+        || wallet_tokens.key != &associated_token_address(wallet.key, mint.key, token_program.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // The associated token program's `CreateIdempotent`, built by hand as
+    // the crate version we build against predates it. It takes the token
+    // program as an account and so works for Token-2022 too, and checks an
+    // existing account is the wallet's for the mint instead of failing.
+    invoke(
+        &Instruction {
+            program_id: spl_associated_token_account::id(),
+            accounts: vec![
+                AccountMeta::new(*payer.key, true),
+                AccountMeta::new(*wallet_tokens.key, false),
+                AccountMeta::new_readonly(*wallet.key, false),
+                AccountMeta::new_readonly(*mint.key, false),
+                AccountMeta::new_readonly(*system_program.key, false),
+                AccountMeta::new_readonly(*token_program.key, false),
+            ],
+            data: vec![ATA_CREATE_IDEMPOTENT],
+        },
+        &[
+            payer.clone(),
+            wallet_tokens.clone(),
+            wallet.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+    )
+}
+
+/// Hands out the next stream sequence number, creating the counter on the
+/// first stream.
+pub(crate) fn next_stream_seq<'a>(
+    program_id: &Pubkey,
+    counter: &Option<AccountInfo<'a>>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+    let counter = counter.as_ref().ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+    let (counter_pubkey, nonce) = Pubkey::find_program_address(&[STREAM_COUNTER_SEED], program_id);
+    if counter.key != &counter_pubkey {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !counter.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if counter.data_is_empty() {
+        let seeds = [STREAM_COUNTER_SEED, &[nonce]];
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                counter.key,
+                Rent::get()?.minimum_balance(STREAM_COUNTER_SIZE),
+                STREAM_COUNTER_SIZE as u64,
+                program_id,
+            ),
+            &[payer.clone(), counter.clone(), system_program.clone()],
+            &[&seeds],
+        )?;
+    } else if counter.owner != program_id {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = counter.try_borrow_mut_data()?;
+    let seq = u64::from_le_bytes(data[0..8].try_into().unwrap()) + 1;
+    data[0..8].clone_from_slice(&seq.to_le_bytes());
+
+    Ok(seq)
+}
+
+/// Mentions the stream's `notify_key` in the logs, so whoever watches that key
+/// learns about every change to the stream.
+pub(crate) fn notify(metadata: &TokenStreamData) {
+    if metadata.ix.notify_key != Pubkey::default() {
+        stack_msg!("Notify: {}", Key(&metadata.ix.notify_key));
+    }
+}
+
+/// `managed_freeze` streams need the mint's freeze authority to be a 1-of-N
+/// multisig that includes the program's freeze PDA, and one of the other
+/// members (the issuer) to co-sign the stream's creation.
+fn check_freeze_delegation(
+    program_id: &Pubkey,
+    mint_info: &spl_token::state::Mint,
+    mint: &Pubkey,
+    freeze_authority: &Option<AccountInfo>,
+    freeze_issuer: &Option<AccountInfo>,
+) -> ProgramResult {
+    let (freeze_authority, freeze_issuer) = match (freeze_authority, freeze_issuer) {
+        (Some(a), Some(i)) => (a, i),
+        _ => return Err(ProgramError::NotEnoughAccountKeys),
+    };
+
+    if mint_info.freeze_authority != COption::Some(*freeze_authority.key) {
+        return Err(FreezeAuthorityMismatch.into());
+    }
+
+    let (freeze_pda, _) =
+        Pubkey::find_program_address(&[FREEZE_AUTHORITY_SEED, mint.as_ref()], program_id);
+    let multisig = unpack_multisig_account(freeze_authority)?;
+    let signers = &multisig.signers[..multisig.n as usize];
+    if multisig.m != 1 || !signers.contains(&freeze_pda) {
+        return Err(FreezeAuthorityMismatch.into());
+    }
+
+    if freeze_issuer.key == &freeze_pda || !signers.contains(freeze_issuer.key) {
+        return Err(FreezeAuthorityMismatch.into());
+    }
+
+    if !freeze_issuer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    Ok(())
+}
+
+/// Thaws or refreezes `token_account` through the mint's freeze authority
+/// multisig, signing as the program's freeze PDA.
+fn set_frozen<'a>(
+    program_id: &Pubkey,
+    freeze: bool,
+    token_program: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    freeze_authority: &Option<AccountInfo<'a>>,
+    freeze_signer: &Option<AccountInfo<'a>>,
+) -> ProgramResult {
+    let (freeze_authority, freeze_signer) = match (freeze_authority, freeze_signer) {
+        (Some(a), Some(s)) => (a, s),
+        _ => return Err(ProgramError::NotEnoughAccountKeys),
+    };
+
+    let (freeze_pda, bump) =
+        Pubkey::find_program_address(&[FREEZE_AUTHORITY_SEED, mint.key.as_ref()], program_id);
+    if freeze_signer.key != &freeze_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let ix = if freeze {
+        spl_token::instruction::freeze_account(
+            token_program.key,
+            token_account.key,
+            mint.key,
+            freeze_authority.key,
+            &[&freeze_pda],
+        )?
+    } else {
+        spl_token::instruction::thaw_account(
+            token_program.key,
+            token_account.key,
+            mint.key,
+            freeze_authority.key,
+            &[&freeze_pda],
+        )?
+    };
+
+    invoke_signed(
+        &ix,
+        &[
+            token_account.clone(),
+            mint.clone(),
+            freeze_authority.clone(),
+            freeze_signer.clone(),
+            token_program.clone(),
+        ],
+        &[&[FREEZE_AUTHORITY_SEED, mint.key.as_ref(), &[bump]]],
+    )
+}
+
+pub fn set_min_claim(program_id: &Pubkey, acc: RecipientAccounts, amount: u64) -> ProgramResult {
+    msg!("Setting minimum claim amount");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    metadata.min_claim_amount = amount;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Minimum claim amount: {}", amount);
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Creates the recipient's associated token account ahead of the first
+/// withdrawal, so the claim itself stays small. Anyone may pay for it.
+pub fn prewarm(program_id: &Pubkey, acc: PrewarmAccounts) -> ProgramResult {
+    msg!("Pre-warming stream accounts");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.payer.is_writable || !acc.recipient_tokens.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if acc.system_program.key != &system_program::id()
+        || !is_token_program(acc.token_program.key)
+        || acc.mint.owner != acc.token_program.key
+        || acc.rent.key != &sysvar::rent::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = acc.metadata.try_borrow_data()?;
+    let metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || acc.recipient_tokens.key != &metadata.recipient_tokens
+        || acc.mint.key != &metadata.mint
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.recipient_tokens.data_is_empty() {
+        msg!(
+            "Recipient tokens {} already exist",
+            acc.recipient_tokens.key
+        );
+        return Ok(());
+    }
+
+    create_recipient_ata(
+        &acc.payer,
+        &acc.recipient,
+        &acc.recipient_tokens,
+        &acc.mint,
+        &acc.system_program,
+        &acc.token_program,
+        &acc.associated_token_program,
+    )?;
+
+    msg!("Created recipient tokens {}", acc.recipient_tokens.key);
+
+    Ok(())
+}
+
+/// Has all further withdrawals paid straight into `forward_tokens`, a token
+/// account of the stream's mint, rather than the recipient's hot wallet.
+pub fn set_forward(program_id: &Pubkey, acc: SetForwardAccounts) -> ProgramResult {
+    msg!("Setting withdrawal forwarding");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if unpack_token_account(&acc.forward_tokens)?.mint != metadata.mint {
+        return Err(MintMismatch.into());
+    }
+
+    // Thawing around withdrawals only covers the recipient's own account.
+    if metadata.ix.managed_freeze {
+        msg!("Error: Streams of permissioned mints can't forward withdrawals");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    metadata.forward_to = *acc.forward_tokens.key;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Forwarding withdrawals to {}", metadata.forward_to);
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+pub fn clear_forward(program_id: &Pubkey, acc: RecipientAccounts) -> ProgramResult {
+    msg!("Clearing withdrawal forwarding");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.recipient.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.recipient.key != &metadata.recipient
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    metadata.forward_to = Pubkey::default();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Permissionless, read-only view of a stream: logs and returns (as return
+/// data) what's vested and withdrawable now, plus the funding runway.
+pub fn preview(
+    program_id: &Pubkey,
+    acc: PreviewAccounts,
+    clock: &impl TimeSource,
+) -> ProgramResult {
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let data = acc.metadata.try_borrow_data()?;
+    let metadata = TokenStreamData::load(&data)?;
+
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = clock.now()?;
+    let preview = StreamPreview {
+        now,
+        vested: metadata.vested(now),
+        available: metadata.available(now),
+        closable_at: metadata.closable_at,
+        funding_runway_seconds: metadata.funding_runway(now),
+    };
+
+    msg!(
+        "Vested: {}, available: {}, closable at: {}",
+        preview.vested,
+        preview.available,
+        preview.closable_at
+    );
+    msg!(
+        "Funding runway: {} seconds ({})",
+        preview.funding_runway_seconds,
+        pretty_time(preview.funding_runway_seconds)
+    );
+
+    set_return_data(&preview.try_to_vec()?);
+
+    Ok(())
+}
+
+/// Permissionless view for governance snapshots: returns (as return data) the
+/// recipient's unvested balance as of `at`, see `TokenStreamData::unvested_at`.
+pub fn vesting_snapshot(program_id: &Pubkey, acc: SnapshotAccounts, at: u64) -> ProgramResult {
+    let at = normalize_timestamp(at)?;
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let data = acc.metadata.try_borrow_data()?;
+    let metadata = TokenStreamData::load(&data)?;
+
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let snapshot = VestingSnapshot {
+        at,
+        recipient: metadata.recipient,
+        mint: metadata.mint,
+        unvested: metadata.unvested_at(at),
+    };
+
+    msg!(
+        "Unvested at {}: {} for {}",
+        snapshot.at,
+        snapshot.unvested,
+        snapshot.recipient
+    );
+
+    set_return_data(&snapshot.try_to_vec()?);
+
+    Ok(())
+}
+
+/// Permissionless view for auditors: returns the escrow's actual balance next
+/// to what the stream says it should hold.
+pub fn escrow_proof(program_id: &Pubkey, acc: EscrowProofAccounts) -> ProgramResult {
+    if acc.metadata.data_is_empty()
+        || acc.metadata.owner != program_id
+        || acc.escrow_tokens.data_is_empty()
+    {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let data = acc.metadata.try_borrow_data()?;
+    let metadata = TokenStreamData::load(&data)?;
+
+    if acc.escrow_tokens.key != &metadata.escrow_tokens
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let proof = EscrowProof {
+        escrow_balance: unpack_token_account(&acc.escrow_tokens)?.amount,
+        deposited: metadata.ix.deposited_amount,
+        withdrawn: metadata.withdrawn_amount,
+        bond: metadata.ix.bond_amount,
+        timestamp: Clock::get()?.unix_timestamp as u64,
+    };
+
+    msg!(
+        "Escrow {}: balance {}, deposited {}, withdrawn {}, bond {}, at {}",
+        acc.escrow_tokens.key,
+        proof.escrow_balance,
+        proof.deposited,
+        proof.withdrawn,
+        proof.bond,
+        proof.timestamp
+    );
+
+    set_return_data(&proof.try_to_vec()?);
+
+    Ok(())
+}
+
+/// Permissionless: recomputes `closable_at` with the current formula, for
+/// streams created before it changed.
+pub fn refresh_closable(program_id: &Pubkey, acc: RefreshClosableAccounts) -> ProgramResult {
+    msg!("Refreshing stream closable time");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if metadata.canceled_at > 0 || metadata.end_cause != EndCause::Open {
+        return Err(StreamClosed.into());
+    }
+
+    let closable_at = metadata.closable();
+    msg!(
+        "Closable at: {} (was {})",
+        closable_at,
+        metadata.closable_at
+    );
+    if closable_at != metadata.closable_at {
+        metadata.closable_at = closable_at;
+        metadata.bump_nonce();
+        let bytes = metadata.try_to_vec()?;
+        data[0..bytes.len()].clone_from_slice(&bytes);
+    }
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Lets the stream's compliance authority block withdrawals (and recipient
+/// transfers) until `frozen_until`, at most `MAX_COMPLIANCE_FREEZE` ahead.
+/// The sender can still cancel. A `frozen_until` in the past lifts the freeze.
+/// An active freeze can be shortened or lifted, not extended, so back to back
+/// freezes can't lock the recipient out indefinitely.
+pub fn compliance_freeze(
+    program_id: &Pubkey,
+    acc: ComplianceFreezeAccounts,
+    frozen_until: u64,
+) -> ProgramResult {
+    msg!("Setting compliance freeze");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.compliance_authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if metadata.ix.compliance_authority == Pubkey::default()
+        || acc.compliance_authority.key != &metadata.ix.compliance_authority
+        || !metadata.has_address(program_id, acc.metadata.key)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if frozen_until > now + MAX_COMPLIANCE_FREEZE {
+        msg!(
+            "Error: Freeze can last at most {}",
+            pretty_time(MAX_COMPLIANCE_FREEZE)
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if metadata.compliance_frozen(now) && frozen_until > metadata.compliance_frozen_until {
+        msg!(
+            "Error: Already frozen until {}, the freeze can't be extended",
+            metadata.compliance_frozen_until
+        );
+        return Err(ComplianceFrozen.into());
+    }
+
+    metadata.compliance_frozen_until = frozen_until;
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    if metadata.compliance_frozen(now) {
+        msg!("Withdrawals frozen until {}", frozen_until);
+    } else {
+        msg!("Withdrawals unfrozen");
+    }
+
+    notify(&metadata);
+
+    Ok(())
+}
+
+/// Permissionless view summing up locked, vested and withdrawn amounts over
+/// the given streams of one mint, so projects can back "X% of supply is still
+/// locked" statements with a simulation of the program itself.
+pub fn mint_summary(program_id: &Pubkey, acc: MintSummaryAccounts) -> ProgramResult {
+    if acc.streams.len() > MAX_MINT_SUMMARY_STREAMS {
+        msg!(
+            "Error: At most {} streams per summary",
+            MAX_MINT_SUMMARY_STREAMS
+        );
+        return Err(TooManyStreams.into());
+    }
+
+    let mint_info = unpack_mint_account(&acc.mint)?;
+    let now = Clock::get()?.unix_timestamp as u64;
+
+    let mut summary = MintSummary {
+        supply: mint_info.supply,
+        timestamp: now,
+        ..Default::default()
+    };
+
+    for (i, stream) in acc.streams.iter().enumerate() {
+        if stream.data_is_empty() || stream.owner != program_id {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if acc.streams[..i].iter().any(|s| s.key == stream.key) {
+            msg!("Error: {} listed twice", stream.key);
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let metadata = TokenStreamData::load(&stream.data.borrow())?;
+
+        if !metadata.has_address(program_id, stream.key) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if acc.mint.key != &metadata.mint {
+            return Err(MintMismatch.into());
+        }
+
+        summary.streams += 1;
+        if metadata.canceled_at > 0 {
+            summary.canceled += 1;
+            continue;
+        }
+
+        let vested = metadata.vested(now);
+        summary.deposited += metadata.ix.deposited_amount;
+        summary.vested += vested;
+        summary.withdrawn += metadata.withdrawn_amount;
+        summary.locked += metadata.ix.deposited_amount - vested;
+    }
+
+    msg!(
+        "{} streams of {}: locked {}, vested {}, withdrawn {}, supply {}",
+        summary.streams,
+        acc.mint.key,
+        encode_base10(summary.locked, mint_info.decimals.into()),
+        encode_base10(summary.vested, mint_info.decimals.into()),
+        encode_base10(summary.withdrawn, mint_info.decimals.into()),
+        encode_base10(summary.supply, mint_info.decimals.into())
+    );
+
+    set_return_data(&summary.try_to_vec()?);
+
     Ok(())
 }
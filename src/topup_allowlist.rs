@@ -0,0 +1,148 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    borsh as solana_borsh,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, InvalidMetadata, TopUpAllowlistFull};
+use crate::state::{
+    InitTopUpAllowlistAccounts, TokenStreamData, TopUpAllowlist, TopUpAllowlistUpdate,
+    UpdateTopUpAllowlistAccounts, MAX_TOPUP_ALLOWLIST, TOPUP_ALLOWLIST_SEED, TOPUP_ALLOWLIST_SIZE,
+};
+
+/// Fails unless `sender` is the sender of the stream at `metadata`.
+fn check_sender(program_id: &Pubkey, metadata: &AccountInfo, sender: &Pubkey) -> ProgramResult {
+    if metadata.data_is_empty() || metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let stream = TokenStreamData::load(&metadata.try_borrow_data()?)?;
+
+    if &stream.sender != sender || !stream.has_address(program_id, metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(())
+}
+
+pub fn initialize_topup_allowlist(
+    program_id: &Pubkey,
+    acc: InitTopUpAllowlistAccounts,
+) -> ProgramResult {
+    msg!("Initializing top-up allowlist");
+
+    if !acc.allowlist.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if !acc.sender.is_writable || !acc.allowlist.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    check_sender(program_id, &acc.metadata, acc.sender.key)?;
+
+    let (allowlist_pubkey, nonce) = Pubkey::find_program_address(
+        &[TOPUP_ALLOWLIST_SEED, acc.metadata.key.as_ref()],
+        program_id,
+    );
+
+    if acc.allowlist.key != &allowlist_pubkey || acc.system_program.key != &system_program::id() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let seeds = [TOPUP_ALLOWLIST_SEED, acc.metadata.key.as_ref(), &[nonce]];
+    invoke_signed(
+        &system_instruction::create_account(
+            acc.sender.key,
+            acc.allowlist.key,
+            Rent::get()?.minimum_balance(TOPUP_ALLOWLIST_SIZE),
+            TOPUP_ALLOWLIST_SIZE as u64,
+            program_id,
+        ),
+        &[
+            acc.sender.clone(),
+            acc.allowlist.clone(),
+            acc.system_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    let allowlist = TopUpAllowlist {
+        stream: *acc.metadata.key,
+        members: vec![],
+    };
+    let bytes = allowlist.try_to_vec()?;
+    let mut data = acc.allowlist.try_borrow_mut_data()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!("Top-up allowlist of {}", acc.metadata.key);
+
+    Ok(())
+}
+
+pub fn update_topup_allowlist(
+    program_id: &Pubkey,
+    acc: UpdateTopUpAllowlistAccounts,
+    update: TopUpAllowlistUpdate,
+) -> ProgramResult {
+    msg!("Updating top-up allowlist");
+
+    if acc.allowlist.data_is_empty() || acc.allowlist.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.allowlist.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    check_sender(program_id, &acc.metadata, acc.sender.key)?;
+
+    let (allowlist_pubkey, _) = Pubkey::find_program_address(
+        &[TOPUP_ALLOWLIST_SEED, acc.metadata.key.as_ref()],
+        program_id,
+    );
+    if acc.allowlist.key != &allowlist_pubkey {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = acc.allowlist.try_borrow_mut_data()?;
+    let mut allowlist: TopUpAllowlist = match solana_borsh::try_from_slice_unchecked(&data) {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidMetadata.into()),
+    };
+
+    if update.allowed {
+        if !allowlist.is_allowed(&update.member) {
+            if allowlist.members.len() >= MAX_TOPUP_ALLOWLIST {
+                return Err(TopUpAllowlistFull.into());
+            }
+            allowlist.members.push(update.member);
+        }
+        msg!("Allowed {} to top up", update.member);
+    } else {
+        allowlist.members.retain(|m| m != &update.member);
+        msg!("Removed {} from the top-up allowlist", update.member);
+    }
+
+    // Clear the old encoding first, the list may have shrunk.
+    let bytes = allowlist.try_to_vec()?;
+    data.fill(0);
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+use crate::error::StreamFlowError::{AccountsNotWritable, StreamClosed};
+use crate::state::{EndCause, StreamUpdate, TokenStreamData, UpdateStreamAccounts};
+use crate::token::notify;
+use crate::utils::normalize_timestamp;
+
+/// Amends a running stream's terms in place, keeping its history and cliff.
+/// Changes that leave the recipient worse off (a later end of a fixed
+/// schedule, a lower release rate, or flags that weaken their position) need
+/// the recipient to co-sign.
+pub fn update_stream(
+    program_id: &Pubkey,
+    acc: UpdateStreamAccounts,
+    update: StreamUpdate,
+) -> ProgramResult {
+    msg!("Updating SPL token stream");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if !acc.metadata.is_writable {
+        return Err(AccountsNotWritable.into());
+    }
+
+    let mut data = acc.metadata.try_borrow_mut_data()?;
+    let mut metadata = TokenStreamData::load(&data)?;
+
+    if acc.sender.key != &metadata.sender || !metadata.has_address(program_id, acc.metadata.key) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !acc.sender.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    if metadata.canceled_at > 0
+        || metadata.end_cause != EndCause::Open
+        || metadata.effective_now(now) >= metadata.closable_at
+    {
+        return Err(StreamClosed.into());
+    }
+
+    let ix = &mut metadata.ix;
+    let mut unfavorable = false;
+
+    if let Some(end_time) = update.end_time {
+        let end_time = normalize_timestamp(end_time)?;
+        if end_time < ix.end_time {
+            msg!("Error: End time can only be extended");
+            return Err(ProgramError::InvalidArgument);
+        }
+        unfavorable |= end_time > ix.end_time && ix.release_rate == 0;
+        ix.end_time = end_time;
+    }
+
+    if let Some(release_rate) = update.release_rate {
+        if (release_rate == 0) != (ix.release_rate == 0) {
+            msg!("Error: Release rate can't be added to or removed from a stream");
+            return Err(ProgramError::InvalidArgument);
+        }
+        unfavorable |= release_rate < ix.release_rate;
+        ix.release_rate = release_rate;
+    }
+
+    if let Some(cancelable) = update.cancelable_by_sender {
+        unfavorable |= cancelable && !ix.cancelable_by_sender;
+        ix.cancelable_by_sender = cancelable;
+    }
+
+    if let Some(cancelable) = update.cancelable_by_recipient {
+        unfavorable |= !cancelable && ix.cancelable_by_recipient;
+        ix.cancelable_by_recipient = cancelable;
+    }
+
+    if let Some(transferable) = update.transferable_by_sender {
+        unfavorable |= transferable && !ix.transferable_by_sender;
+        ix.transferable_by_sender = transferable;
+    }
+
+    if let Some(transferable) = update.transferable_by_recipient {
+        unfavorable |= !transferable && ix.transferable_by_recipient;
+        ix.transferable_by_recipient = transferable;
+    }
+
+    if unfavorable
+        && (!metadata.recipient_revealed()
+            || acc.recipient.key != &metadata.recipient
+            || !acc.recipient.is_signer)
+    {
+        msg!("Error: Change is unfavorable to the recipient, who must co-sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    metadata.ix.check_schedule()?;
+    metadata.cache_schedule();
+    if metadata.vested(now) < metadata.withdrawn_amount {
+        msg!("Error: Update would unvest tokens already withdrawn");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    metadata.closable_at = metadata.closable();
+    metadata.bump_nonce();
+
+    let bytes = metadata.try_to_vec()?;
+    data[0..bytes.len()].clone_from_slice(&bytes);
+
+    msg!(
+        "End time: {}, release rate: {}, closable at: {}",
+        metadata.ix.end_time,
+        metadata.ix.release_rate,
+        metadata.closable_at
+    );
+
+    notify(&metadata);
+
+    Ok(())
+}
@@ -1,6 +1,61 @@
-use std::iter::FromIterator;
+use std::convert::TryInto;
+use std::fmt::{self, Display, Write};
 
-use solana_program::{account_info::AccountInfo, program_error::ProgramError, program_pack::Pack};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable,
+    hash::hashv,
+    log::sol_log,
+    msg,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+use crate::error::StreamFlowError::TimestampPrecisionError;
+
+/// Where handlers get the current unix timestamp from. The stream math only
+/// ever takes `now` as an argument; this keeps the one sysvar read swappable,
+/// so views can be evaluated at a chosen time and off-chain code can drive
+/// the same paths with `MockClock`.
+pub trait TimeSource {
+    fn now(&self) -> Result<u64, ProgramError>;
+}
+
+/// The cluster clock.
+pub struct SysvarClock;
+
+impl TimeSource for SysvarClock {
+    fn now(&self) -> Result<u64, ProgramError> {
+        Ok(Clock::get()?.unix_timestamp as u64)
+    }
+}
+
+/// A fixed timestamp.
+pub struct MockClock(pub u64);
+
+impl TimeSource for MockClock {
+    fn now(&self) -> Result<u64, ProgramError> {
+        Ok(self.0)
+    }
+}
+
+/// Timestamps from here on are taken for milliseconds: in seconds it's the
+/// year 5138, in milliseconds 1973.
+pub const MILLISECOND_TIMESTAMP_MIN: u64 = 100_000_000_000;
+
+/// Checks that an instruction timestamp is in seconds. JS clients often pass
+/// `Date.now()` unscaled, which in seconds would lock funds for millennia;
+/// those are rejected rather than guessed at.
+pub fn normalize_timestamp(ts: u64) -> Result<u64, ProgramError> {
+    if ts >= MILLISECOND_TIMESTAMP_MIN {
+        msg!("Error: Timestamp {} looks like milliseconds", ts);
+        return Err(TimestampPrecisionError.into());
+    }
+
+    Ok(ts)
+}
 
 pub fn duration_sanity(now: u64, start: u64, end: u64, cliff: u64) -> bool {
     let cliff_cond = if cliff == 0 {
@@ -12,20 +67,92 @@ pub fn duration_sanity(now: u64, start: u64, end: u64, cliff: u64) -> bool {
     now < start && start < end && cliff_cond
 }
 
+/// Drops control characters, which break indexers rendering stream names.
+/// Borsh already rejects names that aren't valid UTF-8.
+pub fn sanitize_name(name: &str) -> String {
+    name.chars().filter(|c| !c.is_control()).collect()
+}
+
+pub mod token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+/// Token-2022 tags extended accounts with their type right after the base
+/// account layout.
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// SPL Token, or with the `token2022` feature also Token-2022, whose base
+/// account layouts and instructions are the same.
+pub fn is_token_program(key: &Pubkey) -> bool {
+    key == &spl_token::id() || (cfg!(feature = "token2022") && key == &token_2022::id())
+}
+
+/// Associated token account of `wallet` under either token program.
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &spl_associated_token_account::id(),
+    )
+    .0
+}
+
 pub fn unpack_token_account(
     account_info: &AccountInfo,
 ) -> Result<spl_token::state::Account, ProgramError> {
-    if account_info.owner != &spl_token::id() {
+    if !is_token_program(account_info.owner) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let data = account_info.data.borrow();
+    let len = spl_token::state::Account::LEN;
+    if data.len() < len || (data.len() > len && data[len] != ACCOUNT_TYPE_ACCOUNT) {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    spl_token::state::Account::unpack(&account_info.data.borrow())
+    spl_token::state::Account::unpack(&data[..len])
+}
+
+/// Transfer fees Token-2022 withheld in a token account, read from its
+/// `TransferFeeAmount` extension. 0 for SPL Token accounts.
+pub fn withheld_fees(account_info: &AccountInfo) -> u64 {
+    const TRANSFER_FEE_AMOUNT: u16 = 2;
+
+    let data = account_info.data.borrow();
+    let mut offset = spl_token::state::Account::LEN + 1;
+    while offset + 4 <= data.len() {
+        let kind = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let value = &data[offset + 4..data.len().min(offset + 4 + len)];
+        if kind == TRANSFER_FEE_AMOUNT && value.len() == 8 {
+            return u64::from_le_bytes(value.try_into().unwrap());
+        }
+        offset += 4 + len;
+    }
+
+    0
+}
+
+pub fn unpack_multisig_account(
+    account_info: &AccountInfo,
+) -> Result<spl_token::state::Multisig, ProgramError> {
+    if !is_token_program(account_info.owner) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    spl_token::state::Multisig::unpack(&account_info.data.borrow())
 }
 
 pub fn unpack_mint_account(
     account_info: &AccountInfo,
 ) -> Result<spl_token::state::Mint, ProgramError> {
-    spl_token::state::Mint::unpack(&account_info.data.borrow())
+    let data = account_info.data.borrow();
+    let len = spl_token::state::Mint::LEN;
+    if data.len() > len && data.get(spl_token::state::Account::LEN) != Some(&ACCOUNT_TYPE_MINT) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    spl_token::state::Mint::unpack(&data[..len.min(data.len())])
 }
 
 pub fn pretty_time(t: u64) -> String {
@@ -41,16 +168,204 @@ pub fn pretty_time(t: u64) -> String {
 }
 
 pub fn encode_base10(amount: u64, decimal_places: usize) -> String {
-    let mut s: Vec<char> = format!("{:0width$}", amount, width = 1 + decimal_places)
-        .chars()
-        .collect();
-    s.insert(s.len() - decimal_places, '.');
+    Base10(amount, decimal_places).to_string()
+}
+
+/// Displays a token amount in whole tokens of a mint with the given decimal
+/// places, without trailing zeros, e.g. `Base10(1_500, 3)` as `1.5`.
+pub struct Base10(pub u64, pub usize);
+
+impl Display for Base10 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Base10(amount, decimal_places) = *self;
+        // Least significant first; positions past `len` are zeros.
+        let mut digits = [0u8; 20];
+        let mut len = 0;
+        let mut rest = amount;
+        loop {
+            digits[len] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            len += 1;
+            if rest == 0 {
+                break;
+            }
+        }
+        let digit = |i: usize| if i < len { digits[i] as char } else { '0' };
 
-    String::from_iter(&s)
-        .trim_end_matches('0')
-        .trim_end_matches('.')
-        .to_string()
+        if len > decimal_places {
+            for i in (decimal_places..len).rev() {
+                f.write_char(digit(i))?;
+            }
+        } else {
+            f.write_char('0')?;
+        }
+
+        let trailing_zeros = (0..decimal_places).take_while(|&i| digit(i) == '0').count();
+        if trailing_zeros < decimal_places {
+            f.write_char('.')?;
+            for i in (trailing_zeros..decimal_places).rev() {
+                f.write_char(digit(i))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays a pubkey in base58 like its own `Display`, which encodes into a
+/// `String` first.
+pub struct Key<'a>(pub &'a Pubkey);
+
+impl Display for Key<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut encoded = [0u8; 44];
+        let len = bs58::encode(self.0)
+            .into(&mut encoded[..])
+            .map_err(|_| fmt::Error)?;
+        // The base58 alphabet is ASCII.
+        f.write_str(std::str::from_utf8(&encoded[..len]).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Longest `stack_msg!` line; longer ones are cut short.
+pub const LOG_LINE_LEN: usize = 256;
+
+struct LogLine {
+    buf: [u8; LOG_LINE_LEN],
+    len: usize,
+}
+
+impl Write for LogLine {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LOG_LINE_LEN - self.len;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        if take < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Logs `args` formatted into a buffer on the stack, see `stack_msg!`.
+pub fn log_args(args: fmt::Arguments) {
+    let mut line = LogLine {
+        buf: [0; LOG_LINE_LEN],
+        len: 0,
+    };
+    // Overlong lines are logged cut short, at a character boundary.
+    let _ = line.write_fmt(args);
+    if let Ok(s) = std::str::from_utf8(&line.buf[..line.len]) {
+        sol_log(s);
+    }
+}
+
+/// `msg!` without the heap: formats on the stack instead of into a `String`.
+/// For handlers that run often, withdrawals and cancels, where every
+/// allocation counts against the 32KB heap. Log amounts with `Base10` and
+/// pubkeys with `Key`, which don't allocate either.
+#[macro_export]
+macro_rules! stack_msg {
+    ($($arg:tt)+) => {
+        $crate::utils::log_args(format_args!($($arg)+))
+    };
+}
+
+/// Commitment stored for private streams: sha256(recipient || salt).
+pub fn recipient_hash(recipient: &Pubkey, salt: &[u8; 32]) -> [u8; 32] {
+    hashv(&[recipient.as_ref(), salt]).to_bytes()
+}
+
+/// Reads the upgrade authority out of the program's `ProgramData` account.
+/// Used to gate one-time initialization of global admin accounts.
+pub fn program_upgrade_authority(
+    program_id: &Pubkey,
+    program_data: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    let (program_data_key, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if program_data.key != &program_data_key || program_data.owner != &bpf_loader_upgradeable::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // UpgradeableLoaderState::ProgramData { slot: u64, upgrade_authority_address: Option<Pubkey> }
+    let data = program_data.data.borrow();
+    if data.len() < 45 || data[0..4] != 3u32.to_le_bytes() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if data[12] == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Pubkey::new(&data[13..45])))
 }
 
+/// Written over the start of program-owned accounts on close. It can't be
+/// the `magic` of any account type, so an account revived within the same
+/// transaction by refunding its rent never deserializes as live state.
+pub const CLOSED_ACCOUNT_DISCRIMINATOR: [u8; 8] = [0xff; 8];
 
+/// `account` passed in an optional slot, unless it is a placeholder: clients
+/// fill slots they skip with the program id or the default pubkey.
+pub fn optional_slot<'a>(
+    program_id: &Pubkey,
+    account: &AccountInfo<'a>,
+) -> Option<AccountInfo<'a>> {
+    if account.key == program_id || account.key == &Pubkey::default() {
+        return None;
+    }
+    Some(account.clone())
+}
 
+/// Closes a program-owned `account` into `destination`: zeroes its data,
+/// marks it with `CLOSED_ACCOUNT_DISCRIMINATOR` and only then moves its
+/// lamports. Every close path of metadata, ledgers, pools and other program
+/// accounts must go through this.
+pub fn close_program_account(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    destination: &AccountInfo,
+) -> Result<(), ProgramError> {
+    if account.owner != program_id || !account.is_writable || !destination.is_writable {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut data = account.try_borrow_mut_data()?;
+    data.fill(0);
+    let len = data.len().min(CLOSED_ACCOUNT_DISCRIMINATOR.len());
+    data[..len].clone_from_slice(&CLOSED_ACCOUNT_DISCRIMINATOR[..len]);
+
+    let refund = destination
+        .lamports()
+        .checked_add(account.lamports())
+        .ok_or(ProgramError::InvalidAccountData)?;
+    **destination.try_borrow_mut_lamports()? = refund;
+    **account.try_borrow_mut_lamports()? = 0;
+
+    Ok(())
+}
+
+/// Formats a clock or lamport dependent value for logging. Built with the
+/// `deterministic-logs` feature these are masked, so identical inputs produce
+/// byte-identical logs that can be snapshot tested.
+pub fn volatile<T: Display>(value: T) -> Volatile<T> {
+    Volatile(value)
+}
+
+pub struct Volatile<T>(T);
+
+impl<T: Display> Display for Volatile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if cfg!(feature = "deterministic-logs") {
+            f.write_str("*")
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
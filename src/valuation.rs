@@ -0,0 +1,77 @@
+//! Off-chain valuation of vesting positions for reporting.
+
+use crate::state::TokenStreamData;
+
+/// USD value of a stream's tokens at a point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Valuation {
+    pub vested: f64,
+    pub unvested: f64,
+    pub withdrawn: f64,
+}
+
+/// One row of an amortization style vesting table.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AmortizationRow {
+    pub period_start: u64,
+    pub period_end: u64,
+    pub vested_amount: u64,
+    pub vested_value: f64,
+    pub cumulative_amount: u64,
+    pub cumulative_value: f64,
+}
+
+/// Upper bound on rows produced by `amortization_table`.
+pub const MAX_AMORTIZATION_ROWS: usize = 1000;
+
+/// Converts base units into a USD value given the price of one whole token.
+pub fn to_usd(amount: u64, decimals: u8, price: f64) -> f64 {
+    amount as f64 / 10f64.powi(decimals.into()) * price
+}
+
+pub fn value_at(stream: &TokenStreamData, at: u64, decimals: u8, price: f64) -> Valuation {
+    let vested = stream.vested(at);
+    let unvested = stream.ix.deposited_amount.saturating_sub(vested);
+
+    Valuation {
+        vested: to_usd(vested, decimals, price),
+        unvested: to_usd(unvested, decimals, price),
+        withdrawn: to_usd(stream.withdrawn_amount, decimals, price),
+    }
+}
+
+/// Splits the stream's schedule into `step` second rows, from `start_time`
+/// until the stream is closable, valuing each row's vesting at `price`.
+pub fn amortization_table(
+    stream: &TokenStreamData,
+    step: u64,
+    decimals: u8,
+    price: f64,
+) -> Vec<AmortizationRow> {
+    let mut rows = vec![];
+    if step == 0 {
+        return rows;
+    }
+
+    let end = stream.closable_at.max(stream.ix.start_time);
+    let mut period_start = stream.ix.start_time;
+    let mut cumulative_amount = 0;
+    while period_start < end && rows.len() < MAX_AMORTIZATION_ROWS {
+        let period_end = period_start.saturating_add(step).min(end);
+        let vested = stream.vested(period_end);
+        let vested_amount = vested.saturating_sub(cumulative_amount);
+        cumulative_amount = vested;
+
+        rows.push(AmortizationRow {
+            period_start,
+            period_end,
+            vested_amount,
+            vested_value: to_usd(vested_amount, decimals, price),
+            cumulative_amount,
+            cumulative_value: to_usd(cumulative_amount, decimals, price),
+        });
+        period_start = period_end;
+    }
+
+    rows
+}
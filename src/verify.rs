@@ -0,0 +1,124 @@
+//! `verify_stream`: re-runs the invariants handlers rely on over one stream
+//! and reports what fails as a `Verified` event, so auditors and monitoring
+//! bots can health check every live stream without trusting an indexer.
+//! Failures don't fail the instruction; the event carries them instead.
+
+use solana_program::{
+    entrypoint::ProgramResult, msg, program::set_return_data, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::events::{emit, StreamEvent, Verified};
+use crate::state::{EndCause, TokenStreamData, VerifyStreamAccounts};
+use crate::utils::{associated_token_address, is_token_program, unpack_token_account};
+
+/// The metadata isn't at the address its own fields derive.
+pub const METADATA_ADDRESS: u32 = 1 << 0;
+/// `escrow_tokens` isn't the stream's escrow PDA.
+pub const ESCROW_ADDRESS: u32 = 1 << 1;
+/// `recipient_tokens` isn't the recipient's associated token account.
+pub const RECIPIENT_TOKENS_ADDRESS: u32 = 1 << 2;
+/// More withdrawn than deposited, or more fees paid than withdrawn.
+pub const BOOKKEEPING: u32 = 1 << 3;
+/// The escrow holds less than the deposit still owed plus the bond.
+pub const ESCROW_SHORTFALL: u32 = 1 << 4;
+/// The escrow holds another mint or isn't its own authority.
+pub const ESCROW_ACCOUNT: u32 = 1 << 5;
+/// The schedule fails the checks of `create`.
+pub const SCHEDULE: u32 = 1 << 6;
+/// The cached vesting start or per-period amount is stale.
+pub const SCHEDULE_CACHE: u32 = 1 << 7;
+
+/// Permissionless. Emits `Verified` with the failure codes above OR-ed
+/// together, and returns them as little-endian return data.
+pub fn verify_stream(program_id: &Pubkey, acc: VerifyStreamAccounts) -> ProgramResult {
+    msg!("Verifying stream");
+
+    if acc.metadata.data_is_empty() || acc.metadata.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let data = acc.metadata.try_borrow_data()?;
+    let metadata = TokenStreamData::load(&data)?;
+
+    if acc.escrow_tokens.key != &metadata.escrow_tokens
+        || acc.mint.key != &metadata.mint
+        || !is_token_program(acc.mint.owner)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut failures = 0;
+
+    if !metadata.has_address(program_id, acc.metadata.key) {
+        failures |= METADATA_ADDRESS;
+    }
+
+    let (escrow_tokens_pubkey, _) = Pubkey::find_program_address(
+        &[acc.metadata.key.as_ref(), &metadata.escrow_seed()],
+        program_id,
+    );
+    if metadata.escrow_tokens != escrow_tokens_pubkey {
+        failures |= ESCROW_ADDRESS;
+    }
+
+    if metadata.recipient != Pubkey::default()
+        && metadata.recipient_tokens
+            != associated_token_address(&metadata.recipient, &metadata.mint, acc.mint.owner)
+    {
+        failures |= RECIPIENT_TOKENS_ADDRESS;
+    }
+
+    if metadata.withdrawn_amount > metadata.ix.deposited_amount
+        || metadata.fees_paid > metadata.withdrawn_amount
+    {
+        failures |= BOOKKEEPING;
+    }
+
+    // Until the stream ends, with a cancel or the last withdrawal, the escrow
+    // backs what's left of the deposit and the bond; a bond outlives the
+    // last withdrawal.
+    let owed = metadata
+        .ix
+        .deposited_amount
+        .saturating_sub(metadata.withdrawn_amount)
+        .saturating_add(metadata.ix.bond_amount);
+    if acc.escrow_tokens.data_is_empty() || !is_token_program(acc.escrow_tokens.owner) {
+        if metadata.canceled_at == 0 && metadata.end_cause == EndCause::Open && owed > 0 {
+            failures |= ESCROW_SHORTFALL;
+        }
+    } else {
+        let escrow = unpack_token_account(&acc.escrow_tokens)?;
+        if escrow.mint != metadata.mint || &escrow.owner != acc.escrow_tokens.key {
+            failures |= ESCROW_ACCOUNT;
+        }
+        if metadata.canceled_at == 0 && escrow.amount < owed {
+            failures |= ESCROW_SHORTFALL;
+        }
+    }
+
+    if metadata.ix.check_schedule().is_err() {
+        failures |= SCHEDULE;
+    }
+
+    let mut recached = metadata.clone();
+    recached.cache_schedule();
+    if recached.effective_cliff != metadata.effective_cliff
+        || recached.period_amount_scaled != metadata.period_amount_scaled
+    {
+        failures |= SCHEDULE_CACHE;
+    }
+
+    msg!("Failures: {:#x}", failures);
+
+    emit(StreamEvent::Verified(Verified {
+        stream: *acc.metadata.key,
+        passed: failures == 0,
+        failures,
+        notify_key: metadata.ix.notify_key,
+        origin: metadata.ix.origin,
+    }));
+    set_return_data(&failures.to_le_bytes());
+
+    Ok(())
+}